@@ -0,0 +1,53 @@
+#![cfg_attr(feature = "fail-on-warnings", deny(warnings))]
+#![cfg_attr(feature = "fail-on-warnings", deny(clippy::all))]
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
+
+/// Implements `From<T> for CelMap` and `From<T> for CelValue` for a struct with named fields.
+/// Every field type must implement `Into<CelValue>` (nested structs can derive `IntoCelMap`).
+#[proc_macro_derive(IntoCelMap)]
+pub fn derive_into_cel_map(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return syn::Error::new_spanned(name, "IntoCelMap requires named fields")
+                    .to_compile_error()
+                    .into()
+            }
+        },
+        _ => {
+            return syn::Error::new_spanned(name, "IntoCelMap can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+    let inserts = fields.iter().map(|field| {
+        let ident = field.ident.as_ref().expect("named field");
+        let key = ident.to_string();
+        quote! { map.insert(#key, value.#ident); }
+    });
+
+    quote! {
+        impl #impl_generics From<#name #ty_generics> for ::cel_interpreter::CelMap #where_clause {
+            fn from(value: #name #ty_generics) -> Self {
+                let mut map = ::cel_interpreter::CelMap::new();
+                #(#inserts)*
+                map
+            }
+        }
+
+        impl #impl_generics From<#name #ty_generics> for ::cel_interpreter::CelValue #where_clause {
+            fn from(value: #name #ty_generics) -> Self {
+                ::cel_interpreter::CelValue::from(::cel_interpreter::CelMap::from(value))
+            }
+        }
+    }
+    .into()
+}
@@ -1,27 +1,647 @@
-use chrono::{NaiveDate, Utc};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use lru::LruCache;
+use regex::Regex;
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+use uuid::Uuid;
 
-use std::rc::Rc;
+use std::{cell::RefCell, num::NonZeroUsize, sync::Arc};
 
 use super::value::*;
-use crate::error::*;
+use crate::{cel_type::*, error::*};
 
 pub(crate) fn date(args: Vec<CelValue>) -> Result<CelValue, CelError> {
     if args.is_empty() {
         return Ok(CelValue::Date(Utc::now().date_naive()));
     }
 
-    let s: Rc<String> = assert_arg(args.get(0))?;
+    let s: Arc<String> = assert_arg(args.first())?;
     Ok(CelValue::Date(NaiveDate::parse_from_str(&s, "%Y-%m-%d")?))
 }
 
+pub(crate) fn timestamp(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    if args.is_empty() {
+        return Ok(CelValue::Timestamp(Utc::now()));
+    }
+
+    let s: Arc<String> = assert_arg(args.first())?;
+    Ok(CelValue::Timestamp(
+        DateTime::parse_from_rfc3339(&s)?.with_timezone(&Utc),
+    ))
+}
+
+/// `duration('1h30m')` parses a sequence of decimal numbers with units `h`, `m`, `s`, `ms`, `us`, `ns`,
+/// optionally signed, as in CEL.
+pub(crate) fn duration(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    Ok(CelValue::Duration(parse_duration(&s)?))
+}
+
+fn parse_duration(s: &str) -> Result<Duration, CelError> {
+    let err = || CelError::InvalidArgument(format!("invalid duration '{s}'"));
+    let (negative, mut rest) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if rest.is_empty() {
+        return Err(err());
+    }
+    let mut nanos = Decimal::ZERO;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(err)?;
+        let (number, tail) = rest.split_at(number_len);
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let unit_nanos: i64 = match unit {
+            "h" => 3_600_000_000_000,
+            "m" => 60_000_000_000,
+            "s" => 1_000_000_000,
+            "ms" => 1_000_000,
+            "us" => 1_000,
+            "ns" => 1,
+            _ => return Err(err()),
+        };
+        let number: Decimal = number.parse().map_err(|_| err())?;
+        nanos = number
+            .checked_mul(Decimal::from(unit_nanos))
+            .and_then(|n| n.checked_add(nanos))
+            .ok_or_else(err)?;
+        rest = tail;
+    }
+    let nanos = nanos.trunc().to_i64().ok_or_else(err)?;
+    Ok(Duration::nanoseconds(if negative { -nanos } else { nanos }))
+}
+
+pub(crate) fn contains(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    let sub: Arc<String> = assert_arg(args.get(1))?;
+    Ok(CelValue::Bool(s.contains(sub.as_str())))
+}
+
+pub(crate) fn starts_with(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    let prefix: Arc<String> = assert_arg(args.get(1))?;
+    Ok(CelValue::Bool(s.starts_with(prefix.as_str())))
+}
+
+pub(crate) fn ends_with(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    let suffix: Arc<String> = assert_arg(args.get(1))?;
+    Ok(CelValue::Bool(s.ends_with(suffix.as_str())))
+}
+
+pub(crate) fn lower_ascii(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    Ok(CelValue::String(Arc::from(s.to_ascii_lowercase())))
+}
+
+const REGEX_CACHE_SIZE: usize = 256;
+
+thread_local! {
+    static REGEX_CACHE: RefCell<LruCache<String, Regex>> =
+        RefCell::new(LruCache::new(NonZeroUsize::new(REGEX_CACHE_SIZE).unwrap()));
+}
+
+pub(crate) fn matches(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    let pattern: Arc<String> = assert_arg(args.get(1))?;
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(re) = cache.get(pattern.as_str()) {
+            return Ok(CelValue::Bool(re.is_match(&s)));
+        }
+        let re = Regex::new(&pattern)?;
+        let res = re.is_match(&s);
+        cache.put(pattern.to_string(), re);
+        Ok(CelValue::Bool(res))
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn cached_regexes() -> usize {
+    REGEX_CACHE.with(|cache| cache.borrow().len())
+}
+
+/// Number of unicode code points in a string, bytes in bytes, or elements in a list or map.
+pub(crate) fn size(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let size = match args.first() {
+        Some(CelValue::String(s)) => s.chars().count(),
+        Some(CelValue::Bytes(b)) => b.len(),
+        Some(CelValue::List(l)) => l.len(),
+        Some(CelValue::Map(m)) => m.len(),
+        Some(v) => {
+            return Err(CelError::WrongArgumentType(
+                CelType::from(v),
+                CelType::String,
+            ))
+        }
+        None => return Err(CelError::MissingArgument),
+    };
+    Ok(CelValue::Int(size as i64))
+}
+
+/// `uuid()` generates a random (v4) uuid, `uuid(s)` parses one.
 pub(crate) fn uuid(args: Vec<CelValue>) -> Result<CelValue, CelError> {
-    let s: Rc<String> = assert_arg(args.get(0))?;
-    Ok(CelValue::Uuid(
-        s.parse()
-            .map_err(|e| CelError::UuidError(format!("{e:?}")))?,
+    if args.is_empty() {
+        return Ok(CelValue::Uuid(Uuid::new_v4()));
+    }
+
+    let s: Arc<String> = assert_arg(args.first())?;
+    Ok(CelValue::Uuid(s.parse().map_err(|e| {
+        CelError::UuidError(format!("'{s}' is not a valid uuid: {e}"))
+    })?))
+}
+
+/// `int(x)` converts to a signed integer, truncating decimals towards zero.
+/// Timestamps convert to seconds since the unix epoch.
+pub(crate) fn int(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let res = match args.first() {
+        Some(CelValue::Int(i)) => Some(*i),
+        Some(CelValue::UInt(u)) => i64::try_from(*u).ok(),
+        Some(CelValue::BigInt(i)) => i64::try_from(*i).ok(),
+        Some(CelValue::Double(d)) => d.trunc().to_i64(),
+        Some(CelValue::String(s)) => Some(
+            s.trim()
+                .parse::<i64>()
+                .map_err(|_| CelError::InvalidArgument(format!("cannot convert '{s}' to int")))?,
+        ),
+        Some(CelValue::Timestamp(t)) => Some(t.timestamp()),
+        Some(v) => return Err(CelError::WrongArgumentType(CelType::from(v), CelType::Int)),
+        None => return Err(CelError::MissingArgument),
+    };
+    res.map(CelValue::Int)
+        .ok_or_else(|| CelError::InvalidArgument("int() out of range".to_string()))
+}
+
+/// `uint(x)` converts to an unsigned integer, truncating decimals towards zero.
+pub(crate) fn uint(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let res = match args.first() {
+        Some(CelValue::Int(i)) => u64::try_from(*i).ok(),
+        Some(CelValue::UInt(u)) => Some(*u),
+        Some(CelValue::BigInt(i)) => u64::try_from(*i).ok(),
+        Some(CelValue::Double(d)) => d.trunc().to_u64(),
+        Some(CelValue::String(s)) => Some(
+            s.trim()
+                .parse::<u64>()
+                .map_err(|_| CelError::InvalidArgument(format!("cannot convert '{s}' to uint")))?,
+        ),
+        Some(v) => return Err(CelError::WrongArgumentType(CelType::from(v), CelType::UInt)),
+        None => return Err(CelError::MissingArgument),
+    };
+    res.map(CelValue::UInt)
+        .ok_or_else(|| CelError::InvalidArgument("uint() out of range".to_string()))
+}
+
+/// `bigint(x)` converts to a 128 bit integer, truncating decimals towards zero.
+pub(crate) fn big_int(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let res =
+        match args.first() {
+            Some(CelValue::Int(i)) => Some(i128::from(*i)),
+            Some(CelValue::UInt(u)) => Some(i128::from(*u)),
+            Some(CelValue::BigInt(i)) => Some(*i),
+            Some(CelValue::Double(d)) => d.trunc().to_i128(),
+            Some(CelValue::String(s)) => Some(s.trim().parse::<i128>().map_err(|_| {
+                CelError::InvalidArgument(format!("cannot convert '{s}' to bigint"))
+            })?),
+            Some(v) => {
+                return Err(CelError::WrongArgumentType(
+                    CelType::from(v),
+                    CelType::BigInt,
+                ))
+            }
+            None => return Err(CelError::MissingArgument),
+        };
+    res.map(CelValue::BigInt)
+        .ok_or_else(|| CelError::InvalidArgument("bigint() out of range".to_string()))
+}
+
+/// `double(x)` / `decimal(x)` convert to a decimal number, `Double` is backed by `Decimal`.
+pub(crate) fn decimal(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let res = match args.first() {
+        Some(CelValue::Int(i)) => Decimal::from(*i),
+        Some(CelValue::UInt(u)) => Decimal::from(*u),
+        Some(CelValue::BigInt(i)) => big_int_to_decimal(*i)?,
+        Some(CelValue::Double(d)) => *d,
+        Some(CelValue::String(s)) => s
+            .trim()
+            .parse::<Decimal>()
+            .map_err(|_| CelError::InvalidArgument(format!("cannot convert '{s}' to decimal")))?,
+        Some(v) => {
+            return Err(CelError::WrongArgumentType(
+                CelType::from(v),
+                CelType::Double,
+            ))
+        }
+        None => return Err(CelError::MissingArgument),
+    };
+    Ok(CelValue::Double(res))
+}
+
+pub(crate) fn string(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let res = match args.first() {
+        Some(CelValue::String(s)) => return Ok(CelValue::String(Arc::clone(s))),
+        Some(CelValue::Int(i)) => i.to_string(),
+        Some(CelValue::UInt(u)) => u.to_string(),
+        Some(CelValue::BigInt(i)) => i.to_string(),
+        Some(CelValue::Double(d)) => d.to_string(),
+        Some(CelValue::Bool(b)) => b.to_string(),
+        Some(CelValue::Bytes(b)) => String::from_utf8(b.to_vec())
+            .map_err(|_| CelError::InvalidArgument("bytes are not valid utf-8".to_string()))?,
+        Some(CelValue::Uuid(u)) => u.to_string(),
+        Some(CelValue::Date(d)) => d.to_string(),
+        Some(CelValue::Timestamp(t)) => t.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        Some(CelValue::Duration(d)) => format_duration(d),
+        Some(v) => {
+            return Err(CelError::WrongArgumentType(
+                CelType::from(v),
+                CelType::String,
+            ))
+        }
+        None => return Err(CelError::MissingArgument),
+    };
+    Ok(CelValue::String(Arc::from(res)))
+}
+
+/// `format(template, args...)` substitutes each directive in `template` with the next argument:
+/// `%s` takes anything `string()` accepts, `%d` an integer and `%f` / `%.Nf` a number
+/// (rounded half away from zero to `N` decimal places). `%%` is a literal `%`.
+pub(crate) fn format(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let template: Arc<String> = assert_arg(args.first())?;
+    let mut values = args.into_iter().skip(1);
+    let mut res = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                digits.push(d);
+            }
+            precision = Some(digits.parse::<u32>().map_err(|_| {
+                CelError::InvalidArgument(format!("bad precision in format string '{template}'"))
+            })?);
+        }
+        match (chars.next(), precision) {
+            (Some('%'), None) => res.push('%'),
+            (Some('s'), None) => {
+                if let CelValue::String(s) =
+                    string(vec![values.next().ok_or(CelError::MissingArgument)?])?
+                {
+                    res.push_str(&s);
+                }
+            }
+            (Some('d'), None) => match values.next().ok_or(CelError::MissingArgument)? {
+                CelValue::Int(i) => res.push_str(&i.to_string()),
+                CelValue::UInt(u) => res.push_str(&u.to_string()),
+                CelValue::BigInt(i) => res.push_str(&i.to_string()),
+                v => return Err(CelError::WrongArgumentType(CelType::from(&v), CelType::Int)),
+            },
+            (Some('f'), precision) => {
+                let mut d: Decimal = assert_arg(values.next().as_ref())?;
+                if let Some(scale) = precision {
+                    d = d.round_dp_with_strategy(scale, RoundingStrategy::MidpointAwayFromZero);
+                    d.rescale(scale);
+                }
+                res.push_str(&d.to_string());
+            }
+            _ => {
+                return Err(CelError::InvalidArgument(format!(
+                    "unsupported directive in format string '{template}'"
+                )))
+            }
+        }
+    }
+    if values.next().is_some() {
+        return Err(CelError::InvalidArgument(format!(
+            "too many arguments for format string '{template}'"
+        )));
+    }
+    Ok(CelValue::String(Arc::from(res)))
+}
+
+/// `bytes(s)` is the utf-8 encoding of `s`.
+pub(crate) fn bytes(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    bytes_arg(args.first()).map(CelValue::Bytes)
+}
+
+pub(crate) fn base64_encode(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let b = bytes_arg(args.first())?;
+    Ok(CelValue::String(Arc::from(BASE64.encode(b.as_slice()))))
+}
+
+pub(crate) fn base64_decode(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    let b = BASE64
+        .decode(s.as_bytes())
+        .map_err(|e| CelError::InvalidArgument(format!("invalid base64 '{s}': {e}")))?;
+    Ok(CelValue::Bytes(Arc::new(b)))
+}
+
+pub(crate) fn hex_encode(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let b = bytes_arg(args.first())?;
+    Ok(CelValue::String(Arc::from(hex::encode(b.as_slice()))))
+}
+
+pub(crate) fn hex_decode(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let s: Arc<String> = assert_arg(args.first())?;
+    let b = hex::decode(s.as_bytes())
+        .map_err(|e| CelError::InvalidArgument(format!("invalid hex '{s}': {e}")))?;
+    Ok(CelValue::Bytes(Arc::new(b)))
+}
+
+// Strings are accepted wherever bytes are expected and encoded as utf-8
+fn bytes_arg(arg: Option<&CelValue>) -> Result<Arc<Vec<u8>>, CelError> {
+    match arg {
+        Some(CelValue::Bytes(b)) => Ok(Arc::clone(b)),
+        Some(CelValue::String(s)) => Ok(Arc::new(s.as_bytes().to_vec())),
+        Some(v) => Err(CelError::WrongArgumentType(
+            CelType::from(v),
+            CelType::Bytes,
+        )),
+        None => Err(CelError::MissingArgument),
+    }
+}
+
+/// `min(a, b, ...)` or `min(list)`, comparing numbers by value across types.
+pub(crate) fn min(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    extreme(args, std::cmp::Ordering::Less)
+}
+
+/// `max(a, b, ...)` or `max(list)`, comparing numbers by value across types.
+pub(crate) fn max(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    extreme(args, std::cmp::Ordering::Greater)
+}
+
+fn extreme(args: Vec<CelValue>, wanted: std::cmp::Ordering) -> Result<CelValue, CelError> {
+    let values = match args.as_slice() {
+        [CelValue::List(list)] => list.as_ref().clone(),
+        _ => args,
+    };
+    let mut values = values.into_iter();
+    let mut res = values.next().ok_or(CelError::MissingArgument)?;
+    for v in values {
+        if v.try_cmp(&res)? == wanted {
+            res = v;
+        }
+    }
+    Ok(res)
+}
+
+pub(crate) fn abs(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    match args.first() {
+        Some(CelValue::Int(i)) => i
+            .checked_abs()
+            .map(CelValue::Int)
+            .ok_or_else(|| CelError::Unexpected("Integer arithmetic overflowed".to_string())),
+        Some(CelValue::UInt(u)) => Ok(CelValue::UInt(*u)),
+        Some(CelValue::BigInt(i)) => i
+            .checked_abs()
+            .map(CelValue::BigInt)
+            .ok_or_else(|| CelError::Unexpected("Integer arithmetic overflowed".to_string())),
+        Some(CelValue::Double(d)) => Ok(CelValue::Double(d.abs())),
+        Some(v) => Err(CelError::WrongArgumentType(
+            CelType::from(v),
+            CelType::Double,
+        )),
+        None => Err(CelError::MissingArgument),
+    }
+}
+
+/// `sum(list)` adds up a list of numbers. Integers stay integers (of the widest type involved)
+/// unless a decimal is present, `sum([])` is `0`.
+pub(crate) fn sum(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let list: Arc<Vec<CelValue>> = assert_arg(args.first())?;
+    let overflow = || CelError::Unexpected("Integer arithmetic overflowed".to_string());
+    if list.iter().all(|v| v.as_i128().is_some()) {
+        let mut total: i128 = 0;
+        for v in list.iter() {
+            total = total
+                .checked_add(v.as_i128().expect("integer"))
+                .ok_or_else(overflow)?;
+        }
+        return Ok(if list.iter().any(|v| matches!(v, CelValue::BigInt(_))) {
+            CelValue::BigInt(total)
+        } else if !list.is_empty() && list.iter().all(|v| matches!(v, CelValue::UInt(_))) {
+            CelValue::UInt(u64::try_from(total).map_err(|_| overflow())?)
+        } else {
+            CelValue::Int(i64::try_from(total).map_err(|_| overflow())?)
+        });
+    }
+    let mut total = Decimal::ZERO;
+    for v in list.iter() {
+        total = total
+            .checked_add(Decimal::try_from(v)?)
+            .ok_or_else(|| CelError::Unexpected("Decimal arithmetic overflowed".to_string()))?;
+    }
+    Ok(CelValue::Double(total))
+}
+
+/// `merge(a, b, ...)` combines maps, keys of later maps override those of earlier ones.
+pub(crate) fn merge(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    if args.is_empty() {
+        return Err(CelError::MissingArgument);
+    }
+    let mut res = CelMap::new();
+    for arg in args.iter() {
+        for (k, v) in map_arg(Some(arg))?.iter() {
+            res.insert(k.clone(), v.clone());
+        }
+    }
+    Ok(CelValue::from(res))
+}
+
+/// `keys(map)` in ascending order.
+pub(crate) fn keys(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let map = map_arg(args.first())?;
+    Ok(CelValue::from(
+        sorted_entries(&map)
+            .into_iter()
+            .map(|(k, _)| CelValue::from(k))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// `values(map)` in the order of their keys.
+pub(crate) fn values(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let map = map_arg(args.first())?;
+    Ok(CelValue::from(
+        sorted_entries(&map)
+            .into_iter()
+            .map(|(_, v)| v.clone())
+            .collect::<Vec<_>>(),
     ))
 }
 
+fn sorted_entries(map: &CelMap) -> Vec<(&CelKey, &CelValue)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries
+}
+
+fn map_arg(arg: Option<&CelValue>) -> Result<Arc<CelMap>, CelError> {
+    match arg {
+        Some(CelValue::Map(map)) => Ok(Arc::clone(map)),
+        Some(v) => Err(CelError::WrongArgumentType(CelType::from(v), CelType::Map)),
+        None => Err(CelError::MissingArgument),
+    }
+}
+
+const BASIS_POINTS_PER_UNIT: i64 = 10_000;
+
+/// `bps(value)` turns a number of basis points into a decimal rate (`bps(250) == 0.025`).
+pub(crate) fn bps(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let bps: Decimal = assert_arg(args.first())?;
+    Ok(CelValue::Double(bps / Decimal::from(BASIS_POINTS_PER_UNIT)))
+}
+
+/// `applyRate(amount, bps[, scale])` computes `amount * bps / 10000`, rounded half away from zero
+/// to `scale` decimal places (default 2) only once the full-precision result is known.
+pub(crate) fn apply_rate(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let amount: Decimal = assert_arg(args.first())?;
+    let bps: Decimal = assert_arg(args.get(1))?;
+    let scale = assert_scale(args.get(2))?;
+    let result = amount
+        .checked_mul(bps)
+        .ok_or_else(|| CelError::InvalidArgument("applyRate overflowed".to_string()))?
+        / Decimal::from(BASIS_POINTS_PER_UNIT);
+    let mut result = result.round_dp_with_strategy(scale, RoundingStrategy::MidpointAwayFromZero);
+    result.rescale(scale);
+    Ok(CelValue::Double(result))
+}
+
+/// `allocate(total, weights[, scale])` splits `total` proportionally to `weights`.
+/// Parts are rounded down to `scale` decimal places (default 2, or more if `total` is more precise)
+/// and the remaining smallest units go to the parts with the largest remainders,
+/// earlier parts first on ties, so the parts always sum exactly to `total`.
+pub(crate) fn allocate(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let total: Decimal = assert_arg(args.first())?;
+    let weights: Arc<Vec<CelValue>> = assert_arg(args.get(1))?;
+    let scale = assert_scale(args.get(2))?.max(total.scale());
+
+    let weights = weights
+        .iter()
+        .map(Decimal::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    if weights.iter().any(|w| w.is_sign_negative()) {
+        return Err(CelError::InvalidArgument(
+            "allocate weights must not be negative".to_string(),
+        ));
+    }
+    let weight_sum = weights
+        .iter()
+        .try_fold(Decimal::ZERO, |sum, w| sum.checked_add(*w))
+        .ok_or_else(allocate_overflow)?;
+    if weight_sum.is_zero() {
+        return Err(CelError::InvalidArgument(
+            "allocate weights must not sum to zero".to_string(),
+        ));
+    }
+
+    let abs_total = total.abs();
+    let mut parts = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (i, w) in weights.iter().enumerate() {
+        let exact = abs_total
+            .checked_mul(*w)
+            .and_then(|n| n.checked_div(weight_sum))
+            .ok_or_else(allocate_overflow)?;
+        let part = exact.round_dp_with_strategy(scale, RoundingStrategy::ToZero);
+        remainders.push((exact - part, i));
+        parts.push(part);
+    }
+    remainders.sort_by(|(l, li), (r, ri)| r.cmp(l).then(li.cmp(ri)));
+    let unit = Decimal::new(1, scale);
+    let mut left = abs_total - parts.iter().sum::<Decimal>();
+    for (_, i) in remainders.iter() {
+        if left < unit {
+            break;
+        }
+        parts[*i] += unit;
+        left -= unit;
+    }
+
+    Ok(CelValue::List(Arc::from(
+        parts
+            .into_iter()
+            .map(|p| CelValue::Double(if total.is_sign_negative() { -p } else { p }))
+            .collect::<Vec<_>>(),
+    )))
+}
+
+fn allocate_overflow() -> CelError {
+    CelError::InvalidArgument("allocate total or weights are too large".to_string())
+}
+
+/// `round(x[, scale[, mode]])` rounds to `scale` decimal places (default 0).
+/// `mode` is one of `half_up` (default, half away from zero), `half_even` (banker's rounding),
+/// `half_down`, `up` (away from zero), `down` (towards zero), `ceiling` or `floor`.
+pub(crate) fn round(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    let strategy = match args.get(2) {
+        Some(mode) => rounding_strategy(&Arc::<String>::try_from(mode)?)?,
+        None => RoundingStrategy::MidpointAwayFromZero,
+    };
+    round_with(args, strategy)
+}
+
+pub(crate) fn floor(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    round_with(args, RoundingStrategy::ToNegativeInfinity)
+}
+
+pub(crate) fn ceil(args: Vec<CelValue>) -> Result<CelValue, CelError> {
+    round_with(args, RoundingStrategy::ToPositiveInfinity)
+}
+
+fn round_with(args: Vec<CelValue>, strategy: RoundingStrategy) -> Result<CelValue, CelError> {
+    let x: Decimal = assert_arg(args.first())?;
+    let scale = match args.get(1) {
+        Some(_) => assert_scale(args.get(1))?,
+        None => 0,
+    };
+    let mut res = x.round_dp_with_strategy(scale, strategy);
+    res.rescale(scale);
+    Ok(CelValue::Double(res))
+}
+
+fn rounding_strategy(mode: &str) -> Result<RoundingStrategy, CelError> {
+    Ok(match mode {
+        "half_up" => RoundingStrategy::MidpointAwayFromZero,
+        "half_even" => RoundingStrategy::MidpointNearestEven,
+        "half_down" => RoundingStrategy::MidpointTowardZero,
+        "up" => RoundingStrategy::AwayFromZero,
+        "down" => RoundingStrategy::ToZero,
+        "ceiling" => RoundingStrategy::ToPositiveInfinity,
+        "floor" => RoundingStrategy::ToNegativeInfinity,
+        _ => {
+            return Err(CelError::InvalidArgument(format!(
+                "unknown rounding mode '{mode}'"
+            )))
+        }
+    })
+}
+
+// Largest scale a `Decimal` can represent
+const MAX_SCALE: u32 = 28;
+
+fn assert_scale(arg: Option<&CelValue>) -> Result<u32, CelError> {
+    let scale = match arg {
+        Some(CelValue::Int(s)) if *s >= 0 => u32::try_from(*s).ok(),
+        Some(CelValue::UInt(s)) => u32::try_from(*s).ok(),
+        Some(v) => return Err(CelError::WrongArgumentType(CelType::from(v), CelType::Int)),
+        None => return Ok(2),
+    };
+    scale
+        .filter(|s| *s <= MAX_SCALE)
+        .ok_or_else(|| CelError::InvalidArgument(format!("scale must be at most {MAX_SCALE}")))
+}
+
 fn assert_arg<'a, T: TryFrom<&'a CelValue, Error = CelError>>(
     arg: Option<&'a CelValue>,
 ) -> Result<T, CelError> {
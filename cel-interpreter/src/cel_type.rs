@@ -1,8 +1,10 @@
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CelType {
     Map,
+    List,
     Int,
     UInt,
+    BigInt,
     Double,
     String,
     Bytes,
@@ -10,5 +12,7 @@ pub enum CelType {
     Null,
 
     Date,
+    Timestamp,
+    Duration,
     Uuid,
 }
@@ -0,0 +1,264 @@
+use cel_parser::ast::{self, Expression, Span};
+
+use std::sync::Arc;
+
+use crate::{cel_type::*, context::*, error::*, interpreter::*, value::*};
+
+// Static checking works on sample values rather than on types so that operators are checked
+// by the same code that evaluates them. `None` stands for a value whose type isn't known
+// before evaluation (comprehension variables, `null`, results of indexing ...).
+type Sample = Option<CelValue>;
+
+pub(crate) fn check(expr: &Expression, ctx: &CelContext) -> Vec<CelError> {
+    let mut checker = Checker {
+        ctx,
+        scope: Vec::new(),
+        errors: Vec::new(),
+    };
+    checker.check(expr);
+    checker.errors
+}
+
+struct Checker<'a> {
+    ctx: &'a CelContext,
+    scope: Vec<String>,
+    errors: Vec<CelError>,
+}
+
+impl<'a> Checker<'a> {
+    fn check(&mut self, expr: &Expression) -> Sample {
+        use Expression::*;
+        match expr {
+            Ternary(cond, left, right, span) => {
+                let cond = self.check(cond);
+                self.expect_bool(cond, *span);
+                match (self.check(left), self.check(right)) {
+                    (Some(l), Some(r)) if CelType::from(&l) == CelType::from(&r) => Some(l),
+                    _ => None,
+                }
+            }
+            Logic(_, left, right, span) => {
+                let left = self.check(left);
+                self.expect_bool(left, *span);
+                let right = self.check(right);
+                self.expect_bool(right, *span);
+                Some(CelValue::Bool(true))
+            }
+            Relation(op, left, right, span) => {
+                if let (Some(l), Some(r)) = (self.check(left), self.check(right)) {
+                    if let Err(e) = evaluate_relation(op, l, r) {
+                        self.error(e, *span);
+                    }
+                }
+                Some(CelValue::Bool(true))
+            }
+            Arithmetic(op, left, right, span) => {
+                let (l, r) = (self.check(left), self.check(right));
+                let (l, r) = (l?, r?);
+                match evaluate_arithmetic(*op, l, r) {
+                    Ok(v) => sample(v),
+                    // Samples can be zero where the actual value won't be
+                    Err(CelError::DivisionByZero) => None,
+                    Err(e) => {
+                        self.error(e, *span);
+                        None
+                    }
+                }
+            }
+            Unary(_, expr, _) => {
+                self.check(expr);
+                None
+            }
+            Member(target, member, span) => self.check_member(target, member, *span),
+            List(items) => items
+                .iter()
+                .map(|item| self.check(item))
+                .collect::<Option<Vec<_>>>()
+                .map(CelValue::from),
+            Map(entries) => {
+                let mut map = CelMap::new();
+                let mut known = true;
+                for (k, v) in entries {
+                    match (self.check(k), self.check(v)) {
+                        (Some(CelValue::String(k)), Some(v)) => map.insert(k.as_str(), v),
+                        _ => known = false,
+                    }
+                }
+                known.then(|| CelValue::from(map))
+            }
+            Struct(_, fields) => {
+                for (_, v) in fields {
+                    self.check(v);
+                }
+                None
+            }
+            Literal(val) => sample(CelValue::from(val)),
+            Error => None,
+            Ident(name, span) => {
+                if self.scope.iter().any(|var| var == name.as_str()) {
+                    return None;
+                }
+                match self.ctx.lookup(name) {
+                    Ok(ContextItem::Value(v)) => sample(v.clone()),
+                    Ok(ContextItem::Function(_)) => None,
+                    Err(e) => {
+                        self.error(e, *span);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_member(&mut self, target: &Expression, member: &ast::Member, span: Span) -> Sample {
+        use ast::Member::*;
+        match (target, member) {
+            (Expression::Ident(name, _), FunctionCall(args)) if name.as_str() == "has" => {
+                if let [Expression::Member(target, _, _)] = args.as_slice() {
+                    self.check(target);
+                }
+                Some(CelValue::Bool(true))
+            }
+            (Expression::Ident(name, name_span), FunctionCall(args)) => {
+                let args = args.iter().map(|a| self.check(a)).collect();
+                self.check_call(name, *name_span, args, span)
+            }
+            (Expression::Member(target, attr, name_span), FunctionCall(args)) => {
+                let name = match attr.as_ref() {
+                    Attribute(name) => name,
+                    _ => {
+                        self.check(target);
+                        return None;
+                    }
+                };
+                self.check_method(target, name, *name_span, args, span)
+            }
+            (_, FunctionCall(args)) => {
+                self.check(target);
+                for a in args {
+                    self.check(a);
+                }
+                None
+            }
+            (_, Attribute(field)) => match self.check(target)? {
+                CelValue::Map(map) => {
+                    let key = CelKey::from(field.as_str());
+                    if map.contains_key(&key) {
+                        sample(map.get(key))
+                    } else {
+                        self.error(CelError::UnknownField(field.to_string()), span);
+                        None
+                    }
+                }
+                v => {
+                    self.error(CelError::BadType(CelType::Map, CelType::from(&v)), span);
+                    None
+                }
+            },
+            (_, Index(idx)) => {
+                let target = self.check(target);
+                self.check(idx);
+                match target {
+                    Some(CelValue::List(_) | CelValue::Map(_)) | None => None,
+                    Some(v) => {
+                        self.error(CelError::BadType(CelType::List, CelType::from(&v)), span);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_method(
+        &mut self,
+        target: &Expression,
+        name: &str,
+        name_span: Span,
+        args: &[Expression],
+        span: Span,
+    ) -> Sample {
+        if let Some(f) = namespaced_function(target, name, self.ctx) {
+            let args = args.iter().map(|a| self.check(a)).collect();
+            return self.call(f, args, span);
+        }
+        let receiver = self.check(target);
+        match (name, args) {
+            ("orValue", [default]) => {
+                let default = self.check(default);
+                receiver.or(default)
+            }
+            (
+                "all" | "exists" | "exists_one" | "filter" | "map",
+                [Expression::Ident(var, _), rest @ ..],
+            ) if !rest.is_empty() => {
+                self.scope.push(var.to_string());
+                for a in rest {
+                    self.check(a);
+                }
+                self.scope.pop();
+                match name {
+                    "filter" | "map" => None,
+                    _ => Some(CelValue::Bool(true)),
+                }
+            }
+            _ => {
+                let mut values = vec![receiver];
+                values.extend(args.iter().map(|a| self.check(a)));
+                self.check_call(name, name_span, values, span)
+            }
+        }
+    }
+
+    fn check_call(&mut self, name: &str, name_span: Span, args: Vec<Sample>, span: Span) -> Sample {
+        let f = match self.ctx.lookup(name) {
+            Ok(ContextItem::Function(f)) => Arc::clone(f),
+            Ok(_) => return None,
+            Err(e) => {
+                self.error(e, name_span);
+                return None;
+            }
+        };
+        self.call(f, args, span)
+    }
+
+    fn call(&mut self, f: CelFunction, args: Vec<Sample>, span: Span) -> Sample {
+        let args = args.into_iter().collect::<Option<Vec<_>>>()?;
+        match f(args) {
+            Ok(v) => sample(v),
+            // Other errors depend on the actual values rather than their types
+            Err(
+                e @ (CelError::BadType(..)
+                | CelError::WrongArgumentType(..)
+                | CelError::Incomparable(..)),
+            ) => {
+                self.error(e, span);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn expect_bool(&mut self, val: Sample, span: Span) {
+        match val {
+            Some(CelValue::Bool(_)) | None => (),
+            Some(v) => self.error(CelError::BadType(CelType::Bool, CelType::from(&v)), span),
+        }
+    }
+
+    fn error(&mut self, error: CelError, span: Span) {
+        self.errors.push(CelError::Located {
+            span,
+            snippet: String::new(),
+            line: 0,
+            column: 0,
+            error: Box::new(error),
+        });
+    }
+}
+
+fn sample(val: CelValue) -> Sample {
+    match val {
+        CelValue::Null => None,
+        v => Some(v),
+    }
+}
@@ -1,11 +1,13 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{builtins, error::*, value::*};
 
-type CelFunction = Box<dyn Fn(Vec<CelValue>) -> Result<CelValue, CelError>>;
-#[derive(Debug)]
+pub(crate) type CelFunction =
+    Arc<dyn Fn(Vec<CelValue>) -> Result<CelValue, CelError> + Send + Sync>;
+#[derive(Debug, Clone)]
 pub struct CelContext {
     idents: HashMap<String, ContextItem>,
+    parent: Option<Arc<CelContext>>,
 }
 
 impl CelContext {
@@ -13,33 +15,177 @@ impl CelContext {
         let mut idents = HashMap::new();
         idents.insert(
             "date".to_string(),
-            ContextItem::Function(Box::new(builtins::date)),
+            ContextItem::Function(Arc::new(builtins::date)),
+        );
+        idents.insert(
+            "timestamp".to_string(),
+            ContextItem::Function(Arc::new(builtins::timestamp)),
+        );
+        idents.insert(
+            "duration".to_string(),
+            ContextItem::Function(Arc::new(builtins::duration)),
         );
         idents.insert(
             "uuid".to_string(),
-            ContextItem::Function(Box::new(builtins::uuid)),
+            ContextItem::Function(Arc::new(builtins::uuid)),
+        );
+        idents.insert(
+            "int".to_string(),
+            ContextItem::Function(Arc::new(builtins::int)),
+        );
+        idents.insert(
+            "uint".to_string(),
+            ContextItem::Function(Arc::new(builtins::uint)),
+        );
+        idents.insert(
+            "bigint".to_string(),
+            ContextItem::Function(Arc::new(builtins::big_int)),
+        );
+        idents.insert(
+            "double".to_string(),
+            ContextItem::Function(Arc::new(builtins::decimal)),
+        );
+        idents.insert(
+            "decimal".to_string(),
+            ContextItem::Function(Arc::new(builtins::decimal)),
+        );
+        idents.insert(
+            "string".to_string(),
+            ContextItem::Function(Arc::new(builtins::string)),
+        );
+        idents.insert(
+            "round".to_string(),
+            ContextItem::Function(Arc::new(builtins::round)),
+        );
+        idents.insert(
+            "floor".to_string(),
+            ContextItem::Function(Arc::new(builtins::floor)),
+        );
+        idents.insert(
+            "ceil".to_string(),
+            ContextItem::Function(Arc::new(builtins::ceil)),
+        );
+        idents.insert(
+            "allocate".to_string(),
+            ContextItem::Function(Arc::new(builtins::allocate)),
+        );
+        idents.insert(
+            "bps".to_string(),
+            ContextItem::Function(Arc::new(builtins::bps)),
+        );
+        idents.insert(
+            "applyRate".to_string(),
+            ContextItem::Function(Arc::new(builtins::apply_rate)),
+        );
+        idents.insert(
+            "contains".to_string(),
+            ContextItem::Function(Arc::new(builtins::contains)),
+        );
+        idents.insert(
+            "startsWith".to_string(),
+            ContextItem::Function(Arc::new(builtins::starts_with)),
+        );
+        idents.insert(
+            "endsWith".to_string(),
+            ContextItem::Function(Arc::new(builtins::ends_with)),
+        );
+        idents.insert(
+            "lowerAscii".to_string(),
+            ContextItem::Function(Arc::new(builtins::lower_ascii)),
+        );
+        idents.insert(
+            "matches".to_string(),
+            ContextItem::Function(Arc::new(builtins::matches)),
+        );
+        idents.insert(
+            "size".to_string(),
+            ContextItem::Function(Arc::new(builtins::size)),
+        );
+        idents.insert(
+            "format".to_string(),
+            ContextItem::Function(Arc::new(builtins::format)),
+        );
+        idents.insert(
+            "bytes".to_string(),
+            ContextItem::Function(Arc::new(builtins::bytes)),
+        );
+        idents.insert(
+            "base64.encode".to_string(),
+            ContextItem::Function(Arc::new(builtins::base64_encode)),
+        );
+        idents.insert(
+            "base64.decode".to_string(),
+            ContextItem::Function(Arc::new(builtins::base64_decode)),
+        );
+        idents.insert(
+            "hex.encode".to_string(),
+            ContextItem::Function(Arc::new(builtins::hex_encode)),
+        );
+        idents.insert(
+            "hex.decode".to_string(),
+            ContextItem::Function(Arc::new(builtins::hex_decode)),
+        );
+        idents.insert(
+            "min".to_string(),
+            ContextItem::Function(Arc::new(builtins::min)),
+        );
+        idents.insert(
+            "max".to_string(),
+            ContextItem::Function(Arc::new(builtins::max)),
+        );
+        idents.insert(
+            "abs".to_string(),
+            ContextItem::Function(Arc::new(builtins::abs)),
+        );
+        idents.insert(
+            "sum".to_string(),
+            ContextItem::Function(Arc::new(builtins::sum)),
+        );
+        idents.insert(
+            "merge".to_string(),
+            ContextItem::Function(Arc::new(builtins::merge)),
+        );
+        idents.insert(
+            "keys".to_string(),
+            ContextItem::Function(Arc::new(builtins::keys)),
+        );
+        idents.insert(
+            "values".to_string(),
+            ContextItem::Function(Arc::new(builtins::values)),
         );
         idents.insert(
             "SETTLED".to_string(),
-            ContextItem::Value(CelValue::String(Rc::from("SETTLED".to_string()))),
+            ContextItem::Value(CelValue::String(Arc::from("SETTLED".to_string()))),
         );
         idents.insert(
             "PENDING".to_string(),
-            ContextItem::Value(CelValue::String(Rc::from("PENDING".to_string()))),
+            ContextItem::Value(CelValue::String(Arc::from("PENDING".to_string()))),
         );
         idents.insert(
             "ENCUMBERED".to_string(),
-            ContextItem::Value(CelValue::String(Rc::from("ENCUMBERED".to_string()))),
+            ContextItem::Value(CelValue::String(Arc::from("ENCUMBERED".to_string()))),
         );
         idents.insert(
             "DEBIT".to_string(),
-            ContextItem::Value(CelValue::String(Rc::from("DEBIT".to_string()))),
+            ContextItem::Value(CelValue::String(Arc::from("DEBIT".to_string()))),
         );
         idents.insert(
             "CREDIT".to_string(),
-            ContextItem::Value(CelValue::String(Rc::from("CREDIT".to_string()))),
+            ContextItem::Value(CelValue::String(Arc::from("CREDIT".to_string()))),
         );
-        Self { idents }
+        Self {
+            idents,
+            parent: None,
+        }
+    }
+
+    /// Creates an empty context that falls back to `parent` for identifiers it doesn't define,
+    /// so a large shared context can be extended or shadowed without being copied.
+    pub fn child(parent: &Arc<CelContext>) -> Self {
+        Self {
+            idents: HashMap::new(),
+            parent: Some(Arc::clone(parent)),
+        }
     }
 }
 impl Default for CelContext {
@@ -48,6 +194,7 @@ impl Default for CelContext {
     }
 }
 
+#[derive(Clone)]
 pub(crate) enum ContextItem {
     Value(CelValue),
     Function(CelFunction),
@@ -63,14 +210,52 @@ impl std::fmt::Debug for ContextItem {
 }
 
 impl CelContext {
-    pub(crate) fn lookup(&self, name: Rc<String>) -> Result<&ContextItem, CelError> {
-        self.idents
-            .get(name.as_ref())
-            .ok_or_else(|| CelError::UnknownIdent(name.to_string()))
+    /// Makes `date()` and `timestamp()` without arguments evaluate relative to `now`
+    /// instead of the system clock.
+    pub fn set_now(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        self.idents.insert(
+            "date".to_string(),
+            ContextItem::Function(Arc::new(move |args| {
+                if args.is_empty() {
+                    Ok(CelValue::Date(now.date_naive()))
+                } else {
+                    builtins::date(args)
+                }
+            })),
+        );
+        self.idents.insert(
+            "timestamp".to_string(),
+            ContextItem::Function(Arc::new(move |args| {
+                if args.is_empty() {
+                    Ok(CelValue::Timestamp(now))
+                } else {
+                    builtins::timestamp(args)
+                }
+            })),
+        );
+    }
+
+    pub(crate) fn lookup(&self, name: &str) -> Result<&ContextItem, CelError> {
+        match (self.idents.get(name), &self.parent) {
+            (Some(item), _) => Ok(item),
+            (None, Some(parent)) => parent.lookup(name),
+            (None, None) => Err(CelError::UnknownIdent(name.to_string())),
+        }
     }
 
     pub fn add_variable(&mut self, name: impl Into<String>, value: impl Into<CelValue>) {
         self.idents
             .insert(name.into(), ContextItem::Value(value.into()));
     }
+
+    /// Registers `f` under `name`, replacing any builtin of the same name.
+    /// Arguments are passed through as evaluated so `f` can be variadic.
+    pub fn add_function(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn(Vec<CelValue>) -> Result<CelValue, CelError> + Send + Sync + 'static,
+    ) {
+        self.idents
+            .insert(name.into(), ContextItem::Function(Arc::new(f)));
+    }
 }
@@ -0,0 +1,137 @@
+use std::collections::BTreeSet;
+
+use crate::value::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Added {
+        path: String,
+        value: CelValue,
+    },
+    Removed {
+        path: String,
+        value: CelValue,
+    },
+    Changed {
+        path: String,
+        old: CelValue,
+        new: CelValue,
+    },
+}
+
+/// Recursively compares two values, reporting every key that was added, removed or changed.
+/// Paths are rendered like `params.fees[1]`, the root value has the empty path.
+pub fn cel_diff(old: &CelValue, new: &CelValue) -> Vec<Difference> {
+    let mut diffs = Vec::new();
+    diff_into(String::new(), old, new, &mut diffs);
+    diffs
+}
+
+fn diff_into(path: String, old: &CelValue, new: &CelValue, diffs: &mut Vec<Difference>) {
+    match (old, new) {
+        (CelValue::Map(old), CelValue::Map(new)) => {
+            let keys: BTreeSet<&CelKey> = old.iter().chain(new.iter()).map(|(k, _)| k).collect();
+            for key in keys {
+                let path = child_path(&path, key);
+                match (old.contains_key(key), new.contains_key(key)) {
+                    (true, true) => {
+                        diff_into(path, &old.get(key.clone()), &new.get(key.clone()), diffs)
+                    }
+                    (true, false) => diffs.push(Difference::Removed {
+                        path,
+                        value: old.get(key.clone()),
+                    }),
+                    _ => diffs.push(Difference::Added {
+                        path,
+                        value: new.get(key.clone()),
+                    }),
+                }
+            }
+        }
+        (CelValue::List(old), CelValue::List(new)) => {
+            for i in 0..old.len().max(new.len()) {
+                let path = format!("{path}[{i}]");
+                match (old.get(i), new.get(i)) {
+                    (Some(old), Some(new)) => diff_into(path, old, new, diffs),
+                    (Some(old), None) => diffs.push(Difference::Removed {
+                        path,
+                        value: old.clone(),
+                    }),
+                    (None, Some(new)) => diffs.push(Difference::Added {
+                        path,
+                        value: new.clone(),
+                    }),
+                    (None, None) => (),
+                }
+            }
+        }
+        (old, new) if old != new => diffs.push(Difference::Changed {
+            path,
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => (),
+    }
+}
+
+fn child_path(parent: &str, key: &CelKey) -> String {
+    match key {
+        CelKey::String(s) if parent.is_empty() => s.to_string(),
+        CelKey::String(s) => format!("{parent}.{s}"),
+        CelKey::Int(i) => format!("{parent}[{i}]"),
+        CelKey::UInt(u) => format!("{parent}[{u}]"),
+        CelKey::Bool(b) => format!("{parent}[{b}]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_map_with_changed_leaf() {
+        let mut fee = CelMap::new();
+        fee.insert("amount", 10);
+        let mut old = CelMap::new();
+        old.insert("fee", fee);
+        old.insert("memo", "hello");
+
+        let mut fee = CelMap::new();
+        fee.insert("amount", 20);
+        let mut new = CelMap::new();
+        new.insert("fee", fee);
+        new.insert("memo", "hello");
+
+        assert_eq!(
+            cel_diff(&CelValue::from(old), &CelValue::from(new)),
+            vec![Difference::Changed {
+                path: "fee.amount".to_string(),
+                old: CelValue::Int(10),
+                new: CelValue::Int(20),
+            }]
+        );
+    }
+
+    #[test]
+    fn list_elements() {
+        let mut old = CelMap::new();
+        old.insert("fees", vec![1, 2]);
+        let mut new = CelMap::new();
+        new.insert("fees", vec![1, 3, 4]);
+
+        assert_eq!(
+            cel_diff(&CelValue::from(old), &CelValue::from(new)),
+            vec![
+                Difference::Changed {
+                    path: "fees[1]".to_string(),
+                    old: CelValue::Int(2),
+                    new: CelValue::Int(3),
+                },
+                Difference::Added {
+                    path: "fees[2]".to_string(),
+                    value: CelValue::Int(4),
+                },
+            ]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use cel_parser::{Span, SyntaxError};
 use chrono::ParseError;
 use thiserror::Error;
 
@@ -5,25 +6,58 @@ use crate::cel_type::*;
 
 #[derive(Error, Debug)]
 pub enum CelError {
-    #[error("CelError - CelParseError: {0}")]
-    CelParseError(String),
+    #[error("CelError - CelParseError: {}", display_all(.0))]
+    CelParseError(Vec<SyntaxError>),
     #[error("CelError - BadType: expected {0:?} found {1:?}")]
     BadType(CelType, CelType),
     #[error("CelError - UnknownIdentifier: {0}")]
     UnknownIdent(String),
+    #[error("CelError - UnknownField: {0}")]
+    UnknownField(String),
+    #[error("CelError - IndexOutOfBounds: {0} for list of length {1}")]
+    IndexOutOfBounds(String, usize),
     #[error("CelError - IllegalTarget")]
     IllegalTarget,
     #[error("CelError - MissingArgument")]
     MissingArgument,
     #[error("CelError - WrongArgumentType: {0:?} instead of {1:?}")]
     WrongArgumentType(CelType, CelType),
+    #[error("CelError - InvalidArgument: {0}")]
+    InvalidArgument(String),
     #[error("CelError - ChronoParseError: {0}")]
     ChronoParseError(#[from] ParseError),
+    #[error("CelError - RegexError: {0}")]
+    RegexError(#[from] regex::Error),
+    #[error("CelError - DivisionByZero")]
+    DivisionByZero,
+    #[error("CelError - Incomparable: {0:?} and {1:?}")]
+    Incomparable(CelType, CelType),
     #[error("CelError - UuidError: {0}")]
     UuidError(String),
+    #[cfg(feature = "binary")]
+    #[error("CelError - BinaryError: {0}")]
+    BinaryError(String),
     #[error("CelError - Unexpected: {0}")]
     Unexpected(String),
 
     #[error("Error evaluating cell expression '{0}' - {1}")]
     EvaluationError(String, Box<Self>),
+    /// `error` raised while evaluating `snippet`, the part of the source expression at `span`.
+    /// `line` and `column` are 1 based.
+    #[error("{snippet}: {error} at {line}:{column}")]
+    Located {
+        span: Span,
+        snippet: String,
+        line: usize,
+        column: usize,
+        error: Box<Self>,
+    },
+}
+
+fn display_all(errors: &[SyntaxError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
@@ -1,15 +1,13 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use cel_parser::{
-    ast::{self, ArithmeticOp, Expression},
-    parser::ExpressionParser,
-};
+use cel_parser::ast::{self, ArithmeticOp, Expression, LogicOp, RelationOp};
 
-use std::rc::Rc;
+use std::{cmp::Ordering, sync::Arc};
 
-use crate::{context::*, error::*, value::*};
+use crate::{cel_type::*, checker, context::*, error::*, value::*};
 
+/// A parsed expression. It is `Send + Sync` so it can be parsed once and then cached.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(try_from = "String")]
 #[serde(into = "String")]
@@ -30,12 +28,46 @@ impl CelExpression {
     }
 
     pub fn evaluate(&self, ctx: &CelContext) -> Result<CelValue, CelError> {
-        match evaluate_expression(&self.expr, ctx)? {
-            EvalType::Value(val) => Ok(val),
-            EvalType::ContextItem(ContextItem::Value(val)) => Ok(val.clone()),
-            _ => Err(CelError::Unexpected(
+        match evaluate_expression(&self.expr, ctx) {
+            Ok(EvalType::Value(val)) => Ok(val),
+            Ok(EvalType::ContextItem(ContextItem::Value(val))) => Ok(val.clone()),
+            Ok(_) => Err(self.locate(CelError::Unexpected(
                 "evaluate didn't return a value".to_string(),
-            )),
+            ))),
+            Err(e) => Err(self.locate(e)),
+        }
+    }
+
+    /// Checks the expression against the values in `ctx` without evaluating it, reporting
+    /// every operand of the wrong type and every unknown identifier.
+    /// Maps in `ctx` are treated as records so accessing an absent key is an unknown field,
+    /// `null` values stand for values of any type.
+    pub fn type_check(&self, ctx: &CelContext) -> Result<(), Vec<CelError>> {
+        let errors: Vec<_> = checker::check(&self.expr, ctx)
+            .into_iter()
+            .map(|e| self.locate(e))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Renders the location of the innermost failing sub expression, or the whole expression
+    // if the error didn't originate from a located node.
+    fn locate(&self, e: CelError) -> CelError {
+        let (span, error) = match e {
+            CelError::Located { span, error, .. } => (span, error),
+            e => (ast::Span::new(0, self.source.len()), Box::new(e)),
+        };
+        let (line, column) = span.line_column(&self.source);
+        CelError::Located {
+            span,
+            snippet: self.source[span.start..span.end].to_string(),
+            line,
+            column,
+            error,
         }
     }
 }
@@ -47,7 +79,7 @@ enum EvalType<'a> {
 
 impl<'a> EvalType<'a> {
     fn try_bool(&self) -> Result<bool, CelError> {
-        if let EvalType::Value(val) = self {
+        if let EvalType::Value(val) | EvalType::ContextItem(ContextItem::Value(val)) = self {
             val.try_bool()
         } else {
             Err(CelError::Unexpected(
@@ -75,7 +107,7 @@ impl<'a> EvalType<'a> {
     }
 
     fn try_value(&self) -> Result<CelValue, CelError> {
-        if let EvalType::Value(val) = self {
+        if let EvalType::Value(val) | EvalType::ContextItem(ContextItem::Value(val)) = self {
             Ok(val.clone())
         } else {
             Err(CelError::Unexpected("Couldn't unwrap value".to_string()))
@@ -87,10 +119,17 @@ fn evaluate_expression<'a>(
     expr: &Expression,
     ctx: &'a CelContext,
 ) -> Result<EvalType<'a>, CelError> {
-    match evaluate_expression_inner(expr, ctx) {
-        Ok(val) => Ok(val),
-        Err(e) => Err(CelError::EvaluationError(format!("{expr:?}"), Box::new(e))),
-    }
+    evaluate_expression_inner(expr, ctx).map_err(|e| match (e, expr.span()) {
+        (e @ CelError::Located { .. }, _) | (e, None) => e,
+        // snippet and position are filled in by `CelExpression::evaluate` which has the source
+        (e, Some(span)) => CelError::Located {
+            span,
+            snippet: String::new(),
+            line: 0,
+            column: 0,
+            error: Box::new(e),
+        },
+    })
 }
 
 fn evaluate_expression_inner<'a>(
@@ -99,17 +138,72 @@ fn evaluate_expression_inner<'a>(
 ) -> Result<EvalType<'a>, CelError> {
     use Expression::*;
     match expr {
-        Ternary(cond, left, right) => {
+        Ternary(cond, left, right, _) => {
             if evaluate_expression(cond, ctx)?.try_bool()? {
                 evaluate_expression(left, ctx)
             } else {
                 evaluate_expression(right, ctx)
             }
         }
-        Member(expr, member) => {
+        Logic(op, left, right, _) => evaluate_logic(*op, left, right, ctx),
+        Relation(op, left, right, _) => {
+            let left = evaluate_expression(left, ctx)?;
+            let right = evaluate_expression(right, ctx)?;
+            Ok(EvalType::Value(evaluate_relation(
+                op,
+                left.try_value()?,
+                right.try_value()?,
+            )?))
+        }
+        Member(expr, member, _) => {
+            if let (Ident(name, _), ast::Member::FunctionCall(args)) =
+                (expr.as_ref(), member.as_ref())
+            {
+                if name.as_str() == "has" {
+                    return evaluate_has(args, ctx);
+                }
+            }
+            if let (Member(target, name, _), ast::Member::FunctionCall(args)) =
+                (expr.as_ref(), member.as_ref())
+            {
+                if let ast::Member::Attribute(name) = name.as_ref() {
+                    // Namespaced function, `ns.f(x)` for a function registered as "ns.f"
+                    if let Some(f) = namespaced_function(target, name, ctx) {
+                        let mut values = Vec::new();
+                        for e in args {
+                            values.push(evaluate_expression(e, ctx)?.try_value()?)
+                        }
+                        return Ok(EvalType::Value(f(values)?));
+                    }
+                    if let ("orValue", [default]) = (name.as_str(), args.as_slice()) {
+                        return match evaluate_optional(target, ctx)? {
+                            Some(v) => Ok(EvalType::Value(v)),
+                            None => evaluate_expression(default, ctx),
+                        };
+                    }
+                    if let Some(res) = evaluate_macro(target, name, args, ctx) {
+                        return res;
+                    }
+                    // Receiver style call, `x.f(y)` is `f(x, y)`
+                    if let Ok(ContextItem::Function(f)) = ctx.lookup(name) {
+                        let mut values = vec![evaluate_expression(target, ctx)?.try_value()?];
+                        for e in args {
+                            values.push(evaluate_expression(e, ctx)?.try_value()?)
+                        }
+                        return Ok(EvalType::Value(f(values)?));
+                    }
+                }
+            }
             let ident = evaluate_expression(expr, ctx)?;
             evaluate_member(ident, member, ctx)
         }
+        List(exprs) => {
+            let mut list = Vec::new();
+            for e in exprs {
+                list.push(evaluate_expression(e, ctx)?.try_value()?)
+            }
+            Ok(EvalType::Value(CelValue::List(Arc::from(list))))
+        }
         Map(entries) => {
             let mut map = CelMap::new();
             for (k, v) in entries {
@@ -119,9 +213,9 @@ fn evaluate_expression_inner<'a>(
             }
             Ok(EvalType::Value(CelValue::from(map)))
         }
-        Ident(name) => Ok(EvalType::ContextItem(ctx.lookup(Rc::clone(name))?)),
+        Ident(name, _) => Ok(EvalType::ContextItem(ctx.lookup(name)?)),
         Literal(val) => Ok(EvalType::Value(CelValue::from(val))),
-        Arithmetic(op, left, right) => {
+        Arithmetic(op, left, right, _) => {
             let left = evaluate_expression(left, ctx)?;
             let right = evaluate_expression(right, ctx)?;
             Ok(EvalType::Value(evaluate_arithmetic(
@@ -134,6 +228,190 @@ fn evaluate_expression_inner<'a>(
     }
 }
 
+pub(crate) fn namespaced_function(
+    target: &Expression,
+    name: &str,
+    ctx: &CelContext,
+) -> Option<CelFunction> {
+    match target {
+        Expression::Ident(ns, _) => match ctx.lookup(&format!("{ns}.{name}")) {
+            Ok(ContextItem::Function(f)) => Some(Arc::clone(f)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// `has(x.field)` tests for the presence of `field` without evaluating it.
+fn evaluate_has<'a>(args: &[Expression], ctx: &CelContext) -> Result<EvalType<'a>, CelError> {
+    let (target, field) = match args {
+        [Expression::Member(target, member, _)] => match member.as_ref() {
+            ast::Member::Attribute(field) => (target, field),
+            _ => return Err(CelError::IllegalTarget),
+        },
+        _ => {
+            return Err(CelError::Unexpected(
+                "has() expects a single field selection".to_string(),
+            ))
+        }
+    };
+    match evaluate_expression(target, ctx)?.try_value()? {
+        CelValue::Map(map) => Ok(EvalType::Value(CelValue::Bool(
+            map.contains_key(&CelKey::from(field.as_str())),
+        ))),
+        v => Err(CelError::BadType(CelType::Map, CelType::from(&v))),
+    }
+}
+
+// `x.orValue(default)` is `default` when `x` or any map / list it is selected from is absent.
+// Returns `None` instead of failing when selecting from `null`, a missing key or index.
+fn evaluate_optional(expr: &Expression, ctx: &CelContext) -> Result<Option<CelValue>, CelError> {
+    let (target, member) = match expr {
+        Expression::Member(target, member, _)
+            if !matches!(**member, ast::Member::FunctionCall(_)) =>
+        {
+            (target, member)
+        }
+        _ => {
+            return match evaluate_expression(expr, ctx)?.try_value()? {
+                CelValue::Null => Ok(None),
+                v => Ok(Some(v)),
+            }
+        }
+    };
+    let res = match (evaluate_optional(target, ctx)?, member.as_ref()) {
+        (None, _) => return Ok(None),
+        (Some(CelValue::List(list)), ast::Member::Index(idx)) => {
+            match evaluate_expression(idx, ctx)?.try_value()? {
+                CelValue::Int(i) => usize::try_from(i).ok().and_then(|i| list.get(i).cloned()),
+                CelValue::UInt(u) => usize::try_from(u).ok().and_then(|i| list.get(i).cloned()),
+                v => return Err(CelError::BadType(CelType::Int, CelType::from(&v))),
+            }
+        }
+        (Some(v), member) => Some(evaluate_member(EvalType::Value(v), member, ctx)?.try_value()?),
+    };
+    Ok(res.filter(|v| v != &CelValue::Null))
+}
+
+// The comprehension macros `all`, `exists`, `exists_one`, `map` and `filter`.
+// Returns `None` when `target.name(args)` isn't a macro invocation.
+fn evaluate_macro<'a>(
+    target: &Expression,
+    name: &str,
+    args: &[Expression],
+    ctx: &CelContext,
+) -> Option<Result<EvalType<'a>, CelError>> {
+    let (var, predicate, transform) = match (name, args) {
+        ("all" | "exists" | "exists_one" | "filter" | "map", [Expression::Ident(var, _), expr]) => {
+            (var, expr, None)
+        }
+        ("map", [Expression::Ident(var, _), predicate, transform]) => {
+            (var, predicate, Some(transform))
+        }
+        _ => return None,
+    };
+    Some(evaluate_comprehension(
+        target, name, var, predicate, transform, ctx,
+    ))
+}
+
+fn evaluate_comprehension<'a>(
+    target: &Expression,
+    name: &str,
+    var: &str,
+    predicate: &Expression,
+    transform: Option<&Expression>,
+    ctx: &CelContext,
+) -> Result<EvalType<'a>, CelError> {
+    let items: Vec<CelValue> = match evaluate_expression(target, ctx)?.try_value()? {
+        CelValue::List(list) => list.as_ref().clone(),
+        CelValue::Map(map) => map.iter().map(|(k, _)| CelValue::from(k)).collect(),
+        v => return Err(CelError::BadType(CelType::List, CelType::from(&v))),
+    };
+    let mut scope = ctx.clone();
+    let mut eval = |item: &CelValue, expr: &Expression| {
+        scope.add_variable(var, item.clone());
+        evaluate_expression(expr, &scope).and_then(|v| v.try_value())
+    };
+    let res = match (name, transform) {
+        ("all", _) | ("exists", _) => {
+            // Like && and ||, an error is absorbed if another element decides the result
+            let decisive = name == "exists";
+            let mut error = None;
+            for item in items.iter() {
+                match eval(item, predicate).and_then(|v| v.try_bool()) {
+                    Ok(b) if b == decisive => return Ok(EvalType::Value(CelValue::Bool(b))),
+                    Ok(_) => (),
+                    Err(e) => error = error.or(Some(e)),
+                }
+            }
+            match error {
+                Some(e) => return Err(e),
+                None => CelValue::Bool(!decisive),
+            }
+        }
+        ("exists_one", _) => {
+            let mut count = 0;
+            for item in items.iter() {
+                if eval(item, predicate)?.try_bool()? {
+                    count += 1;
+                }
+            }
+            CelValue::Bool(count == 1)
+        }
+        ("filter", _) => {
+            let mut res = Vec::new();
+            for item in items {
+                if eval(&item, predicate)?.try_bool()? {
+                    res.push(item);
+                }
+            }
+            CelValue::from(res)
+        }
+        (_, None) => {
+            let mut res = Vec::new();
+            for item in items.iter() {
+                res.push(eval(item, predicate)?);
+            }
+            CelValue::from(res)
+        }
+        (_, Some(transform)) => {
+            let mut res = Vec::new();
+            for item in items.iter() {
+                if eval(item, predicate)?.try_bool()? {
+                    res.push(eval(item, transform)?);
+                }
+            }
+            CelValue::from(res)
+        }
+    };
+    Ok(EvalType::Value(res))
+}
+
+// CEL's logical operators are commutative with respect to errors:
+// an error on either side is absorbed if the other side decides the result
+// (`false && err` and `err && false` are both `false`).
+fn evaluate_logic<'a>(
+    op: LogicOp,
+    left: &Expression,
+    right: &Expression,
+    ctx: &CelContext,
+) -> Result<EvalType<'a>, CelError> {
+    let decisive = matches!(op, LogicOp::Or);
+    let left = evaluate_expression(left, ctx).and_then(|l| l.try_bool());
+    if let Ok(l) = left {
+        if l == decisive {
+            return Ok(EvalType::Value(CelValue::Bool(l)));
+        }
+    }
+    let right = evaluate_expression(right, ctx).and_then(|r| r.try_bool());
+    match (left, right) {
+        (_, Ok(r)) if r == decisive => Ok(EvalType::Value(CelValue::Bool(r))),
+        (Ok(_), Ok(r)) => Ok(EvalType::Value(CelValue::Bool(r))),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    }
+}
+
 fn evaluate_member<'a>(
     target: EvalType,
     member: &ast::Member,
@@ -141,12 +419,32 @@ fn evaluate_member<'a>(
 ) -> Result<EvalType<'a>, CelError> {
     use ast::Member::*;
     match member {
-        Attribute(name) => match target {
-            EvalType::ContextItem(ContextItem::Value(CelValue::Map(map))) => {
-                Ok(EvalType::Value(map.get(name)))
-            }
+        Attribute(name) => match target.try_value() {
+            Ok(CelValue::Map(map)) => Ok(EvalType::Value(map.get(name.as_str()))),
             _ => Err(CelError::IllegalTarget),
         },
+        Index(idx) => {
+            let idx = evaluate_expression(idx, ctx)?.try_value()?;
+            match target.try_value() {
+                Ok(CelValue::List(list)) => {
+                    let i = match idx {
+                        CelValue::Int(i) => usize::try_from(i).ok(),
+                        CelValue::UInt(u) => usize::try_from(u).ok(),
+                        v => {
+                            return Err(CelError::BadType(CelType::Int, CelType::from(&v)));
+                        }
+                    };
+                    i.and_then(|i| list.get(i))
+                        .cloned()
+                        .map(EvalType::Value)
+                        .ok_or_else(|| CelError::IndexOutOfBounds(format!("{idx:?}"), list.len()))
+                }
+                Ok(CelValue::Map(map)) => {
+                    Ok(EvalType::Value(map.get(EvalType::Value(idx).try_key()?)))
+                }
+                _ => Err(CelError::IllegalTarget),
+            }
+        }
         FunctionCall(exprs) => match target {
             EvalType::ContextItem(ContextItem::Function(f)) => {
                 let mut args = Vec::new();
@@ -157,35 +455,209 @@ fn evaluate_member<'a>(
             }
             _ => Err(CelError::IllegalTarget),
         },
-        _ => unimplemented!(),
     }
 }
 
-fn evaluate_arithmetic(
+pub(crate) fn evaluate_relation(
+    op: &RelationOp,
+    left: CelValue,
+    right: CelValue,
+) -> Result<CelValue, CelError> {
+    let res = match op {
+        RelationOp::Equals => equals(&left, &right),
+        RelationOp::NotEquals => !equals(&left, &right),
+        RelationOp::LessThan => left.try_cmp(&right)?.is_lt(),
+        RelationOp::LessThanEq => left.try_cmp(&right)?.is_le(),
+        RelationOp::GreaterThan => left.try_cmp(&right)?.is_gt(),
+        RelationOp::GreaterThanEq => left.try_cmp(&right)?.is_ge(),
+        RelationOp::In => match right {
+            CelValue::List(list) => list.contains(&left),
+            CelValue::Map(map) => map.contains_key(&EvalType::Value(left).try_key()?),
+            v => return Err(CelError::BadType(CelType::List, CelType::from(&v))),
+        },
+    };
+    Ok(CelValue::Bool(res))
+}
+
+// Numbers are equal by value across types and uuids equal their string representation
+fn equals(left: &CelValue, right: &CelValue) -> bool {
+    match (left, right) {
+        (CelValue::Int(_) | CelValue::UInt(_) | CelValue::BigInt(_) | CelValue::Double(_), _) => {
+            matches!(left.try_cmp(right), Ok(Ordering::Equal))
+        }
+        (CelValue::Uuid(u), CelValue::String(s)) | (CelValue::String(s), CelValue::Uuid(u)) => {
+            s.parse::<uuid::Uuid>().map(|s| &s == u).unwrap_or(false)
+        }
+        _ => left == right,
+    }
+}
+
+pub(crate) fn evaluate_arithmetic(
     op: ArithmeticOp,
     left: CelValue,
     right: CelValue,
 ) -> Result<CelValue, CelError> {
     use CelValue::*;
+    if matches!(
+        op,
+        ArithmeticOp::Multiply | ArithmeticOp::Add | ArithmeticOp::Subtract
+    ) {
+        match (&left, &right) {
+            (BigInt(n), Double(_)) => {
+                return evaluate_arithmetic(op, Double(big_int_to_decimal(*n)?), right)
+            }
+            (Double(_), BigInt(n)) => {
+                return evaluate_arithmetic(op, left, Double(big_int_to_decimal(*n)?))
+            }
+            _ => (),
+        }
+        // BigInt arithmetic stays in i128 and errors on overflow instead of losing precision
+        if let Some((l, r)) = big_int_operands(&left, &right) {
+            let res = match op {
+                ArithmeticOp::Multiply => l.checked_mul(r),
+                ArithmeticOp::Add => l.checked_add(r),
+                _ => l.checked_sub(r),
+            };
+            return res
+                .map(BigInt)
+                .ok_or_else(|| CelError::Unexpected("Integer arithmetic overflowed".to_string()));
+        }
+        if let (Some(l), Some(r)) = (left.as_i128(), right.as_i128()) {
+            let unsigned = matches!((&left, &right), (UInt(_), UInt(_)));
+            return integer_arithmetic(op, l, r, unsigned);
+        }
+        if let (Double(_), Int(_) | UInt(_) | Double(_)) | (Int(_) | UInt(_), Double(_)) =
+            (&left, &right)
+        {
+            return decimal_arithmetic(op, Decimal::try_from(&left)?, Decimal::try_from(&right)?);
+        }
+    }
     match op {
-        ArithmeticOp::Multiply => match (left, right) {
-            (UInt(l), UInt(r)) => Ok(UInt(l * r)),
-            (UInt(l), Int(r)) => Ok(Int(l as i64 * r)),
-            (UInt(l), Double(r)) => Ok(Double(Decimal::from(l) * r)),
-            (Int(l), UInt(r)) => Ok(Int(l * r as i64)),
-            (Int(l), Int(r)) => Ok(Int(l * r)),
-            (Int(l), Double(r)) => Ok(Double(Decimal::from(l) * r)),
-            (Double(l), UInt(r)) => Ok(Double(l * Decimal::from(r))),
-            (Double(l), Int(r)) => Ok(Double(l * Decimal::from(r))),
-            (Double(l), Double(r)) => Ok(Double(l * r)),
+        ArithmeticOp::Multiply => Err(CelError::Unexpected(
+            "Invalid operands for multiplication".to_string(),
+        )),
+        ArithmeticOp::Add => match (left, right) {
+            (Timestamp(t), Duration(d)) | (Duration(d), Timestamp(t)) => {
+                t.checked_add_signed(d).map(Timestamp).ok_or_else(overflow)
+            }
+            (Date(t), Duration(d)) | (Duration(d), Date(t)) => {
+                t.checked_add_signed(d).map(Date).ok_or_else(overflow)
+            }
+            (Duration(l), Duration(r)) => l.checked_add(&r).map(Duration).ok_or_else(overflow),
+            _ => Err(CelError::Unexpected(
+                "Invalid operands for addition".to_string(),
+            )),
+        },
+        ArithmeticOp::Subtract => match (left, right) {
+            (Timestamp(l), Timestamp(r)) => Ok(Duration(l.signed_duration_since(r))),
+            (Timestamp(t), Duration(d)) => {
+                t.checked_sub_signed(d).map(Timestamp).ok_or_else(overflow)
+            }
+            (Date(l), Date(r)) => Ok(Duration(l.signed_duration_since(r))),
+            (Date(t), Duration(d)) => t.checked_sub_signed(d).map(Date).ok_or_else(overflow),
+            (Duration(l), Duration(r)) => l.checked_sub(&r).map(Duration).ok_or_else(overflow),
             _ => Err(CelError::Unexpected(
-                "Invalid operands for multiplication".to_string(),
+                "Invalid operands for subtraction".to_string(),
             )),
         },
-        _ => unimplemented!(),
+        ArithmeticOp::Divide | ArithmeticOp::Modulus => evaluate_division(op, left, right),
+    }
+}
+
+// Integer division truncates towards zero and the remainder takes the sign of the dividend.
+// Decimals divide exactly (up to Decimal's precision).
+fn evaluate_division(
+    op: ArithmeticOp,
+    left: CelValue,
+    right: CelValue,
+) -> Result<CelValue, CelError> {
+    use CelValue::*;
+    let divide = op == ArithmeticOp::Divide;
+    if let (Some(l), Some(r)) = (left.as_i128(), right.as_i128()) {
+        if r == 0 {
+            return Err(CelError::DivisionByZero);
+        }
+        let res = if divide {
+            l.checked_div(r)
+        } else {
+            l.checked_rem(r)
+        };
+        let res = match (&left, &right) {
+            (BigInt(_), _) | (_, BigInt(_)) => res.map(BigInt),
+            (UInt(_), UInt(_)) => res.and_then(|n| u64::try_from(n).ok()).map(UInt),
+            _ => res.and_then(|n| i64::try_from(n).ok()).map(Int),
+        };
+        return res
+            .ok_or_else(|| CelError::Unexpected("Integer arithmetic overflowed".to_string()));
+    }
+    let (l, r) = match (Decimal::try_from(&left), Decimal::try_from(&right)) {
+        (Ok(l), Ok(r)) => (l, r),
+        _ => {
+            return Err(CelError::Unexpected(format!(
+                "Invalid operands for {}",
+                if divide { "division" } else { "modulo" }
+            )))
+        }
+    };
+    if r.is_zero() {
+        return Err(CelError::DivisionByZero);
+    }
+    let res = if divide {
+        l.checked_div(r)
+    } else {
+        l.checked_rem(r)
+    };
+    res.map(Double)
+        .ok_or_else(|| CelError::Unexpected("Decimal arithmetic overflowed".to_string()))
+}
+
+// Integer results that don't fit their operand type are promoted to Int and then to Decimal
+// rather than wrapping
+fn integer_arithmetic(
+    op: ArithmeticOp,
+    l: i128,
+    r: i128,
+    unsigned: bool,
+) -> Result<CelValue, CelError> {
+    let res = match op {
+        ArithmeticOp::Multiply => l.checked_mul(r),
+        ArithmeticOp::Add => l.checked_add(r),
+        _ => l.checked_sub(r),
+    };
+    if let Some(n) = res {
+        if let Some(n) = u64::try_from(n).ok().filter(|_| unsigned) {
+            return Ok(CelValue::UInt(n));
+        }
+        if let Ok(n) = i64::try_from(n) {
+            return Ok(CelValue::Int(n));
+        }
+    }
+    decimal_arithmetic(op, big_int_to_decimal(l)?, big_int_to_decimal(r)?)
+}
+
+fn decimal_arithmetic(op: ArithmeticOp, l: Decimal, r: Decimal) -> Result<CelValue, CelError> {
+    let res = match op {
+        ArithmeticOp::Multiply => l.checked_mul(r),
+        ArithmeticOp::Add => l.checked_add(r),
+        _ => l.checked_sub(r),
+    };
+    res.map(CelValue::Double)
+        .ok_or_else(|| CelError::Unexpected("Decimal arithmetic overflowed".to_string()))
+}
+
+fn big_int_operands(left: &CelValue, right: &CelValue) -> Option<(i128, i128)> {
+    use CelValue::*;
+    if matches!(left, BigInt(_)) || matches!(right, BigInt(_)) {
+        Some((left.as_i128()?, right.as_i128()?))
+    } else {
+        None
     }
 }
 
+fn overflow() -> CelError {
+    CelError::Unexpected("Date/time arithmetic overflowed".to_string())
+}
+
 impl From<CelExpression> for String {
     fn from(expr: CelExpression) -> Self {
         expr.source
@@ -196,9 +668,7 @@ impl TryFrom<String> for CelExpression {
     type Error = CelError;
 
     fn try_from(source: String) -> Result<Self, Self::Error> {
-        let expr = ExpressionParser::new()
-            .parse(&source)
-            .map_err(|e| CelError::CelParseError(e.to_string()))?;
+        let expr = cel_parser::parse(&source).map_err(CelError::CelParseError)?;
         Ok(Self { source, expr })
     }
 }
@@ -222,6 +692,14 @@ mod tests {
     use super::*;
     use chrono::NaiveDate;
 
+    #[test]
+    fn can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CelExpression>();
+        assert_send_sync::<CelContext>();
+        assert_send_sync::<CelValue>();
+    }
+
     #[test]
     fn literals() {
         let expression = "true".parse::<CelExpression>().unwrap();
@@ -262,22 +740,1228 @@ mod tests {
     }
 
     #[test]
-    fn lookup() {
-        let expression = "params.hello".parse::<CelExpression>().unwrap();
+    fn ternary() {
         let mut context = CelContext::new();
         let mut params = CelMap::new();
-        params.insert("hello", 42);
+        params.insert("charge_fee", CelValue::Bool(true));
+        params.insert("fee", Decimal::from(5));
         context.add_variable("params", params);
-        assert_eq!(expression.evaluate(&context).unwrap(), CelValue::Int(42));
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("params.charge_fee ? params.fee : 0").unwrap(),
+            CelValue::Double(Decimal::from(5))
+        );
+        assert_eq!(
+            evaluate("params.charge_fee == false ? 0 : params.fee").unwrap(),
+            CelValue::Double(Decimal::from(5))
+        );
+        assert_eq!(
+            evaluate("false ? 1 : true ? 2 : 3").unwrap(),
+            CelValue::Int(2)
+        );
+        // The branch not taken is never evaluated
+        assert_eq!(evaluate("true ? 1 : missing").unwrap(), CelValue::Int(1));
+        assert_eq!(evaluate("false ? missing : 2").unwrap(), CelValue::Int(2));
+        assert!(evaluate("1 ? 1 : 2").is_err());
     }
 
     #[test]
-    fn function() {
-        let expression = "date('2022-10-10')".parse::<CelExpression>().unwrap();
+    fn large_amounts() {
         let context = CelContext::new();
+        let expression = "79228162514264337593543950335"
+            .parse::<CelExpression>()
+            .unwrap();
         assert_eq!(
             expression.evaluate(&context).unwrap(),
-            CelValue::Date(NaiveDate::parse_from_str("2022-10-10", "%Y-%m-%d").unwrap())
+            CelValue::Double(Decimal::MAX)
+        );
+
+        let expression = "9223372036854775807 * 10".parse::<CelExpression>().unwrap();
+        assert_eq!(
+            expression.evaluate(&context).unwrap(),
+            CelValue::Double(Decimal::from(i64::MAX) * Decimal::from(10))
+        );
+
+        let expression = "9223372036854775807 + 1".parse::<CelExpression>().unwrap();
+        assert_eq!(
+            expression.evaluate(&context).unwrap(),
+            CelValue::Double(Decimal::from(i64::MAX) + Decimal::ONE)
+        );
+        let expression = "-9223372036854775807 - 2".parse::<CelExpression>().unwrap();
+        assert_eq!(
+            expression.evaluate(&context).unwrap(),
+            CelValue::Double(-Decimal::from(i64::MAX) - Decimal::TWO)
+        );
+
+        // Results beyond Decimal's range are errors, not panics
+        for source in [
+            "9223372036854775807 * 9223372036854775807",
+            "79228162514264337593543950335 * 2",
+            "decimal('79228162514264337593543950335') * 10",
+            "2 * 79228162514264337593543950335",
+            "79228162514264337593543950335 + 1",
+            "-79228162514264337593543950335 - 1.5",
+        ] {
+            let expression = source.parse::<CelExpression>().unwrap();
+            assert!(expression.evaluate(&context).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn big_ints() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        // 1 million tokens with 18 decimals in raw units
+        params.insert("wei", CelValue::BigInt(1_000_000_000_000_000_000_000_000));
+        context.add_variable("params", params);
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("params.wei * 1000").unwrap(),
+            CelValue::BigInt(1_000_000_000_000_000_000_000_000_000)
+        );
+        assert_eq!(
+            evaluate("params.wei + 1").unwrap(),
+            CelValue::BigInt(1_000_000_000_000_000_000_000_001)
+        );
+        assert_eq!(
+            evaluate("params.wei - params.wei").unwrap(),
+            CelValue::BigInt(0)
+        );
+        assert_eq!(
+            evaluate("params.wei * 0.5").unwrap(),
+            CelValue::Double(Decimal::from(500_000_000_000_000_000_000_000_i128))
+        );
+        assert_eq!(evaluate("params.wei > 0").unwrap(), CelValue::Bool(true));
+        assert_eq!(
+            evaluate("params.wei < bigint('1000000000000000000000001')").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("string(params.wei)").unwrap(),
+            CelValue::from("1000000000000000000000000")
+        );
+        assert_eq!(
+            Decimal::try_from(&evaluate("params.wei").unwrap()).unwrap(),
+            Decimal::from(1_000_000_000_000_000_000_000_000_i128)
+        );
+        assert!(evaluate("params.wei * params.wei").is_err());
+        assert!(evaluate("decimal(params.wei * 1000000000000)").is_err());
+        assert!(evaluate("int(params.wei)").is_err());
+    }
+
+    #[test]
+    fn logic_absorbs_errors() {
+        let context = CelContext::new();
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+        assert_eq!(evaluate("false && missing").unwrap(), CelValue::Bool(false));
+        assert_eq!(evaluate("missing && false").unwrap(), CelValue::Bool(false));
+        assert_eq!(evaluate("true || missing").unwrap(), CelValue::Bool(true));
+        assert_eq!(evaluate("missing || true").unwrap(), CelValue::Bool(true));
+        assert!(evaluate("true && missing").is_err());
+        assert!(evaluate("missing && true").is_err());
+        assert!(evaluate("false || missing").is_err());
+        assert!(evaluate("missing || false").is_err());
+    }
+
+    #[test]
+    fn logic_guards() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", Decimal::from(10));
+        context.add_variable("params", params);
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("has(params.amount) && params.amount > 0.0").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("has(params.fee) && params.fee.value > 0").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(
+            evaluate("has(params.amount) || params.fee.value > 0").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert!(evaluate("params.fee.value > 0 && true").is_err());
+    }
+
+    #[test]
+    fn error_locations() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", "12");
+        context.add_variable("params", params);
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        let err = evaluate("params.amount * 2").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "params.amount * 2: CelError - Unexpected: Invalid operands for multiplication at 1:1"
+        );
+        match evaluate("1 < 2 &&\n  params.amount.value == 3").unwrap_err() {
+            CelError::Located {
+                span,
+                snippet,
+                line,
+                column,
+                error,
+            } => {
+                assert_eq!(snippet, "params.amount.value");
+                assert_eq!(span.start, 11);
+                assert_eq!((line, column), (2, 3));
+                assert!(matches!(*error, CelError::IllegalTarget));
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+        let err = evaluate("decimal(params.amount) > decimal('x')").unwrap_err();
+        assert!(err.to_string().starts_with("decimal('x'): "));
+        assert!(err.to_string().ends_with(" at 1:26"));
+        match "params.(amount +\n 1 2"
+            .parse::<CelExpression>()
+            .unwrap_err()
+        {
+            CelError::CelParseError(errors) => {
+                let positions: Vec<_> = errors.iter().map(|e| (e.line, e.column)).collect();
+                assert_eq!(positions, vec![(1, 8), (2, 4)]);
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn type_check() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", Decimal::ONE);
+        params.insert("currency", "");
+        params.insert("effective", CelValue::Date(NaiveDate::MIN));
+        params.insert("meta", CelValue::Null);
+        context.add_variable("params", params);
+        let check = |source: &str| {
+            source
+                .parse::<CelExpression>()
+                .unwrap()
+                .type_check(&context)
+        };
+
+        assert!(check("params.amount * 2").is_ok());
+        assert!(check("params.currency == 'BTC' ? params.amount : 0").is_ok());
+        assert!(check("params.effective + duration('24h')").is_ok());
+        assert!(check("params.meta.anything.goes").is_ok());
+        assert!(check("[1, 2].all(x, x > params.amount)").is_ok());
+        assert!(check("has(params.missing) && decimal(params.currency) > 0.0").is_ok());
+
+        let errors = check("params.amont * 2").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "params.amont: CelError - UnknownField: amont at 1:1"
+        );
+
+        let errors = check("params.amount * params.currency > 1 || unknown").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            &errors[0],
+            CelError::Located { error, .. } if matches!(**error, CelError::Unexpected(_))
+        ));
+        assert!(matches!(
+            &errors[1],
+            CelError::Located { error, column: 40, .. }
+                if matches!(**error, CelError::UnknownIdent(_))
+        ));
+
+        assert!(check("params.currency < 1").is_err());
+        assert!(check("params.amount ? 1 : 2").is_err());
+        assert!(check("decimal(params.effective)").is_err());
+        assert!(check("params.currency.startsWith(1)").is_err());
+        assert!(check("params.currency.frobnicate()").is_err());
+    }
+
+    #[test]
+    fn relations() {
+        let context = CelContext::new();
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+        assert_eq!(evaluate("1 < 2").unwrap(), CelValue::Bool(true));
+        assert_eq!(evaluate("'b' >= 'a'").unwrap(), CelValue::Bool(true));
+        assert_eq!(evaluate("1 == 2").unwrap(), CelValue::Bool(false));
+        assert_eq!(evaluate("1.5 != 1.5").unwrap(), CelValue::Bool(false));
+    }
+
+    #[test]
+    fn membership() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("allowed_currencies", vec!["btc", "usd"]);
+        let mut limits = CelMap::new();
+        limits.insert("btc", Decimal::ONE);
+        params.insert("limits", limits);
+        context.add_variable("params", params);
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("'btc' in params.allowed_currencies").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("'eur' in params.allowed_currencies").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(
+            evaluate("'btc' in params.limits").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("'usd' in params.limits").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(evaluate("2 in [1, 2, 3]").unwrap(), CelValue::Bool(true));
+        assert!(evaluate("'b' in 'abc'").is_err());
+    }
+
+    #[test]
+    fn null_comparisons() {
+        let context = CelContext::new();
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+        assert_eq!(evaluate("null == null").unwrap(), CelValue::Bool(true));
+        assert_eq!(evaluate("1 == null").unwrap(), CelValue::Bool(false));
+        assert_eq!(evaluate("'a' != null").unwrap(), CelValue::Bool(true));
+        assert!(evaluate("1 < null").is_err());
+        assert!(evaluate("null >= null").is_err());
+    }
+
+    #[test]
+    fn lists() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("tiers", vec![10, 20, 30]);
+        let mut fee = CelMap::new();
+        fee.insert("amount", 5);
+        params.insert("fee", fee);
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(evaluate("params.tiers[1]").unwrap(), CelValue::Int(20));
+        assert_eq!(evaluate("[1, 'two', 3][1]").unwrap(), CelValue::from("two"));
+        assert_eq!(evaluate("[[1, 2], [3]][0][1]").unwrap(), CelValue::Int(2));
+        assert_eq!(evaluate("params['fee'].amount").unwrap(), CelValue::Int(5));
+        assert_eq!(evaluate("[1, 2] == [1, 2]").unwrap(), CelValue::Bool(true));
+        assert!(evaluate("params.tiers[3]").is_err());
+        assert!(evaluate("params.tiers[-1]").is_err());
+    }
+
+    #[test]
+    fn lookup() {
+        let expression = "params.hello".parse::<CelExpression>().unwrap();
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("hello", 42);
+        context.add_variable("params", params);
+        assert_eq!(expression.evaluate(&context).unwrap(), CelValue::Int(42));
+    }
+
+    #[test]
+    fn function() {
+        let expression = "date('2022-10-10')".parse::<CelExpression>().unwrap();
+        let context = CelContext::new();
+        assert_eq!(
+            expression.evaluate(&context).unwrap(),
+            CelValue::Date(NaiveDate::parse_from_str("2022-10-10", "%Y-%m-%d").unwrap())
+        );
+    }
+
+    #[test]
+    fn custom_functions() {
+        let mut context = CelContext::new();
+        context.add_function("sat_to_btc", |args| {
+            let sats = match args.first() {
+                Some(CelValue::Int(i)) => *i,
+                _ => return Err(CelError::MissingArgument),
+            };
+            Ok(CelValue::Double(Decimal::new(sats, 8)))
+        });
+        context.add_function("sum", |args| {
+            let mut total = 0;
+            for arg in args {
+                match arg {
+                    CelValue::Int(i) => total += i,
+                    v => return Err(CelError::WrongArgumentType(CelType::from(&v), CelType::Int)),
+                }
+            }
+            Ok(CelValue::Int(total))
+        });
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("sat_to_btc(150000000)").unwrap(),
+            CelValue::Double(Decimal::new(15, 1))
+        );
+        assert_eq!(evaluate("sum()").unwrap(), CelValue::Int(0));
+        assert_eq!(evaluate("sum(1, 2, 3)").unwrap(), CelValue::Int(6));
+        assert_eq!(evaluate("1.sum(2)").unwrap(), CelValue::Int(3));
+        assert!(evaluate("sat_to_btc()").is_err());
+        assert!(evaluate("sum(1, 'a')").is_err());
+    }
+
+    #[test]
+    fn conversions() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", "100.50");
+        context.add_variable("params", params);
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("decimal(params.amount)").unwrap(),
+            CelValue::Double("100.50".parse().unwrap())
+        );
+        assert_eq!(
+            evaluate("double(params.amount) * 2").unwrap(),
+            CelValue::Double("201.00".parse().unwrap())
+        );
+        assert_eq!(evaluate("double(3)").unwrap(), CelValue::Double(3.into()));
+        assert_eq!(
+            evaluate("int(params.amount.size())").unwrap(),
+            CelValue::Int(6)
+        );
+        assert_eq!(evaluate("int(decimal('-2.9'))").unwrap(), CelValue::Int(-2));
+        assert_eq!(evaluate("int('42')").unwrap(), CelValue::Int(42));
+        assert_eq!(evaluate("uint(42)").unwrap(), CelValue::UInt(42));
+        assert_eq!(
+            evaluate("int(timestamp('1970-01-01T00:01:00Z'))").unwrap(),
+            CelValue::Int(60)
+        );
+        assert_eq!(evaluate("string(42)").unwrap(), CelValue::from("42"));
+        assert_eq!(evaluate("string(1.50)").unwrap(), CelValue::from("1.50"));
+        assert_eq!(evaluate("string(true)").unwrap(), CelValue::from("true"));
+        assert_eq!(
+            evaluate("string(date('2022-11-01'))").unwrap(),
+            CelValue::from("2022-11-01")
+        );
+        assert_eq!(
+            evaluate("string(duration('1m30s'))").unwrap(),
+            CelValue::from("90s")
+        );
+
+        assert!(evaluate("int(params.amount)").is_err());
+        assert!(evaluate("decimal('abc')").is_err());
+        assert!(evaluate("uint(-1)").is_err());
+        assert!(evaluate("int(9223372036854775807 * 10)").is_err());
+        assert!(evaluate("int(null)").is_err());
+    }
+
+    #[test]
+    fn fixed_now() {
+        let mut context = CelContext::new();
+        let now = chrono::DateTime::parse_from_rfc3339("2022-11-01T12:30:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        context.set_now(now);
+        let expression = "date()".parse::<CelExpression>().unwrap();
+        assert_eq!(
+            expression.evaluate(&context).unwrap(),
+            CelValue::Date(now.date_naive())
+        );
+        let expression = "timestamp()".parse::<CelExpression>().unwrap();
+        assert_eq!(
+            expression.evaluate(&context).unwrap(),
+            CelValue::Timestamp(now)
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        let context = CelContext::new();
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+        assert_eq!(
+            evaluate("timestamp('2022-11-01T14:30:00+02:00')").unwrap(),
+            evaluate("timestamp('2022-11-01T12:30:00Z')").unwrap()
+        );
+        assert_eq!(
+            evaluate("timestamp('2022-11-01T12:30:00Z') < timestamp('2022-11-01T12:30:01Z')")
+                .unwrap(),
+            CelValue::Bool(true)
+        );
+        assert!(evaluate("timestamp('2022-11-01T12:30:00Z') < date('2022-11-02')").is_err());
+        let expression = "timestamp('2022-11-01T12:30:00Z')"
+            .parse::<CelExpression>()
+            .unwrap();
+        let json: serde_json::Value = expression.try_evaluate(&context).unwrap();
+        assert_eq!(json, serde_json::json!("2022-11-01T12:30:00Z"));
+        let t: chrono::DateTime<chrono::Utc> = expression.try_evaluate(&context).unwrap();
+        assert_eq!(CelValue::from(t), expression.evaluate(&context).unwrap());
+    }
+
+    #[test]
+    fn allocate() {
+        let context = CelContext::new();
+        let parts = |expr: &str| -> Vec<Decimal> {
+            match expr
+                .parse::<CelExpression>()
+                .unwrap()
+                .evaluate(&context)
+                .unwrap()
+            {
+                CelValue::List(parts) => parts
+                    .iter()
+                    .map(|p| Decimal::try_from(p).unwrap())
+                    .collect(),
+                v => panic!("expected a list, got {v:?}"),
+            }
+        };
+        assert_eq!(
+            parts("allocate(100, [1, 1, 1])"),
+            vec![
+                Decimal::new(3334, 2),
+                Decimal::new(3333, 2),
+                Decimal::new(3333, 2)
+            ]
+        );
+        assert_eq!(
+            parts("allocate(10, [1, 2])"),
+            vec![Decimal::new(333, 2), Decimal::new(667, 2)]
+        );
+        let split = parts("allocate(-1, [1, 1, 1], 0)");
+        assert_eq!(split.iter().sum::<Decimal>(), Decimal::from(-1));
+        assert_eq!(split, vec![Decimal::from(-1), Decimal::ZERO, Decimal::ZERO]);
+        assert!("allocate(1, [0, 0])"
+            .parse::<CelExpression>()
+            .unwrap()
+            .evaluate(&context)
+            .is_err());
+        for source in [
+            "allocate(1, [1, 1], 29)",
+            "allocate(1, [1, 1], 4294967298)",
+            "allocate(79228162514264337593543950335, [2, 1])",
+            "allocate(1, [79228162514264337593543950335, 1])",
+        ] {
+            let result = source.parse::<CelExpression>().unwrap().evaluate(&context);
+            assert!(matches!(
+                result,
+                Err(CelError::Located { error, .. }) if matches!(*error, CelError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rates() {
+        let context = CelContext::new();
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+        assert_eq!(
+            evaluate("bps(250)").unwrap(),
+            CelValue::Double(Decimal::new(25, 3))
+        );
+        let fee = evaluate("applyRate(1000, 250)").unwrap();
+        assert_eq!(fee, CelValue::Double(Decimal::new(2500, 2)));
+        assert_eq!(format!("{:?}", fee), "Double(25.00)");
+        // 999 * 1 / 10000 = 0.0999, only the final result is rounded
+        assert_eq!(
+            evaluate("applyRate(999, 1)").unwrap(),
+            CelValue::Double(Decimal::new(10, 2))
+        );
+        assert_eq!(
+            evaluate("applyRate(1, 5, 4)").unwrap(),
+            CelValue::Double(Decimal::new(5, 4))
+        );
+    }
+
+    #[test]
+    fn rounding() {
+        let context = CelContext::new();
+        let evaluate = |source: &str| source.parse::<CelExpression>().unwrap().evaluate(&context);
+        let dec = |s: &str| CelValue::Double(s.parse().unwrap());
+
+        assert_eq!(evaluate("round(2.345, 2)").unwrap(), dec("2.35"));
+        assert_eq!(evaluate("round(-2.345, 2)").unwrap(), dec("-2.35"));
+        assert_eq!(evaluate("round(2.5)").unwrap(), dec("3"));
+        assert_eq!(
+            evaluate("round(2.345, 2, 'half_even')").unwrap(),
+            dec("2.34")
+        );
+        assert_eq!(
+            evaluate("round(2.355, 2, 'half_even')").unwrap(),
+            dec("2.36")
+        );
+        assert_eq!(
+            evaluate("round(2.345, 2, 'half_down')").unwrap(),
+            dec("2.34")
+        );
+        assert_eq!(evaluate("round(2.341, 2, 'up')").unwrap(), dec("2.35"));
+        assert_eq!(evaluate("round(2.349, 2, 'down')").unwrap(), dec("2.34"));
+        assert_eq!(evaluate("round(1.5, 2)").unwrap(), dec("1.50"));
+        assert_eq!(evaluate("floor(-1.5)").unwrap(), dec("-2"));
+        assert_eq!(evaluate("floor(1.239, 2)").unwrap(), dec("1.23"));
+        assert_eq!(evaluate("ceil(1.231, 2)").unwrap(), dec("1.24"));
+        assert_eq!(evaluate("ceil(7)").unwrap(), dec("7"));
+        assert!(evaluate("round(1.5, 0, 'nearest')").is_err());
+        assert!(evaluate("round('1.5')").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        let context = CelContext::new();
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+        assert_eq!(
+            evaluate("duration('72h')").unwrap(),
+            CelValue::Duration(chrono::Duration::hours(72))
+        );
+        assert_eq!(
+            evaluate("duration('-1.5h30m')").unwrap(),
+            CelValue::Duration(-chrono::Duration::hours(2))
+        );
+        assert!(evaluate("duration('3d')").is_err());
+        assert!(evaluate("duration('h')").is_err());
+        assert_eq!(
+            evaluate("timestamp('2022-11-01T12:00:00Z') + duration('72h')").unwrap(),
+            evaluate("timestamp('2022-11-04T12:00:00Z')").unwrap()
+        );
+        assert_eq!(
+            evaluate("timestamp('2022-11-01T12:00:00Z') - duration('90m')").unwrap(),
+            evaluate("timestamp('2022-11-01T10:30:00Z')").unwrap()
+        );
+        assert_eq!(
+            evaluate("date('2022-11-04') - date('2022-11-01')").unwrap(),
+            evaluate("duration('72h')").unwrap()
+        );
+        assert_eq!(
+            evaluate("date('2022-11-01') + duration('48h')").unwrap(),
+            evaluate("date('2022-11-03')").unwrap()
+        );
+        assert_eq!(
+            evaluate("duration('1h') < duration('61m')").unwrap(),
+            CelValue::Bool(true)
+        );
+        let json: serde_json::Value = "duration('1m1.5s')"
+            .parse::<CelExpression>()
+            .unwrap()
+            .try_evaluate(&context)
+            .unwrap();
+        assert_eq!(json, serde_json::json!("61.5s"));
+    }
+
+    #[test]
+    fn macros() {
+        let mut context = CelContext::new();
+        let mut items = Vec::new();
+        for amount in [10, 0, 25] {
+            let mut item = CelMap::new();
+            item.insert("amount", amount);
+            items.push(CelValue::from(item));
+        }
+        let mut params = CelMap::new();
+        params.insert("items", items);
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("params.items.filter(i, i.amount > 0).map(i, i.amount)").unwrap(),
+            CelValue::from(vec![10, 25])
+        );
+        assert_eq!(
+            evaluate("params.items.map(i, i.amount > 0, i.amount * 2)").unwrap(),
+            CelValue::from(vec![20, 50])
+        );
+        assert_eq!(
+            evaluate("params.items.all(i, i.amount >= 0)").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("params.items.exists(i, i.amount > 20)").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("params.items.exists_one(i, i.amount > 5)").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(
+            evaluate("[1, 2, 3].exists_one(x, x == 2)").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("{'a': 1, 'b': 2}.all(k, k == 'a' || k == 'b')").unwrap(),
+            CelValue::Bool(true)
+        );
+        // the error on 'a' is absorbed by the decisive element
+        assert_eq!(
+            evaluate("['a', 1].exists(x, x > 0)").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert!(evaluate("['a', 1].all(x, x > 0)").is_err());
+        assert!(evaluate("x").is_err());
+    }
+
+    #[test]
+    fn has() {
+        let mut context = CelContext::new();
+        let mut meta = CelMap::new();
+        meta.insert("fee", 5);
+        meta.insert("memo", CelValue::Null);
+        let mut params = CelMap::new();
+        params.insert("meta", meta);
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("has(params.meta.fee)").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("has(params.meta.memo)").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("has(params.meta.discount)").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(
+            evaluate("has(params.meta.discount) ? params.meta.discount : 0").unwrap(),
+            CelValue::Int(0)
+        );
+        assert!(evaluate("has(params.meta.fee.amount)").is_err());
+        assert!(evaluate("has(params)").is_err());
+    }
+
+    #[test]
+    fn string_functions() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("account_code", "liab:customer:42");
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("params.account_code.startsWith('liab:')").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("params.account_code.endsWith(':42')").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("params.account_code.contains('asset')").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(
+            evaluate("'BTC'.lowerAscii()").unwrap(),
+            CelValue::from("btc")
+        );
+        assert_eq!(evaluate("'héllo'.size()").unwrap(), CelValue::Int(5));
+        assert_eq!(evaluate("size([1, 2, 3])").unwrap(), CelValue::Int(3));
+        assert_eq!(evaluate("size({'a': 1})").unwrap(), CelValue::Int(1));
+        assert!(evaluate("size(1)").is_err());
+        assert!(evaluate("params.account_code.startsWith(1)").is_err());
+    }
+
+    #[test]
+    fn regex_matches() {
+        let context = CelContext::new();
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("matches('ACC-0042', '^ACC-[0-9]{4}$')").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("'ACC-42'.matches('^ACC-[0-9]{4}$')").unwrap(),
+            CelValue::Bool(false)
+        );
+        assert_eq!(
+            evaluate("'ACC-4242'.matches('^ACC-[0-9]{4}$')").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert!(evaluate("matches('x', '(')").is_err());
+
+        let cached = crate::builtins::cached_regexes();
+        for code in ["SET-1", "SET-22", "ACC-1"] {
+            evaluate(&format!("'{code}'.matches('^SET-[0-9]+$')")).unwrap();
+        }
+        assert_eq!(crate::builtins::cached_regexes(), cached + 1);
+    }
+
+    #[test]
+    fn json_metadata() {
+        let mut context = CelContext::new();
+        context.add_variable(
+            "metadata",
+            serde_json::json!({
+                "customer": { "tier": "gold", "tags": ["vip", "early"] },
+                "rate": 0.25,
+                "retries": -2,
+                "archived": null
+            }),
+        );
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("metadata.customer.tier").unwrap(),
+            CelValue::from("gold")
+        );
+        assert_eq!(
+            evaluate("metadata.customer.tags[1]").unwrap(),
+            CelValue::from("early")
+        );
+        assert_eq!(
+            evaluate("metadata.rate").unwrap(),
+            CelValue::Double(Decimal::new(25, 2))
+        );
+        assert_eq!(evaluate("metadata.retries").unwrap(), CelValue::Int(-2));
+        assert_eq!(evaluate("metadata.archived").unwrap(), CelValue::Null);
+        assert_eq!(
+            CelValue::from(serde_json::json!(1e-7)),
+            CelValue::Double(Decimal::new(1, 7))
+        );
+    }
+
+    #[test]
+    fn derived_context_maps() {
+        #[derive(crate::IntoCelMap)]
+        struct Fee {
+            amount: Decimal,
+            currency: String,
+        }
+
+        #[derive(crate::IntoCelMap)]
+        struct Params {
+            account_id: uuid::Uuid,
+            effective: chrono::DateTime<chrono::Utc>,
+            fee: Fee,
+            memo: Option<String>,
+            reversal: bool,
+        }
+
+        let account_id = uuid::Uuid::new_v4();
+        let mut context = CelContext::new();
+        context.add_variable(
+            "params",
+            Params {
+                account_id,
+                effective: chrono::Utc::now(),
+                fee: Fee {
+                    amount: Decimal::new(150, 2),
+                    currency: "USD".to_string(),
+                },
+                memo: None,
+                reversal: false,
+            },
+        );
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("params.account_id").unwrap(),
+            CelValue::from(account_id)
+        );
+        assert_eq!(
+            evaluate("params.fee.amount").unwrap(),
+            CelValue::Double(Decimal::new(150, 2))
+        );
+        assert_eq!(
+            evaluate("params.fee.currency").unwrap(),
+            CelValue::from("USD")
+        );
+        assert_eq!(evaluate("params.memo").unwrap(), CelValue::Null);
+        assert_eq!(evaluate("params.reversal").unwrap(), CelValue::Bool(false));
+        assert_eq!(
+            evaluate("params.effective < timestamp('2100-01-01T00:00:00Z')").unwrap(),
+            CelValue::Bool(true)
+        );
+    }
+
+    #[test]
+    fn format_strings() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("journal", "main");
+        params.insert("currency", "USD");
+        params.insert("amount", Decimal::new(12345, 3));
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("format('acct:%s:%s', params.journal, params.currency)").unwrap(),
+            CelValue::from("acct:main:USD")
+        );
+        assert_eq!(
+            evaluate("'%d%% of %.2f %s'.format(5, params.amount, params.currency)").unwrap(),
+            CelValue::from("5% of 12.35 USD")
+        );
+        assert_eq!(
+            evaluate("format('%f', 1.5)").unwrap(),
+            CelValue::from("1.5")
+        );
+        assert!(evaluate("format('%s:%s', 'a')").is_err());
+        assert!(evaluate("format('%s', 'a', 'b')").is_err());
+        assert!(evaluate("format('%d', 'a')").is_err());
+        assert!(evaluate("format('%x', 1)").is_err());
+    }
+
+    #[test]
+    fn bytes_functions() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert(
+            "preimage",
+            CelValue::Bytes(Arc::new(vec![0xde, 0xad, 0xbe, 0xef])),
+        );
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("hex.encode(params.preimage)").unwrap(),
+            CelValue::from("deadbeef")
+        );
+        assert_eq!(
+            evaluate("hex.decode('DEADBEEF') == params.preimage").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("base64.encode(params.preimage)").unwrap(),
+            CelValue::from("3q2+7w==")
+        );
+        assert_eq!(
+            evaluate("base64.decode(base64.encode('hello')) == bytes('hello')").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("string(bytes('héllo'))").unwrap(),
+            CelValue::from("héllo")
+        );
+        assert!(evaluate("hex.decode('xyz')").is_err());
+        assert!(evaluate("base64.decode('***')").is_err());
+        assert!(evaluate("bytes(1)").is_err());
+    }
+
+    #[test]
+    fn uuids() {
+        let id = uuid::Uuid::new_v4();
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("id", id);
+        params.insert("ref", id.to_string());
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert!(matches!(evaluate("uuid()").unwrap(), CelValue::Uuid(_)));
+        assert_eq!(evaluate("uuid() == uuid()").unwrap(), CelValue::Bool(false));
+        assert_eq!(evaluate("uuid(params.ref)").unwrap(), CelValue::Uuid(id));
+        assert_eq!(
+            evaluate("params.id == params.ref").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate(&format!("'{}' != params.id", uuid::Uuid::new_v4())).unwrap(),
+            CelValue::Bool(true)
+        );
+        assert_eq!(
+            evaluate("params.id == 'not-a-uuid'").unwrap(),
+            CelValue::Bool(false)
+        );
+        let err = evaluate("uuid('not-a-uuid')").unwrap_err();
+        assert!(err.to_string().contains("'not-a-uuid' is not a valid uuid"));
+    }
+
+    #[test]
+    fn or_value() {
+        let mut context = CelContext::new();
+        let mut meta = CelMap::new();
+        meta.insert("tags", vec!["vip"]);
+        let mut params = CelMap::new();
+        params.insert("meta", meta);
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(
+            evaluate("params.meta.fee.amount.orValue(0)").unwrap(),
+            CelValue::Int(0)
+        );
+        assert_eq!(
+            evaluate("params.missing.fee.orValue('none')").unwrap(),
+            CelValue::from("none")
+        );
+        assert_eq!(
+            evaluate("params.meta.tags[0].orValue('')").unwrap(),
+            CelValue::from("vip")
+        );
+        assert_eq!(
+            evaluate("params.meta.tags[3].orValue('')").unwrap(),
+            CelValue::from("")
+        );
+        assert_eq!(
+            evaluate("params.meta['tags'][0].orValue('')").unwrap(),
+            CelValue::from("vip")
+        );
+        // Only absence is absorbed
+        assert!(evaluate("params.meta.tags.first.orValue(0)").is_err());
+        assert!(evaluate("params.meta.fee.amount").is_err());
+    }
+
+    #[test]
+    fn child_contexts() {
+        let mut shared = CelContext::new();
+        shared.add_variable("journal", "main");
+        shared.add_variable("rate", Decimal::new(5, 2));
+        let shared = Arc::new(shared);
+
+        let mut first = CelContext::child(&shared);
+        first.add_variable("amount", Decimal::from(100));
+        let mut second = CelContext::child(&shared);
+        second.add_variable("amount", Decimal::from(200));
+        second.add_variable("journal", "other");
+
+        let expression = "format('%s:%.2f', journal, amount * rate)"
+            .parse::<CelExpression>()
+            .unwrap();
+        assert_eq!(
+            expression.evaluate(&first).unwrap(),
+            CelValue::from("main:5.00")
+        );
+        assert_eq!(
+            expression.evaluate(&second).unwrap(),
+            CelValue::from("other:10.00")
+        );
+        assert!("amount"
+            .parse::<CelExpression>()
+            .unwrap()
+            .evaluate(&shared)
+            .is_err());
+    }
+
+    #[test]
+    fn cross_type_comparisons() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", CelValue::UInt(5));
+        params.insert("fee", Decimal::new(1, 2));
+        params.insert("effective", NaiveDate::from_ymd_opt(2022, 11, 1).unwrap());
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        for expr in [
+            "params.amount >= 0.01",
+            "params.fee < 1",
+            "params.amount > -1",
+            "params.amount == 5.0",
+            "params.fee != 0",
+            "bigint('100000000000000000000000000000000') > 1.5",
+            "bigint('-100000000000000000000000000000000') < -1.5",
+            "params.effective < date('2022-11-02')",
+            "'abc' < 'abd'",
+        ] {
+            assert_eq!(evaluate(expr).unwrap(), CelValue::Bool(true), "{expr}");
+        }
+        assert_eq!(evaluate("1 == '1'").unwrap(), CelValue::Bool(false));
+        assert!(evaluate("1 < '1'").is_err());
+    }
+
+    #[test]
+    fn addition_and_subtraction() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", Decimal::new(10050, 2));
+        params.insert("fee", CelValue::UInt(3));
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(evaluate("1 + 2").unwrap(), CelValue::Int(3));
+        assert_eq!(evaluate("5 - 3").unwrap(), CelValue::Int(2));
+        assert_eq!(
+            evaluate("1.5 + 2.5").unwrap(),
+            CelValue::Double(Decimal::from(4))
+        );
+        assert_eq!(
+            evaluate("params.fee + params.fee").unwrap(),
+            CelValue::UInt(6)
+        );
+        assert_eq!(evaluate("params.fee - 5").unwrap(), CelValue::Int(-2));
+        assert_eq!(evaluate("uint(2) - params.fee").unwrap(), CelValue::Int(-1));
+        assert_eq!(
+            evaluate("params.amount - params.fee").unwrap(),
+            CelValue::Double(Decimal::new(9750, 2))
+        );
+        assert_eq!(
+            evaluate("1 + params.amount").unwrap(),
+            CelValue::Double(Decimal::new(10150, 2))
+        );
+    }
+
+    #[test]
+    fn division_and_modulo() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("amount", Decimal::new(10000, 2));
+        params.insert("installments", CelValue::UInt(3));
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(evaluate("7 / 2").unwrap(), CelValue::Int(3));
+        assert_eq!(evaluate("7 % 2").unwrap(), CelValue::Int(1));
+        assert_eq!(evaluate("-7 / 2").unwrap(), CelValue::Int(-3));
+        assert_eq!(evaluate("-7 % 2").unwrap(), CelValue::Int(-1));
+        assert_eq!(
+            evaluate("params.installments / params.installments").unwrap(),
+            CelValue::UInt(1)
+        );
+        assert_eq!(
+            evaluate("bigint('100000000000000000000') % 7").unwrap(),
+            CelValue::BigInt(100000000000000000000 % 7)
+        );
+        assert_eq!(
+            evaluate("floor(params.amount / params.installments, 2)").unwrap(),
+            CelValue::Double(Decimal::new(3333, 2))
+        );
+        assert_eq!(
+            evaluate("params.amount % params.installments").unwrap(),
+            CelValue::Double(Decimal::new(100, 2))
+        );
+        assert_eq!(
+            evaluate("(params.amount * 100) % 3 == 1").unwrap(),
+            CelValue::Bool(true)
+        );
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("params.amount % 0").is_err());
+        assert!(evaluate("'a' / 2").is_err());
+    }
+
+    #[test]
+    fn map_literals() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("reference", "inv-42");
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        let mut fee = CelMap::new();
+        fee.insert("amount", 5);
+        let mut expected = CelMap::new();
+        expected.insert("reference", "inv-42");
+        expected.insert("tags", vec!["fee", "card"]);
+        expected.insert("fee", fee);
+        assert_eq!(
+            evaluate(
+                "{'reference': params.reference, 'tags': ['fee', 'card'], 'fee': {'amount': 5}}"
+            )
+            .unwrap(),
+            CelValue::from(expected)
+        );
+        assert_eq!(evaluate("{'a': 1}.a").unwrap(), CelValue::Int(1));
+        assert_eq!(evaluate("{1: 'one'}[1]").unwrap(), CelValue::from("one"));
+        assert_eq!(evaluate("size({})").unwrap(), CelValue::Int(0));
+        assert!(evaluate("{[1]: 'list'}").is_err());
+    }
+
+    #[test]
+    fn math_functions() {
+        let mut context = CelContext::new();
+        let mut params = CelMap::new();
+        params.insert("fee", Decimal::new(725, 2));
+        params.insert("amounts", vec![Decimal::new(150, 2), Decimal::new(250, 2)]);
+        params.insert("counts", vec![CelValue::UInt(3), CelValue::UInt(4)]);
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        assert_eq!(evaluate("min(params.fee, 5)").unwrap(), CelValue::Int(5));
+        assert_eq!(
+            evaluate("max(params.fee, 5)").unwrap(),
+            CelValue::Double(Decimal::new(725, 2))
+        );
+        assert_eq!(evaluate("max([3, 9, 4])").unwrap(), CelValue::Int(9));
+        assert_eq!(evaluate("min(2)").unwrap(), CelValue::Int(2));
+        assert_eq!(evaluate("abs(-3)").unwrap(), CelValue::Int(3));
+        assert_eq!(
+            evaluate("abs(-1.25)").unwrap(),
+            CelValue::Double(Decimal::new(125, 2))
+        );
+        assert_eq!(
+            evaluate("sum(params.amounts)").unwrap(),
+            CelValue::Double(Decimal::new(400, 2))
+        );
+        assert_eq!(evaluate("sum(params.counts)").unwrap(), CelValue::UInt(7));
+        assert_eq!(evaluate("sum([1, 2, 3])").unwrap(), CelValue::Int(6));
+        assert_eq!(evaluate("sum([])").unwrap(), CelValue::Int(0));
+        assert_eq!(
+            evaluate("sum([1, 0.5])").unwrap(),
+            CelValue::Double(Decimal::new(15, 1))
+        );
+        assert!(evaluate("min([])").is_err());
+        assert!(evaluate("max(1, 'a')").is_err());
+        assert!(evaluate("sum(['a'])").is_err());
+        assert!(evaluate("abs('a')").is_err());
+    }
+
+    #[test]
+    fn map_functions() {
+        let mut context = CelContext::new();
+        let mut tx_meta = CelMap::new();
+        tx_meta.insert("reference", "inv-42");
+        tx_meta.insert("channel", "card");
+        let mut params = CelMap::new();
+        params.insert("meta", tx_meta);
+        context.add_variable("params", params);
+        let evaluate = |expr: &str| expr.parse::<CelExpression>().unwrap().evaluate(&context);
+
+        let mut expected = CelMap::new();
+        expected.insert("reference", "inv-42");
+        expected.insert("channel", "fee");
+        expected.insert("leg", 1);
+        assert_eq!(
+            evaluate("merge(params.meta, {'channel': 'fee', 'leg': 1})").unwrap(),
+            CelValue::from(expected)
+        );
+        assert_eq!(
+            evaluate("keys(params.meta)").unwrap(),
+            CelValue::from(vec!["channel", "reference"])
+        );
+        assert_eq!(
+            evaluate("params.meta.values()").unwrap(),
+            CelValue::from(vec!["card", "inv-42"])
+        );
+        assert_eq!(
+            evaluate("merge(params.meta, {}, {'a': 1}).size()").unwrap(),
+            CelValue::Int(3)
+        );
+        assert!(evaluate("merge(params.meta, [1])").is_err());
+        assert!(evaluate("keys('a')").is_err());
+    }
+
+    #[test]
+    fn host_type_conversions() {
+        let context = CelContext::new();
+        fn eval<'a, T: TryFrom<CelResult<'a>, Error = CelError>>(
+            expr: &'a CelExpression,
+            ctx: &CelContext,
+        ) -> Result<T, CelError> {
+            expr.try_evaluate(ctx)
+        }
+        let parse = |expr: &str| expr.parse::<CelExpression>().unwrap();
+
+        assert!(eval::<bool>(&parse("1 < 2"), &context).unwrap());
+        assert_eq!(eval::<i64>(&parse("-7"), &context).unwrap(), -7);
+        assert_eq!(eval::<i64>(&parse("bigint('42')"), &context).unwrap(), 42);
+        assert_eq!(eval::<u64>(&parse("7"), &context).unwrap(), 7);
+        assert!(eval::<u64>(&parse("-7"), &context).is_err());
+        assert!(eval::<i64>(&parse("1.5"), &context).is_err());
+        assert_eq!(
+            eval::<Vec<u8>>(&parse("hex.decode('00ff')"), &context).unwrap(),
+            vec![0, 255]
+        );
+        let t = eval::<chrono::DateTime<chrono::Utc>>(
+            &parse("timestamp('2022-11-01T12:30:00Z')"),
+            &context,
+        )
+        .unwrap();
+        assert_eq!(t.to_rfc3339(), "2022-11-01T12:30:00+00:00");
+        let map = eval::<std::collections::HashMap<String, serde_json::Value>>(
+            &parse("{'ref': 'inv-42', 'legs': [1, 2], 'hash': b'ab'}"),
+            &context,
+        )
+        .unwrap();
+        assert_eq!(map["ref"], serde_json::json!("inv-42"));
+        assert_eq!(map["legs"], serde_json::json!([1, 2]));
+        assert_eq!(map["hash"], serde_json::json!("YWI="));
+        assert!(
+            eval::<std::collections::HashMap<String, serde_json::Value>>(&parse("[1]"), &context)
+                .is_err()
         );
     }
 }
@@ -1,13 +1,20 @@
 #![cfg_attr(feature = "fail-on-warnings", deny(warnings))]
 #![cfg_attr(feature = "fail-on-warnings", deny(clippy::all))]
 
+extern crate self as cel_interpreter;
+
 mod builtins;
 mod cel_type;
+mod checker;
 mod context;
 mod error;
 mod interpreter;
 mod value;
 
+#[cfg(feature = "debug")]
+pub mod debug;
+
+pub use cel_derive::IntoCelMap;
 pub use cel_type::*;
 pub use context::*;
 pub use error::*;
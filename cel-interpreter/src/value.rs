@@ -1,9 +1,10 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use cel_parser::{ast::Literal, Expression};
-use chrono::NaiveDate;
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
 use rust_decimal::Decimal;
 use uuid::Uuid;
 
-use std::{collections::HashMap, rc::Rc};
+use std::{cmp::Ordering, collections::HashMap, sync::Arc};
 
 use crate::{cel_type::*, error::*};
 
@@ -13,17 +14,24 @@ pub struct CelResult<'a> {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "binary", derive(serde::Serialize, serde::Deserialize))]
 pub enum CelValue {
-    Map(Rc<CelMap>),
+    Map(Arc<CelMap>),
+    List(Arc<Vec<CelValue>>),
     Int(i64),
     UInt(u64),
+    /// Integer amounts that don't fit an `i64`, e.g. raw token units.
+    BigInt(i128),
     Double(Decimal),
-    String(Rc<String>),
-    Bytes(Rc<Vec<u8>>),
+    String(Arc<String>),
+    Bytes(Arc<Vec<u8>>),
     Bool(bool),
     Null,
 
     Date(NaiveDate),
+    Timestamp(DateTime<Utc>),
+    #[cfg_attr(feature = "binary", serde(with = "duration_serde"))]
+    Duration(Duration),
     Uuid(Uuid),
 }
 
@@ -35,9 +43,54 @@ impl CelValue {
             Err(CelError::BadType(CelType::Bool, CelType::from(self)))
         }
     }
+
+    // Ordering is defined between values of the same type and across numeric types (by value).
+    // In particular `Null` is never ordered, it can only be checked for (in)equality.
+    pub(crate) fn try_cmp(&self, other: &CelValue) -> Result<Ordering, CelError> {
+        use CelValue::*;
+        if let (Some(l), Some(r)) = (self.as_i128(), other.as_i128()) {
+            return Ok(l.cmp(&r));
+        }
+        match (self, other) {
+            (Double(l), Double(r)) => Ok(l.cmp(r)),
+            (Double(l), r) if r.as_i128().is_some() => Ok(cmp_decimal(*l, r.as_i128().unwrap())),
+            (l, Double(r)) if l.as_i128().is_some() => {
+                Ok(cmp_decimal(*r, l.as_i128().unwrap()).reverse())
+            }
+            (String(l), String(r)) => Ok(l.cmp(r)),
+            (Bytes(l), Bytes(r)) => Ok(l.cmp(r)),
+            (Bool(l), Bool(r)) => Ok(l.cmp(r)),
+            (Date(l), Date(r)) => Ok(l.cmp(r)),
+            (Timestamp(l), Timestamp(r)) => Ok(l.cmp(r)),
+            (Duration(l), Duration(r)) => Ok(l.cmp(r)),
+            (l, r) => Err(CelError::Incomparable(CelType::from(l), CelType::from(r))),
+        }
+    }
+
+    pub(crate) fn as_i128(&self) -> Option<i128> {
+        match self {
+            CelValue::Int(i) => Some(i128::from(*i)),
+            CelValue::UInt(u) => Some(i128::from(*u)),
+            CelValue::BigInt(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Compact encoding for caching or sending values between services.
+    /// Shared `Arc`s are written out in full and come back as separate allocations.
+    #[cfg(feature = "binary")]
+    pub fn to_binary(&self) -> Result<Vec<u8>, CelError> {
+        bincode::serialize(self).map_err(|e| CelError::BinaryError(e.to_string()))
+    }
+
+    #[cfg(feature = "binary")]
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, CelError> {
+        bincode::deserialize(bytes).map_err(|e| CelError::BinaryError(e.to_string()))
+    }
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "binary", derive(serde::Serialize, serde::Deserialize))]
 pub struct CelMap {
     inner: HashMap<CelKey, CelValue>,
 }
@@ -53,10 +106,26 @@ impl CelMap {
         self.inner.insert(k.into(), val.into());
     }
 
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &CelKey) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&CelKey, &CelValue)> {
+        self.inner.iter()
+    }
+
     pub fn get(&self, key: impl Into<CelKey>) -> CelValue {
         self.inner
             .get(&key.into())
-            .map(Clone::clone)
+            .cloned()
             .unwrap_or(CelValue::Null)
     }
 }
@@ -71,7 +140,7 @@ impl From<HashMap<String, CelValue>> for CelMap {
     fn from(map: HashMap<String, CelValue>) -> Self {
         let mut res = CelMap::new();
         for (k, v) in map {
-            res.insert(CelKey::String(Rc::from(k)), v);
+            res.insert(CelKey::String(Arc::from(k)), v);
         }
         res
     }
@@ -79,7 +148,27 @@ impl From<HashMap<String, CelValue>> for CelMap {
 
 impl From<CelMap> for CelValue {
     fn from(m: CelMap) -> Self {
-        CelValue::Map(Rc::from(m))
+        CelValue::Map(Arc::from(m))
+    }
+}
+
+impl<T: Into<CelValue>> From<Vec<T>> for CelValue {
+    fn from(v: Vec<T>) -> Self {
+        CelValue::List(Arc::from(
+            v.into_iter().map(Into::into).collect::<Vec<CelValue>>(),
+        ))
+    }
+}
+
+impl<T: Into<CelValue>> From<Option<T>> for CelValue {
+    fn from(v: Option<T>) -> Self {
+        v.map(Into::into).unwrap_or(CelValue::Null)
+    }
+}
+
+impl From<bool> for CelValue {
+    fn from(b: bool) -> Self {
+        CelValue::Bool(b)
     }
 }
 
@@ -97,7 +186,7 @@ impl From<Decimal> for CelValue {
 
 impl From<String> for CelValue {
     fn from(s: String) -> Self {
-        CelValue::String(Rc::from(s))
+        CelValue::String(Arc::from(s))
     }
 }
 
@@ -113,9 +202,21 @@ impl From<Uuid> for CelValue {
     }
 }
 
+impl From<DateTime<Utc>> for CelValue {
+    fn from(t: DateTime<Utc>) -> Self {
+        CelValue::Timestamp(t)
+    }
+}
+
+impl From<Duration> for CelValue {
+    fn from(d: Duration) -> Self {
+        CelValue::Duration(d)
+    }
+}
+
 impl From<&str> for CelValue {
     fn from(s: &str) -> Self {
-        CelValue::String(Rc::from(s.to_string()))
+        CelValue::String(Arc::from(s.to_string()))
     }
 }
 
@@ -131,54 +232,73 @@ impl From<serde_json::Value> for CelValue {
                 } else if let Some(i) = n.as_i64() {
                     CelValue::Int(i)
                 } else {
-                    unimplemented!()
+                    let n = n.to_string();
+                    n.parse()
+                        .or_else(|_| Decimal::from_scientific(&n))
+                        .map(CelValue::Double)
+                        // Out of Decimal range - keep the original representation
+                        .unwrap_or_else(|_| CelValue::String(Arc::from(n)))
                 }
             }
-            String(s) => CelValue::String(Rc::from(s)),
+            String(s) => CelValue::String(Arc::from(s)),
+            Array(a) => CelValue::from(a.into_iter().map(CelValue::from).collect::<Vec<_>>()),
             Object(o) => {
                 let mut map = CelMap::new();
                 for (k, v) in o.into_iter() {
-                    map.insert(CelKey::String(Rc::from(k)), CelValue::from(v));
+                    map.insert(CelKey::String(Arc::from(k)), CelValue::from(v));
                 }
-                CelValue::Map(Rc::from(map))
+                CelValue::Map(Arc::from(map))
             }
-            _ => unimplemented!(),
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "binary", derive(serde::Serialize, serde::Deserialize))]
 pub enum CelKey {
     Int(i64),
     UInt(u64),
     Bool(bool),
-    String(Rc<String>),
+    String(Arc<String>),
 }
 
 impl From<&str> for CelKey {
     fn from(s: &str) -> Self {
-        CelKey::String(Rc::from(s.to_string()))
+        CelKey::String(Arc::from(s.to_string()))
     }
 }
 
 impl From<String> for CelKey {
     fn from(s: String) -> Self {
-        CelKey::String(Rc::from(s))
+        CelKey::String(Arc::from(s))
     }
 }
 
-impl From<&Rc<String>> for CelKey {
-    fn from(s: &Rc<String>) -> Self {
+impl From<&Arc<String>> for CelKey {
+    fn from(s: &Arc<String>) -> Self {
         CelKey::String(s.clone())
     }
 }
 
+impl From<&CelKey> for CelValue {
+    fn from(k: &CelKey) -> Self {
+        match k {
+            CelKey::Int(i) => CelValue::Int(*i),
+            CelKey::UInt(u) => CelValue::UInt(*u),
+            CelKey::Bool(b) => CelValue::Bool(*b),
+            CelKey::String(s) => CelValue::String(s.clone()),
+        }
+    }
+}
+
 impl From<&CelValue> for CelType {
     fn from(v: &CelValue) -> Self {
         match v {
             CelValue::Map(_) => CelType::Map,
+            CelValue::List(_) => CelType::List,
             CelValue::Int(_) => CelType::Int,
             CelValue::UInt(_) => CelType::UInt,
+            CelValue::BigInt(_) => CelType::BigInt,
             CelValue::Double(_) => CelType::Double,
             CelValue::String(_) => CelType::String,
             CelValue::Bytes(_) => CelType::Bytes,
@@ -186,6 +306,8 @@ impl From<&CelValue> for CelType {
             CelValue::Null => CelType::Null,
 
             CelValue::Date(_) => CelType::Date,
+            CelValue::Timestamp(_) => CelType::Timestamp,
+            CelValue::Duration(_) => CelType::Duration,
             CelValue::Uuid(_) => CelType::Uuid,
         }
     }
@@ -206,7 +328,7 @@ impl From<&Literal> for CelValue {
     }
 }
 
-impl TryFrom<&CelValue> for Rc<String> {
+impl TryFrom<&CelValue> for Arc<String> {
     type Error = CelError;
 
     fn try_from(v: &CelValue) -> Result<Self, Self::Error> {
@@ -218,6 +340,32 @@ impl TryFrom<&CelValue> for Rc<String> {
     }
 }
 
+impl TryFrom<&CelValue> for Decimal {
+    type Error = CelError;
+
+    fn try_from(v: &CelValue) -> Result<Self, Self::Error> {
+        match v {
+            CelValue::Double(n) => Ok(*n),
+            CelValue::Int(n) => Ok(Decimal::from(*n)),
+            CelValue::UInt(n) => Ok(Decimal::from(*n)),
+            CelValue::BigInt(n) => big_int_to_decimal(*n),
+            _ => Err(CelError::BadType(CelType::Double, CelType::from(v))),
+        }
+    }
+}
+
+impl TryFrom<&CelValue> for Arc<Vec<CelValue>> {
+    type Error = CelError;
+
+    fn try_from(v: &CelValue) -> Result<Self, Self::Error> {
+        if let CelValue::List(l) = v {
+            Ok(l.clone())
+        } else {
+            Err(CelError::BadType(CelType::List, CelType::from(v)))
+        }
+    }
+}
+
 impl<'a> TryFrom<CelResult<'a>> for NaiveDate {
     type Error = CelError;
 
@@ -233,6 +381,36 @@ impl<'a> TryFrom<CelResult<'a>> for NaiveDate {
     }
 }
 
+impl<'a> TryFrom<CelResult<'a>> for DateTime<Utc> {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        if let CelValue::Timestamp(t) = val {
+            Ok(t)
+        } else {
+            Err(CelError::EvaluationError(
+                format!("{expr:?}"),
+                Box::new(CelError::BadType(CelType::Timestamp, CelType::from(&val))),
+            ))
+        }
+    }
+}
+
+impl<'a> TryFrom<CelResult<'a>> for Duration {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        if let CelValue::Duration(d) = val {
+            Ok(d)
+        } else {
+            Err(CelError::EvaluationError(
+                format!("{expr:?}"),
+                Box::new(CelError::BadType(CelType::Duration, CelType::from(&val))),
+            ))
+        }
+    }
+}
+
 impl<'a> TryFrom<CelResult<'a>> for Uuid {
     type Error = CelError;
 
@@ -271,6 +449,8 @@ impl<'a> TryFrom<CelResult<'a>> for Decimal {
             CelValue::Double(n) => Ok(n),
             CelValue::Int(n) => Ok(Decimal::from(n)),
             CelValue::UInt(n) => Ok(Decimal::from(n)),
+            CelValue::BigInt(n) => big_int_to_decimal(n)
+                .map_err(|e| CelError::EvaluationError(format!("{expr:?}"), Box::new(e))),
             _ => Err(CelError::EvaluationError(
                 format!("{expr:?}"),
                 Box::new(CelError::BadType(CelType::Double, CelType::from(&val))),
@@ -279,6 +459,80 @@ impl<'a> TryFrom<CelResult<'a>> for Decimal {
     }
 }
 
+impl<'a> TryFrom<CelResult<'a>> for bool {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        if let CelValue::Bool(b) = val {
+            Ok(b)
+        } else {
+            Err(CelError::EvaluationError(
+                format!("{expr:?}"),
+                Box::new(CelError::BadType(CelType::Bool, CelType::from(&val))),
+            ))
+        }
+    }
+}
+
+impl<'a> TryFrom<CelResult<'a>> for i64 {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        val.as_i128()
+            .and_then(|n| i64::try_from(n).ok())
+            .ok_or_else(|| {
+                CelError::EvaluationError(
+                    format!("{expr:?}"),
+                    Box::new(CelError::BadType(CelType::Int, CelType::from(&val))),
+                )
+            })
+    }
+}
+
+impl<'a> TryFrom<CelResult<'a>> for u64 {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        val.as_i128()
+            .and_then(|n| u64::try_from(n).ok())
+            .ok_or_else(|| {
+                CelError::EvaluationError(
+                    format!("{expr:?}"),
+                    Box::new(CelError::BadType(CelType::UInt, CelType::from(&val))),
+                )
+            })
+    }
+}
+
+impl<'a> TryFrom<CelResult<'a>> for Vec<u8> {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        if let CelValue::Bytes(b) = val {
+            Ok(b.to_vec())
+        } else {
+            Err(CelError::EvaluationError(
+                format!("{expr:?}"),
+                Box::new(CelError::BadType(CelType::Bytes, CelType::from(&val))),
+            ))
+        }
+    }
+}
+
+impl<'a> TryFrom<CelResult<'a>> for HashMap<String, serde_json::Value> {
+    type Error = CelError;
+
+    fn try_from(CelResult { expr, val }: CelResult) -> Result<Self, Self::Error> {
+        match serde_json::Value::try_from(CelResult { expr, val })? {
+            serde_json::Value::Object(map) => Ok(map.into_iter().collect()),
+            v => Err(CelError::EvaluationError(
+                format!("{expr:?}"),
+                Box::new(CelError::Unexpected(format!("expected a map, found {v}"))),
+            )),
+        }
+    }
+}
+
 impl From<&CelKey> for CelType {
     fn from(v: &CelKey) -> Self {
         match v {
@@ -302,6 +556,33 @@ impl TryFrom<&CelKey> for String {
     }
 }
 
+// Integers too large for a Decimal are beyond any Decimal value
+fn cmp_decimal(l: Decimal, r: i128) -> Ordering {
+    match big_int_to_decimal(r) {
+        Ok(r) => l.cmp(&r),
+        Err(_) => 0.cmp(&r),
+    }
+}
+
+pub(crate) fn big_int_to_decimal(n: i128) -> Result<Decimal, CelError> {
+    Decimal::try_from_i128_with_scale(n, 0)
+        .map_err(|_| CelError::InvalidArgument(format!("{n} does not fit a decimal")))
+}
+
+// CEL's string form of a duration: seconds with an `s` suffix, e.g. "259200s"
+pub(crate) fn format_duration(d: &Duration) -> String {
+    format!(
+        "{}s",
+        Decimal::from_i128_with_scale(
+            d.num_nanoseconds()
+                .map(i128::from)
+                .unwrap_or_else(|| i128::from(d.num_seconds()) * 1_000_000_000),
+            9
+        )
+        .normalize()
+    )
+}
+
 impl<'a> TryFrom<CelResult<'a>> for serde_json::Value {
     type Error = CelError;
 
@@ -310,12 +591,16 @@ impl<'a> TryFrom<CelResult<'a>> for serde_json::Value {
         Ok(match val {
             CelValue::Int(n) => Value::from(n),
             CelValue::UInt(n) => Value::from(n),
+            CelValue::BigInt(n) => Value::from(n.to_string()),
             CelValue::Double(n) => Value::from(n.to_string()),
             CelValue::Bool(b) => Value::from(b),
             CelValue::String(n) => Value::from(n.as_str()),
             CelValue::Null => Value::Null,
             CelValue::Date(d) => Value::from(d.to_string()),
+            CelValue::Timestamp(t) => Value::from(t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            CelValue::Duration(d) => Value::from(format_duration(&d)),
             CelValue::Uuid(u) => Value::from(u.to_string()),
+            CelValue::Bytes(b) => Value::from(BASE64.encode(b.as_slice())),
             CelValue::Map(m) => {
                 let mut res = serde_json::Map::new();
                 for (k, v) in m.inner.iter() {
@@ -328,7 +613,66 @@ impl<'a> TryFrom<CelResult<'a>> for serde_json::Value {
                 }
                 Value::from(res)
             }
-            _ => unimplemented!(),
+            CelValue::List(l) => Value::from(
+                l.iter()
+                    .map(|v| {
+                        Self::try_from(CelResult {
+                            expr,
+                            val: v.clone(),
+                        })
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()?,
+            ),
         })
     }
 }
+
+// chrono's Duration has no serde support, it is encoded as (seconds, subsecond nanos)
+#[cfg(feature = "binary")]
+mod duration_serde {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        let secs = d.num_seconds();
+        let nanos = (*d - Duration::seconds(secs))
+            .num_nanoseconds()
+            .expect("subsecond nanos fit in i64");
+        (secs, nanos).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let (secs, nanos) = <(i64, i64)>::deserialize(d)?;
+        Ok(Duration::seconds(secs) + Duration::nanoseconds(nanos))
+    }
+}
+
+#[cfg(all(test, feature = "binary"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_round_trip() {
+        let mut inner = CelMap::new();
+        inner.insert("amount", Decimal::new(1234, 2));
+        inner.insert(CelKey::Int(7), CelValue::Bytes(Arc::new(vec![1, 2, 3])));
+        inner.insert(CelKey::Bool(true), CelValue::Null);
+        let mut outer = CelMap::new();
+        outer.insert("fee", inner);
+        outer.insert("id", Uuid::new_v4());
+        outer.insert(
+            CelKey::UInt(1),
+            CelValue::Date(NaiveDate::from_ymd_opt(2022, 11, 1).unwrap()),
+        );
+        outer.insert("at", Utc::now());
+        outer.insert("window", Duration::milliseconds(-1500));
+        outer.insert("count", CelValue::UInt(3));
+        outer.insert("ok", CelValue::Bool(false));
+        outer.insert("n", -5);
+        outer.insert("name", CelValue::String(Arc::new("hello".to_string())));
+        let value = CelValue::from(outer);
+
+        let bytes = value.to_binary().unwrap();
+        assert_eq!(CelValue::from_binary(&bytes).unwrap(), value);
+    }
+}
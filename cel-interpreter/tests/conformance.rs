@@ -0,0 +1,418 @@
+//! Runs cel-spec conformance cases (`tests/simple/testdata/*.textproto`) and reports how many
+//! pass per section. The bundled smoke cases must all pass, the official suite is only reported:
+//!
+//! CEL_SPEC_DIR=../cel-spec cargo test -p cel-interpreter --features conformance \
+//!     --test conformance -- --nocapture
+#![cfg(feature = "conformance")]
+
+use cel_interpreter::*;
+use rust_decimal::Decimal;
+
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+#[test]
+fn smoke() {
+    let report =
+        run_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/smoke.textproto"));
+    report.print();
+    assert!(report.failures().is_empty(), "{:#?}", report.failures());
+}
+
+#[test]
+fn cel_spec() {
+    let dir = match std::env::var("CEL_SPEC_DIR") {
+        Ok(dir) => PathBuf::from(dir).join("tests/simple/testdata"),
+        Err(_) => {
+            println!("CEL_SPEC_DIR not set, skipping the cel-spec conformance suite");
+            return;
+        }
+    };
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "textproto"))
+        .collect();
+    files.sort();
+    let (mut passed, mut total) = (0, 0);
+    for file in files {
+        let report = run_file(&file);
+        report.print();
+        passed += report.passed();
+        total += report.cases.len();
+    }
+    println!("cel-spec conformance: {passed}/{total} passed");
+}
+
+struct Report {
+    name: String,
+    // (section, test, outcome)
+    cases: Vec<(String, String, Outcome)>,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Passed,
+    Failed(String),
+    Unsupported(String),
+}
+
+impl Report {
+    fn passed(&self) -> usize {
+        self.cases
+            .iter()
+            .filter(|(.., outcome)| matches!(outcome, Outcome::Passed))
+            .count()
+    }
+
+    fn failures(&self) -> Vec<String> {
+        self.cases
+            .iter()
+            .filter(|(.., outcome)| !matches!(outcome, Outcome::Passed))
+            .map(|(section, test, outcome)| format!("{section}/{test}: {outcome:?}"))
+            .collect()
+    }
+
+    fn print(&self) {
+        let mut sections: Vec<&str> = self.cases.iter().map(|(s, ..)| s.as_str()).collect();
+        sections.dedup();
+        for section in sections {
+            let cases: Vec<_> = self.cases.iter().filter(|(s, ..)| s == section).collect();
+            let passed = cases
+                .iter()
+                .filter(|(.., o)| matches!(o, Outcome::Passed))
+                .count();
+            println!("{}/{section}: {passed}/{}", self.name, cases.len());
+            for (_, test, outcome) in cases {
+                match outcome {
+                    Outcome::Passed => (),
+                    Outcome::Failed(reason) => println!("  FAILED {test}: {reason}"),
+                    Outcome::Unsupported(reason) => println!("  unsupported {test}: {reason}"),
+                }
+            }
+        }
+    }
+}
+
+fn run_file(path: &Path) -> Report {
+    let source = std::fs::read(path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    let file = Parser::new(&source)
+        .message()
+        .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    let mut report = Report {
+        name: file.string("name").unwrap_or_default(),
+        cases: Vec::new(),
+    };
+    for section in file.messages("section") {
+        let section_name = section.string("name").unwrap_or_default();
+        for test in section.messages("test") {
+            let outcome = catch_unwind(AssertUnwindSafe(|| run_test(test)))
+                .unwrap_or_else(|_| Outcome::Failed("panicked".to_string()));
+            report.cases.push((
+                section_name.clone(),
+                test.string("name").unwrap_or_default(),
+                outcome,
+            ));
+        }
+    }
+    report
+}
+
+fn run_test(test: &Message) -> Outcome {
+    if test.get("container").is_some() || test.get("type_env").is_some() {
+        return Outcome::Unsupported("containers and type environments".to_string());
+    }
+    let mut ctx = CelContext::new();
+    for binding in test.messages("bindings") {
+        let value = binding
+            .message("value")
+            .and_then(|v| v.message("value"))
+            .ok_or("binding without a value")
+            .and_then(to_cel_value);
+        match (binding.string("key"), value) {
+            (Some(key), Ok(value)) => ctx.add_variable(key, value),
+            (_, Err(e)) => return Outcome::Unsupported(e.to_string()),
+            (None, _) => return Outcome::Unsupported("binding without a key".to_string()),
+        }
+    }
+    let expr = match test.string("expr") {
+        Some(expr) => expr,
+        None => return Outcome::Unsupported("no expression".to_string()),
+    };
+    let res = expr
+        .parse::<CelExpression>()
+        .and_then(|expr| expr.evaluate(&ctx));
+    let expected = match (test.message("value"), test.get("eval_error")) {
+        (Some(value), _) => match to_cel_value(value) {
+            Ok(value) => value,
+            Err(e) => return Outcome::Unsupported(e.to_string()),
+        },
+        (None, Some(_)) => {
+            return match res {
+                Err(_) => Outcome::Passed,
+                Ok(v) => Outcome::Failed(format!("expected an error, got {v:?}")),
+            }
+        }
+        // No expected value means the expression just has to evaluate
+        (None, None) if test.get("typed_result").is_none() && test.get("unknown").is_none() => {
+            CelValue::Bool(true)
+        }
+        _ => return Outcome::Unsupported("typed or unknown results".to_string()),
+    };
+    match res {
+        Ok(v) if v == expected => Outcome::Passed,
+        Ok(v) => Outcome::Failed(format!("expected {expected:?}, got {v:?}")),
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+fn to_cel_value(value: &Message) -> Result<CelValue, &'static str> {
+    let (kind, value) = value.fields.first().ok_or("empty value")?;
+    let scalar = || match value {
+        Value::Scalar(s) => Ok(String::from_utf8_lossy(s).into_owned()),
+        Value::Message(_) => Err("expected a scalar"),
+    };
+    Ok(match kind.as_str() {
+        "null_value" => CelValue::Null,
+        "bool_value" => CelValue::Bool(scalar()? == "true"),
+        "int64_value" => CelValue::Int(scalar()?.parse().map_err(|_| "bad int64")?),
+        "uint64_value" => CelValue::UInt(scalar()?.parse().map_err(|_| "bad uint64")?),
+        "double_value" => {
+            let s = scalar()?;
+            CelValue::Double(
+                s.parse::<Decimal>()
+                    .or_else(|_| Decimal::from_scientific(&s))
+                    .map_err(|_| "double not representable as a decimal")?,
+            )
+        }
+        "string_value" => CelValue::from(scalar()?),
+        "bytes_value" => match value {
+            Value::Scalar(b) => CelValue::Bytes(std::sync::Arc::new(b.clone())),
+            Value::Message(_) => return Err("expected a scalar"),
+        },
+        "list_value" => match value {
+            Value::Message(list) => CelValue::from(
+                list.messages("values")
+                    .map(to_cel_value)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            Value::Scalar(_) => return Err("expected a list"),
+        },
+        "map_value" => match value {
+            Value::Message(map) => {
+                let mut res = CelMap::new();
+                for entry in map.messages("entries") {
+                    let key = match to_cel_value(entry.message("key").ok_or("entry without key")?)?
+                    {
+                        CelValue::String(s) => CelKey::String(s),
+                        CelValue::Int(i) => CelKey::Int(i),
+                        CelValue::UInt(u) => CelKey::UInt(u),
+                        CelValue::Bool(b) => CelKey::Bool(b),
+                        _ => return Err("unsupported map key"),
+                    };
+                    res.insert(
+                        key,
+                        to_cel_value(entry.message("value").ok_or("entry without value")?)?,
+                    );
+                }
+                CelValue::from(res)
+            }
+            Value::Scalar(_) => return Err("expected a map"),
+        },
+        _ => return Err("unsupported value type"),
+    })
+}
+
+// A minimal protobuf text format reader, enough for the conformance test files.
+
+#[derive(Debug, Default)]
+struct Message {
+    fields: Vec<(String, Value)>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Scalar(Vec<u8>),
+    Message(Message),
+}
+
+impl Message {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        match self.get(name)? {
+            Value::Scalar(s) => Some(String::from_utf8_lossy(s).into_owned()),
+            Value::Message(_) => None,
+        }
+    }
+
+    fn message<'a>(&'a self, name: &'a str) -> Option<&'a Message> {
+        self.messages(name).next()
+    }
+
+    fn messages<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Message> + 'a {
+        self.fields.iter().filter_map(move |(n, v)| match v {
+            Value::Message(m) if n == name => Some(m),
+            _ => None,
+        })
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn message(&mut self) -> Result<Message, String> {
+        let mut message = Message::default();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some(b'}' | b'>') => return Ok(message),
+                Some(b',' | b';') => {
+                    self.pos += 1;
+                    continue;
+                }
+                _ => (),
+            }
+            let name = self.word()?;
+            self.skip_whitespace();
+            if self.peek() == Some(b':') {
+                self.pos += 1;
+                self.skip_whitespace();
+            }
+            if self.peek() == Some(b'[') {
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b']') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(b',') => self.pos += 1,
+                        _ => message.fields.push((name.clone(), self.value()?)),
+                    }
+                }
+            } else {
+                message.fields.push((name, self.value()?));
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(open @ (b'{' | b'<')) => {
+                self.pos += 1;
+                let message = self.message()?;
+                let close = if open == b'{' { b'}' } else { b'>' };
+                if self.peek() != Some(close) {
+                    return Err(format!("expected '{}' at {}", close as char, self.pos));
+                }
+                self.pos += 1;
+                Ok(Value::Message(message))
+            }
+            Some(b'"' | b'\'') => {
+                // Adjacent string literals are concatenated
+                let mut res = Vec::new();
+                while let Some(quote @ (b'"' | b'\'')) = self.peek() {
+                    res.extend(self.string(quote)?);
+                    self.skip_whitespace();
+                }
+                Ok(Value::Scalar(res))
+            }
+            _ => Ok(Value::Scalar(self.word()?.into_bytes())),
+        }
+    }
+
+    fn string(&mut self, quote: u8) -> Result<Vec<u8>, String> {
+        self.pos += 1;
+        let mut res = Vec::new();
+        loop {
+            let c = self.next().ok_or("unterminated string")?;
+            match c {
+                c if c == quote => return Ok(res),
+                b'\\' => {
+                    let escaped = self.next().ok_or("unterminated escape")?;
+                    match escaped {
+                        b'n' => res.push(b'\n'),
+                        b'r' => res.push(b'\r'),
+                        b't' => res.push(b'\t'),
+                        b'a' => res.push(0x07),
+                        b'b' => res.push(0x08),
+                        b'f' => res.push(0x0c),
+                        b'v' => res.push(0x0b),
+                        b'x' => {
+                            let digits = self.take_while(2, |c| c.is_ascii_hexdigit());
+                            res.push(u8::from_str_radix(&digits, 16).map_err(|e| e.to_string())?);
+                        }
+                        b'0'..=b'7' => {
+                            self.pos -= 1;
+                            let digits = self.take_while(3, |c| (b'0'..=b'7').contains(&c));
+                            res.push(u8::from_str_radix(&digits, 8).map_err(|e| e.to_string())?);
+                        }
+                        b'u' | b'U' => {
+                            let len = if escaped == b'u' { 4 } else { 8 };
+                            let digits = self.take_while(len, |c| c.is_ascii_hexdigit());
+                            let c = u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or("bad unicode escape")?;
+                            res.extend(c.to_string().as_bytes());
+                        }
+                        c => res.push(c),
+                    }
+                }
+                c => res.push(c),
+            }
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        let word = self.take_while(usize::MAX, |c| {
+            c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b'-' | b'+')
+        });
+        if word.is_empty() {
+            Err(format!("unexpected input at {}", self.pos))
+        } else {
+            Ok(word)
+        }
+    }
+
+    fn take_while(&mut self, max: usize, f: impl Fn(u8) -> bool) -> String {
+        let start = self.pos;
+        while self.pos - start < max && self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.input[start..self.pos]).into_owned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                b'#' => while !matches!(self.next(), None | Some(b'\n')) {},
+                c if c.is_ascii_whitespace() => self.pos += 1,
+                _ => return,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+}
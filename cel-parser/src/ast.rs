@@ -1,6 +1,6 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum LogicOp {
     And,
     Or,
@@ -41,46 +41,84 @@ pub enum LeftRightOp {
     Arithmetic(ArithmeticOp),
 }
 
+/// Byte offsets of an expression within its source.
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// 1 based line and column of the start of the span within `source`.
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        (
+            before.matches('\n').count() + 1,
+            before.chars().rev().take_while(|c| *c != '\n').count() + 1,
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Ternary(Box<Expression>, Box<Expression>, Box<Expression>),
-    Relation(RelationOp, Box<Expression>, Box<Expression>),
-    Arithmetic(ArithmeticOp, Box<Expression>, Box<Expression>),
-    Unary(UnaryOp, Box<Expression>),
+    Ternary(Box<Expression>, Box<Expression>, Box<Expression>, Span),
+    Logic(LogicOp, Box<Expression>, Box<Expression>, Span),
+    Relation(RelationOp, Box<Expression>, Box<Expression>, Span),
+    Arithmetic(ArithmeticOp, Box<Expression>, Box<Expression>, Span),
+    Unary(UnaryOp, Box<Expression>, Span),
 
-    Member(Box<Expression>, Box<Member>),
+    Member(Box<Expression>, Box<Member>, Span),
 
     List(Vec<Expression>),
     Map(Vec<(Expression, Expression)>),
-    Struct(Vec<Rc<String>>, Vec<(Rc<String>, Expression)>),
+    Struct(Vec<Arc<String>>, Vec<(Arc<String>, Expression)>),
 
     Literal(Literal),
-    Ident(Rc<String>),
+    Ident(Arc<String>, Span),
+
+    /// Stands in for a syntax error the parser recovered from, successfully parsed expressions
+    /// never contain it.
+    Error,
 }
 
 impl Expression {
-    pub(crate) fn from_op(op: LeftRightOp, left: Box<Expression>, right: Box<Expression>) -> Self {
+    pub(crate) fn from_op(
+        op: LeftRightOp,
+        left: Box<Expression>,
+        right: Box<Expression>,
+        span: Span,
+    ) -> Self {
         use LeftRightOp::*;
         match op {
-            Logic(LogicOp::Or) => Expression::Ternary(
-                left,
-                Box::new(Expression::Literal(Literal::Bool(true))),
-                right,
-            ),
-            Logic(LogicOp::And) => Expression::Ternary(
-                left,
-                right,
-                Box::new(Expression::Literal(Literal::Bool(false))),
-            ),
-            Relation(op) => Expression::Relation(op, left, right),
-            Arithmetic(op) => Expression::Arithmetic(op, left, right),
+            Logic(op) => Expression::Logic(op, left, right, span),
+            Relation(op) => Expression::Relation(op, left, right, span),
+            Arithmetic(op) => Expression::Arithmetic(op, left, right, span),
+        }
+    }
+
+    /// Location in the source of the expression, `None` for literals and list / map constructors.
+    pub fn span(&self) -> Option<Span> {
+        use Expression::*;
+        match self {
+            Ternary(.., span)
+            | Logic(.., span)
+            | Relation(.., span)
+            | Arithmetic(.., span)
+            | Unary(.., span)
+            | Member(.., span)
+            | Ident(.., span) => Some(*span),
+            _ => None,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Member {
-    Attribute(Rc<String>),
+    Attribute(Arc<String>),
     FunctionCall(Vec<Expression>),
     Index(Box<Expression>),
 }
@@ -89,22 +127,19 @@ pub enum Member {
 pub enum Literal {
     Int(i64),
     UInt(u64),
-    Double(Rc<String>),
-    String(Rc<String>),
-    Bytes(Rc<Vec<u8>>),
+    Double(Arc<String>),
+    String(Arc<String>),
+    Bytes(Arc<Vec<u8>>),
     Bool(bool),
     Null,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::ExpressionParser;
-    use crate::{ArithmeticOp::*, Expression, Expression::*, Literal::*, Member::*};
+    use crate::{ArithmeticOp::*, Expression, Expression::*, Literal::*, Member::*, Span};
 
     fn parse(input: &str) -> Expression {
-        ExpressionParser::new()
-            .parse(input)
-            .unwrap_or_else(|e| panic!("{}", e))
+        crate::parse(input).unwrap_or_else(|e| panic!("{:?}", e))
     }
 
     fn assert_parse_eq(input: &str, expected: Expression) {
@@ -118,27 +153,45 @@ mod tests {
             Arithmetic(
                 Add,
                 Literal(Int(1)).into(),
-                Arithmetic(Multiply, Literal(Int(2)).into(), Literal(Int(3)).into()).into(),
-            )
-            .into(),
+                Arithmetic(
+                    Multiply,
+                    Literal(Int(2)).into(),
+                    Literal(Int(3)).into(),
+                    Span::new(4, 9),
+                )
+                .into(),
+                Span::new(0, 9),
+            ),
         );
         assert_parse_eq(
             "1 * 2 + 3",
             Arithmetic(
                 Add,
-                Arithmetic(Multiply, Literal(Int(1)).into(), Literal(Int(2)).into()).into(),
+                Arithmetic(
+                    Multiply,
+                    Literal(Int(1)).into(),
+                    Literal(Int(2)).into(),
+                    Span::new(0, 5),
+                )
+                .into(),
                 Literal(Int(3)).into(),
-            )
-            .into(),
+                Span::new(0, 9),
+            ),
         );
         assert_parse_eq(
             "1 * (2 + 3)",
             Arithmetic(
                 Multiply,
                 Literal(Int(1)).into(),
-                Arithmetic(Add, Literal(Int(2)).into(), Literal(Int(3)).into()).into(),
-            )
-            .into(),
+                Arithmetic(
+                    Add,
+                    Literal(Int(2)).into(),
+                    Literal(Int(3)).into(),
+                    Span::new(5, 10),
+                )
+                .into(),
+                Span::new(0, 11),
+            ),
         )
     }
 
@@ -157,8 +210,9 @@ mod tests {
         assert_parse_eq(
             "hello.world",
             Member(
-                Ident("hello".to_string().into()).into(),
+                Ident("hello".to_string().into(), Span::new(0, 5)).into(),
                 Attribute("world".to_string().into()).into(),
+                Span::new(0, 11),
             ),
         )
     }
@@ -169,13 +223,121 @@ mod tests {
             "a.b[1]",
             Member(
                 Member(
-                    Ident("a".to_string().into()).into(),
+                    Ident("a".to_string().into(), Span::new(0, 1)).into(),
                     Attribute("b".to_string().into()).into(),
+                    Span::new(0, 3),
                 )
                 .into(),
                 Index(Literal(Int(1)).into()).into(),
-            )
-            .into(),
+                Span::new(0, 6),
+            ),
         )
     }
+
+    #[test]
+    fn nested_ternary() {
+        assert_parse_eq(
+            "a ? 1 : b ? 2 : 3",
+            Ternary(
+                Ident("a".to_string().into(), Span::new(0, 1)).into(),
+                Literal(Int(1)).into(),
+                Ternary(
+                    Ident("b".to_string().into(), Span::new(8, 9)).into(),
+                    Literal(Int(2)).into(),
+                    Literal(Int(3)).into(),
+                    Span::new(8, 17),
+                )
+                .into(),
+                Span::new(0, 17),
+            ),
+        )
+    }
+
+    #[test]
+    fn list_and_map_literals() {
+        assert_parse_eq(
+            "[1, [2],]",
+            List(vec![Literal(Int(1)), List(vec![Literal(Int(2))])]),
+        );
+        assert_parse_eq(
+            "{'a': 1, 'b': x}",
+            Map(vec![
+                (Literal(String("a".to_string().into())), Literal(Int(1))),
+                (
+                    Literal(String("b".to_string().into())),
+                    Ident("x".to_string().into(), Span::new(14, 15)),
+                ),
+            ]),
+        );
+        assert_parse_eq("{}", Map(vec![]));
+        assert_parse_eq("[]", List(vec![]));
+    }
+
+    #[test]
+    fn comments() {
+        assert_parse_eq(
+            "// fee in cents\n1 * // applied once\n 2 // done",
+            Arithmetic(
+                Multiply,
+                Literal(Int(1)).into(),
+                Literal(Int(2)).into(),
+                Span::new(16, 38),
+            ),
+        );
+        assert_parse_eq(
+            "'https://example.com' // not part of the url",
+            Literal(String("https://example.com".to_string().into())),
+        );
+    }
+
+    #[test]
+    fn string_literals() {
+        let string = |s: &str| Literal(String(s.to_string().into()));
+        assert_parse_eq(r#""a\nb\t\"c\"""#, string("a\nb\t\"c\""));
+        assert_parse_eq(r#"'it\'s'"#, string("it's"));
+        assert_parse_eq(r#"'\x41\101\u00e9\U0001F600\u{1F600}'"#, string("AAé😀😀"));
+        assert_parse_eq(r#"r'^ACC-\d{4}$'"#, string(r"^ACC-\d{4}$"));
+        assert_parse_eq(r#"R"C:\temp""#, string(r"C:\temp"));
+        assert_parse_eq(r#""""say "hi" """"#, string(r#"say "hi" "#));
+        assert_parse_eq("'''multi\nline'''", string("multi\nline"));
+        assert_parse_eq(
+            r#"b'\xff\000a'"#,
+            Literal(Bytes(vec![0xff, 0, b'a'].into())),
+        );
+        assert_parse_eq(r#"rb'\xff'"#, Literal(Bytes(br"\xff".to_vec().into())));
+        for invalid in [r"'\q'", r"'\x4'", r"'\u{110000}'", r"'\400'"] {
+            assert!(crate::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn multiple_syntax_errors() {
+        let messages = |src: &str| -> Vec<std::string::String> {
+            crate::parse(src)
+                .unwrap_err()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        assert_eq!(
+            messages("f(1 +, 2 +) + [1 2] + 'x\\q'"),
+            vec![
+                "unexpected `,` at 1:6",
+                "unexpected `)` at 1:11",
+                "unexpected `2` at 1:18",
+                "invalid escape sequence \\q at 1:23",
+            ]
+        );
+        assert_eq!(
+            messages("a.b.\n + c ? d"),
+            vec![
+                "unexpected `+`, expected an identifier at 2:2",
+                "unexpected end of expression, expected `:` at 2:9",
+            ]
+        );
+        assert_eq!(
+            messages("(a + b"),
+            vec!["unexpected end of expression, expected `)` at 1:7"]
+        );
+    }
 }
@@ -0,0 +1,74 @@
+use lalrpop_util::ParseError;
+
+use crate::Span;
+
+/// A syntax error at `span` of the source, `line` and `column` are 1 based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl SyntaxError {
+    pub(crate) fn at(span: Span, message: String) -> Self {
+        Self {
+            span,
+            line: 0,
+            column: 0,
+            message,
+        }
+    }
+
+    pub(crate) fn new<T: std::fmt::Display>(
+        e: ParseError<usize, T, SyntaxError>,
+        source: &str,
+    ) -> Self {
+        let mut error = match e {
+            ParseError::InvalidToken { location } => Self::at(
+                Span::new(location, location + 1),
+                "invalid token".to_string(),
+            ),
+            ParseError::UnrecognizedEOF { location, expected } => Self::at(
+                Span::new(location, location),
+                format!("unexpected end of expression{}", expecting(&expected)),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => Self::at(
+                Span::new(start, end),
+                format!("unexpected `{token}`{}", expecting(&expected)),
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Self::at(Span::new(start, end), format!("unexpected `{token}`")),
+            ParseError::User { error } => error,
+        };
+        (error.line, error.column) = error.span.line_column(source);
+        error
+    }
+}
+
+// Long lists of alternatives (eg. after an operator) don't help much
+fn expecting(expected: &[String]) -> String {
+    if expected.is_empty() || expected.len() > 4 {
+        return String::new();
+    }
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|token| match token.strip_prefix("r#") {
+            Some(regex) if regex.contains("a-zA-Z") => "an identifier".to_string(),
+            Some(_) => "a literal".to_string(),
+            None => token.replace('"', "`"),
+        })
+        .collect();
+    format!(", expected {}", expected.join(" or "))
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.line, self.column)
+    }
+}
@@ -4,7 +4,28 @@
 use lalrpop_util::lalrpop_mod;
 
 pub mod ast;
+mod error;
+mod literal;
 
 pub use ast::*;
+pub use error::*;
 
 lalrpop_mod!(#[allow(clippy::all)] pub parser, "/cel.rs");
+
+/// Parses `source`, recovering from syntax errors so that all of them are reported at once.
+pub fn parse(source: &str) -> Result<Expression, Vec<SyntaxError>> {
+    let mut recovered = Vec::new();
+    let res = parser::ExpressionParser::new().parse(&mut recovered, source);
+    let mut errors: Vec<_> = recovered
+        .into_iter()
+        .map(|e| SyntaxError::new(e.error, source))
+        .collect();
+    match res {
+        Ok(expr) if errors.is_empty() => Ok(expr),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(SyntaxError::new(e, source));
+            Err(errors)
+        }
+    }
+}
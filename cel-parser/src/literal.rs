@@ -0,0 +1,124 @@
+use lalrpop_util::ParseError;
+
+use std::sync::Arc;
+
+use crate::{Literal, Span, SyntaxError};
+
+/// Turns a quoted string or bytes token (eg. `'a\n'`, `r"\d+"`, `b'\xff'`) into its literal value.
+/// Escape sequences follow the CEL spec, plus `\u{...}` for code points of any length.
+pub(crate) fn parse_quoted<T>(
+    token: &str,
+    span: Span,
+) -> Result<Literal, ParseError<usize, T, SyntaxError>> {
+    quoted(token).map_err(|message| ParseError::User {
+        error: SyntaxError::at(span, message),
+    })
+}
+
+fn quoted(token: &str) -> Result<Literal, String> {
+    let prefix = token.find(['"', '\'']).unwrap_or(0);
+    let raw = token[..prefix].contains(['r', 'R']);
+    let bytes = token[..prefix].contains(['b', 'B']);
+    let body = &token[prefix..];
+    let quotes = if body.starts_with("\"\"\"") || body.starts_with("'''") {
+        3
+    } else {
+        1
+    };
+    let body = &body[quotes..body.len() - quotes];
+
+    let value = if raw {
+        body.as_bytes().to_vec()
+    } else {
+        unescape(body, bytes)?
+    };
+    if bytes {
+        Ok(Literal::Bytes(Arc::new(value)))
+    } else {
+        String::from_utf8(value)
+            .map(|s| Literal::String(Arc::new(s)))
+            .map_err(|_| format!("invalid utf-8 in string literal {token}"))
+    }
+}
+
+// In bytes literals `\x` and octal escapes are single bytes, in strings they are code points.
+fn unescape(body: &str, bytes: bool) -> Result<Vec<u8>, String> {
+    let mut res = Vec::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut res, c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('f') => '\x0c',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('v') => '\x0b',
+            Some(c @ ('\\' | '\'' | '"' | '`' | '?')) => c,
+            Some(d @ '0'..='3') => {
+                let digits: String = std::iter::once(d).chain(chars.by_ref().take(2)).collect();
+                let n = u32::from_str_radix(&digits, 8)
+                    .map_err(|_| format!("invalid octal escape \\{digits}"))?;
+                push_code(&mut res, n, bytes)?;
+                continue;
+            }
+            Some(x @ ('x' | 'X')) => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let n = hex_digits(&digits, 2).ok_or(format!("invalid escape \\{x}{digits}"))?;
+                push_code(&mut res, n, bytes)?;
+                continue;
+            }
+            Some('u') if chars.as_str().starts_with('{') => {
+                let end = chars
+                    .as_str()
+                    .find('}')
+                    .ok_or("unterminated \\u{...} escape".to_string())?;
+                let digits = chars.as_str()[1..end].to_string();
+                chars.nth(end);
+                unicode(&digits, 1..=6)?
+            }
+            Some('u') => unicode(&chars.by_ref().take(4).collect::<String>(), 4..=4)?,
+            Some('U') => unicode(&chars.by_ref().take(8).collect::<String>(), 8..=8)?,
+            Some(c) => return Err(format!("invalid escape sequence \\{c}")),
+            None => return Err("literal ends with a lone backslash".to_string()),
+        };
+        push_char(&mut res, escaped);
+    }
+    Ok(res)
+}
+
+fn unicode(digits: &str, len: std::ops::RangeInclusive<usize>) -> Result<char, String> {
+    len.contains(&digits.len())
+        .then(|| hex_digits(digits, digits.len()))
+        .flatten()
+        .and_then(char::from_u32)
+        .ok_or(format!("invalid unicode escape {digits}"))
+}
+
+fn hex_digits(digits: &str, len: usize) -> Option<u32> {
+    if digits.len() != len {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn push_code(res: &mut Vec<u8>, n: u32, bytes: bool) -> Result<(), String> {
+    if bytes {
+        res.push(u8::try_from(n).map_err(|_| format!("byte escape {n} out of range"))?);
+    } else {
+        push_char(
+            res,
+            char::from_u32(n).ok_or(format!("invalid code point {n}"))?,
+        );
+    }
+    Ok(())
+}
+
+fn push_char(res: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use derive_builder::Builder;
+use rust_decimal::Decimal;
 
 use crate::primitives::*;
 
@@ -8,6 +9,7 @@ pub struct Account<M> {
     pub code: String,
     pub name: String,
     pub normal_balance_type: DebitOrCredit,
+    pub allowed_direction: Option<DebitOrCredit>,
     pub description: Option<String>,
     pub status: Status,
     pub metadata: Option<M>,
@@ -26,12 +28,27 @@ pub struct NewAccount {
     pub(super) name: String,
     #[builder(default)]
     pub(super) normal_balance_type: DebitOrCredit,
+    /// Restricts entries on the account to a single direction. Unset allows both.
+    #[builder(setter(strip_option), default)]
+    pub(super) allowed_direction: Option<DebitOrCredit>,
     #[builder(setter(strip_option, into), default)]
     pub(super) description: Option<String>,
     #[builder(default)]
     pub(super) status: Status,
     #[builder(setter(custom), default)]
     pub(super) metadata: Option<serde_json::Value>,
+    /// Only `SqlxLedger::create_account` can post it; `Accounts::create` rejects it.
+    #[builder(setter(strip_option), default)]
+    pub(crate) opening_balance: Option<OpeningBalance>,
+}
+
+/// Balance an account starts out with in a journal.
+/// Posted against the ledger's opening balances equity account.
+#[derive(Debug, Clone)]
+pub struct OpeningBalance {
+    pub journal_id: JournalId,
+    pub units: Decimal,
+    pub currency: Currency,
 }
 
 impl NewAccount {
@@ -40,6 +57,10 @@ impl NewAccount {
         builder.id(AccountId::new());
         builder
     }
+
+    pub(crate) fn normal_balance_type(&self) -> DebitOrCredit {
+        self.normal_balance_type
+    }
 }
 
 impl NewAccountBuilder {
@@ -66,6 +87,7 @@ mod tests {
         assert_eq!(new_account.code, "code");
         assert_eq!(new_account.name, "name");
         assert_eq!(new_account.normal_balance_type, DebitOrCredit::Credit);
+        assert_eq!(new_account.allowed_direction, None);
         assert_eq!(new_account.description, None);
         assert_eq!(new_account.status, Status::Active);
         assert_eq!(new_account.metadata, None);
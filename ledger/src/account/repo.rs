@@ -1,25 +1,42 @@
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{Pool, Postgres, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 
+use std::collections::HashMap;
+
 use super::entity::*;
-use crate::{error::*, primitives::*};
+use crate::{error::*, pagination::*, primitives::*};
 
 #[derive(Debug, Clone)]
 pub struct Accounts {
     pool: Pool<Postgres>,
+    dry_run: bool,
 }
 
 impl Accounts {
     pub fn new(pool: &Pool<Postgres>) -> Self {
-        Self { pool: pool.clone() }
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+        }
     }
 
     pub async fn create(&self, new_account: NewAccount) -> Result<AccountId, SqlxLedgerError> {
         let mut tx = self.pool.begin().await?;
         let res = self.create_in_tx(&mut tx, new_account).await?;
-        tx.commit().await?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
         Ok(res)
     }
 
@@ -34,18 +51,24 @@ impl Accounts {
             code,
             name,
             normal_balance_type,
+            allowed_direction,
             description,
             status,
             metadata,
+            opening_balance,
         } = new_account;
+        if opening_balance.is_some() {
+            return Err(SqlxLedgerError::OpeningBalanceRequiresLedger);
+        }
         let record = sqlx::query!(
-            r#"INSERT INTO sqlx_ledger_accounts (id, code, name, normal_balance_type, description, status, metadata)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            r#"INSERT INTO sqlx_ledger_accounts (id, code, name, normal_balance_type, allowed_direction, description, status, metadata)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             RETURNING id, version, created_at"#,
             Uuid::from(id),
             code,
             name,
             normal_balance_type as DebitOrCredit,
+            allowed_direction as Option<DebitOrCredit>,
             description,
             status as Status,
             metadata
@@ -66,14 +89,20 @@ impl Accounts {
             Some(m) => Some(serde_json::to_value(m)?),
             None => None,
         };
+        let mut tx = self.pool.begin().await?;
         sqlx::query_file!(
             "src/account/sql/update-account.sql",
             Uuid::from(id),
             description,
             metadata_json
         )
-        .execute(&self.pool)
+        .execute(&mut tx)
         .await?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
         Ok(id)
     }
 
@@ -87,4 +116,88 @@ impl Accounts {
         .await?;
         Ok(record.map(|r| AccountId::from(r.id)))
     }
+
+    /// Accounts ordered by id, which keeps the ordering stable while accounts are being added.
+    #[instrument(name = "sqlx_ledger.accounts.list", skip(self))]
+    pub async fn list<M: DeserializeOwned>(
+        &self,
+        args: PaginatedQueryArgs<AccountId>,
+    ) -> Result<PaginatedQueryRet<Account<M>, AccountId>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT DISTINCT ON (id) id, version, code, name, normal_balance_type as "normal_balance_type: DebitOrCredit",
+                 allowed_direction as "allowed_direction: DebitOrCredit", description, status as "status: Status",
+                 metadata, modified_at, created_at
+            FROM sqlx_ledger_accounts
+            WHERE ($1::uuid IS NULL OR id > $1)
+            ORDER BY id, version DESC
+            LIMIT $2"#,
+            args.after.map(Uuid::from) as Option<Uuid>,
+            (args.first + 1) as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let has_next_page = records.len() > args.first;
+        let mut entities = Vec::new();
+        for row in records.into_iter().take(args.first) {
+            entities.push(Account {
+                id: AccountId::from(row.id),
+                code: row.code,
+                name: row.name,
+                normal_balance_type: row.normal_balance_type,
+                allowed_direction: row.allowed_direction,
+                description: row.description,
+                status: row.status,
+                metadata: row.metadata.map(serde_json::from_value).transpose()?,
+                version: row.version as u32,
+                modified_at: row.modified_at,
+                created_at: row.created_at,
+            });
+        }
+        let end_cursor = entities.last().map(|account| account.id);
+        Ok(PaginatedQueryRet {
+            entities,
+            has_next_page,
+            end_cursor,
+        })
+    }
+
+    pub(crate) async fn allowed_directions_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        ids: Vec<AccountId>,
+    ) -> Result<HashMap<AccountId, DebitOrCredit>, SqlxLedgerError> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let records = sqlx::query!(
+            r#"SELECT id, allowed_direction as "allowed_direction!: DebitOrCredit" FROM (
+                 SELECT DISTINCT ON (id) id, allowed_direction FROM sqlx_ledger_accounts
+                 WHERE id = ANY($1) ORDER BY id, version DESC
+               ) a WHERE allowed_direction IS NOT NULL"#,
+            &ids[..]
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|r| (AccountId::from(r.id), r.allowed_direction))
+            .collect())
+    }
+
+    pub(crate) async fn ensure_exist_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        ids: Vec<AccountId>,
+    ) -> Result<(), SqlxLedgerError> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let missing = sqlx::query!(
+            r#"SELECT id as "id!" FROM UNNEST($1::uuid[]) AS i(id)
+               WHERE NOT EXISTS (SELECT 1 FROM sqlx_ledger_accounts a WHERE a.id = i.id) LIMIT 1"#,
+            &ids[..]
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        match missing {
+            Some(r) => Err(SqlxLedgerError::AccountNotFound(AccountId::from(r.id))),
+            None => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use derive_builder::Builder;
+use rust_decimal::Decimal;
+
+use crate::primitives::*;
+
+/// Groups accounts and other account sets of a journal under one rolled-up balance.
+pub struct AccountSet {
+    pub id: AccountSetId,
+    pub journal_id: JournalId,
+    pub name: String,
+    pub normal_balance_type: DebitOrCredit,
+    pub description: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountSetMember {
+    Account(AccountId),
+    AccountSet(AccountSetId),
+}
+
+#[derive(Builder, Debug)]
+pub struct NewAccountSet {
+    #[builder(setter(into))]
+    pub id: AccountSetId,
+    #[builder(setter(into))]
+    pub(super) journal_id: JournalId,
+    #[builder(setter(into))]
+    pub(super) name: String,
+    #[builder(default)]
+    pub(super) normal_balance_type: DebitOrCredit,
+    #[builder(setter(strip_option, into), default)]
+    pub(super) description: Option<String>,
+    #[builder(setter(custom), default)]
+    pub(super) metadata: Option<serde_json::Value>,
+}
+
+impl NewAccountSet {
+    pub fn builder() -> NewAccountSetBuilder {
+        let mut builder = NewAccountSetBuilder::default();
+        builder.id(AccountSetId::new());
+        builder
+    }
+}
+
+impl NewAccountSetBuilder {
+    pub fn metadata<T: serde::Serialize>(
+        &mut self,
+        metadata: T,
+    ) -> Result<&mut Self, serde_json::Error> {
+        self.metadata = Some(Some(serde_json::to_value(metadata)?));
+        Ok(self)
+    }
+}
+
+/// Sum of the balances of every account in the set, including those of nested sets.
+/// Each account counts once even if it is reachable through several nested sets.
+#[derive(Debug, Clone)]
+pub struct AccountSetBalance {
+    pub(super) balance_type: DebitOrCredit,
+    pub account_set_id: AccountSetId,
+    pub currency: Currency,
+    pub settled_dr_balance: Decimal,
+    pub settled_cr_balance: Decimal,
+    pub pending_dr_balance: Decimal,
+    pub pending_cr_balance: Decimal,
+    pub encumbered_dr_balance: Decimal,
+    pub encumbered_cr_balance: Decimal,
+    pub modified_at: DateTime<Utc>,
+}
+
+impl AccountSetBalance {
+    pub fn settled(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.settled_cr_balance - self.settled_dr_balance
+        } else {
+            self.settled_dr_balance - self.settled_cr_balance
+        }
+    }
+
+    pub fn pending(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.pending_cr_balance - self.pending_dr_balance
+        } else {
+            self.pending_dr_balance - self.pending_cr_balance
+        }
+    }
+
+    pub fn encumbered(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.encumbered_cr_balance - self.encumbered_dr_balance
+        } else {
+            self.encumbered_dr_balance - self.encumbered_cr_balance
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct BalanceAmounts {
+    pub(super) settled_dr: Decimal,
+    pub(super) settled_cr: Decimal,
+    pub(super) pending_dr: Decimal,
+    pub(super) pending_cr: Decimal,
+    pub(super) encumbered_dr: Decimal,
+    pub(super) encumbered_cr: Decimal,
+}
+
+impl BalanceAmounts {
+    pub(super) fn add_entry(&mut self, layer: Layer, direction: DebitOrCredit, units: Decimal) {
+        let amount = match (layer, direction) {
+            (Layer::Settled, DebitOrCredit::Debit) => &mut self.settled_dr,
+            (Layer::Settled, DebitOrCredit::Credit) => &mut self.settled_cr,
+            (Layer::Pending, DebitOrCredit::Debit) => &mut self.pending_dr,
+            (Layer::Pending, DebitOrCredit::Credit) => &mut self.pending_cr,
+            (Layer::Encumbered, DebitOrCredit::Debit) => &mut self.encumbered_dr,
+            (Layer::Encumbered, DebitOrCredit::Credit) => &mut self.encumbered_cr,
+        };
+        *amount += units;
+    }
+
+    pub(super) fn add(&mut self, other: &BalanceAmounts) {
+        self.settled_dr += other.settled_dr;
+        self.settled_cr += other.settled_cr;
+        self.pending_dr += other.pending_dr;
+        self.pending_cr += other.pending_cr;
+        self.encumbered_dr += other.encumbered_dr;
+        self.encumbered_cr += other.encumbered_cr;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds() {
+        let new_account_set = NewAccountSet::builder()
+            .journal_id(JournalId::new())
+            .name("name")
+            .build()
+            .unwrap();
+        assert_eq!(new_account_set.name, "name");
+        assert_eq!(new_account_set.normal_balance_type, DebitOrCredit::Credit);
+        assert_eq!(new_account_set.metadata, None);
+    }
+
+    #[test]
+    fn fails_when_mandatory_fields_are_missing() {
+        let new_account_set = NewAccountSet::builder().name("name").build();
+        assert!(new_account_set.is_err());
+    }
+}
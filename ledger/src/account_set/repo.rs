@@ -0,0 +1,394 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use super::entity::*;
+use crate::{clock::*, entry::StagedEntry, error::*, primitives::*};
+
+#[derive(Debug, Clone)]
+pub struct AccountSets {
+    pool: PgPool,
+    dry_run: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl AccountSets {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+            clock: Arc::clone(&self.clock),
+        }
+    }
+
+    pub(crate) fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    #[instrument(name = "sqlx_ledger.account_sets.create", skip(self))]
+    pub async fn create(
+        &self,
+        new_account_set: NewAccountSet,
+    ) -> Result<AccountSetId, SqlxLedgerError> {
+        let NewAccountSet {
+            id,
+            journal_id,
+            name,
+            normal_balance_type,
+            description,
+            metadata,
+        } = new_account_set;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_account_sets (id, journal_id, name, normal_balance_type, description, metadata)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+            Uuid::from(id),
+            Uuid::from(journal_id),
+            name,
+            normal_balance_type as DebitOrCredit,
+            description,
+            metadata
+        )
+        .execute(&mut tx)
+        .await?;
+        self.finish(tx).await?;
+        Ok(id)
+    }
+
+    #[instrument(name = "sqlx_ledger.account_sets.find", skip(self))]
+    pub async fn find(&self, id: AccountSetId) -> Result<AccountSet, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT id, journal_id, name, normal_balance_type as "normal_balance_type: DebitOrCredit", description, metadata, created_at
+            FROM sqlx_ledger_account_sets WHERE id = $1"#,
+            Uuid::from(id)
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(AccountSet {
+            id: AccountSetId::from(record.id),
+            journal_id: JournalId::from(record.journal_id),
+            name: record.name,
+            normal_balance_type: record.normal_balance_type,
+            description: record.description,
+            metadata: record.metadata,
+            created_at: record.created_at,
+        })
+    }
+
+    /// Direct members of the set, in the order they were added.
+    #[instrument(name = "sqlx_ledger.account_sets.list_members", skip(self))]
+    pub async fn list_members(
+        &self,
+        id: AccountSetId,
+    ) -> Result<Vec<AccountSetMember>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT member_account_id, member_account_set_id FROM sqlx_ledger_account_set_members
+            WHERE account_set_id = $1 ORDER BY created_at"#,
+            Uuid::from(id)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|r| match (r.member_account_id, r.member_account_set_id) {
+                (Some(account_id), _) => AccountSetMember::Account(account_id.into()),
+                (_, set_id) => {
+                    AccountSetMember::AccountSet(set_id.expect("member is a set").into())
+                }
+            })
+            .collect())
+    }
+
+    /// Adds an account or a nested set of the same journal,
+    /// rolling its current balances up into the set and every set containing it.
+    #[instrument(name = "sqlx_ledger.account_sets.add_member", skip(self))]
+    pub async fn add_member(
+        &self,
+        id: AccountSetId,
+        member: AccountSetMember,
+    ) -> Result<(), SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        let journal_id = sqlx::query!(
+            r#"SELECT journal_id FROM sqlx_ledger_account_sets WHERE id = $1 FOR UPDATE"#,
+            Uuid::from(id)
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .journal_id;
+        let ancestors: Vec<Uuid> = sqlx::query!(
+            r#"WITH RECURSIVE ancestors AS (
+                 SELECT $1::uuid AS id
+                 UNION
+                 SELECT m.account_set_id FROM sqlx_ledger_account_set_members m
+                 JOIN ancestors a ON m.member_account_set_id = a.id
+               ) SELECT id as "id!" FROM ancestors"#,
+            Uuid::from(id)
+        )
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+
+        let (member_account_id, member_account_set_id) = match member {
+            AccountSetMember::Account(account_id) => (Some(Uuid::from(account_id)), None),
+            AccountSetMember::AccountSet(set_id) => {
+                if ancestors.contains(&Uuid::from(set_id)) {
+                    return Err(SqlxLedgerError::AccountSetCycle(id, set_id));
+                }
+                let member_journal_id = sqlx::query!(
+                    r#"SELECT journal_id FROM sqlx_ledger_account_sets WHERE id = $1"#,
+                    Uuid::from(set_id)
+                )
+                .fetch_one(&mut tx)
+                .await?
+                .journal_id;
+                if member_journal_id != journal_id {
+                    return Err(SqlxLedgerError::AccountSetJournalMismatch(id, set_id));
+                }
+                (None, Some(Uuid::from(set_id)))
+            }
+        };
+
+        let before = self.account_closure_in_tx(&mut tx, &ancestors).await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_account_set_members (account_set_id, member_account_id, member_account_set_id)
+            VALUES ($1, $2, $3)"#,
+            Uuid::from(id),
+            member_account_id,
+            member_account_set_id
+        )
+        .execute(&mut tx)
+        .await?;
+        let added: Vec<(Uuid, Uuid)> = self
+            .account_closure_in_tx(&mut tx, &ancestors)
+            .await?
+            .into_iter()
+            .filter(|pair| !before.contains(pair))
+            .collect();
+
+        let account_ids: Vec<Uuid> = added.iter().map(|(_, account_id)| *account_id).collect();
+        let records = sqlx::query!(
+            r#"SELECT c.account_id, c.currency, settled_dr_balance, settled_cr_balance,
+                 pending_dr_balance, pending_cr_balance, encumbered_dr_balance, encumbered_cr_balance
+               FROM sqlx_ledger_current_balances c JOIN sqlx_ledger_balances b
+                 ON b.journal_id = c.journal_id AND b.account_id = c.account_id AND b.currency = c.currency AND b.version = c.version
+               WHERE c.journal_id = $1 AND c.account_id = ANY($2)
+               FOR UPDATE OF c"#,
+            journal_id,
+            &account_ids[..]
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let mut account_balances: HashMap<Uuid, Vec<(String, BalanceAmounts)>> = HashMap::new();
+        for r in records {
+            account_balances.entry(r.account_id).or_default().push((
+                r.currency,
+                BalanceAmounts {
+                    settled_dr: r.settled_dr_balance,
+                    settled_cr: r.settled_cr_balance,
+                    pending_dr: r.pending_dr_balance,
+                    pending_cr: r.pending_cr_balance,
+                    encumbered_dr: r.encumbered_dr_balance,
+                    encumbered_cr: r.encumbered_cr_balance,
+                },
+            ));
+        }
+        let mut deltas = BTreeMap::new();
+        for (set_id, account_id) in added {
+            for (currency, amounts) in account_balances.get(&account_id).into_iter().flatten() {
+                deltas
+                    .entry((AccountSetId::from(set_id), currency.clone()))
+                    .or_insert_with(BalanceAmounts::default)
+                    .add(amounts);
+            }
+        }
+        self.add_to_balances_in_tx(&mut tx, deltas, self.clock.now())
+            .await?;
+        self.finish(tx).await
+    }
+
+    #[instrument(name = "sqlx_ledger.account_sets.balance", skip(self))]
+    pub async fn balance(
+        &self,
+        id: AccountSetId,
+        currency: Currency,
+    ) -> Result<Option<AccountSetBalance>, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT s.normal_balance_type as "normal_balance_type: DebitOrCredit",
+                 settled_dr_balance, settled_cr_balance, pending_dr_balance, pending_cr_balance,
+                 encumbered_dr_balance, encumbered_cr_balance, modified_at
+               FROM sqlx_ledger_account_set_balances b JOIN sqlx_ledger_account_sets s ON s.id = b.account_set_id
+               WHERE b.account_set_id = $1 AND b.currency = $2"#,
+            Uuid::from(id),
+            currency.code()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(record.map(|r| AccountSetBalance {
+            balance_type: r.normal_balance_type,
+            account_set_id: id,
+            currency,
+            settled_dr_balance: r.settled_dr_balance,
+            settled_cr_balance: r.settled_cr_balance,
+            pending_dr_balance: r.pending_dr_balance,
+            pending_cr_balance: r.pending_cr_balance,
+            encumbered_dr_balance: r.encumbered_dr_balance,
+            encumbered_cr_balance: r.encumbered_cr_balance,
+            modified_at: r.modified_at,
+        }))
+    }
+
+    /// Rolls posted entries up into the balances of every set containing their accounts.
+    pub(crate) async fn update_balances_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        journal_id: JournalId,
+        entries: &[StagedEntry],
+    ) -> Result<(), SqlxLedgerError> {
+        let modified_at = match entries.last() {
+            Some(entry) => entry.created_at,
+            None => return Ok(()),
+        };
+        let amounts = entries
+            .iter()
+            .map(|e| (e.account_id, e.currency, e.layer, e.direction, e.units))
+            .collect();
+        self.add_amounts_in_tx(tx, journal_id, amounts, modified_at)
+            .await
+    }
+
+    /// Rolls amounts posted to accounts up into the balances of every set containing them.
+    pub(crate) async fn add_amounts_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        journal_id: JournalId,
+        amounts: Vec<(AccountId, Currency, Layer, DebitOrCredit, Decimal)>,
+        modified_at: DateTime<Utc>,
+    ) -> Result<(), SqlxLedgerError> {
+        let account_ids: Vec<Uuid> = amounts.iter().map(|a| Uuid::from(a.0)).collect();
+        let records = sqlx::query!(
+            r#"WITH RECURSIVE ancestors AS (
+                 SELECT account_set_id, member_account_id AS account_id FROM sqlx_ledger_account_set_members
+                 WHERE member_account_id = ANY($1)
+                 UNION
+                 SELECT m.account_set_id, a.account_id FROM sqlx_ledger_account_set_members m
+                 JOIN ancestors a ON m.member_account_set_id = a.account_set_id
+               ) SELECT a.account_set_id as "account_set_id!", a.account_id as "account_id!" FROM ancestors a
+               JOIN sqlx_ledger_account_sets s ON s.id = a.account_set_id WHERE s.journal_id = $2"#,
+            &account_ids[..],
+            Uuid::from(journal_id)
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut sets_by_account: HashMap<AccountId, Vec<AccountSetId>> = HashMap::new();
+        for r in records {
+            sets_by_account
+                .entry(AccountId::from(r.account_id))
+                .or_default()
+                .push(AccountSetId::from(r.account_set_id));
+        }
+        let mut deltas = BTreeMap::new();
+        for (account_id, currency, layer, direction, units) in amounts {
+            for set_id in sets_by_account.get(&account_id).into_iter().flatten() {
+                deltas
+                    .entry((*set_id, currency.code().to_string()))
+                    .or_insert_with(BalanceAmounts::default)
+                    .add_entry(layer, direction, units);
+            }
+        }
+        self.add_to_balances_in_tx(tx, deltas, modified_at).await
+    }
+
+    // Rows are upserted in key order so concurrent postings lock them in the same order.
+    async fn add_to_balances_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        deltas: BTreeMap<(AccountSetId, String), BalanceAmounts>,
+        modified_at: DateTime<Utc>,
+    ) -> Result<(), SqlxLedgerError> {
+        if deltas.is_empty() {
+            return Ok(());
+        }
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"INSERT INTO sqlx_ledger_account_set_balances AS b (
+                 account_set_id, currency, settled_dr_balance, settled_cr_balance,
+                 pending_dr_balance, pending_cr_balance, encumbered_dr_balance, encumbered_cr_balance, modified_at)"#,
+        );
+        query_builder.push_values(deltas, |mut builder, ((set_id, currency), amounts)| {
+            builder.push_bind(Uuid::from(set_id));
+            builder.push_bind(currency);
+            builder.push_bind(amounts.settled_dr);
+            builder.push_bind(amounts.settled_cr);
+            builder.push_bind(amounts.pending_dr);
+            builder.push_bind(amounts.pending_cr);
+            builder.push_bind(amounts.encumbered_dr);
+            builder.push_bind(amounts.encumbered_cr);
+            builder.push_bind(modified_at);
+        });
+        query_builder.push(
+            r#" ON CONFLICT (account_set_id, currency) DO UPDATE SET
+                 settled_dr_balance = b.settled_dr_balance + EXCLUDED.settled_dr_balance,
+                 settled_cr_balance = b.settled_cr_balance + EXCLUDED.settled_cr_balance,
+                 pending_dr_balance = b.pending_dr_balance + EXCLUDED.pending_dr_balance,
+                 pending_cr_balance = b.pending_cr_balance + EXCLUDED.pending_cr_balance,
+                 encumbered_dr_balance = b.encumbered_dr_balance + EXCLUDED.encumbered_dr_balance,
+                 encumbered_cr_balance = b.encumbered_cr_balance + EXCLUDED.encumbered_cr_balance,
+                 modified_at = EXCLUDED.modified_at"#,
+        );
+        query_builder.build().execute(&mut *tx).await?;
+        Ok(())
+    }
+
+    // Every (set, account) pair where the account is a member of the set, directly or nested.
+    async fn account_closure_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        set_ids: &[Uuid],
+    ) -> Result<HashSet<(Uuid, Uuid)>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"WITH RECURSIVE tree AS (
+                 SELECT id AS root, id AS account_set_id FROM sqlx_ledger_account_sets WHERE id = ANY($1)
+                 UNION
+                 SELECT t.root, m.member_account_set_id FROM sqlx_ledger_account_set_members m
+                 JOIN tree t ON m.account_set_id = t.account_set_id WHERE m.member_account_set_id IS NOT NULL
+               ) SELECT DISTINCT t.root as "root!", m.member_account_id as "account_id!" FROM tree t
+               JOIN sqlx_ledger_account_set_members m ON m.account_set_id = t.account_set_id
+               WHERE m.member_account_id IS NOT NULL"#,
+            set_ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|r| (r.root, r.account_id))
+            .collect())
+    }
+
+    async fn finish(&self, tx: Transaction<'_, Postgres>) -> Result<(), SqlxLedgerError> {
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
 
 use crate::entry::StagedEntry;
@@ -6,7 +6,7 @@ use crate::primitives::*;
 
 #[derive(Debug, Clone)]
 pub struct AccountBalance {
-    pub(super) balance_type: DebitOrCredit,
+    pub(crate) balance_type: DebitOrCredit,
     pub details: BalanceDetails,
 }
 
@@ -34,6 +34,111 @@ impl AccountBalance {
             self.details.encumbered_dr_balance - self.details.encumbered_cr_balance
         }
     }
+
+    pub fn layer(&self, layer: Layer) -> Decimal {
+        match layer {
+            Layer::Settled => self.settled(),
+            Layer::Pending => self.pending(),
+            Layer::Encumbered => self.encumbered(),
+        }
+    }
+}
+
+/// Sum of the entries of an account whose transactions are effective on or before `effective`,
+/// regardless of when they were posted.
+#[derive(Debug, Clone)]
+pub struct EffectiveBalance {
+    pub(crate) balance_type: DebitOrCredit,
+    pub journal_id: JournalId,
+    pub account_id: AccountId,
+    pub currency: Currency,
+    pub effective: NaiveDate,
+    pub settled_dr: Decimal,
+    pub settled_cr: Decimal,
+    pub pending_dr: Decimal,
+    pub pending_cr: Decimal,
+    pub encumbered_dr: Decimal,
+    pub encumbered_cr: Decimal,
+}
+
+impl EffectiveBalance {
+    pub fn settled(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.settled_cr - self.settled_dr
+        } else {
+            self.settled_dr - self.settled_cr
+        }
+    }
+
+    pub fn pending(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.pending_cr - self.pending_dr
+        } else {
+            self.pending_dr - self.pending_cr
+        }
+    }
+
+    pub fn encumbered(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.encumbered_cr - self.encumbered_dr
+        } else {
+            self.encumbered_dr - self.encumbered_cr
+        }
+    }
+}
+
+/// Lower bound on an account balance that a posting must not break.
+#[derive(Debug, Clone)]
+pub struct BalanceConstraint {
+    pub account_id: AccountId,
+    pub currency: Currency,
+    pub layer: Layer,
+    pub min: Decimal,
+}
+
+/// Net movement of an account between two balance versions.
+/// Version `n` is the balance after the `n`-th entry on the account in that journal and currency,
+/// so a delta from `from_version` to `to_version` covers the entries `from_version + 1 ..= to_version`.
+#[derive(Debug, Clone)]
+pub struct BalanceDelta {
+    pub(super) balance_type: DebitOrCredit,
+    pub journal_id: JournalId,
+    pub account_id: AccountId,
+    pub currency: Currency,
+    pub from_version: i32,
+    pub to_version: i32,
+    pub settled_dr: Decimal,
+    pub settled_cr: Decimal,
+    pub pending_dr: Decimal,
+    pub pending_cr: Decimal,
+    pub encumbered_dr: Decimal,
+    pub encumbered_cr: Decimal,
+}
+
+impl BalanceDelta {
+    pub fn settled(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.settled_cr - self.settled_dr
+        } else {
+            self.settled_dr - self.settled_cr
+        }
+    }
+
+    pub fn pending(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.pending_cr - self.pending_dr
+        } else {
+            self.pending_dr - self.pending_cr
+        }
+    }
+
+    pub fn encumbered(&self) -> Decimal {
+        if self.balance_type == DebitOrCredit::Credit {
+            self.encumbered_cr - self.encumbered_dr
+        } else {
+            self.encumbered_dr - self.encumbered_cr
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
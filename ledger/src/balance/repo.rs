@@ -1,3 +1,4 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use sqlx::{PgPool, Postgres, QueryBuilder, Row, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
@@ -5,7 +6,7 @@ use uuid::Uuid;
 use std::collections::HashMap;
 
 use super::entity::*;
-use crate::{error::*, primitives::*};
+use crate::{error::*, metrics, primitives::*};
 
 #[derive(Debug, Clone)]
 pub struct Balances {
@@ -67,12 +68,235 @@ impl Balances {
             },
         }))
     }
+
+    /// Every balance version is kept as its own row in `sqlx_ledger_balances`,
+    /// so a version-pinned balance is read directly and never changes after later posts.
+    #[instrument(name = "sqlx_ledger.balances.find_at_version", skip(self))]
+    pub async fn find_at_version(
+        &self,
+        journal_id: JournalId,
+        account_id: AccountId,
+        currency: Currency,
+        version: i32,
+    ) -> Result<AccountBalance, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT
+              a.normal_balance_type as "normal_balance_type: DebitOrCredit", entry_id,
+              settled_dr_balance, settled_cr_balance, settled_entry_id, settled_modified_at,
+              pending_dr_balance, pending_cr_balance, pending_entry_id, pending_modified_at,
+              encumbered_dr_balance, encumbered_cr_balance, encumbered_entry_id, encumbered_modified_at,
+              b.version, modified_at, created_at
+                FROM sqlx_ledger_balances b
+                JOIN ( SELECT id, normal_balance_type FROM sqlx_ledger_accounts WHERE id = $2 LIMIT 1 ) a
+                  ON a.id = b.account_id
+                WHERE b.journal_id = $1 AND b.account_id = $2 AND b.currency = $3 AND b.version = $4"#,
+            Uuid::from(journal_id),
+            Uuid::from(account_id),
+            currency.code(),
+            version
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(SqlxLedgerError::BalanceVersionNotFound(version))?;
+        Ok(AccountBalance {
+            balance_type: record.normal_balance_type,
+            details: BalanceDetails {
+                journal_id,
+                account_id,
+                entry_id: EntryId::from(record.entry_id),
+                currency,
+                settled_dr_balance: record.settled_dr_balance,
+                settled_cr_balance: record.settled_cr_balance,
+                settled_entry_id: EntryId::from(record.settled_entry_id),
+                settled_modified_at: record.settled_modified_at,
+                pending_dr_balance: record.pending_dr_balance,
+                pending_cr_balance: record.pending_cr_balance,
+                pending_entry_id: EntryId::from(record.pending_entry_id),
+                pending_modified_at: record.pending_modified_at,
+                encumbered_dr_balance: record.encumbered_dr_balance,
+                encumbered_cr_balance: record.encumbered_cr_balance,
+                encumbered_entry_id: EntryId::from(record.encumbered_entry_id),
+                encumbered_modified_at: record.encumbered_modified_at,
+                version: record.version,
+                modified_at: record.modified_at,
+                created_at: record.created_at,
+            },
+        })
+    }
+
+    /// The balance as it was at `at`: the latest version modified at or before it.
+    /// Returns `None` if nothing had been posted to the account in that currency yet.
+    #[instrument(name = "sqlx_ledger.balances.find_at", skip(self))]
+    pub async fn find_at(
+        &self,
+        journal_id: JournalId,
+        account_id: AccountId,
+        currency: Currency,
+        at: DateTime<Utc>,
+    ) -> Result<Option<AccountBalance>, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT
+              a.normal_balance_type as "normal_balance_type: DebitOrCredit", entry_id,
+              settled_dr_balance, settled_cr_balance, settled_entry_id, settled_modified_at,
+              pending_dr_balance, pending_cr_balance, pending_entry_id, pending_modified_at,
+              encumbered_dr_balance, encumbered_cr_balance, encumbered_entry_id, encumbered_modified_at,
+              b.version, modified_at, created_at
+                FROM sqlx_ledger_balances b
+                JOIN ( SELECT id, normal_balance_type FROM sqlx_ledger_accounts WHERE id = $2 LIMIT 1 ) a
+                  ON a.id = b.account_id
+                WHERE b.journal_id = $1 AND b.account_id = $2 AND b.currency = $3 AND b.modified_at <= $4
+                ORDER BY b.version DESC LIMIT 1"#,
+            Uuid::from(journal_id),
+            Uuid::from(account_id),
+            currency.code(),
+            at
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(record.map(|record| AccountBalance {
+            balance_type: record.normal_balance_type,
+            details: BalanceDetails {
+                journal_id,
+                account_id,
+                entry_id: EntryId::from(record.entry_id),
+                currency,
+                settled_dr_balance: record.settled_dr_balance,
+                settled_cr_balance: record.settled_cr_balance,
+                settled_entry_id: EntryId::from(record.settled_entry_id),
+                settled_modified_at: record.settled_modified_at,
+                pending_dr_balance: record.pending_dr_balance,
+                pending_cr_balance: record.pending_cr_balance,
+                pending_entry_id: EntryId::from(record.pending_entry_id),
+                pending_modified_at: record.pending_modified_at,
+                encumbered_dr_balance: record.encumbered_dr_balance,
+                encumbered_cr_balance: record.encumbered_cr_balance,
+                encumbered_entry_id: EntryId::from(record.encumbered_entry_id),
+                encumbered_modified_at: record.encumbered_modified_at,
+                version: record.version,
+                modified_at: record.modified_at,
+                created_at: record.created_at,
+            },
+        }))
+    }
+
+    /// The balance by effective date rather than posting time, so backdated transactions
+    /// count towards the day they are effective on.
+    #[instrument(name = "sqlx_ledger.balances.find_effective_at", skip(self))]
+    pub async fn find_effective_at(
+        &self,
+        journal_id: JournalId,
+        account_id: AccountId,
+        currency: Currency,
+        effective: NaiveDate,
+    ) -> Result<EffectiveBalance, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT
+              (SELECT normal_balance_type FROM sqlx_ledger_accounts WHERE id = $2 LIMIT 1) as "normal_balance_type: DebitOrCredit",
+              COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'settled' AND e.direction = 'debit'), 0) as "settled_dr!",
+              COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'settled' AND e.direction = 'credit'), 0) as "settled_cr!",
+              COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'pending' AND e.direction = 'debit'), 0) as "pending_dr!",
+              COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'pending' AND e.direction = 'credit'), 0) as "pending_cr!",
+              COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'encumbered' AND e.direction = 'debit'), 0) as "encumbered_dr!",
+              COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'encumbered' AND e.direction = 'credit'), 0) as "encumbered_cr!"
+                FROM sqlx_ledger_entries e
+                JOIN sqlx_ledger_transactions t ON t.id = e.transaction_id AND t.version = 1
+                WHERE e.journal_id = $1 AND e.account_id = $2 AND e.currency = $3 AND t.effective <= $4"#,
+            Uuid::from(journal_id),
+            Uuid::from(account_id),
+            currency.code(),
+            effective
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(EffectiveBalance {
+            balance_type: record.normal_balance_type.unwrap_or_default(),
+            journal_id,
+            account_id,
+            currency,
+            effective,
+            settled_dr: record.settled_dr,
+            settled_cr: record.settled_cr,
+            pending_dr: record.pending_dr,
+            pending_cr: record.pending_cr,
+            encumbered_dr: record.encumbered_dr,
+            encumbered_cr: record.encumbered_cr,
+        })
+    }
+
+    /// What the entries after balance version `from_version` up to and including `to_version`
+    /// added, as the difference of the two versions as read by `find_at_version`.
+    /// A `from_version` of 0 counts from the start; a version that doesn't exist fails with
+    /// `BalanceVersionNotFound`.
+    #[instrument(name = "sqlx_ledger.balances.delta", skip(self))]
+    pub async fn delta(
+        &self,
+        journal_id: JournalId,
+        account_id: AccountId,
+        currency: Currency,
+        from_version: i32,
+        to_version: i32,
+    ) -> Result<BalanceDelta, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT
+              a.normal_balance_type as "normal_balance_type: DebitOrCredit", b.version,
+              settled_dr_balance, settled_cr_balance, pending_dr_balance, pending_cr_balance,
+              encumbered_dr_balance, encumbered_cr_balance
+                FROM sqlx_ledger_balances b
+                JOIN ( SELECT id, normal_balance_type FROM sqlx_ledger_accounts WHERE id = $2 LIMIT 1 ) a
+                  ON a.id = b.account_id
+                WHERE b.journal_id = $1 AND b.account_id = $2 AND b.currency = $3 AND b.version IN ($4, $5)"#,
+            Uuid::from(journal_id),
+            Uuid::from(account_id),
+            currency.code(),
+            from_version,
+            to_version
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let to = records
+            .iter()
+            .find(|r| r.version == to_version)
+            .ok_or(SqlxLedgerError::BalanceVersionNotFound(to_version))?;
+        let mut delta = BalanceDelta {
+            balance_type: to.normal_balance_type,
+            journal_id,
+            account_id,
+            currency,
+            from_version,
+            to_version,
+            settled_dr: to.settled_dr_balance,
+            settled_cr: to.settled_cr_balance,
+            pending_dr: to.pending_dr_balance,
+            pending_cr: to.pending_cr_balance,
+            encumbered_dr: to.encumbered_dr_balance,
+            encumbered_cr: to.encumbered_cr_balance,
+        };
+        if from_version > 0 {
+            let from = records
+                .iter()
+                .find(|r| r.version == from_version)
+                .ok_or(SqlxLedgerError::BalanceVersionNotFound(from_version))?;
+            delta.settled_dr -= from.settled_dr_balance;
+            delta.settled_cr -= from.settled_cr_balance;
+            delta.pending_dr -= from.pending_dr_balance;
+            delta.pending_cr -= from.pending_cr_balance;
+            delta.encumbered_dr -= from.encumbered_dr_balance;
+            delta.encumbered_cr -= from.encumbered_cr_balance;
+        }
+        Ok(delta)
+    }
+
+    #[instrument(
+        name = "sqlx_ledger.balances.find_for_update",
+        skip_all,
+        fields(%journal_id, n = ids.len())
+    )]
     pub(crate) async fn find_for_update<'a>(
         &self,
         journal_id: JournalId,
         ids: Vec<(AccountId, &Currency)>,
         tx: &mut Transaction<'a, Postgres>,
-    ) -> Result<HashMap<AccountId, BalanceDetails>, SqlxLedgerError> {
+    ) -> Result<HashMap<(AccountId, Currency), BalanceDetails>, SqlxLedgerError> {
         let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
             r#"SELECT
               b.journal_id, b.account_id, entry_id, b.currency,
@@ -95,17 +319,20 @@ impl Balances {
         );
 
         let query = query_builder.build();
+        let started = std::time::Instant::now();
         let records = query.fetch_all(&mut *tx).await?;
+        metrics::record_duration(metrics::BALANCE_LOCK_WAIT_DURATION, started.elapsed());
         let mut ret = HashMap::new();
         for r in records {
             let account_id = AccountId::from(r.get::<Uuid, _>("account_id"));
+            let currency: Currency = r.get::<&str, _>("currency").parse()?;
             ret.insert(
-                account_id,
+                (account_id, currency),
                 BalanceDetails {
                     account_id,
                     journal_id: JournalId::from(r.get::<Uuid, _>("journal_id")),
                     entry_id: EntryId::from(r.get::<Uuid, _>("entry_id")),
-                    currency: r.get::<&str, _>("currency").parse()?,
+                    currency,
                     settled_dr_balance: r.get("settled_dr_balance"),
                     settled_cr_balance: r.get("settled_cr_balance"),
                     settled_entry_id: EntryId::from(r.get::<Uuid, _>("settled_entry_id")),
@@ -127,6 +354,169 @@ impl Balances {
         Ok(ret)
     }
 
+    /// Recomputes every balance version of the given accounts from their entries,
+    /// ordered by `created_at`, replacing the versions recorded so far.
+    pub(crate) async fn rebuild_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        keys: Vec<(JournalId, AccountId, Currency)>,
+    ) -> Result<(), SqlxLedgerError> {
+        let mut journal_ids = Vec::with_capacity(keys.len());
+        let mut account_ids = Vec::with_capacity(keys.len());
+        let mut currencies = Vec::with_capacity(keys.len());
+        for (journal_id, account_id, currency) in keys {
+            journal_ids.push(Uuid::from(journal_id));
+            account_ids.push(Uuid::from(account_id));
+            currencies.push(currency.code().to_string());
+        }
+        sqlx::query!(
+            r#"DELETE FROM sqlx_ledger_current_balances c
+               USING UNNEST($1::uuid[], $2::uuid[], $3::varchar[]) AS k(journal_id, account_id, currency)
+               WHERE c.journal_id = k.journal_id AND c.account_id = k.account_id AND c.currency = k.currency"#,
+            &journal_ids[..],
+            &account_ids[..],
+            &currencies[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"DELETE FROM sqlx_ledger_balances b
+               USING UNNEST($1::uuid[], $2::uuid[], $3::varchar[]) AS k(journal_id, account_id, currency)
+               WHERE b.journal_id = k.journal_id AND b.account_id = k.account_id AND b.currency = k.currency"#,
+            &journal_ids[..],
+            &account_ids[..],
+            &currencies[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        // The entry and time of a layer's last change are those of the entry opening the run
+        // of rows with the same count of entries in that layer, or of the first entry if none.
+        sqlx::query!(
+            r#"WITH running AS (
+                 SELECT e.journal_id, e.account_id, e.currency, e.id, e.created_at,
+                   ROW_NUMBER() OVER w AS version,
+                   COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'settled' AND e.direction = 'debit') OVER w, 0) AS settled_dr,
+                   COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'settled' AND e.direction = 'credit') OVER w, 0) AS settled_cr,
+                   COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'pending' AND e.direction = 'debit') OVER w, 0) AS pending_dr,
+                   COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'pending' AND e.direction = 'credit') OVER w, 0) AS pending_cr,
+                   COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'encumbered' AND e.direction = 'debit') OVER w, 0) AS encumbered_dr,
+                   COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'encumbered' AND e.direction = 'credit') OVER w, 0) AS encumbered_cr,
+                   COUNT(*) FILTER (WHERE e.layer = 'settled') OVER w AS settled_n,
+                   COUNT(*) FILTER (WHERE e.layer = 'pending') OVER w AS pending_n,
+                   COUNT(*) FILTER (WHERE e.layer = 'encumbered') OVER w AS encumbered_n
+                 FROM sqlx_ledger_entries e
+                 JOIN UNNEST($1::uuid[], $2::uuid[], $3::varchar[]) AS k(journal_id, account_id, currency)
+                   ON e.journal_id = k.journal_id AND e.account_id = k.account_id AND e.currency = k.currency
+                 WINDOW w AS (PARTITION BY e.journal_id, e.account_id, e.currency
+                   ORDER BY e.created_at, e.transaction_id, e.sequence ROWS UNBOUNDED PRECEDING)
+               )
+               INSERT INTO sqlx_ledger_balances
+                 (journal_id, account_id, entry_id, currency,
+                  settled_dr_balance, settled_cr_balance, settled_entry_id, settled_modified_at,
+                  pending_dr_balance, pending_cr_balance, pending_entry_id, pending_modified_at,
+                  encumbered_dr_balance, encumbered_cr_balance, encumbered_entry_id, encumbered_modified_at,
+                  version, modified_at, created_at)
+               SELECT journal_id, account_id, id, currency,
+                 settled_dr, settled_cr,
+                 FIRST_VALUE(id) OVER settled, FIRST_VALUE(created_at) OVER settled,
+                 pending_dr, pending_cr,
+                 FIRST_VALUE(id) OVER pending, FIRST_VALUE(created_at) OVER pending,
+                 encumbered_dr, encumbered_cr,
+                 FIRST_VALUE(id) OVER encumbered, FIRST_VALUE(created_at) OVER encumbered,
+                 version::INT, created_at,
+                 FIRST_VALUE(created_at) OVER (PARTITION BY journal_id, account_id, currency ORDER BY version)
+               FROM running
+               WINDOW settled AS (PARTITION BY journal_id, account_id, currency, settled_n ORDER BY version),
+                 pending AS (PARTITION BY journal_id, account_id, currency, pending_n ORDER BY version),
+                 encumbered AS (PARTITION BY journal_id, account_id, currency, encumbered_n ORDER BY version)"#,
+            &journal_ids[..],
+            &account_ids[..],
+            &currencies[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_current_balances (journal_id, account_id, currency, version)
+               SELECT b.journal_id, b.account_id, b.currency, MAX(b.version) FROM sqlx_ledger_balances b
+               JOIN UNNEST($1::uuid[], $2::uuid[], $3::varchar[]) AS k(journal_id, account_id, currency)
+                 ON b.journal_id = k.journal_id AND b.account_id = k.account_id AND b.currency = k.currency
+               GROUP BY b.journal_id, b.account_id, b.currency"#,
+            &journal_ids[..],
+            &account_ids[..],
+            &currencies[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Current balances of the given accounts as seen from within `tx`.
+    pub(crate) async fn find_all_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        journal_id: JournalId,
+        ids: Vec<(AccountId, &Currency)>,
+    ) -> Result<Vec<AccountBalance>, SqlxLedgerError> {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"SELECT
+              a.normal_balance_type, b.journal_id, b.account_id, entry_id, b.currency,
+              settled_dr_balance, settled_cr_balance, settled_entry_id, settled_modified_at,
+              pending_dr_balance, pending_cr_balance, pending_entry_id, pending_modified_at,
+              encumbered_dr_balance, encumbered_cr_balance, encumbered_entry_id, encumbered_modified_at,
+              c.version, modified_at, created_at
+                FROM sqlx_ledger_balances b JOIN (
+                    SELECT * FROM sqlx_ledger_current_balances WHERE journal_id = "#,
+        );
+        query_builder.push_bind(Uuid::from(journal_id));
+        query_builder.push(r#" AND (account_id, currency) IN"#);
+        query_builder.push_tuples(ids, |mut builder, (id, currency)| {
+            builder.push_bind(Uuid::from(id));
+            builder.push_bind(currency.code());
+        });
+        query_builder.push(
+            r#") c ON
+                b.journal_id = c.journal_id AND b.account_id = c.account_id AND b.currency = c.currency AND b.version = c.version
+                JOIN ( SELECT DISTINCT ON (id) id, normal_balance_type FROM sqlx_ledger_accounts ORDER BY id, version DESC ) a
+                  ON a.id = b.account_id
+                ORDER BY b.account_id, b.currency"#,
+        );
+
+        let records = query_builder.build().fetch_all(&mut *tx).await?;
+        let mut ret = Vec::new();
+        for r in records {
+            ret.push(AccountBalance {
+                balance_type: r.get("normal_balance_type"),
+                details: BalanceDetails {
+                    account_id: AccountId::from(r.get::<Uuid, _>("account_id")),
+                    journal_id: JournalId::from(r.get::<Uuid, _>("journal_id")),
+                    entry_id: EntryId::from(r.get::<Uuid, _>("entry_id")),
+                    currency: r.get::<&str, _>("currency").parse()?,
+                    settled_dr_balance: r.get("settled_dr_balance"),
+                    settled_cr_balance: r.get("settled_cr_balance"),
+                    settled_entry_id: EntryId::from(r.get::<Uuid, _>("settled_entry_id")),
+                    settled_modified_at: r.get("settled_modified_at"),
+                    pending_dr_balance: r.get("pending_dr_balance"),
+                    pending_cr_balance: r.get("pending_cr_balance"),
+                    pending_entry_id: EntryId::from(r.get::<Uuid, _>("pending_entry_id")),
+                    pending_modified_at: r.get("pending_modified_at"),
+                    encumbered_dr_balance: r.get("encumbered_dr_balance"),
+                    encumbered_cr_balance: r.get("encumbered_cr_balance"),
+                    encumbered_entry_id: EntryId::from(r.get::<Uuid, _>("encumbered_entry_id")),
+                    encumbered_modified_at: r.get("encumbered_modified_at"),
+                    version: r.get("version"),
+                    modified_at: r.get("modified_at"),
+                    created_at: r.get("created_at"),
+                },
+            });
+        }
+        Ok(ret)
+    }
+
+    #[instrument(
+        name = "sqlx_ledger.balances.update_balances",
+        skip_all,
+        fields(%journal_id, n = new_balances.len())
+    )]
     pub(crate) async fn update_balances<'a>(
         &self,
         journal_id: JournalId,
@@ -0,0 +1,56 @@
+/// Rows in the text format of `COPY ... FROM STDIN`.
+#[derive(Default)]
+pub(crate) struct CopyRows {
+    buf: String,
+    row_started: bool,
+}
+
+impl CopyRows {
+    pub(crate) fn push(&mut self, field: impl std::fmt::Display) -> &mut Self {
+        self.push_opt(Some(field))
+    }
+
+    pub(crate) fn push_opt(&mut self, field: Option<impl std::fmt::Display>) -> &mut Self {
+        if self.row_started {
+            self.buf.push('\t');
+        }
+        self.row_started = true;
+        match field {
+            Some(field) => {
+                for c in field.to_string().chars() {
+                    match c {
+                        '\\' => self.buf.push_str("\\\\"),
+                        '\t' => self.buf.push_str("\\t"),
+                        '\n' => self.buf.push_str("\\n"),
+                        '\r' => self.buf.push_str("\\r"),
+                        c => self.buf.push(c),
+                    }
+                }
+            }
+            None => self.buf.push_str("\\N"),
+        }
+        self
+    }
+
+    pub(crate) fn end_row(&mut self) {
+        self.buf.push('\n');
+        self.row_started = false;
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_fields() {
+        let mut rows = CopyRows::default();
+        rows.push("a\tb\\c").push_opt(None::<&str>).push(1);
+        rows.end_row();
+        assert_eq!(rows.into_bytes(), b"a\\tb\\\\c\t\\N\t1\n");
+    }
+}
@@ -0,0 +1,85 @@
+use sqlx::{Pool, Postgres};
+use tracing::instrument;
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{error::*, primitives::*};
+
+/// Custom units stored with the ledger. Registered codes parse like built-in ones
+/// in every process once `load` has run; posting a transaction loads them on first use.
+#[derive(Debug, Clone)]
+pub struct Currencies {
+    pool: Pool<Postgres>,
+    dry_run: bool,
+    loaded: Arc<AtomicBool>,
+}
+
+impl Currencies {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+            loaded: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+            loaded: Arc::clone(&self.loaded),
+        }
+    }
+
+    /// Registers `code` with `exponent` decimal places. Posting more precise units fails.
+    /// Registering an existing code again with the same exponent is a no-op.
+    #[instrument(name = "sqlx_ledger.currencies.register", skip(self))]
+    pub async fn register(&self, code: &str, exponent: u32) -> Result<Currency, SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        let existing = sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_currencies (code, exponent) VALUES ($1, $2)
+               ON CONFLICT (code) DO UPDATE SET code = EXCLUDED.code
+               RETURNING exponent"#,
+            code,
+            exponent as i32
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .exponent;
+        if existing != exponent as i32 {
+            return Err(SqlxLedgerError::CurrencyAlreadyRegistered(code.to_string()));
+        }
+        let currency = Currency::register_custom(code, exponent)?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(currency)
+    }
+
+    /// Makes every currency registered with the ledger parseable in this process.
+    #[instrument(name = "sqlx_ledger.currencies.load", skip(self))]
+    pub async fn load(&self) -> Result<Vec<Currency>, SqlxLedgerError> {
+        let records =
+            sqlx::query!(r#"SELECT code, exponent FROM sqlx_ledger_currencies ORDER BY code"#)
+                .fetch_all(&self.pool)
+                .await?;
+        let mut currencies = Vec::with_capacity(records.len());
+        for r in records {
+            currencies.push(Currency::register_custom(&r.code, r.exponent as u32)?);
+        }
+        self.loaded.store(true, Ordering::Release);
+        Ok(currencies)
+    }
+
+    pub(crate) async fn ensure_loaded(&self) -> Result<(), SqlxLedgerError> {
+        if !self.loaded.load(Ordering::Acquire) {
+            self.load().await?;
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,41 @@
+use chrono::{DateTime, Utc};
 use derive_builder::Builder;
 use rust_decimal::Decimal;
 
-use crate::primitives::*;
+use crate::{balance::AccountBalance, primitives::*};
 
-#[derive(Builder)]
+pub struct Entry {
+    pub id: EntryId,
+    pub version: u32,
+    pub transaction_id: TransactionId,
+    pub journal_id: JournalId,
+    pub account_id: AccountId,
+    pub entry_type: String,
+    pub layer: Layer,
+    pub units: Decimal,
+    pub currency: Currency,
+    pub direction: DebitOrCredit,
+    pub sequence: u32,
+    pub description: Option<String>,
+    pub conversion: Option<Conversion>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Records that the units of an entry were converted from an amount in another currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    pub from_currency: Currency,
+    pub from_units: Decimal,
+    pub rate: Decimal,
+}
+
+/// An entry together with the balance of its account, in the entry's currency, right after it was posted.
+pub struct AccountEntry {
+    pub entry: Entry,
+    pub balance: AccountBalance,
+}
+
+#[derive(Builder, Clone)]
 pub(crate) struct NewEntry {
     pub(super) account_id: AccountId,
     pub(super) entry_type: String,
@@ -13,6 +45,10 @@ pub(crate) struct NewEntry {
     pub(super) direction: DebitOrCredit,
     #[builder(setter(strip_option), default)]
     pub(super) description: Option<String>,
+    #[builder(setter(strip_option), default)]
+    pub(super) name: Option<String>,
+    #[builder(setter(strip_option), default)]
+    pub(super) conversion: Option<Conversion>,
 }
 
 impl NewEntry {
@@ -20,3 +56,55 @@ impl NewEntry {
         NewEntryBuilder::default()
     }
 }
+
+/// An entry of an `ImportedTransaction`.
+#[derive(Builder, Debug, Clone)]
+pub struct ImportedEntry {
+    #[builder(setter(into))]
+    pub(crate) entry_type: String,
+    #[builder(setter(into))]
+    pub(crate) account_id: AccountId,
+    #[builder(default = "Layer::Settled")]
+    pub(crate) layer: Layer,
+    pub(crate) direction: DebitOrCredit,
+    pub(crate) units: Decimal,
+    pub(crate) currency: Currency,
+    #[builder(setter(strip_option, into), default)]
+    pub(crate) description: Option<String>,
+}
+
+impl ImportedEntry {
+    pub fn builder() -> ImportedEntryBuilder {
+        ImportedEntryBuilder::default()
+    }
+}
+
+/// An entry as produced by evaluating a tx template, before it is posted.
+#[derive(Debug, Clone)]
+pub struct EvaluatedEntry {
+    pub name: Option<String>,
+    pub account_id: AccountId,
+    pub entry_type: String,
+    pub layer: Layer,
+    pub units: Decimal,
+    pub currency: Currency,
+    pub direction: DebitOrCredit,
+    pub description: Option<String>,
+    pub conversion: Option<Conversion>,
+}
+
+impl From<NewEntry> for EvaluatedEntry {
+    fn from(entry: NewEntry) -> Self {
+        Self {
+            name: entry.name,
+            account_id: entry.account_id,
+            entry_type: entry.entry_type,
+            layer: entry.layer,
+            units: entry.units,
+            currency: entry.currency,
+            direction: entry.direction,
+            description: entry.description,
+            conversion: entry.conversion,
+        }
+    }
+}
@@ -2,4 +2,7 @@ mod entity;
 mod repo;
 
 pub(crate) use entity::*;
+pub use entity::{
+    AccountEntry, Conversion, Entry, EvaluatedEntry, ImportedEntry, ImportedEntryBuilder,
+};
 pub use repo::*;
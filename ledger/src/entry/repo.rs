@@ -1,22 +1,30 @@
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use sqlx::{PgPool, Postgres, QueryBuilder, Row, Transaction};
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use tracing::instrument;
 use uuid::Uuid;
 
-use std::collections::HashMap;
-
 use super::entity::*;
-use crate::{error::*, primitives::*};
+use crate::{
+    balance::*, copy::CopyRows, error::*, pagination::*, primitives::*,
+    transaction::ImportedTransaction,
+};
+
+// Postgres accepts at most 65535 bind parameters per statement
+const ENTRIES_PER_STATEMENT: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub struct Entries {
-    _pool: PgPool,
+    pool: PgPool,
 }
 
 #[derive(Debug)]
 pub(crate) struct StagedEntry {
     pub(crate) account_id: AccountId,
     pub(crate) entry_id: EntryId,
+    pub(crate) journal_id: JournalId,
+    pub(crate) transaction_id: TransactionId,
+    pub(crate) entry_type: String,
     pub(crate) units: Decimal,
     pub(crate) currency: Currency,
     pub(crate) direction: DebitOrCredit,
@@ -26,80 +34,317 @@ pub(crate) struct StagedEntry {
 
 impl Entries {
     pub fn new(pool: &PgPool) -> Self {
-        Self {
-            _pool: pool.clone(),
+        Self { pool: pool.clone() }
+    }
+
+    #[instrument(name = "sqlx_ledger.entries.list_by_transaction_id", skip(self))]
+    pub async fn list_by_transaction_id(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<Vec<Entry>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT id, version, transaction_id, journal_id, account_id, entry_type, layer as "layer: Layer",
+              units, currency, direction as "direction: DebitOrCredit", sequence, description,
+              converted_from_currency, converted_from_units, conversion_rate, created_at
+            FROM sqlx_ledger_entries WHERE transaction_id = $1 ORDER BY sequence"#,
+            Uuid::from(transaction_id)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut entries = Vec::new();
+        for row in records {
+            entries.push(Entry {
+                id: EntryId::from(row.id),
+                version: row.version as u32,
+                transaction_id: TransactionId::from(row.transaction_id),
+                journal_id: JournalId::from(row.journal_id),
+                account_id: AccountId::from(row.account_id),
+                entry_type: row.entry_type,
+                layer: row.layer,
+                units: row.units,
+                currency: row.currency.parse()?,
+                direction: row.direction,
+                sequence: row.sequence as u32,
+                description: row.description,
+                conversion: conversion(
+                    row.converted_from_currency,
+                    row.converted_from_units,
+                    row.conversion_rate,
+                )?,
+                created_at: row.created_at,
+            });
         }
+        Ok(entries)
     }
 
-    pub(crate) async fn create_all<'a>(
+    /// Statement lines of an account in posting order.
+    #[instrument(name = "sqlx_ledger.entries.list_by_account_id", skip(self))]
+    pub async fn list_by_account_id(
         &self,
         journal_id: JournalId,
-        transaction_id: TransactionId,
-        entries: Vec<NewEntry>,
-        tx: &mut Transaction<'a, Postgres>,
-    ) -> Result<Vec<StagedEntry>, SqlxLedgerError> {
-        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-            r#"WITH new_entries as (
-                 INSERT INTO sqlx_ledger_entries
-                  (id, transaction_id, journal_id, entry_type, layer,
-                   units, currency, direction, description, sequence, account_id)"#,
-        );
-        let mut partial_ret = HashMap::new();
-        let mut sequence = 1;
-        query_builder.push_values(
-            entries,
-            |mut builder,
-             NewEntry {
-                 account_id,
-                 entry_type,
-                 layer,
-                 units,
-                 currency,
-                 direction,
-                 description,
-             }: NewEntry| {
-                builder.push("gen_random_uuid()");
-                builder.push_bind(Uuid::from(transaction_id));
-                builder.push_bind(Uuid::from(journal_id));
-                builder.push_bind(entry_type);
-                builder.push_bind(layer);
-                builder.push_bind(units);
-                builder.push_bind(currency.code());
-                builder.push_bind(direction);
-                builder.push_bind(description);
-                builder.push_bind(sequence);
-                builder.push("(SELECT id FROM sqlx_ledger_accounts WHERE id = ");
-                builder.push_bind_unseparated(Uuid::from(account_id));
-                builder.push_unseparated(")");
-                partial_ret.insert(sequence, (account_id, units, currency, layer, direction));
-                sequence += 1;
-            },
-        );
-        query_builder.push(
-            "RETURNING id, sequence, created_at ) SELECT * FROM new_entries ORDER BY sequence",
-        );
-        let query = query_builder.build();
-        let records = query.fetch_all(&mut *tx).await?;
+        account_id: AccountId,
+    ) -> Result<Vec<Entry>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT id, version, transaction_id, journal_id, account_id, entry_type, layer as "layer: Layer",
+              units, currency, direction as "direction: DebitOrCredit", sequence, description,
+              converted_from_currency, converted_from_units, conversion_rate, created_at
+            FROM sqlx_ledger_entries WHERE journal_id = $1 AND account_id = $2 ORDER BY created_at, transaction_id, sequence"#,
+            Uuid::from(journal_id),
+            Uuid::from(account_id)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut entries = Vec::new();
+        for row in records {
+            entries.push(Entry {
+                id: EntryId::from(row.id),
+                version: row.version as u32,
+                transaction_id: TransactionId::from(row.transaction_id),
+                journal_id: JournalId::from(row.journal_id),
+                account_id: AccountId::from(row.account_id),
+                entry_type: row.entry_type,
+                layer: row.layer,
+                units: row.units,
+                currency: row.currency.parse()?,
+                direction: row.direction,
+                sequence: row.sequence as u32,
+                description: row.description,
+                conversion: conversion(
+                    row.converted_from_currency,
+                    row.converted_from_units,
+                    row.conversion_rate,
+                )?,
+                created_at: row.created_at,
+            });
+        }
+        Ok(entries)
+    }
 
-        let mut ret = Vec::new();
-        sequence = 1;
-        for r in records {
-            let entry_id: Uuid = r.get("id");
-            let created_at = r.get("created_at");
-            let (account_id, units, currency, layer, direction) =
-                partial_ret.remove(&sequence).expect("sequence not found");
-            ret.push(StagedEntry {
-                entry_id: entry_id.into(),
-                account_id,
-                units,
-                currency,
-                layer,
-                direction,
-                created_at,
+    /// Activity of an account in one currency, in posting order, with the running balance after each entry.
+    /// The cursor is the balance version, i.e. the position of the entry on the account.
+    #[instrument(name = "sqlx_ledger.entries.list_for_account", skip(self))]
+    pub async fn list_for_account(
+        &self,
+        journal_id: JournalId,
+        account_id: AccountId,
+        currency: Currency,
+        args: PaginatedQueryArgs<i32>,
+    ) -> Result<PaginatedQueryRet<AccountEntry, i32>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT e.id, e.version as entry_version, e.transaction_id, e.entry_type, e.layer as "layer: Layer",
+              e.units, e.direction as "direction: DebitOrCredit", e.sequence, e.description,
+              e.converted_from_currency, e.converted_from_units, e.conversion_rate, e.created_at as entry_created_at,
+              a.normal_balance_type as "normal_balance_type: DebitOrCredit",
+              b.settled_dr_balance, b.settled_cr_balance, b.settled_entry_id, b.settled_modified_at,
+              b.pending_dr_balance, b.pending_cr_balance, b.pending_entry_id, b.pending_modified_at,
+              b.encumbered_dr_balance, b.encumbered_cr_balance, b.encumbered_entry_id, b.encumbered_modified_at,
+              b.version, b.modified_at, b.created_at
+            FROM sqlx_ledger_balances b
+            JOIN sqlx_ledger_entries e ON e.id = b.entry_id
+            JOIN ( SELECT id, normal_balance_type FROM sqlx_ledger_accounts WHERE id = $2 LIMIT 1 ) a
+              ON a.id = b.account_id
+            WHERE b.journal_id = $1 AND b.account_id = $2 AND b.currency = $3 AND b.version > $4
+            ORDER BY b.version
+            LIMIT $5"#,
+            Uuid::from(journal_id),
+            Uuid::from(account_id),
+            currency.code(),
+            args.after.unwrap_or(0),
+            (args.first + 1) as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let has_next_page = records.len() > args.first;
+        let mut entities = Vec::new();
+        for row in records.into_iter().take(args.first) {
+            let entry_id = EntryId::from(row.id);
+            entities.push(AccountEntry {
+                entry: Entry {
+                    id: entry_id,
+                    version: row.entry_version as u32,
+                    transaction_id: TransactionId::from(row.transaction_id),
+                    journal_id,
+                    account_id,
+                    entry_type: row.entry_type,
+                    layer: row.layer,
+                    units: row.units,
+                    currency,
+                    direction: row.direction,
+                    sequence: row.sequence as u32,
+                    description: row.description,
+                    conversion: conversion(
+                        row.converted_from_currency,
+                        row.converted_from_units,
+                        row.conversion_rate,
+                    )?,
+                    created_at: row.entry_created_at,
+                },
+                balance: AccountBalance {
+                    balance_type: row.normal_balance_type,
+                    details: BalanceDetails {
+                        journal_id,
+                        account_id,
+                        entry_id,
+                        currency,
+                        settled_dr_balance: row.settled_dr_balance,
+                        settled_cr_balance: row.settled_cr_balance,
+                        settled_entry_id: EntryId::from(row.settled_entry_id),
+                        settled_modified_at: row.settled_modified_at,
+                        pending_dr_balance: row.pending_dr_balance,
+                        pending_cr_balance: row.pending_cr_balance,
+                        pending_entry_id: EntryId::from(row.pending_entry_id),
+                        pending_modified_at: row.pending_modified_at,
+                        encumbered_dr_balance: row.encumbered_dr_balance,
+                        encumbered_cr_balance: row.encumbered_cr_balance,
+                        encumbered_entry_id: EntryId::from(row.encumbered_entry_id),
+                        encumbered_modified_at: row.encumbered_modified_at,
+                        version: row.version,
+                        modified_at: row.modified_at,
+                        created_at: row.created_at,
+                    },
+                },
             });
-            sequence += 1;
         }
+        let end_cursor = entities.last().map(|e| e.balance.details.version);
+        Ok(PaginatedQueryRet {
+            entities,
+            has_next_page,
+            end_cursor,
+        })
+    }
+
+    /// Inserts the entries of several transactions with a single statement.
+    /// Staged entries are returned in the order they were given.
+    /// Writes the entries of historical transactions with `COPY`, in the order they are given.
+    pub(crate) async fn copy_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        transactions: &[ImportedTransaction],
+    ) -> Result<(), SqlxLedgerError> {
+        let mut rows = CopyRows::default();
+        for t in transactions {
+            let created_at = t.created_at.to_rfc3339();
+            for (entry, sequence) in t.entries.iter().zip(1..) {
+                rows.push(EntryId::new())
+                    .push(t.id)
+                    .push(entry.account_id)
+                    .push(t.journal_id)
+                    .push(&entry.entry_type)
+                    .push(entry.layer.to_string().to_lowercase())
+                    .push(entry.units)
+                    .push(entry.currency.code())
+                    .push(entry.direction.to_string().to_lowercase())
+                    .push(sequence)
+                    .push_opt(entry.description.as_ref())
+                    .push(&created_at)
+                    .push(&created_at);
+                rows.end_row();
+            }
+        }
+        let mut copy = tx
+            .copy_in_raw(
+                r#"COPY sqlx_ledger_entries (id, transaction_id, account_id, journal_id, entry_type, layer, units, currency, direction, sequence, description, modified_at, created_at) FROM STDIN"#,
+            )
+            .await?;
+        copy.send(rows.into_bytes()).await?;
+        copy.finish().await?;
+        Ok(())
+    }
 
+    pub(crate) async fn create_all<'a>(
+        &self,
+        transactions: Vec<(JournalId, TransactionId, Vec<NewEntry>)>,
+        created_at: DateTime<Utc>,
+        tx: &mut Transaction<'a, Postgres>,
+    ) -> Result<Vec<StagedEntry>, SqlxLedgerError> {
+        let mut ret = Vec::new();
+        let mut entries: Vec<_> = transactions
+            .into_iter()
+            .flat_map(|(journal_id, transaction_id, entries)| {
+                entries
+                    .into_iter()
+                    .zip(1..)
+                    .map(move |(entry, sequence)| (journal_id, transaction_id, sequence, entry))
+            })
+            .collect();
+        while !entries.is_empty() {
+            let rest = entries.split_off(entries.len().min(ENTRIES_PER_STATEMENT));
+            let chunk = std::mem::replace(&mut entries, rest);
+            let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+                r#"INSERT INTO sqlx_ledger_entries
+                  (id, transaction_id, journal_id, entry_type, layer,
+                   units, currency, direction, description, converted_from_currency, converted_from_units,
+                   conversion_rate, sequence, modified_at, created_at, account_id)"#,
+            );
+            query_builder.push_values(
+                chunk,
+                |mut builder,
+                 (
+                    journal_id,
+                    transaction_id,
+                    sequence,
+                    NewEntry {
+                        account_id,
+                        entry_type,
+                        layer,
+                        units,
+                        currency,
+                        direction,
+                        description,
+                        conversion,
+                        ..
+                    },
+                ): (JournalId, TransactionId, i32, NewEntry)| {
+                    let entry_id = EntryId::new();
+                    builder.push_bind(Uuid::from(entry_id));
+                    builder.push_bind(Uuid::from(transaction_id));
+                    builder.push_bind(Uuid::from(journal_id));
+                    builder.push_bind(entry_type.clone());
+                    builder.push_bind(layer);
+                    builder.push_bind(units);
+                    builder.push_bind(currency.code());
+                    builder.push_bind(direction);
+                    builder.push_bind(description);
+                    builder.push_bind(conversion.map(|c| c.from_currency.code()));
+                    builder.push_bind(conversion.map(|c| c.from_units));
+                    builder.push_bind(conversion.map(|c| c.rate));
+                    builder.push_bind(sequence);
+                    builder.push_bind(created_at);
+                    builder.push_bind(created_at);
+                    builder.push("(SELECT id FROM sqlx_ledger_accounts WHERE id = ");
+                    builder.push_bind_unseparated(Uuid::from(account_id));
+                    builder.push_unseparated(" LIMIT 1)");
+                    ret.push(StagedEntry {
+                        entry_id,
+                        journal_id,
+                        transaction_id,
+                        entry_type,
+                        account_id,
+                        units,
+                        currency,
+                        layer,
+                        direction,
+                        created_at,
+                    });
+                },
+            );
+            query_builder.build().execute(&mut *tx).await?;
+        }
         Ok(ret)
     }
 }
+
+fn conversion(
+    from_currency: Option<String>,
+    from_units: Option<Decimal>,
+    rate: Option<Decimal>,
+) -> Result<Option<Conversion>, SqlxLedgerError> {
+    match (from_currency, from_units, rate) {
+        (Some(from_currency), Some(from_units), Some(rate)) => Ok(Some(Conversion {
+            from_currency: from_currency.parse()?,
+            from_units,
+            rate,
+        })),
+        _ => Ok(None),
+    }
+}
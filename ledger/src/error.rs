@@ -1,10 +1,15 @@
+use chrono::NaiveDate;
 use rust_decimal::Decimal;
 use sqlx::error::DatabaseError;
 use thiserror::Error;
 
 use cel_interpreter::CelError;
 
-use crate::{primitives::*, tx_template::ParamDataType};
+use crate::{
+    primitives::*,
+    tx_template::{ParamDataType, TxTemplateTypeError},
+    velocity::VelocityLimitExceeded,
+};
 
 #[derive(Error, Debug)]
 pub enum SqlxLedgerError {
@@ -18,6 +23,8 @@ pub enum SqlxLedgerError {
     CelError(#[from] CelError),
     #[error("SqlxLedgerError - TxParamTypeMismatch: expected {0:?}")]
     TxParamTypeMismatch(ParamDataType),
+    #[error("SqlxLedgerError - TxTemplateTypeErrors: {}", display_all(.0))]
+    TxTemplateTypeErrors(Vec<TxTemplateTypeError>),
     #[error("SqlxLedgerError - TooManyParameters")]
     TooManyParameters,
     #[error("SqlxLedgerError - UnknownLayer: {0:?}")]
@@ -26,10 +33,90 @@ pub enum SqlxLedgerError {
     UnknownDebitOrCredit(String),
     #[error("SqlxLedgerError - UnknownCurrency: {0}")]
     UnknownCurrency(String),
+    #[error("SqlxLedgerError - InvalidExchangeRate: {0}/{1} at {2}")]
+    InvalidExchangeRate(Currency, Currency, Decimal),
+    #[error("SqlxLedgerError - ExchangeRateNotFound: {0}/{1}")]
+    ExchangeRateNotFound(Currency, Currency),
+    #[error("SqlxLedgerError - CurrencyAlreadyRegistered: {0}")]
+    CurrencyAlreadyRegistered(String),
+    #[error("SqlxLedgerError - InvalidUnitsPrecision: {1} exceeds the precision of {0}")]
+    InvalidUnitsPrecision(Currency, Decimal),
     #[error("SqlxLedgerError - UnbalancedTransaction: currency {0} amount {1}")]
     UnbalancedTransaction(Currency, Decimal),
+    #[error("SqlxLedgerError - UnbalancedEntryGroup: group {0} currency {1} amount {2}")]
+    UnbalancedEntryGroup(String, Currency, Decimal),
+    #[error("SqlxLedgerError - JournalNotFound: {0}")]
+    JournalNotFound(JournalId),
     #[error("SqlxLedgerError - OptimisticLockingError")]
     OptimisticLockingError,
+    #[error("SqlxLedgerError - BalanceVersionNotFound: {0}")]
+    BalanceVersionNotFound(i32),
+    #[error("SqlxLedgerError - PoolTimeout")]
+    PoolTimeout,
+    #[error("SqlxLedgerError - DirectionNotAllowed: account {0} does not allow {1:?} entries")]
+    DirectionNotAllowed(AccountId, DebitOrCredit),
+    #[error("SqlxLedgerError - OpeningBalanceEquityAccountNotConfigured")]
+    OpeningBalanceEquityAccountNotConfigured,
+    #[error("SqlxLedgerError - OpeningBalanceRequiresLedger: create accounts with an opening balance through SqlxLedger::create_account")]
+    OpeningBalanceRequiresLedger,
+    #[error("SqlxLedgerError - InvalidVelocityWindow: rolling window of {0} seconds")]
+    InvalidVelocityWindow(i64),
+    #[error("SqlxLedgerError - VelocityLimitExceeded: {0}")]
+    VelocityLimitExceeded(VelocityLimitExceeded),
+    #[error("SqlxLedgerError - TransactionNotFound: {0}")]
+    TransactionNotFound(TransactionId),
+    #[error("SqlxLedgerError - TransactionAlreadyVoided: {0}")]
+    TransactionAlreadyVoided(TransactionId),
+    #[error(
+        "SqlxLedgerError - TransactionNotVoidable: {0} effective {1} is outside the void policy"
+    )]
+    TransactionNotVoidable(TransactionId, NaiveDate),
+    #[error("SqlxLedgerError - AccountSetCycle: {1} already contains {0}")]
+    AccountSetCycle(AccountSetId, AccountSetId),
+    #[error(
+        "SqlxLedgerError - AccountSetJournalMismatch: {0} and {1} belong to different journals"
+    )]
+    AccountSetJournalMismatch(AccountSetId, AccountSetId),
+    #[error("SqlxLedgerError - InsufficientBalance: account {0} {1} {2:?} balance would be {3}")]
+    InsufficientBalance(AccountId, Currency, Layer, Decimal),
+    #[error("SqlxLedgerError - PeriodClosed: journal {0} is closed for {1}")]
+    PeriodClosed(JournalId, NaiveDate),
+    #[error("SqlxLedgerError - PeriodAlreadyClosed: journal {0} period starting {1}")]
+    PeriodAlreadyClosed(JournalId, NaiveDate),
+    #[error("SqlxLedgerError - TxTemplateNotFound: {0}")]
+    TxTemplateNotFound(String),
+    #[error("SqlxLedgerError - TxTemplateVersionNotFound: {0} version {1}")]
+    TxTemplateVersionNotFound(String, u32),
+    #[error("SqlxLedgerError - AccountNotFound: {0}")]
+    AccountNotFound(AccountId),
+    #[error("SqlxLedgerError - EmptyTransaction: {0} has no entries")]
+    EmptyTransaction(TransactionId),
+    #[error("SqlxLedgerError - NoEntriesEmitted: every entry condition of {0} was false")]
+    NoEntriesEmitted(String),
+    #[error("SqlxLedgerError - Csv: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("SqlxLedgerError - InvalidCsvRecord: line {0}: {1}")]
+    InvalidCsvRecord(u64, String),
+    #[error("SqlxLedgerError - Io: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "parquet")]
+    #[error("SqlxLedgerError - Arrow: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[cfg(feature = "parquet")]
+    #[error("SqlxLedgerError - Parquet: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+impl SqlxLedgerError {
+    /// Serialization failures and deadlocks, after which the whole transaction can be retried.
+    pub(crate) fn is_retryable(&self) -> bool {
+        match self {
+            SqlxLedgerError::UnknwownSqlx(sqlx::Error::Database(e)) => {
+                matches!(e.code().as_deref(), Some("40001") | Some("40P01"))
+            }
+            _ => false,
+        }
+    }
 }
 
 impl From<sqlx::Error> for SqlxLedgerError {
@@ -38,7 +125,16 @@ impl From<sqlx::Error> for SqlxLedgerError {
             sqlx::Error::Database(err) if err.message().contains("duplicate key") => {
                 SqlxLedgerError::DuplicateKey(err)
             }
+            sqlx::Error::PoolTimedOut => SqlxLedgerError::PoolTimeout,
             e => SqlxLedgerError::UnknwownSqlx(e),
         }
     }
 }
+
+fn display_all(errors: &[TxTemplateTypeError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
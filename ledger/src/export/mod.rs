@@ -0,0 +1,5 @@
+#[cfg(feature = "parquet")]
+mod parquet;
+mod repo;
+
+pub use repo::*;
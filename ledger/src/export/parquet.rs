@@ -0,0 +1,336 @@
+use arrow_array::{
+    builder::{
+        ArrayBuilder, Date32Builder, Decimal128Builder, Int32Builder, StringBuilder,
+        TimestampMicrosecondBuilder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::TryStreamExt;
+use parquet::arrow::ArrowWriter;
+use rust_decimal::Decimal;
+use sqlx::{postgres::PgRow, Row};
+use tracing::instrument;
+use uuid::Uuid;
+
+use std::{io::Write, sync::Arc};
+
+use super::repo::*;
+use crate::{error::*, primitives::*};
+
+const ROWS_PER_BATCH: usize = 8192;
+// Amounts are written as Decimal128(38, 18)
+const PARQUET_UNITS_SCALE: i8 = 18;
+
+#[derive(Clone, Copy)]
+enum Column {
+    Utf8,
+    Date,
+    Timestamp,
+    Int,
+    Units,
+}
+
+enum Value {
+    Utf8(Option<String>),
+    Date(NaiveDate),
+    Timestamp(DateTime<Utc>),
+    Int(i32),
+    Units(Decimal),
+}
+
+const TRANSACTION_COLUMNS: &[(&str, Column, bool)] = &[
+    ("transaction_id", Column::Utf8, false),
+    ("journal_id", Column::Utf8, false),
+    ("tx_template_id", Column::Utf8, false),
+    ("tx_template_version", Column::Int, false),
+    ("correlation_id", Column::Utf8, false),
+    ("effective", Column::Date, false),
+    ("external_id", Column::Utf8, false),
+    ("description", Column::Utf8, true),
+    ("metadata", Column::Utf8, true),
+    ("actor_id", Column::Utf8, true),
+    ("voids", Column::Utf8, true),
+    ("voided_by", Column::Utf8, true),
+    ("created_at", Column::Timestamp, false),
+];
+
+const ENTRY_COLUMNS: &[(&str, Column, bool)] = &[
+    ("transaction_id", Column::Utf8, false),
+    ("journal_id", Column::Utf8, false),
+    ("effective", Column::Date, false),
+    ("created_at", Column::Timestamp, false),
+    ("entry_id", Column::Utf8, false),
+    ("sequence", Column::Int, false),
+    ("entry_type", Column::Utf8, false),
+    ("account_id", Column::Utf8, false),
+    ("layer", Column::Utf8, false),
+    ("direction", Column::Utf8, false),
+    ("units", Column::Units, false),
+    ("currency", Column::Utf8, false),
+    ("description", Column::Utf8, true),
+];
+
+const BALANCE_COLUMNS: &[(&str, Column, bool)] = &[
+    ("journal_id", Column::Utf8, false),
+    ("account_id", Column::Utf8, false),
+    ("currency", Column::Utf8, false),
+    ("version", Column::Int, false),
+    ("entry_id", Column::Utf8, false),
+    ("settled_dr_balance", Column::Units, false),
+    ("settled_cr_balance", Column::Units, false),
+    ("pending_dr_balance", Column::Units, false),
+    ("pending_cr_balance", Column::Units, false),
+    ("encumbered_dr_balance", Column::Units, false),
+    ("encumbered_cr_balance", Column::Units, false),
+    ("modified_at", Column::Timestamp, false),
+];
+
+impl Exports {
+    /// Same rows as `transactions_csv`, as a Parquet file.
+    #[instrument(name = "sqlx_ledger.exports.transactions_parquet", skip(self, writer))]
+    pub async fn transactions_parquet<W: Write + Send>(
+        &self,
+        journal_id: JournalId,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        writer: W,
+    ) -> Result<(), SqlxLedgerError> {
+        let rows = sqlx::query(
+            r#"SELECT * FROM (
+                 SELECT DISTINCT ON (id) * FROM sqlx_ledger_transactions
+                 WHERE journal_id = $1 AND created_at >= $2 AND created_at < $3
+                 ORDER BY id, version DESC
+               ) t ORDER BY created_at, id"#,
+        )
+        .bind(Uuid::from(journal_id))
+        .bind(from)
+        .bind(until)
+        .fetch(&self.pool);
+        write_parquet(TRANSACTION_COLUMNS, rows, writer, |r| {
+            vec![
+                uuid(r, "id"),
+                uuid(r, "journal_id"),
+                uuid(r, "tx_template_id"),
+                Value::Int(r.get("tx_template_version")),
+                uuid(r, "correlation_id"),
+                Value::Date(r.get("effective")),
+                Value::Utf8(r.get("external_id")),
+                Value::Utf8(r.get("description")),
+                Value::Utf8(
+                    r.get::<Option<serde_json::Value>, _>("metadata")
+                        .map(|m| m.to_string()),
+                ),
+                Value::Utf8(r.get("actor_id")),
+                uuid(r, "voids"),
+                uuid(r, "voided_by"),
+                Value::Timestamp(r.get("created_at")),
+            ]
+        })
+        .await
+    }
+
+    /// Same rows as `entries_csv`, without the fields of their transaction, as a Parquet file.
+    /// Fails on units with more than 18 decimal places rather than rounding them.
+    #[instrument(name = "sqlx_ledger.exports.entries_parquet", skip(self, writer))]
+    pub async fn entries_parquet<W: Write + Send>(
+        &self,
+        journal_id: JournalId,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        writer: W,
+    ) -> Result<(), SqlxLedgerError> {
+        let rows = sqlx::query(
+            r#"SELECT e.*, t.effective FROM sqlx_ledger_entries e
+               JOIN sqlx_ledger_transactions t ON t.id = e.transaction_id AND t.version = 1
+               WHERE e.journal_id = $1 AND e.created_at >= $2 AND e.created_at < $3
+               ORDER BY e.created_at, e.transaction_id, e.sequence"#,
+        )
+        .bind(Uuid::from(journal_id))
+        .bind(from)
+        .bind(until)
+        .fetch(&self.pool);
+        write_parquet(ENTRY_COLUMNS, rows, writer, |r| {
+            vec![
+                uuid(r, "transaction_id"),
+                uuid(r, "journal_id"),
+                Value::Date(r.get("effective")),
+                Value::Timestamp(r.get("created_at")),
+                uuid(r, "id"),
+                Value::Int(r.get("sequence")),
+                Value::Utf8(r.get("entry_type")),
+                uuid(r, "account_id"),
+                Value::Utf8(Some(r.get::<Layer, _>("layer").to_string())),
+                Value::Utf8(Some(r.get::<DebitOrCredit, _>("direction").to_string())),
+                Value::Units(r.get("units")),
+                Value::Utf8(r.get("currency")),
+                Value::Utf8(r.get("description")),
+            ]
+        })
+        .await
+    }
+
+    /// Same rows as `balances_csv`, as a Parquet file.
+    /// Fails on amounts with more than 18 decimal places rather than rounding them.
+    #[instrument(name = "sqlx_ledger.exports.balances_parquet", skip(self, writer))]
+    pub async fn balances_parquet<W: Write + Send>(
+        &self,
+        journal_id: JournalId,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        writer: W,
+    ) -> Result<(), SqlxLedgerError> {
+        let rows = sqlx::query(
+            r#"SELECT * FROM sqlx_ledger_balances
+               WHERE journal_id = $1 AND modified_at >= $2 AND modified_at < $3
+               ORDER BY account_id, currency, version"#,
+        )
+        .bind(Uuid::from(journal_id))
+        .bind(from)
+        .bind(until)
+        .fetch(&self.pool);
+        write_parquet(BALANCE_COLUMNS, rows, writer, |r| {
+            vec![
+                uuid(r, "journal_id"),
+                uuid(r, "account_id"),
+                Value::Utf8(r.get("currency")),
+                Value::Int(r.get("version")),
+                uuid(r, "entry_id"),
+                Value::Units(r.get("settled_dr_balance")),
+                Value::Units(r.get("settled_cr_balance")),
+                Value::Units(r.get("pending_dr_balance")),
+                Value::Units(r.get("pending_cr_balance")),
+                Value::Units(r.get("encumbered_dr_balance")),
+                Value::Units(r.get("encumbered_cr_balance")),
+                Value::Timestamp(r.get("modified_at")),
+            ]
+        })
+        .await
+    }
+}
+
+async fn write_parquet<W: Write + Send>(
+    columns: &[(&str, Column, bool)],
+    mut rows: impl futures::Stream<Item = Result<PgRow, sqlx::Error>> + Unpin,
+    writer: W,
+    to_values: impl Fn(&PgRow) -> Vec<Value>,
+) -> Result<(), SqlxLedgerError> {
+    let schema: SchemaRef = Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|(name, column, nullable)| Field::new(*name, column.data_type(), *nullable))
+            .collect::<Vec<_>>(),
+    ));
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+    let mut builders: Vec<_> = columns
+        .iter()
+        .map(|(_, column, _)| column.builder())
+        .collect();
+    let mut buffered = 0;
+    while let Some(row) = rows.try_next().await? {
+        for (builder, value) in builders.iter_mut().zip(to_values(&row)) {
+            append(builder.as_mut(), value)?;
+        }
+        buffered += 1;
+        if buffered == ROWS_PER_BATCH {
+            writer.write(&finish_batch(&schema, &mut builders)?)?;
+            buffered = 0;
+        }
+    }
+    if buffered > 0 {
+        writer.write(&finish_batch(&schema, &mut builders)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+fn finish_batch(
+    schema: &SchemaRef,
+    builders: &mut [Box<dyn ArrayBuilder>],
+) -> Result<RecordBatch, ArrowError> {
+    let arrays: Vec<ArrayRef> = builders.iter_mut().map(|b| b.finish()).collect();
+    RecordBatch::try_new(schema.clone(), arrays)
+}
+
+impl Column {
+    fn data_type(&self) -> DataType {
+        match self {
+            Column::Utf8 => DataType::Utf8,
+            Column::Date => DataType::Date32,
+            Column::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            Column::Int => DataType::Int32,
+            Column::Units => DataType::Decimal128(38, PARQUET_UNITS_SCALE),
+        }
+    }
+
+    fn builder(&self) -> Box<dyn ArrayBuilder> {
+        match self {
+            Column::Utf8 => Box::new(StringBuilder::new()),
+            Column::Date => Box::new(Date32Builder::new()),
+            Column::Timestamp => Box::new(TimestampMicrosecondBuilder::new().with_timezone("UTC")),
+            Column::Int => Box::new(Int32Builder::new()),
+            Column::Units => Box::new(
+                Decimal128Builder::new()
+                    .with_precision_and_scale(38, PARQUET_UNITS_SCALE)
+                    .expect("valid decimal precision"),
+            ),
+        }
+    }
+}
+
+fn append(builder: &mut dyn ArrayBuilder, value: Value) -> Result<(), ArrowError> {
+    let any = builder.as_any_mut();
+    match value {
+        Value::Utf8(v) => downcast::<StringBuilder>(any).append_option(v),
+        Value::Date(v) => downcast::<Date32Builder>(any).append_value(
+            (v - NaiveDate::from_ymd_opt(1970, 1, 1).expect("epoch")).num_days() as i32,
+        ),
+        Value::Timestamp(v) => {
+            downcast::<TimestampMicrosecondBuilder>(any).append_value(v.timestamp_micros())
+        }
+        Value::Int(v) => downcast::<Int32Builder>(any).append_value(v),
+        Value::Units(v) => downcast::<Decimal128Builder>(any).append_value(units(v)?),
+    }
+    Ok(())
+}
+
+fn downcast<T: 'static>(any: &mut dyn std::any::Any) -> &mut T {
+    any.downcast_mut().expect("builder matches its column")
+}
+
+fn units(units: Decimal) -> Result<i128, ArrowError> {
+    let normalized = units.normalize();
+    (PARQUET_UNITS_SCALE as u32)
+        .checked_sub(normalized.scale())
+        .and_then(|exp| 10i128.checked_pow(exp))
+        .and_then(|factor| normalized.mantissa().checked_mul(factor))
+        .ok_or_else(|| {
+            ArrowError::ComputeError(format!(
+                "{units} does not fit Decimal128(38, {PARQUET_UNITS_SCALE})"
+            ))
+        })
+}
+
+fn uuid(row: &PgRow, column: &str) -> Value {
+    Value::Utf8(row.get::<Option<Uuid>, _>(column).map(|id| id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_are_scaled() {
+        assert_eq!(
+            units(Decimal::new(15, 1)).unwrap(),
+            1_500_000_000_000_000_000
+        );
+        assert_eq!(
+            units(Decimal::new(15_000, 4)).unwrap(),
+            units(Decimal::new(15, 1)).unwrap()
+        );
+        assert!(units(Decimal::MAX).is_err());
+        assert!(units(Decimal::new(1, 19)).is_err());
+    }
+}
@@ -3,11 +3,12 @@ use derive_builder::Builder;
 
 use crate::primitives::*;
 
-pub struct Journal {
-    pub id: AccountId,
+pub struct Journal<M> {
+    pub id: JournalId,
     pub name: String,
     pub description: Option<String>,
     pub status: Status,
+    pub metadata: Option<M>,
     pub version: u32,
     pub modified_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
@@ -23,6 +24,8 @@ pub struct NewJournal {
     pub(super) description: Option<String>,
     #[builder(default)]
     pub(super) status: Status,
+    #[builder(setter(custom), default)]
+    pub(super) metadata: Option<serde_json::Value>,
 }
 
 impl NewJournal {
@@ -33,6 +36,16 @@ impl NewJournal {
     }
 }
 
+impl NewJournalBuilder {
+    pub fn metadata<T: serde::Serialize>(
+        &mut self,
+        metadata: T,
+    ) -> Result<&mut Self, serde_json::Error> {
+        self.metadata = Some(Some(serde_json::to_value(metadata)?));
+        Ok(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,6 +56,7 @@ mod tests {
         assert_eq!(new_journal.name, "name");
         assert_eq!(new_journal.description, None);
         assert_eq!(new_journal.status, Status::Active);
+        assert_eq!(new_journal.metadata, None);
     }
 
     #[test]
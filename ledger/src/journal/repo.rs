@@ -1,24 +1,46 @@
+use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{Pool, Postgres, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 
 use super::entity::*;
-use crate::{error::*, primitives::*};
+use crate::{error::*, pagination::*, primitives::*};
+
+/// Namespace (first key) of the advisory locks on journals. The second key is `hashtext(journal_id::text)`.
+/// Postings hold the lock shared. [`Journals::lock_in_tx`], closing a period, imports
+/// and partition maintenance take it exclusively.
+/// Applications taking their own two-key advisory locks should avoid this namespace.
+pub const JOURNAL_ADVISORY_LOCK_NAMESPACE: i32 = 0x5351_4c4c;
 
 #[derive(Debug, Clone)]
 pub struct Journals {
     pool: Pool<Postgres>,
+    dry_run: bool,
 }
 
 impl Journals {
     pub fn new(pool: &Pool<Postgres>) -> Self {
-        Self { pool: pool.clone() }
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+        }
     }
 
     pub async fn create(&self, new_journal: NewJournal) -> Result<JournalId, SqlxLedgerError> {
         let mut tx = self.pool.begin().await?;
         let res = self.create_in_tx(&mut tx, new_journal).await?;
-        tx.commit().await?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
         Ok(res)
     }
 
@@ -33,18 +55,176 @@ impl Journals {
             name,
             description,
             status,
+            metadata,
         } = new_journal;
         let record = sqlx::query!(
-            r#"INSERT INTO sqlx_ledger_journals (id, name, description, status)
-            VALUES ($1, $2, $3, $4)
+            r#"INSERT INTO sqlx_ledger_journals (id, name, description, status, metadata)
+            VALUES ($1, $2, $3, $4, $5)
             RETURNING id, version, created_at"#,
             Uuid::from(id),
             name,
             description,
             status as Status,
+            metadata
         )
         .fetch_one(&mut *tx)
         .await?;
         Ok(JournalId::from(record.id))
     }
+
+    /// Journals ordered by id, which keeps the ordering stable while journals are being added.
+    #[instrument(name = "sqlx_ledger.journals.list", skip(self))]
+    pub async fn list<M: DeserializeOwned>(
+        &self,
+        args: PaginatedQueryArgs<JournalId>,
+    ) -> Result<PaginatedQueryRet<Journal<M>, JournalId>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT DISTINCT ON (id) id, version, name, description, status as "status: Status", metadata, modified_at, created_at
+            FROM sqlx_ledger_journals
+            WHERE ($1::uuid IS NULL OR id > $1)
+            ORDER BY id, version DESC
+            LIMIT $2"#,
+            args.after.map(Uuid::from) as Option<Uuid>,
+            (args.first + 1) as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let has_next_page = records.len() > args.first;
+        let mut entities = Vec::new();
+        for row in records.into_iter().take(args.first) {
+            entities.push(Journal {
+                id: JournalId::from(row.id),
+                name: row.name,
+                description: row.description,
+                status: row.status,
+                metadata: row.metadata.map(serde_json::from_value).transpose()?,
+                version: row.version as u32,
+                modified_at: row.modified_at,
+                created_at: row.created_at,
+            });
+        }
+        let end_cursor = entities.last().map(|journal| journal.id);
+        Ok(PaginatedQueryRet {
+            entities,
+            has_next_page,
+            end_cursor,
+        })
+    }
+
+    /// Journals whose metadata contains `filter` (JSONB `@>`), ordered by id.
+    #[instrument(name = "sqlx_ledger.journals.find_by_metadata", skip(self, filter))]
+    pub async fn find_by_metadata<M: DeserializeOwned>(
+        &self,
+        filter: impl Serialize,
+    ) -> Result<Vec<Journal<M>>, SqlxLedgerError> {
+        let filter = serde_json::to_value(filter)?;
+        let records = sqlx::query!(
+            r#"SELECT id, version, name, description, status as "status: Status", metadata, modified_at, created_at
+            FROM (
+              SELECT DISTINCT ON (id) * FROM sqlx_ledger_journals ORDER BY id, version DESC
+            ) j
+            WHERE metadata @> $1
+            ORDER BY id"#,
+            filter
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut journals = Vec::new();
+        for row in records {
+            journals.push(Journal {
+                id: JournalId::from(row.id),
+                name: row.name,
+                description: row.description,
+                status: row.status,
+                metadata: row.metadata.map(serde_json::from_value).transpose()?,
+                version: row.version as u32,
+                modified_at: row.modified_at,
+                created_at: row.created_at,
+            });
+        }
+        Ok(journals)
+    }
+
+    /// Serializes critical sections on a journal across processes.
+    /// Blocks until no other transaction holds the lock, which is released when `tx` ends.
+    #[instrument(name = "sqlx_ledger.journals.lock_in_tx", skip(self, tx))]
+    pub async fn lock_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        journal_id: JournalId,
+    ) -> Result<(), SqlxLedgerError> {
+        lock_journals_in_tx(tx, vec![journal_id]).await
+    }
+}
+
+/// Waits for the postings to the journals to finish and holds off new ones until `tx` ends.
+pub(crate) async fn lock_journals_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    journal_ids: Vec<JournalId>,
+) -> Result<(), SqlxLedgerError> {
+    lock_keys_in_tx(tx, journal_ids, "pg_advisory_xact_lock").await
+}
+
+/// Fails with `JournalNotFound` for the first of `journal_ids` that doesn't exist.
+pub(crate) async fn ensure_journals_exist(
+    executor: impl sqlx::PgExecutor<'_>,
+    journal_ids: Vec<JournalId>,
+) -> Result<(), SqlxLedgerError> {
+    let mut journal_ids: Vec<Uuid> = journal_ids.into_iter().map(Uuid::from).collect();
+    journal_ids.sort();
+    journal_ids.dedup();
+    let found: Vec<Uuid> = sqlx::query!(
+        r#"SELECT DISTINCT id FROM sqlx_ledger_journals WHERE id = ANY($1)"#,
+        &journal_ids[..]
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+    if let Some(missing) = journal_ids.into_iter().find(|id| !found.contains(id)) {
+        return Err(SqlxLedgerError::JournalNotFound(missing.into()));
+    }
+    Ok(())
+}
+
+/// Holds off exclusive locks on the journals until `tx` ends, as postings do.
+pub(crate) async fn lock_journals_shared_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    journal_ids: Vec<JournalId>,
+) -> Result<(), SqlxLedgerError> {
+    lock_keys_in_tx(tx, journal_ids, "pg_advisory_xact_lock_shared").await
+}
+
+/// Takes the lock of every journal exclusively.
+pub(crate) async fn lock_all_journals_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<(), SqlxLedgerError> {
+    sqlx::query(
+        r#"SELECT pg_advisory_xact_lock($1, hashtext(k))
+           FROM (SELECT id::text AS k FROM sqlx_ledger_journals ORDER BY k) AS j"#,
+    )
+    .bind(JOURNAL_ADVISORY_LOCK_NAMESPACE)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+// Keys are taken in order so that callers locking several journals don't deadlock
+async fn lock_keys_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    journal_ids: Vec<JournalId>,
+    lock: &str,
+) -> Result<(), SqlxLedgerError> {
+    let mut keys: Vec<String> = journal_ids.iter().map(|id| id.to_string()).collect();
+    keys.sort();
+    keys.dedup();
+    sqlx::query(&format!(
+        "SELECT {lock}($1, hashtext(k)) FROM unnest($2::text[]) AS k"
+    ))
+    .bind(JOURNAL_ADVISORY_LOCK_NAMESPACE)
+    .bind(&keys[..])
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
 }
@@ -0,0 +1,344 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use tracing::instrument;
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use super::SqlxLedger;
+use crate::{
+    entry::ImportedEntry,
+    error::*,
+    journal::{ensure_journals_exist, lock_journals_in_tx},
+    primitives::*,
+    transaction::ImportedTransaction,
+};
+
+// Transactions written per round of `COPY`
+const TRANSACTIONS_PER_COPY: usize = 10_000;
+
+/// What `SqlxLedger::import` wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub transactions: usize,
+    pub entries: usize,
+    /// Account and currency pairs whose balance history was rebuilt.
+    pub balances: usize,
+}
+
+impl SqlxLedger {
+    /// Bulk loads historical transactions with `COPY` in a single database transaction and
+    /// recomputes the balances of every account they touch once everything is written.
+    ///
+    /// Templates, velocity limits, direction restrictions and external id deduplication are
+    /// bypassed: a transaction only has to balance per currency and fall in an open period.
+    /// The balance history of the touched accounts is rebuilt in `created_at` order, so postings
+    /// to the imported journals wait for the import once its transactions are written.
+    #[instrument(name = "sqlx_ledger.ledger.import", skip_all)]
+    pub async fn import(
+        &self,
+        transactions: impl IntoIterator<Item = ImportedTransaction>,
+    ) -> Result<ImportSummary, SqlxLedgerError> {
+        self.import_all(transactions.into_iter().map(Ok)).await
+    }
+
+    /// Imports transactions from CSV with one record per entry. Consecutive records with the
+    /// same `transaction_id` form one transaction, whose fields are read from its first record.
+    ///
+    /// Required columns: `transaction_id`, `journal_id`, `tx_template_id`, `effective`,
+    /// `created_at`, `entry_type`, `account_id`, `direction`, `units` and `currency`.
+    /// Optional ones: `external_id`, `transaction_description`, `metadata`, `layer`
+    /// (`SETTLED` by default) and `description`. `reader` is read synchronously.
+    #[instrument(name = "sqlx_ledger.ledger.import_csv", skip_all)]
+    pub async fn import_csv(
+        &self,
+        reader: impl std::io::Read,
+    ) -> Result<ImportSummary, SqlxLedgerError> {
+        self.import_all(CsvTransactions::new(reader)?).await
+    }
+
+    async fn import_all(
+        &self,
+        transactions: impl Iterator<Item = Result<ImportedTransaction, SqlxLedgerError>>,
+    ) -> Result<ImportSummary, SqlxLedgerError> {
+        self.currencies.ensure_loaded().await?;
+        let mut tx = self.pool.begin().await?;
+        let mut summary = ImportSummary::default();
+        let mut effective = HashSet::new();
+        let mut amounts: HashMap<_, Decimal> = HashMap::new();
+        let mut modified_at: HashMap<JournalId, DateTime<Utc>> = HashMap::new();
+        let mut batch = Vec::with_capacity(TRANSACTIONS_PER_COPY);
+        let mut transactions = transactions.peekable();
+        while let Some(transaction) = transactions.next() {
+            let transaction = transaction?;
+            check_balanced(&transaction)?;
+            summary.transactions += 1;
+            summary.entries += transaction.entries.len();
+            effective.insert((transaction.journal_id, transaction.effective));
+            let latest = modified_at
+                .entry(transaction.journal_id)
+                .or_insert(transaction.created_at);
+            *latest = (*latest).max(transaction.created_at);
+            for entry in transaction.entries.iter() {
+                *amounts
+                    .entry((
+                        transaction.journal_id,
+                        entry.account_id,
+                        entry.currency,
+                        entry.layer,
+                        entry.direction,
+                    ))
+                    .or_insert(Decimal::ZERO) += entry.units;
+            }
+            batch.push(transaction);
+            if batch.len() == TRANSACTIONS_PER_COPY || transactions.peek().is_none() {
+                self.transactions.copy_in_tx(&mut tx, &batch).await?;
+                self.entries.copy_in_tx(&mut tx, &batch).await?;
+                batch.clear();
+            }
+        }
+        if summary.transactions == 0 {
+            return Ok(summary);
+        }
+        let journal_ids: Vec<_> = modified_at.keys().copied().collect();
+        ensure_journals_exist(&mut *tx, journal_ids.clone()).await?;
+        lock_journals_in_tx(&mut tx, journal_ids).await?;
+
+        let keys: HashSet<_> = amounts
+            .keys()
+            .map(|(journal_id, account_id, currency, ..)| (*journal_id, *account_id, *currency))
+            .collect();
+        summary.balances = keys.len();
+        let account_ids: HashSet<_> = keys.iter().map(|(_, account_id, _)| *account_id).collect();
+        self.accounts
+            .ensure_exist_in_tx(&mut tx, account_ids.into_iter().collect())
+            .await?;
+        self.periods
+            .ensure_open_in_tx(&mut tx, effective.into_iter().collect())
+            .await?;
+        self.balances
+            .rebuild_in_tx(&mut tx, keys.into_iter().collect())
+            .await?;
+        let mut amounts_by_journal: HashMap<JournalId, Vec<_>> = HashMap::new();
+        for ((journal_id, account_id, currency, layer, direction), units) in amounts {
+            amounts_by_journal
+                .entry(journal_id)
+                .or_default()
+                .push((account_id, currency, layer, direction, units));
+        }
+        for (journal_id, amounts) in amounts_by_journal {
+            self.account_sets
+                .add_amounts_in_tx(&mut tx, journal_id, amounts, modified_at[&journal_id])
+                .await?;
+        }
+
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(summary)
+    }
+}
+
+fn check_balanced(transaction: &ImportedTransaction) -> Result<(), SqlxLedgerError> {
+    if transaction.entries.is_empty() {
+        return Err(SqlxLedgerError::EmptyTransaction(transaction.id));
+    }
+    let mut totals = HashMap::new();
+    for entry in transaction.entries.iter() {
+        entry.currency.check_units(entry.units)?;
+        let total = totals.entry(entry.currency).or_insert(Decimal::ZERO);
+        match entry.direction {
+            DebitOrCredit::Debit => *total -= entry.units,
+            DebitOrCredit::Credit => *total += entry.units,
+        };
+    }
+    for (currency, total) in totals {
+        if total != Decimal::ZERO {
+            return Err(SqlxLedgerError::UnbalancedTransaction(currency, total));
+        }
+    }
+    Ok(())
+}
+
+struct CsvColumns {
+    transaction_id: usize,
+    journal_id: usize,
+    tx_template_id: usize,
+    effective: usize,
+    created_at: usize,
+    external_id: Option<usize>,
+    transaction_description: Option<usize>,
+    metadata: Option<usize>,
+    entry_type: usize,
+    account_id: usize,
+    layer: Option<usize>,
+    direction: usize,
+    units: usize,
+    currency: usize,
+    description: Option<usize>,
+}
+
+impl CsvColumns {
+    fn entry(&self, record: &csv::StringRecord) -> Result<ImportedEntry, SqlxLedgerError> {
+        let c = self;
+        let mut builder = ImportedEntry::builder();
+        builder
+            .entry_type(field::<String>(record, c.entry_type, "entry_type")?)
+            .account_id(field::<AccountId>(record, c.account_id, "account_id")?)
+            .direction(field(record, c.direction, "direction")?)
+            .units(field(record, c.units, "units")?)
+            .currency(field(record, c.currency, "currency")?);
+        if let Some(layer) = optional_field(record, c.layer, "layer")? {
+            builder.layer(layer);
+        }
+        if let Some(description) = optional_field::<String>(record, c.description, "description")? {
+            builder.description(description);
+        }
+        Ok(builder.build().expect("Couldn't build entry"))
+    }
+
+    fn transaction(
+        &self,
+        record: &csv::StringRecord,
+    ) -> Result<ImportedTransaction, SqlxLedgerError> {
+        let c = self;
+        let mut builder = ImportedTransaction::builder();
+        builder
+            .id(field::<TransactionId>(
+                record,
+                c.transaction_id,
+                "transaction_id",
+            )?)
+            .journal_id(field::<JournalId>(record, c.journal_id, "journal_id")?)
+            .tx_template_id(field::<TxTemplateId>(
+                record,
+                c.tx_template_id,
+                "tx_template_id",
+            )?)
+            .effective(field(record, c.effective, "effective")?)
+            .created_at(field(record, c.created_at, "created_at")?)
+            .entries(vec![self.entry(record)?]);
+        if let Some(external_id) = optional_field::<String>(record, c.external_id, "external_id")? {
+            builder.external_id(external_id);
+        }
+        if let Some(description) =
+            optional_field::<String>(record, c.transaction_description, "transaction_description")?
+        {
+            builder.description(description);
+        }
+        if let Some(metadata) = optional_field::<String>(record, c.metadata, "metadata")? {
+            builder.metadata(serde_json::from_str(&metadata)?);
+        }
+        Ok(builder.build().expect("Couldn't build transaction"))
+    }
+}
+
+struct CsvTransactions<R> {
+    records: csv::StringRecordsIntoIter<R>,
+    columns: CsvColumns,
+    current: Option<ImportedTransaction>,
+}
+
+impl<R: std::io::Read> CsvTransactions<R> {
+    fn new(reader: R) -> Result<Self, SqlxLedgerError> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let optional = |name: &str| headers.iter().position(|h| h == name);
+        let required = |name: &str| {
+            optional(name).ok_or_else(|| {
+                SqlxLedgerError::InvalidCsvRecord(1, format!("missing column {name}"))
+            })
+        };
+        let columns = CsvColumns {
+            transaction_id: required("transaction_id")?,
+            journal_id: required("journal_id")?,
+            tx_template_id: required("tx_template_id")?,
+            effective: required("effective")?,
+            created_at: required("created_at")?,
+            external_id: optional("external_id"),
+            transaction_description: optional("transaction_description"),
+            metadata: optional("metadata"),
+            entry_type: required("entry_type")?,
+            account_id: required("account_id")?,
+            layer: optional("layer"),
+            direction: required("direction")?,
+            units: required("units")?,
+            currency: required("currency")?,
+            description: optional("description"),
+        };
+        Ok(Self {
+            records: reader.into_records(),
+            columns,
+            current: None,
+        })
+    }
+
+    fn next_record(&mut self) -> Result<Option<ImportedTransaction>, SqlxLedgerError> {
+        for record in self.records.by_ref() {
+            let record = record?;
+            let id =
+                field::<TransactionId>(&record, self.columns.transaction_id, "transaction_id")?;
+            if self
+                .current
+                .as_ref()
+                .is_some_and(|current| current.id == id)
+            {
+                let entry = self.columns.entry(&record)?;
+                if let Some(current) = self.current.as_mut() {
+                    current.entries.push(entry);
+                }
+                continue;
+            }
+            let next = self.columns.transaction(&record)?;
+            if let Some(done) = self.current.replace(next) {
+                return Ok(Some(done));
+            }
+        }
+        Ok(self.current.take())
+    }
+}
+
+impl<R: std::io::Read> Iterator for CsvTransactions<R> {
+    type Item = Result<ImportedTransaction, SqlxLedgerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+fn field<T: FromStr>(
+    record: &csv::StringRecord,
+    column: usize,
+    name: &str,
+) -> Result<T, SqlxLedgerError>
+where
+    T::Err: std::fmt::Display,
+{
+    optional_field(record, Some(column), name)?.ok_or_else(|| {
+        SqlxLedgerError::InvalidCsvRecord(line(record), format!("{name} is missing"))
+    })
+}
+
+fn optional_field<T: FromStr>(
+    record: &csv::StringRecord,
+    column: Option<usize>,
+    name: &str,
+) -> Result<Option<T>, SqlxLedgerError>
+where
+    T::Err: std::fmt::Display,
+{
+    match column.and_then(|column| record.get(column)) {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|e: T::Err| {
+            SqlxLedgerError::InvalidCsvRecord(line(record), format!("{name}: {e}"))
+        }),
+    }
+}
+
+fn line(record: &csv::StringRecord) -> u64 {
+    record.position().map(|p| p.line()).unwrap_or_default()
+}
@@ -1,41 +1,179 @@
-use sqlx::{Acquire, PgPool, Postgres, Transaction};
+mod import;
+mod opening_balance;
+mod retry;
+mod simulation;
+mod void_policy;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use sqlx::{postgres::PgPoolOptions, Acquire, Executor, PgPool, Postgres, Transaction};
 use tracing::instrument;
 
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    account::Accounts, balance::*, entry::*, error::*, journal::*, primitives::*, transaction::*,
+    account::{Accounts, NewAccount},
+    account_set::*,
+    balance::*,
+    clock::*,
+    currency::*,
+    entry::*,
+    error::*,
+    export::*,
+    journal::*,
+    metrics,
+    partition::*,
+    period::*,
+    primitives::*,
+    rate::*,
+    report::*,
+    transaction::*,
     tx_template::*,
+    velocity::*,
 };
+pub use import::*;
+use opening_balance::*;
+pub use retry::*;
+pub use simulation::*;
+pub use void_policy::*;
 
 #[derive(Debug, Clone)]
 pub struct SqlxLedger {
     pool: PgPool,
     accounts: Accounts,
+    account_sets: AccountSets,
     journals: Journals,
     tx_templates: TxTemplates,
     transactions: Transactions,
     entries: Entries,
     balances: Balances,
+    currencies: Currencies,
+    exports: Exports,
+    partitions: Partitions,
+    periods: Periods,
+    rates: Rates,
+    reports: Reports,
+    velocity_limits: VelocityLimits,
+    acquire_timeout: Option<Duration>,
+    opening_balance_equity_account_id: Option<AccountId>,
+    dry_run: bool,
+    clock: Arc<dyn Clock>,
+    retry_policy: RetryPolicy,
+    void_policy: VoidPolicy,
 }
 
 impl SqlxLedger {
+    /// Pool options whose connections resolve the ledger's tables and types in `schema`.
+    /// Running the migrations and constructing the ledger with such a pool keeps the ledger
+    /// out of the application's schema. sqlx looks enum types up by name in any schema,
+    /// so a database can only hold one ledger.
+    pub fn pool_options_in_schema(schema: &str) -> PgPoolOptions {
+        let set_search_path = format!("SET search_path TO \"{}\"", schema.replace('"', "\"\""));
+        PgPoolOptions::new().after_connect(move |conn, _| {
+            let set_search_path = set_search_path.clone();
+            Box::pin(async move {
+                conn.execute(set_search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+    }
+
     pub fn new(pool: &PgPool) -> Self {
         Self {
             accounts: Accounts::new(pool),
+            account_sets: AccountSets::new(pool),
             journals: Journals::new(pool),
             tx_templates: TxTemplates::new(pool),
             transactions: Transactions::new(pool),
             entries: Entries::new(pool),
             balances: Balances::new(pool),
+            currencies: Currencies::new(pool),
+            exports: Exports::new(pool),
+            partitions: Partitions::new(pool),
+            periods: Periods::new(pool),
+            rates: Rates::new(pool),
+            reports: Reports::new(pool),
+            velocity_limits: VelocityLimits::new(pool),
             pool: pool.clone(),
+            acquire_timeout: None,
+            opening_balance_equity_account_id: None,
+            dry_run: false,
+            clock: Arc::new(SystemClock),
+            retry_policy: RetryPolicy::default(),
+            void_policy: VoidPolicy::default(),
+        }
+    }
+
+    /// A handle on the same ledger that evaluates and validates every mutating operation
+    /// but rolls back instead of committing. Reads are unaffected.
+    pub fn dry_run(&self) -> Self {
+        Self {
+            accounts: self.accounts.dry_run(),
+            account_sets: self.account_sets.dry_run(),
+            journals: self.journals.dry_run(),
+            tx_templates: self.tx_templates.dry_run(),
+            transactions: self.transactions.dry_run(),
+            currencies: self.currencies.dry_run(),
+            partitions: self.partitions.dry_run(),
+            periods: self.periods.dry_run(),
+            rates: self.rates.dry_run(),
+            reports: self.reports.clone(),
+            velocity_limits: self.velocity_limits.dry_run(),
+            dry_run: true,
+            ..self.clone()
         }
     }
 
+    /// Fail with `SqlxLedgerError::PoolTimeout` instead of waiting on the pool's own acquire timeout.
+    pub fn with_acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.acquire_timeout = Some(timeout);
+        self
+    }
+
+    /// Account that the opening balances of newly created accounts are posted against.
+    pub fn with_opening_balance_equity_account(mut self, account_id: AccountId) -> Self {
+        self.opening_balance_equity_account_id = Some(account_id);
+        self
+    }
+
+    /// Clock used wherever the ledger reads now: to timestamp postings and balances,
+    /// to evaluate `date()` in templates and to pick the current partition month.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        Self {
+            account_sets: self.account_sets.with_clock(Arc::clone(&clock)),
+            partitions: self.partitions.with_clock(Arc::clone(&clock)),
+            tx_templates: self.tx_templates.with_clock(Arc::clone(&clock)),
+            clock,
+            ..self
+        }
+    }
+
+    /// How posting retries serialization failures and deadlocks. Posting within a
+    /// caller's transaction is never retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Which transactions `void_transaction` voids. `force_void_transaction` ignores it.
+    pub fn with_void_policy(mut self, void_policy: VoidPolicy) -> Self {
+        self.void_policy = void_policy;
+        self
+    }
+
     pub fn accounts(&self) -> &Accounts {
         &self.accounts
     }
 
+    pub fn account_sets(&self) -> &AccountSets {
+        &self.account_sets
+    }
+
     pub fn journals(&self) -> &Journals {
         &self.journals
     }
@@ -44,6 +182,10 @@ impl SqlxLedger {
         &self.tx_templates
     }
 
+    pub fn transactions(&self) -> &Transactions {
+        &self.transactions
+    }
+
     pub fn entries(&self) -> &Entries {
         &self.entries
     }
@@ -52,74 +194,782 @@ impl SqlxLedger {
         &self.balances
     }
 
+    pub fn currencies(&self) -> &Currencies {
+        &self.currencies
+    }
+
+    pub fn export(&self) -> &Exports {
+        &self.exports
+    }
+
+    pub fn partitions(&self) -> &Partitions {
+        &self.partitions
+    }
+
+    pub fn periods(&self) -> &Periods {
+        &self.periods
+    }
+
+    pub fn rates(&self) -> &Rates {
+        &self.rates
+    }
+
+    pub fn reports(&self) -> &Reports {
+        &self.reports
+    }
+
+    pub fn velocity_limits(&self) -> &VelocityLimits {
+        &self.velocity_limits
+    }
+
+    pub async fn create_account(
+        &self,
+        new_account: NewAccount,
+    ) -> Result<AccountId, SqlxLedgerError> {
+        let mut tx = self.begin().await?;
+        let account_id = self.create_account_in_tx(&mut tx, new_account).await?;
+        self.finish(tx).await?;
+        Ok(account_id)
+    }
+
+    /// Creates the account, and posts its opening balance if it has one, without committing `tx`.
+    #[instrument(name = "sqlx_ledger.ledger.create_account", skip(self, tx))]
+    pub async fn create_account_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        mut new_account: NewAccount,
+    ) -> Result<AccountId, SqlxLedgerError> {
+        let opening_balance = match new_account.opening_balance.take() {
+            Some(opening_balance) => opening_balance,
+            None => return self.accounts.create_in_tx(tx, new_account).await,
+        };
+        let equity_account_id = self
+            .opening_balance_equity_account_id
+            .ok_or(SqlxLedgerError::OpeningBalanceEquityAccountNotConfigured)?;
+        let tx_template = self.ensure_opening_balance_tx_template(tx).await?;
+
+        let direction = new_account.normal_balance_type();
+        let account_id = self.accounts.create_in_tx(tx, new_account).await?;
+        let mut params = TxParams::new();
+        params.insert("journal_id", opening_balance.journal_id);
+        params.insert("account_id", account_id);
+        params.insert("account_direction", direction);
+        params.insert("equity_account_id", equity_account_id);
+        params.insert("equity_direction", direction.opposite());
+        params.insert("units", opening_balance.units);
+        params.insert("currency", opening_balance.currency.code());
+        let started = Instant::now();
+        let now = self.clock.now();
+        let prepared = self
+            .prepare_with_template(tx_template, Some(params), now)
+            .await?;
+        record_prepared(&prepared.0);
+        let (id, created) = self.post_one_in_tx(tx, prepared, now).await?;
+        self.record_posted(OPENING_BALANCE_TX_TEMPLATE_CODE, id, created, started);
+        Ok(account_id)
+    }
+
+    /// Posting is idempotent on the template's `external_id`: posting again with an
+    /// `external_id` already posted by the same template returns the existing transaction.
+    /// Serialization failures and deadlocks are retried according to the `RetryPolicy`.
+    #[instrument(
+        name = "sqlx_ledger.ledger.post_transaction",
+        skip(self),
+        fields(journal_id, correlation_id, transaction_id)
+    )]
     pub async fn post_transaction(
         &self,
         tx_template_code: &str,
         params: Option<impl Into<TxParams> + std::fmt::Debug>,
-    ) -> Result<(), SqlxLedgerError> {
-        let tx = self.pool.begin().await?;
-        self.post_transaction_in_tx(tx, tx_template_code, params)
+    ) -> Result<TransactionId, SqlxLedgerError> {
+        let started = Instant::now();
+        let now = self.clock.now();
+        let prepared = self
+            .prepare_tx(tx_template_code, params.map(|p| p.into()), now)
             .await?;
-        Ok(())
+        record_prepared(&prepared.0);
+        let (id, created) = self
+            .retrying(|| self.post_prepared(prepared.clone(), now))
+            .await?;
+        self.record_posted(tx_template_code, id, created, started);
+        Ok(id)
     }
 
-    #[instrument(name = "sqlx_ledger.ledger.post_transaction", skip(self, tx))]
+    /// Posts within `tx` without committing it, so the posting commits or rolls back
+    /// together with the caller's own writes. The template is read outside of `tx`,
+    /// so it has to be committed beforehand.
+    #[instrument(
+        name = "sqlx_ledger.ledger.post_transaction_in_tx",
+        skip(self, tx),
+        fields(journal_id, correlation_id, transaction_id)
+    )]
     pub async fn post_transaction_in_tx(
         &self,
-        mut tx: Transaction<'_, Postgres>,
+        tx: &mut Transaction<'_, Postgres>,
         tx_template_code: &str,
         params: Option<impl Into<TxParams> + std::fmt::Debug>,
-    ) -> Result<(), SqlxLedgerError> {
-        let (new_tx, new_entries) = {
-            let tx_template = self.tx_templates.find_core(tx_template_code).await?;
-            // tx_template is not Send (Rc<String> nested in CelExpression)
-            // so we need to drop it before the next await
-            tx_template.prep_tx(params.map(|p| p.into()).unwrap_or_else(TxParams::new))?
-        };
-        let (journal_id, tx_id) = self.transactions.create_in_tx(&mut tx, new_tx).await?;
-        let entries = self
-            .entries
-            .create_all(journal_id, tx_id, new_entries, &mut tx)
+    ) -> Result<TransactionId, SqlxLedgerError> {
+        let started = Instant::now();
+        let now = self.clock.now();
+        let prepared = self
+            .prepare_tx(tx_template_code, params.map(|p| p.into()), now)
             .await?;
+        record_prepared(&prepared.0);
+        let (id, created) = self.post_one_in_tx(tx, prepared, now).await?;
+        self.record_posted(tx_template_code, id, created, started);
+        Ok(id)
+    }
+
+    async fn post_prepared(
+        &self,
+        prepared: (NewTransaction, Vec<NewEntry>),
+        now: DateTime<Utc>,
+    ) -> Result<(TransactionId, bool), SqlxLedgerError> {
+        let mut tx = self.begin().await?;
+        let posted = self.post_one_in_tx(&mut tx, prepared, now).await?;
+        self.finish(tx).await?;
+        Ok(posted)
+    }
+
+    async fn post_one_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        prepared: (NewTransaction, Vec<NewEntry>),
+        now: DateTime<Utc>,
+    ) -> Result<(TransactionId, bool), SqlxLedgerError> {
+        let key = prepared
+            .0
+            .external_id
+            .clone()
+            .map(|external_id| (prepared.0.tx_template_id, external_id));
+        // A savepoint keeps `tx` usable if a concurrent post of the same transaction wins
+        let mut savepoint = tx.begin().await?;
+        match self
+            .post_prepared_in_tx(&mut savepoint, vec![prepared], now)
+            .await
         {
-            let ids: Vec<(AccountId, &Currency)> = entries
-                .iter()
-                .map(|entry| (entry.account_id, &entry.currency))
-                .collect();
-            let mut balance_tx = tx.begin().await?;
+            Ok(mut posted) => {
+                savepoint.commit().await?;
+                Ok(posted.remove(0))
+            }
+            // Lost the race against a concurrent post of the same transaction
+            Err(SqlxLedgerError::DuplicateKey(e))
+                if e.constraint() == Some(TRANSACTIONS_EXTERNAL_ID_CONSTRAINT) =>
+            {
+                savepoint.rollback().await?;
+                let (tx_template_id, external_id) = key.expect("duplicate external_id");
+                self.transactions
+                    .find_ids_by_external_ids(tx, vec![external_id.clone()])
+                    .await?
+                    .remove(&(tx_template_id, external_id))
+                    .map(|id| (id, false))
+                    .ok_or(SqlxLedgerError::DuplicateKey(e))
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-            let mut balances = self
-                .balances
-                .find_for_update(journal_id, ids.clone(), &mut balance_tx)
-                .await?;
-            let mut latest_balances: HashMap<AccountId, BalanceDetails> = HashMap::new();
-            let mut new_balances = Vec::new();
-            for entry in entries.iter() {
-                let balance = match (
-                    latest_balances.remove(&entry.account_id),
-                    balances.remove(&entry.account_id),
-                ) {
-                    (Some(latest), _) => {
-                        new_balances.push(latest.clone());
-                        latest
-                    }
-                    (_, Some(balance)) => balance,
-                    _ => {
-                        latest_balances
-                            .insert(entry.account_id, BalanceDetails::init(journal_id, entry));
+    /// Posts many transactions atomically: all templates are evaluated up front, and the
+    /// transactions, their entries and the resulting balances are written in one database
+    /// transaction with a single statement per table where possible.
+    /// Returns the ids in the order the transactions were given; transactions repeating the
+    /// `external_id` of an earlier one in the batch get the id of the earlier one.
+    #[instrument(name = "sqlx_ledger.ledger.post_transactions", skip_all, fields(n = transactions.len()))]
+    pub async fn post_transactions(
+        &self,
+        transactions: Vec<(&str, TxParams)>,
+    ) -> Result<Vec<TransactionId>, SqlxLedgerError> {
+        let started = Instant::now();
+        let now = self.clock.now();
+        let mut prepared = Vec::with_capacity(transactions.len());
+        let mut codes = Vec::with_capacity(transactions.len());
+        for (tx_template_code, params) in transactions {
+            prepared.push(self.prepare_tx(tx_template_code, Some(params), now).await?);
+            codes.push(tx_template_code);
+        }
+        let posted = self
+            .retrying(|| async {
+                let mut tx = self.begin().await?;
+                let posted = self
+                    .post_prepared_in_tx(&mut tx, prepared.clone(), now)
+                    .await?;
+                self.finish(tx).await?;
+                Ok(posted)
+            })
+            .await?;
+        metrics::record_duration(metrics::POST_DURATION, started.elapsed());
+        if !self.dry_run {
+            codes
+                .into_iter()
+                .zip(posted.iter())
+                .filter(|(_, (_, created))| *created)
+                .for_each(|(code, _)| metrics::transaction_posted(code));
+        }
+        Ok(posted.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Evaluates the template and applies the entries to the balances exactly as posting would,
+    /// including every validation, then rolls everything back.
+    #[instrument(name = "sqlx_ledger.ledger.simulate_transaction", skip(self))]
+    pub async fn simulate_transaction(
+        &self,
+        tx_template_code: &str,
+        params: Option<impl Into<TxParams> + std::fmt::Debug>,
+    ) -> Result<SimulatedTransaction, SqlxLedgerError> {
+        let now = self.clock.now();
+        let (new_tx, new_entries) = self
+            .prepare_tx(tx_template_code, params.map(|p| p.into()), now)
+            .await?;
+        let journal_id = new_tx.journal_id;
+        let entries: Vec<EvaluatedEntry> = new_entries
+            .iter()
+            .cloned()
+            .map(EvaluatedEntry::from)
+            .collect();
+        let mut tx = self.begin().await?;
+        self.post_prepared_in_tx(&mut tx, vec![(new_tx, new_entries)], now)
+            .await?;
+        let mut ids: Vec<(AccountId, &Currency)> = entries
+            .iter()
+            .map(|entry| (entry.account_id, &entry.currency))
+            .collect();
+        ids.sort_by_key(|(account_id, currency)| (*account_id, currency.code()));
+        ids.dedup();
+        let balances = self
+            .balances
+            .find_all_in_tx(&mut tx, journal_id, ids)
+            .await?;
+        tx.rollback().await?;
+        Ok(SimulatedTransaction {
+            journal_id,
+            entries,
+            balances,
+        })
+    }
+
+    async fn prepare_tx(
+        &self,
+        tx_template_code: &str,
+        params: Option<TxParams>,
+        now: DateTime<Utc>,
+    ) -> Result<(NewTransaction, Vec<NewEntry>), SqlxLedgerError> {
+        let tx_template = self.tx_templates.find_core(tx_template_code).await?;
+        self.prepare_with_template(tx_template, params, now).await
+    }
+
+    async fn prepare_with_template(
+        &self,
+        tx_template: Arc<TxTemplateCore>,
+        params: Option<TxParams>,
+        now: DateTime<Utc>,
+    ) -> Result<(NewTransaction, Vec<NewEntry>), SqlxLedgerError> {
+        self.currencies.ensure_loaded().await?;
+        let rates = if tx_template.converts() {
+            self.rates.find_all_at(now).await?
+        } else {
+            ExchangeRates::default()
+        };
+        let started = Instant::now();
+        let prepared = tx_template.prep_tx(params.unwrap_or_default(), now, &rates);
+        metrics::record_duration(metrics::TX_TEMPLATE_EVALUATION_DURATION, started.elapsed());
+        prepared
+    }
+
+    #[instrument(
+        name = "sqlx_ledger.ledger.post_prepared",
+        skip_all,
+        fields(n = prepared.len())
+    )]
+    async fn post_prepared_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        prepared: Vec<(NewTransaction, Vec<NewEntry>)>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(TransactionId, bool)>, SqlxLedgerError> {
+        let external_ids = prepared
+            .iter()
+            .filter_map(|(new_tx, _)| new_tx.external_id.clone())
+            .collect();
+        let posted = self
+            .transactions
+            .find_ids_by_external_ids(tx, external_ids)
+            .await?;
+        let mut ids = Vec::with_capacity(prepared.len());
+        let mut batched = HashMap::new();
+        let mut duplicates = Vec::new();
+        let mut new_txs = Vec::new();
+        let mut new_entries = Vec::new();
+        let mut constraints = Vec::new();
+        for (i, (mut new_tx, entries)) in prepared.into_iter().enumerate() {
+            let key = new_tx
+                .external_id
+                .clone()
+                .map(|external_id| (new_tx.tx_template_id, external_id));
+            let existing = key.as_ref().and_then(|key| posted.get(key).copied());
+            ids.push(existing);
+            if existing.is_some() {
+                continue;
+            }
+            if let Some(key) = key {
+                match batched.entry(key) {
+                    Entry::Occupied(first) => {
+                        duplicates.push((i, *first.get()));
                         continue;
                     }
-                };
-                latest_balances.insert(entry.account_id, balance.update(entry));
+                    Entry::Vacant(first) => {
+                        first.insert(i);
+                    }
+                }
             }
-            new_balances.extend(latest_balances.into_values());
+            constraints.extend(
+                std::mem::take(&mut new_tx.balance_constraints)
+                    .into_iter()
+                    .map(|constraint| (new_tx.journal_id, constraint)),
+            );
+            new_entries.push((i, new_tx.journal_id, new_tx.tx_template_id, entries));
+            new_txs.push(new_tx);
+        }
+        if new_txs.is_empty() {
+            return Ok(ids.into_iter().flatten().map(|id| (id, false)).collect());
+        }
 
-            self.balances
-                .update_balances(journal_id, new_balances, &mut balance_tx)
+        self.periods
+            .ensure_open_in_tx(
+                tx,
+                new_txs
+                    .iter()
+                    .map(|new_tx| (new_tx.journal_id, new_tx.effective))
+                    .collect(),
+            )
+            .await?;
+        let created = self.transactions.create_all_in_tx(tx, new_txs).await?;
+        let mut tx_template_ids = HashMap::new();
+        let mut posted = vec![false; ids.len()];
+        let new_entries = new_entries
+            .into_iter()
+            .zip(created)
+            .map(|((i, journal_id, tx_template_id, entries), tx_id)| {
+                ids[i] = Some(tx_id);
+                posted[i] = true;
+                tx_template_ids.insert(tx_id, tx_template_id);
+                (journal_id, tx_id, entries)
+            })
+            .collect();
+        for (i, first) in duplicates {
+            ids[i] = ids[first];
+        }
+        let entries = self.entries.create_all(new_entries, now, tx).await?;
+        let allowed_directions = self
+            .accounts
+            .allowed_directions_in_tx(tx, entries.iter().map(|e| e.account_id).collect())
+            .await?;
+        for entry in entries.iter() {
+            if let Some(allowed) = allowed_directions.get(&entry.account_id) {
+                if *allowed != entry.direction {
+                    return Err(SqlxLedgerError::DirectionNotAllowed(
+                        entry.account_id,
+                        entry.direction,
+                    ));
+                }
+            }
+        }
+        self.velocity_limits
+            .enforce_in_tx(tx, &tx_template_ids, &entries, now)
+            .await?;
+        let mut entries_by_journal: HashMap<JournalId, Vec<StagedEntry>> = HashMap::new();
+        for entry in entries {
+            entries_by_journal
+                .entry(entry.journal_id)
+                .or_default()
+                .push(entry);
+        }
+        for (journal_id, entries) in entries_by_journal {
+            self.update_balances_in_tx(tx, journal_id, &entries).await?;
+        }
+        for (journal_id, constraint) in constraints {
+            let balance = self
+                .balances
+                .find_all_in_tx(
+                    tx,
+                    journal_id,
+                    vec![(constraint.account_id, &constraint.currency)],
+                )
+                .await?
+                .pop()
+                .map(|balance| balance.layer(constraint.layer))
+                .unwrap_or(Decimal::ZERO);
+            if balance < constraint.min {
+                return Err(SqlxLedgerError::InsufficientBalance(
+                    constraint.account_id,
+                    constraint.currency,
+                    constraint.layer,
+                    balance,
+                ));
+            }
+        }
+        Ok(ids.into_iter().flatten().zip(posted).collect())
+    }
+
+    /// Posts the exact opposite of every entry of a transaction and links the two
+    /// through `voids` / `voided_by`. Returns the id of the reversal.
+    /// Direction restrictions and velocity limits don't apply to the reversal, and the usage
+    /// the original transaction recorded against velocity limits is released.
+    /// Serialization failures and deadlocks are retried according to the `RetryPolicy`.
+    /// Fails with `SqlxLedgerError::TransactionNotVoidable` if the `VoidPolicy` rejects it.
+    #[instrument(name = "sqlx_ledger.ledger.void_transaction", skip(self))]
+    pub async fn void_transaction(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionId, SqlxLedgerError> {
+        self.void_with_policy(transaction_id, self.void_policy)
+            .await
+    }
+
+    /// Voids the transaction like `void_transaction` even if the `VoidPolicy` rejects it.
+    #[instrument(name = "sqlx_ledger.ledger.force_void_transaction", skip(self))]
+    pub async fn force_void_transaction(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionId, SqlxLedgerError> {
+        self.void_with_policy(transaction_id, VoidPolicy::default())
+            .await
+    }
+
+    /// Voids every transaction sharing `correlation_id` in a single database transaction,
+    /// following the `VoidPolicy` like `void_transaction`. Transactions voided already and the
+    /// reversals themselves are skipped. Returns the ids of the reversals in the order the
+    /// voided transactions were created.
+    #[instrument(name = "sqlx_ledger.ledger.void_correlation", skip(self))]
+    pub async fn void_correlation(
+        &self,
+        correlation_id: CorrelationId,
+    ) -> Result<Vec<TransactionId>, SqlxLedgerError> {
+        let now = self.clock.now();
+        let mut voids = Vec::new();
+        for original in self
+            .transactions
+            .list_by_correlation_id(correlation_id)
+            .await?
+        {
+            if original.voided_by.is_some() || original.voids.is_some() {
+                continue;
+            }
+            let entries = self.entries.list_by_transaction_id(original.id).await?;
+            voids.push(prepare_void(original, entries, self.void_policy, now)?);
+        }
+        if voids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.retrying(|| async {
+            let mut tx = self.begin().await?;
+            let void_ids = self.post_voids_in_tx(&mut tx, voids.clone(), now).await?;
+            self.finish(tx).await?;
+            Ok(void_ids)
+        })
+        .await
+    }
+
+    /// Voids the transaction and posts `tx_template_code` with the corrected `params` in its
+    /// place, in a single database transaction. The correction takes the correlation id of the
+    /// original, linking the original, its reversal and the correction.
+    /// Returns the ids of the reversal and of the correction.
+    #[instrument(name = "sqlx_ledger.ledger.amend_transaction", skip(self))]
+    pub async fn amend_transaction(
+        &self,
+        transaction_id: TransactionId,
+        tx_template_code: &str,
+        params: Option<impl Into<TxParams> + std::fmt::Debug>,
+    ) -> Result<(TransactionId, TransactionId), SqlxLedgerError> {
+        let started = Instant::now();
+        let now = self.clock.now();
+        let original = self.transactions.find_by_id(transaction_id).await?;
+        if original.voided_by.is_some() {
+            return Err(SqlxLedgerError::TransactionAlreadyVoided(transaction_id));
+        }
+        let correlation_id = original.correlation_id;
+        let entries = self.entries.list_by_transaction_id(transaction_id).await?;
+        let void = prepare_void(original, entries, self.void_policy, now)?;
+        let mut prepared = self
+            .prepare_tx(tx_template_code, params.map(|p| p.into()), now)
+            .await?;
+        prepared.0.correlation_id = Some(correlation_id);
+        let (void_id, (id, created)) = self
+            .retrying(|| async {
+                let mut tx = self.begin().await?;
+                let void_ids = self
+                    .post_voids_in_tx(&mut tx, vec![void.clone()], now)
+                    .await?;
+                let posted = self.post_one_in_tx(&mut tx, prepared.clone(), now).await?;
+                self.finish(tx).await?;
+                Ok((void_ids[0], posted))
+            })
+            .await?;
+        self.record_posted(tx_template_code, id, created, started);
+        Ok((void_id, id))
+    }
+
+    async fn void_with_policy(
+        &self,
+        transaction_id: TransactionId,
+        policy: VoidPolicy,
+    ) -> Result<TransactionId, SqlxLedgerError> {
+        let now = self.clock.now();
+        let original = self.transactions.find_by_id(transaction_id).await?;
+        if original.voided_by.is_some() {
+            return Err(SqlxLedgerError::TransactionAlreadyVoided(transaction_id));
+        }
+        let entries = self.entries.list_by_transaction_id(transaction_id).await?;
+        let void = prepare_void(original, entries, policy, now)?;
+        let ids = self
+            .retrying(|| async {
+                let mut tx = self.begin().await?;
+                let void_ids = self
+                    .post_voids_in_tx(&mut tx, vec![void.clone()], now)
+                    .await?;
+                self.finish(tx).await?;
+                Ok(void_ids)
+            })
+            .await?;
+        Ok(ids[0])
+    }
+
+    async fn post_voids_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        voids: Vec<PreparedVoid>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<TransactionId>, SqlxLedgerError> {
+        let mut void_ids = Vec::with_capacity(voids.len());
+        for void in voids {
+            let transaction_id = void.transaction_id;
+            if let Some(open_period) = void.open_period {
+                self.periods
+                    .ensure_open_in_tx(tx, vec![open_period])
+                    .await
+                    .map_err(|e| match e {
+                        SqlxLedgerError::PeriodClosed(_, effective) => {
+                            SqlxLedgerError::TransactionNotVoidable(transaction_id, effective)
+                        }
+                        e => e,
+                    })?;
+            }
+            self.periods
+                .ensure_open_in_tx(tx, vec![(void.new_tx.journal_id, void.new_tx.effective)])
+                .await?;
+            let (journal_id, void_id) = self.transactions.create_in_tx(tx, void.new_tx).await?;
+            self.transactions
+                .mark_voided_in_tx(tx, transaction_id, void_id, now)
                 .await?;
-            balance_tx.commit().await?;
+            let entries = self
+                .entries
+                .create_all(vec![(journal_id, void_id, void.new_entries)], now, tx)
+                .await?;
+            self.update_balances_in_tx(tx, journal_id, &entries).await?;
+            self.velocity_limits
+                .release_in_tx(tx, transaction_id)
+                .await?;
+            void_ids.push(void_id);
+        }
+        Ok(void_ids)
+    }
+
+    /// Applies posted entries to the account balances and to the sets containing the accounts.
+    async fn update_balances_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        journal_id: JournalId,
+        entries: &[StagedEntry],
+    ) -> Result<(), SqlxLedgerError> {
+        let ids: Vec<(AccountId, &Currency)> = entries
+            .iter()
+            .map(|entry| (entry.account_id, &entry.currency))
+            .collect();
+        let mut balance_tx = tx.begin().await?;
+
+        let mut balances = self
+            .balances
+            .find_for_update(journal_id, ids.clone(), &mut balance_tx)
+            .await?;
+        let mut latest_balances: HashMap<(AccountId, Currency), BalanceDetails> = HashMap::new();
+        let mut new_balances = Vec::new();
+        for entry in entries.iter() {
+            let key = (entry.account_id, entry.currency);
+            let balance = match (latest_balances.remove(&key), balances.remove(&key)) {
+                (Some(latest), _) => {
+                    new_balances.push(latest.clone());
+                    latest
+                }
+                (_, Some(balance)) => balance,
+                _ => {
+                    latest_balances.insert(key, BalanceDetails::init(journal_id, entry));
+                    continue;
+                }
+            };
+            latest_balances.insert(key, balance.update(entry));
         }
-        tx.commit().await?;
+        new_balances.extend(latest_balances.into_values());
+
+        self.balances
+            .update_balances(journal_id, new_balances, &mut balance_tx)
+            .await?;
+        self.account_sets
+            .update_balances_in_tx(&mut balance_tx, journal_id, entries)
+            .await?;
+        balance_tx.commit().await?;
         Ok(())
     }
+
+    async fn retrying<T, F, Fut>(&self, mut attempt: F) -> Result<T, SqlxLedgerError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, SqlxLedgerError>>,
+    {
+        let mut attempts = 1;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && attempts < self.retry_policy.max_attempts => {
+                    tracing::warn!(attempts, error = %e, "retrying");
+                    tokio::time::sleep(self.retry_policy.backoff(attempts)).await;
+                    attempts += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn record_posted(
+        &self,
+        tx_template_code: &str,
+        id: TransactionId,
+        created: bool,
+        started: Instant,
+    ) {
+        metrics::record_duration(metrics::POST_DURATION, started.elapsed());
+        if created && !self.dry_run {
+            metrics::transaction_posted(tx_template_code);
+        }
+        tracing::Span::current().record("transaction_id", tracing::field::display(id));
+    }
+
+    async fn finish(&self, tx: Transaction<'_, Postgres>) -> Result<(), SqlxLedgerError> {
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    async fn begin(&self) -> Result<Transaction<'_, Postgres>, SqlxLedgerError> {
+        match self.acquire_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.pool.begin())
+                .await
+                .map_err(|_| SqlxLedgerError::PoolTimeout)?
+                .map_err(SqlxLedgerError::from),
+            None => Ok(self.pool.begin().await?),
+        }
+    }
+
+    async fn ensure_opening_balance_tx_template(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Arc<TxTemplateCore>, SqlxLedgerError> {
+        if self.dry_run {
+            // Created within `tx` so that it's rolled back with it
+            let mut savepoint = tx.begin().await?;
+            match self
+                .tx_templates
+                .create_in_tx(&mut savepoint, opening_balance_tx_template())
+                .await
+            {
+                Ok(_) => savepoint.commit().await?,
+                Err(SqlxLedgerError::DuplicateKey(_)) => savepoint.rollback().await?,
+                Err(e) => return Err(e),
+            }
+        } else {
+            match self
+                .tx_templates
+                .create(opening_balance_tx_template())
+                .await
+            {
+                Ok(_) | Err(SqlxLedgerError::DuplicateKey(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.tx_templates
+            .find_core_in_tx(tx, OPENING_BALANCE_TX_TEMPLATE_CODE)
+            .await
+    }
+}
+
+fn record_prepared(new_tx: &NewTransaction) {
+    let span = tracing::Span::current();
+    span.record("journal_id", tracing::field::display(new_tx.journal_id));
+    if let Some(correlation_id) = new_tx.correlation_id {
+        span.record("correlation_id", tracing::field::display(correlation_id));
+    }
+}
+
+#[derive(Clone)]
+struct PreparedVoid {
+    transaction_id: TransactionId,
+    /// Must still be open when the reversal is posted
+    open_period: Option<(JournalId, NaiveDate)>,
+    new_tx: NewTransaction,
+    new_entries: Vec<NewEntry>,
+}
+
+/// The reversal of `original`, effective on the day of `now`.
+fn prepare_void(
+    original: crate::transaction::Transaction,
+    original_entries: Vec<crate::entry::Entry>,
+    policy: VoidPolicy,
+    now: DateTime<Utc>,
+) -> Result<PreparedVoid, SqlxLedgerError> {
+    if !policy.allows_age(original.effective, now.date_naive()) {
+        return Err(SqlxLedgerError::TransactionNotVoidable(
+            original.id,
+            original.effective,
+        ));
+    }
+    let mut tx_builder = NewTransaction::builder();
+    tx_builder
+        .journal_id(original.journal_id)
+        .tx_template_id(original.tx_template_id)
+        .tx_template_version(original.tx_template_version)
+        .effective(now.date_naive())
+        .correlation_id(original.correlation_id)
+        .voids(original.id)
+        .created_at(now);
+    if let Some(description) = original.description {
+        tx_builder.description(format!("Void of {description}"));
+    }
+    let new_entries = original_entries
+        .into_iter()
+        .map(|entry| {
+            let mut builder = NewEntry::builder();
+            builder
+                .account_id(entry.account_id)
+                .entry_type(entry.entry_type)
+                .layer(entry.layer)
+                .units(entry.units)
+                .currency(entry.currency)
+                .direction(entry.direction.opposite());
+            if let Some(description) = entry.description {
+                builder.description(description);
+            }
+            if let Some(conversion) = entry.conversion {
+                builder.conversion(conversion);
+            }
+            builder.build().expect("Couldn't build entry")
+        })
+        .collect();
+    Ok(PreparedVoid {
+        transaction_id: original.id,
+        open_period: policy
+            .reject_closed_periods
+            .then_some((original.journal_id, original.effective)),
+        new_tx: tx_builder.build().expect("tx_build should succeed"),
+        new_entries,
+    })
 }
@@ -0,0 +1,78 @@
+use crate::tx_template::*;
+
+pub(super) const OPENING_BALANCE_TX_TEMPLATE_CODE: &str = "SQLX_LEDGER_OPENING_BALANCE";
+
+pub(super) fn opening_balance_tx_template() -> NewTxTemplate {
+    let params = vec![
+        ParamDefinition::builder()
+            .name("journal_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("account_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("account_direction")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("equity_account_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("equity_direction")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("units")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("currency")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+    ];
+    let entries = vec![
+        EntryInput::builder()
+            .entry_type("'OPENING_BALANCE'")
+            .account_id("params.account_id")
+            .layer("SETTLED")
+            .direction("params.account_direction")
+            .units("params.units")
+            .currency("params.currency")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'OPENING_BALANCE'")
+            .account_id("params.equity_account_id")
+            .layer("SETTLED")
+            .direction("params.equity_direction")
+            .units("params.units")
+            .currency("params.currency")
+            .build()
+            .unwrap(),
+    ];
+    NewTxTemplate::builder()
+        .code(OPENING_BALANCE_TX_TEMPLATE_CODE)
+        .description("Opening balance of an account")
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .description("'Opening balance'")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap()
+}
@@ -0,0 +1,10 @@
+use crate::{balance::AccountBalance, entry::EvaluatedEntry, primitives::*};
+
+/// Accounting impact a transaction would have if it were posted now.
+#[derive(Debug)]
+pub struct SimulatedTransaction {
+    pub journal_id: JournalId,
+    pub entries: Vec<EvaluatedEntry>,
+    /// Balance of every account and currency the entries touch, as it would be after posting.
+    pub balances: Vec<AccountBalance>,
+}
@@ -0,0 +1,34 @@
+use chrono::{Duration, NaiveDate};
+
+/// Which transactions `SqlxLedger::void_transaction` voids. Nothing is rejected by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VoidPolicy {
+    /// Reject transactions effective inside a closed period of their journal.
+    pub reject_closed_periods: bool,
+    /// Reject transactions effective longer ago than this.
+    pub max_age: Option<Duration>,
+}
+
+impl VoidPolicy {
+    pub(super) fn allows_age(&self, effective: NaiveDate, today: NaiveDate) -> bool {
+        self.max_age
+            .is_none_or(|max_age| today.signed_duration_since(effective) <= max_age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_age_is_inclusive() {
+        let today = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
+        let policy = VoidPolicy {
+            max_age: Some(Duration::days(30)),
+            ..VoidPolicy::default()
+        };
+        assert!(policy.allows_age(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(), today));
+        assert!(!policy.allows_age(NaiveDate::from_ymd_opt(2023, 2, 28).unwrap(), today));
+        assert!(VoidPolicy::default().allows_age(NaiveDate::MIN, today));
+    }
+}
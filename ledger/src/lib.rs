@@ -2,17 +2,31 @@
 #![cfg_attr(feature = "fail-on-warnings", deny(clippy::all))]
 
 pub mod account;
+pub mod account_set;
 pub mod balance;
+pub mod currency;
 pub mod entry;
+pub mod export;
 pub mod journal;
+pub mod metrics;
+pub mod partition;
+pub mod period;
+pub mod rate;
+pub mod report;
 pub mod transaction;
 pub mod tx_template;
+pub mod velocity;
 
+mod clock;
+mod copy;
 mod error;
 mod ledger;
 mod macros;
+mod pagination;
 mod primitives;
 
+pub use clock::*;
 pub use error::*;
 pub use ledger::*;
+pub use pagination::*;
 pub use primitives::*;
@@ -0,0 +1,45 @@
+//! Names of the metrics recorded through the `metrics` facade when the `metrics` feature is enabled.
+//! Without the feature recording compiles to nothing.
+
+use std::time::Duration;
+
+/// Counter of posted transactions, labeled by `tx_template_code`.
+pub const TRANSACTIONS_POSTED: &str = "sqlx_ledger_transactions_posted_total";
+/// Histogram of the seconds spent posting, from template lookup to commit.
+pub const POST_DURATION: &str = "sqlx_ledger_post_duration_seconds";
+/// Histogram of the seconds spent evaluating a template's CEL expressions.
+pub const TX_TEMPLATE_EVALUATION_DURATION: &str =
+    "sqlx_ledger_tx_template_evaluation_duration_seconds";
+/// Histogram of the seconds spent selecting the balances to update `FOR UPDATE`, lock waits included.
+pub const BALANCE_LOCK_WAIT_DURATION: &str = "sqlx_ledger_balance_lock_wait_duration_seconds";
+
+/// Registers units and descriptions of the ledger's metrics with the installed recorder.
+#[cfg(feature = "metrics")]
+pub fn describe() {
+    use metrics::{describe_counter, describe_histogram, Unit};
+    describe_counter!(TRANSACTIONS_POSTED, "Transactions posted");
+    describe_histogram!(POST_DURATION, Unit::Seconds, "Time to post transactions");
+    describe_histogram!(
+        TX_TEMPLATE_EVALUATION_DURATION,
+        Unit::Seconds,
+        "Time to evaluate a tx template"
+    );
+    describe_histogram!(
+        BALANCE_LOCK_WAIT_DURATION,
+        Unit::Seconds,
+        "Time waiting for balance locks"
+    );
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn transaction_posted(tx_template_code: &str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(TRANSACTIONS_POSTED, "tx_template_code" => tx_template_code.to_string())
+        .increment(1);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_duration(name: &'static str, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(name).record(elapsed.as_secs_f64());
+}
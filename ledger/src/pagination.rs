@@ -0,0 +1,13 @@
+/// Keyset pagination: up to `first` entities ordered after the `after` cursor.
+#[derive(Debug, Clone)]
+pub struct PaginatedQueryArgs<C> {
+    pub first: usize,
+    pub after: Option<C>,
+}
+
+#[derive(Debug)]
+pub struct PaginatedQueryRet<T, C> {
+    pub entities: Vec<T>,
+    pub has_next_page: bool,
+    pub end_cursor: Option<C>,
+}
@@ -0,0 +1,207 @@
+use chrono::{Datelike, Months, NaiveDate};
+use sqlx::{Pool, Postgres, Row, Transaction};
+use tracing::instrument;
+
+use std::sync::Arc;
+
+use crate::{clock::*, error::*, journal::lock_all_journals_in_tx};
+
+const LEGACY_ENTRIES_PARTITION: &str = "sqlx_ledger_entries_legacy";
+const MONTHLY_ENTRIES_PARTITION_PREFIX: &str = "sqlx_ledger_entries_";
+
+/// Opt-in range partitioning of the entries table by `created_at` month.
+/// Partitions are created ahead of time: posting fails if no partition covers `now`.
+#[derive(Debug, Clone)]
+pub struct Partitions {
+    pool: Pool<Postgres>,
+    dry_run: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl Partitions {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+            clock: Arc::clone(&self.clock),
+        }
+    }
+
+    pub(crate) fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Turns the entries table into one partitioned by month. The existing rows stay in place
+    /// as a single partition up to the start of next month, from which on monthly partitions
+    /// take over. Returns `false` if the table is partitioned already.
+    ///
+    /// Takes an exclusive lock on the table while the existing rows are indexed and scanned.
+    /// The uniqueness of entries becomes per `(id, version, created_at)`.
+    #[instrument(name = "sqlx_ledger.partitions.partition_entries_by_month", skip(self))]
+    pub async fn partition_entries_by_month(&self) -> Result<bool, SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        lock_all_journals_in_tx(&mut tx).await?;
+        sqlx::query("LOCK TABLE sqlx_ledger_entries IN ACCESS EXCLUSIVE MODE")
+            .execute(&mut tx)
+            .await?;
+        let partitioned: bool = sqlx::query_scalar(
+            "SELECT relkind = 'p' FROM pg_class WHERE oid = 'sqlx_ledger_entries'::regclass",
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        if partitioned {
+            return Ok(false);
+        }
+        let today = self.clock.now().date_naive();
+        let bound = month_start(today) + Months::new(1);
+        for statement in [
+            format!("ALTER TABLE sqlx_ledger_entries RENAME TO {LEGACY_ENTRIES_PARTITION}"),
+            format!(
+                "ALTER TABLE {LEGACY_ENTRIES_PARTITION} RENAME CONSTRAINT sqlx_ledger_entries_id_version_key TO {LEGACY_ENTRIES_PARTITION}_id_version_key"
+            ),
+            format!(
+                "CREATE TABLE sqlx_ledger_entries (LIKE {LEGACY_ENTRIES_PARTITION} INCLUDING DEFAULTS) PARTITION BY RANGE (created_at)"
+            ),
+            "ALTER TABLE sqlx_ledger_entries ADD CONSTRAINT sqlx_ledger_entries_id_version_key UNIQUE (id, version, created_at)".to_string(),
+            format!(
+                "ALTER TABLE sqlx_ledger_entries ATTACH PARTITION {LEGACY_ENTRIES_PARTITION} FOR VALUES FROM (MINVALUE) TO ('{bound} 00:00:00+00')"
+            ),
+        ] {
+            sqlx::query(&statement).execute(&mut tx).await?;
+        }
+        create_monthly_in_tx(&mut tx, bound).await?;
+        self.finish(tx).await?;
+        Ok(true)
+    }
+
+    /// Creates the monthly partitions following the latest one, up to the one containing `until`.
+    /// Returns the names of the tables created.
+    #[instrument(name = "sqlx_ledger.partitions.create_entries_partitions", skip(self))]
+    pub async fn create_entries_partitions(
+        &self,
+        until: NaiveDate,
+    ) -> Result<Vec<String>, SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        lock_all_journals_in_tx(&mut tx).await?;
+        let months = monthly_partitions_in_tx(&mut tx).await?;
+        let mut created = Vec::new();
+        if let Some(latest) = months.last() {
+            let mut month = *latest + Months::new(1);
+            while month <= until {
+                created.push(create_monthly_in_tx(&mut tx, month).await?);
+                month = month + Months::new(1);
+            }
+        }
+        self.finish(tx).await?;
+        Ok(created)
+    }
+
+    /// Detaches the partitions holding only entries created before `before`, oldest first.
+    /// The detached tables are kept for archiving and no longer show up in any query.
+    /// Returns their names.
+    ///
+    /// Their entries drop out of [`Reports::trial_balance`](crate::report::Reports::trial_balance)
+    /// and of the closing balances of periods closed afterwards. Account balances and the closing
+    /// balances of periods closed already are not affected.
+    #[instrument(name = "sqlx_ledger.partitions.detach_entries_partitions", skip(self))]
+    pub async fn detach_entries_partitions(
+        &self,
+        before: NaiveDate,
+    ) -> Result<Vec<String>, SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        lock_all_journals_in_tx(&mut tx).await?;
+        let months = monthly_partitions_in_tx(&mut tx).await?;
+        let legacy_attached: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS (
+                 SELECT 1 FROM pg_inherits
+                 WHERE inhparent = 'sqlx_ledger_entries'::regclass AND inhrelid = to_regclass($1))"#,
+        )
+        .bind(LEGACY_ENTRIES_PARTITION)
+        .fetch_one(&mut tx)
+        .await?;
+        let mut detached = Vec::new();
+        // The legacy partition ends where the oldest monthly one starts
+        if legacy_attached && months.first().is_some_and(|first| *first <= before) {
+            detached.push(LEGACY_ENTRIES_PARTITION.to_string());
+        }
+        for month in months {
+            if month + Months::new(1) > before {
+                break;
+            }
+            detached.push(monthly_partition_name(month));
+        }
+        for name in detached.iter() {
+            sqlx::query(&format!(
+                "ALTER TABLE sqlx_ledger_entries DETACH PARTITION {name}"
+            ))
+            .execute(&mut tx)
+            .await?;
+        }
+        self.finish(tx).await?;
+        Ok(detached)
+    }
+
+    async fn finish(&self, tx: Transaction<'_, Postgres>) -> Result<(), SqlxLedgerError> {
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn monthly_partitions_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<NaiveDate>, SqlxLedgerError> {
+    let rows = sqlx::query(
+        r#"SELECT c.relname FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid
+           WHERE i.inhparent = 'sqlx_ledger_entries'::regclass"#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut months: Vec<NaiveDate> = rows
+        .iter()
+        .filter_map(|row| {
+            let name: String = row.get("relname");
+            let month = name.strip_prefix(MONTHLY_ENTRIES_PARTITION_PREFIX)?;
+            NaiveDate::parse_from_str(&format!("{month}_01"), "%Y_%m_%d").ok()
+        })
+        .collect();
+    months.sort();
+    Ok(months)
+}
+
+async fn create_monthly_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    start: NaiveDate,
+) -> Result<String, SqlxLedgerError> {
+    let name = monthly_partition_name(start);
+    let end = start + Months::new(1);
+    sqlx::query(&format!(
+        "CREATE TABLE {name} PARTITION OF sqlx_ledger_entries FOR VALUES FROM ('{start} 00:00:00+00') TO ('{end} 00:00:00+00')"
+    ))
+    .execute(&mut *tx)
+    .await?;
+    Ok(name)
+}
+
+fn monthly_partition_name(start: NaiveDate) -> String {
+    format!(
+        "{MONTHLY_ENTRIES_PARTITION_PREFIX}{:04}_{:02}",
+        start.year(),
+        start.month()
+    )
+}
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("first of month")
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+
+use crate::primitives::*;
+
+/// Range of effective dates from `start` (inclusive) to `end` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountingPeriod {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl AccountingPeriod {
+    pub fn new(start: NaiveDate, end: NaiveDate) -> Self {
+        Self { start, end }
+    }
+
+    /// The calendar month `month` (1-12) of `year`.
+    pub fn month(year: i32, month: u32) -> Option<Self> {
+        let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+        Some(Self {
+            start,
+            end: start.checked_add_months(Months::new(1))?,
+        })
+    }
+
+    /// The calendar month containing `date`.
+    pub fn month_of(date: NaiveDate) -> Self {
+        Self::month(date.year(), date.month()).expect("valid month")
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start <= date && date < self.end
+    }
+}
+
+/// A period of a journal in which no more transactions can be effective.
+#[derive(Debug, Clone)]
+pub struct ClosedPeriod {
+    pub journal_id: JournalId,
+    pub period: AccountingPeriod,
+    pub closed_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month() {
+        let period = AccountingPeriod::month(2023, 12).unwrap();
+        assert_eq!(period.start, NaiveDate::from_ymd_opt(2023, 12, 1).unwrap());
+        assert_eq!(period.end, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        assert!(period.contains(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()));
+        assert!(!period.contains(period.end));
+        assert_eq!(
+            AccountingPeriod::month_of(NaiveDate::from_ymd_opt(2023, 12, 15).unwrap()),
+            period
+        );
+        assert!(AccountingPeriod::month(2023, 13).is_none());
+    }
+}
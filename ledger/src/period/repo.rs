@@ -0,0 +1,206 @@
+use chrono::{Duration, NaiveDate};
+use sqlx::{Pool, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::entity::*;
+use crate::{
+    balance::EffectiveBalance,
+    error::*,
+    journal::{lock_journals_in_tx, lock_journals_shared_in_tx},
+    primitives::*,
+};
+
+#[derive(Debug, Clone)]
+pub struct Periods {
+    pool: Pool<Postgres>,
+    dry_run: bool,
+}
+
+impl Periods {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+        }
+    }
+
+    /// Closes `period` of the journal and records the balance of each account at its end.
+    /// Waits for in-flight postings to the journal; later postings effective inside the period fail.
+    /// The balances are summed from the entries, so any in detached partitions are left out.
+    #[instrument(name = "sqlx_ledger.periods.close", skip(self))]
+    pub async fn close(
+        &self,
+        journal_id: JournalId,
+        period: AccountingPeriod,
+    ) -> Result<(), SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        lock_journals_in_tx(&mut tx, vec![journal_id]).await?;
+        let journal_exists = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM sqlx_ledger_journals WHERE id = $1) as "exists!""#,
+            Uuid::from(journal_id)
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .exists;
+        if !journal_exists {
+            return Err(SqlxLedgerError::JournalNotFound(journal_id));
+        }
+        let overlapping = sqlx::query!(
+            r#"SELECT period_start FROM sqlx_ledger_closed_periods
+               WHERE journal_id = $1 AND period_start < $3 AND $2 < period_end LIMIT 1"#,
+            Uuid::from(journal_id),
+            period.start,
+            period.end
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        if let Some(overlapping) = overlapping {
+            return Err(SqlxLedgerError::PeriodAlreadyClosed(
+                journal_id,
+                overlapping.period_start,
+            ));
+        }
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_closed_periods (journal_id, period_start, period_end) VALUES ($1, $2, $3)"#,
+            Uuid::from(journal_id),
+            period.start,
+            period.end
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_period_closing_balances
+                 (journal_id, period_start, account_id, currency,
+                  settled_dr_balance, settled_cr_balance, pending_dr_balance, pending_cr_balance,
+                  encumbered_dr_balance, encumbered_cr_balance)
+               SELECT $1, $2, e.account_id, e.currency,
+                 COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'settled' AND e.direction = 'debit'), 0),
+                 COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'settled' AND e.direction = 'credit'), 0),
+                 COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'pending' AND e.direction = 'debit'), 0),
+                 COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'pending' AND e.direction = 'credit'), 0),
+                 COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'encumbered' AND e.direction = 'debit'), 0),
+                 COALESCE(SUM(e.units) FILTER (WHERE e.layer = 'encumbered' AND e.direction = 'credit'), 0)
+               FROM sqlx_ledger_entries e
+               JOIN sqlx_ledger_transactions t ON t.id = e.transaction_id AND t.version = 1
+               WHERE e.journal_id = $1 AND t.effective < $3
+               GROUP BY e.account_id, e.currency"#,
+            Uuid::from(journal_id),
+            period.start,
+            period.end
+        )
+        .execute(&mut tx)
+        .await?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "sqlx_ledger.periods.list_closed", skip(self))]
+    pub async fn list_closed(
+        &self,
+        journal_id: JournalId,
+    ) -> Result<Vec<ClosedPeriod>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT period_start, period_end, closed_at FROM sqlx_ledger_closed_periods
+               WHERE journal_id = $1 ORDER BY period_start"#,
+            Uuid::from(journal_id)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|r| ClosedPeriod {
+                journal_id,
+                period: AccountingPeriod::new(r.period_start, r.period_end),
+                closed_at: r.closed_at,
+            })
+            .collect())
+    }
+
+    /// Balances recorded when the period starting at `period_start` was closed, ordered by account and currency.
+    #[instrument(name = "sqlx_ledger.periods.closing_balances", skip(self))]
+    pub async fn closing_balances(
+        &self,
+        journal_id: JournalId,
+        period_start: NaiveDate,
+    ) -> Result<Vec<EffectiveBalance>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT b.account_id, b.currency, p.period_end,
+                 (SELECT normal_balance_type FROM sqlx_ledger_accounts WHERE id = b.account_id LIMIT 1)
+                   as "normal_balance_type: DebitOrCredit",
+                 settled_dr_balance, settled_cr_balance, pending_dr_balance, pending_cr_balance,
+                 encumbered_dr_balance, encumbered_cr_balance
+               FROM sqlx_ledger_period_closing_balances b
+               JOIN sqlx_ledger_closed_periods p ON p.journal_id = b.journal_id AND p.period_start = b.period_start
+               WHERE b.journal_id = $1 AND b.period_start = $2
+               ORDER BY b.account_id, b.currency"#,
+            Uuid::from(journal_id),
+            period_start
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut balances = Vec::new();
+        for r in records {
+            balances.push(EffectiveBalance {
+                balance_type: r.normal_balance_type.unwrap_or_default(),
+                journal_id,
+                account_id: AccountId::from(r.account_id),
+                currency: r.currency.parse()?,
+                effective: r.period_end - Duration::days(1),
+                settled_dr: r.settled_dr_balance,
+                settled_cr: r.settled_cr_balance,
+                pending_dr: r.pending_dr_balance,
+                pending_cr: r.pending_cr_balance,
+                encumbered_dr: r.encumbered_dr_balance,
+                encumbered_cr: r.encumbered_cr_balance,
+            });
+        }
+        Ok(balances)
+    }
+
+    /// Holds off closes of the journals until `tx` ends and fails if any of the
+    /// effective dates falls inside a closed period of its journal.
+    pub(crate) async fn ensure_open_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        mut effective: Vec<(JournalId, NaiveDate)>,
+    ) -> Result<(), SqlxLedgerError> {
+        effective.sort();
+        effective.dedup();
+        lock_journals_shared_in_tx(tx, effective.iter().map(|(id, _)| *id).collect()).await?;
+        let (journal_ids, dates): (Vec<Uuid>, Vec<NaiveDate>) = effective
+            .into_iter()
+            .map(|(journal_id, date)| (Uuid::from(journal_id), date))
+            .unzip();
+        let closed = sqlx::query!(
+            r#"SELECT d.journal_id as "journal_id!", d.effective as "effective!"
+               FROM unnest($1::uuid[], $2::date[]) AS d(journal_id, effective)
+               WHERE EXISTS (
+                 SELECT 1 FROM sqlx_ledger_closed_periods p
+                 WHERE p.journal_id = d.journal_id AND p.period_start <= d.effective AND d.effective < p.period_end)
+               LIMIT 1"#,
+            &journal_ids[..],
+            &dates[..]
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        match closed {
+            Some(closed) => Err(SqlxLedgerError::PeriodClosed(
+                JournalId::from(closed.journal_id),
+                closed.effective,
+            )),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,15 +1,23 @@
 use crate::error::*;
 use cel_interpreter::{CelResult, CelValue};
+use rust_decimal::Decimal;
 use rusty_money::{crypto, iso};
 
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+
 crate::entity_id! { AccountId }
+crate::entity_id! { AccountSetId }
 crate::entity_id! { JournalId }
 crate::entity_id! { TransactionId }
 crate::entity_id! { EntryId }
 crate::entity_id! { TxTemplateId }
 crate::entity_id! { CorrelationId }
+crate::entity_id! { VelocityLimitId }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
 #[sqlx(type_name = "Layer", rename_all = "snake_case")]
 pub enum Layer {
     Settled,
@@ -17,6 +25,16 @@ pub enum Layer {
     Encumbered,
 }
 
+impl From<Layer> for CelValue {
+    fn from(layer: Layer) -> Self {
+        match layer {
+            Layer::Settled => CelValue::from("SETTLED"),
+            Layer::Pending => CelValue::from("PENDING"),
+            Layer::Encumbered => CelValue::from("ENCUMBERED"),
+        }
+    }
+}
+
 impl<'a> TryFrom<CelResult<'a>> for Layer {
     type Error = SqlxLedgerError;
 
@@ -30,13 +48,55 @@ impl<'a> TryFrom<CelResult<'a>> for Layer {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Layer::Settled => write!(f, "SETTLED"),
+            Layer::Pending => write!(f, "PENDING"),
+            Layer::Encumbered => write!(f, "ENCUMBERED"),
+        }
+    }
+}
+
+impl std::str::FromStr for Layer {
+    type Err = SqlxLedgerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "SETTLED" => Ok(Layer::Settled),
+            "PENDING" => Ok(Layer::Pending),
+            "ENCUMBERED" => Ok(Layer::Encumbered),
+            _ => Err(SqlxLedgerError::UnknownLayer(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, sqlx::Type)]
 #[sqlx(type_name = "DebitOrCredit", rename_all = "snake_case")]
 pub enum DebitOrCredit {
     Debit,
+    #[default]
     Credit,
 }
 
+impl DebitOrCredit {
+    pub(crate) fn opposite(&self) -> Self {
+        match self {
+            DebitOrCredit::Debit => DebitOrCredit::Credit,
+            DebitOrCredit::Credit => DebitOrCredit::Debit,
+        }
+    }
+}
+
+impl From<DebitOrCredit> for CelValue {
+    fn from(direction: DebitOrCredit) -> Self {
+        match direction {
+            DebitOrCredit::Debit => CelValue::from("DEBIT"),
+            DebitOrCredit::Credit => CelValue::from("CREDIT"),
+        }
+    }
+}
+
 impl<'a> TryFrom<CelResult<'a>> for DebitOrCredit {
     type Error = SqlxLedgerError;
 
@@ -49,28 +109,52 @@ impl<'a> TryFrom<CelResult<'a>> for DebitOrCredit {
     }
 }
 
-impl Default for DebitOrCredit {
-    fn default() -> Self {
-        Self::Credit
+impl std::fmt::Display for DebitOrCredit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DebitOrCredit::Debit => write!(f, "DEBIT"),
+            DebitOrCredit::Credit => write!(f, "CREDIT"),
+        }
+    }
+}
+
+impl std::str::FromStr for DebitOrCredit {
+    type Err = SqlxLedgerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "DEBIT" => Ok(DebitOrCredit::Debit),
+            "CREDIT" => Ok(DebitOrCredit::Credit),
+            _ => Err(SqlxLedgerError::UnknownDebitOrCredit(s.to_string())),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, sqlx::Type)]
 #[sqlx(type_name = "Status", rename_all = "snake_case")]
 pub enum Status {
+    #[default]
     Active,
 }
 
-impl Default for Status {
-    fn default() -> Self {
-        Self::Active
-    }
-}
-
 #[derive(Debug, Clone, Copy, Eq)]
 pub enum Currency {
     Iso(&'static iso::Currency),
     Crypto(&'static crypto::Currency),
+    Custom(&'static CustomCurrency),
+}
+
+/// A unit registered through `Currencies::register`, e.g. `SATS` with exponent 0.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CustomCurrency {
+    pub code: &'static str,
+    pub exponent: u32,
+}
+
+fn custom_currencies() -> &'static RwLock<HashMap<&'static str, &'static CustomCurrency>> {
+    static CUSTOM_CURRENCIES: OnceLock<RwLock<HashMap<&'static str, &'static CustomCurrency>>> =
+        OnceLock::new();
+    CUSTOM_CURRENCIES.get_or_init(Default::default)
 }
 
 impl Currency {
@@ -78,6 +162,50 @@ impl Currency {
         match self {
             Currency::Iso(c) => c.iso_alpha_code,
             Currency::Crypto(c) => c.code,
+            Currency::Custom(c) => c.code,
+        }
+    }
+
+    /// Number of decimal places of the minor unit.
+    pub fn exponent(&self) -> u32 {
+        match self {
+            Currency::Iso(c) => c.exponent,
+            Currency::Crypto(c) => c.exponent,
+            Currency::Custom(c) => c.exponent,
+        }
+    }
+
+    /// Makes `code` parseable in this process. Built-in codes can't be redefined
+    /// and a custom code keeps the exponent it was first registered with.
+    pub(crate) fn register_custom(code: &str, exponent: u32) -> Result<Self, SqlxLedgerError> {
+        let mut registry = custom_currencies()
+            .write()
+            .expect("poisoned currency registry");
+        if let Some(existing) = registry.get(code) {
+            if existing.exponent != exponent {
+                return Err(SqlxLedgerError::CurrencyAlreadyRegistered(code.to_string()));
+            }
+            return Ok(Currency::Custom(existing));
+        }
+        if iso::find(code).is_some() || crypto::find(code).is_some() {
+            return Err(SqlxLedgerError::CurrencyAlreadyRegistered(code.to_string()));
+        }
+        let currency: &'static CustomCurrency = Box::leak(Box::new(CustomCurrency {
+            code: Box::leak(code.to_string().into_boxed_str()),
+            exponent,
+        }));
+        registry.insert(currency.code, currency);
+        Ok(Currency::Custom(currency))
+    }
+
+    /// Fails if `units` has more decimal places than a custom currency's exponent allows.
+    /// Built-in currencies accept any precision.
+    pub(crate) fn check_units(&self, units: Decimal) -> Result<(), SqlxLedgerError> {
+        match self {
+            Currency::Custom(c) if units.normalize().scale() > c.exponent => {
+                Err(SqlxLedgerError::InvalidUnitsPrecision(*self, units))
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -108,7 +236,14 @@ impl std::str::FromStr for Currency {
             Some(c) => Ok(Currency::Iso(c)),
             _ => match crypto::find(s) {
                 Some(c) => Ok(Currency::Crypto(c)),
-                _ => Err(SqlxLedgerError::UnknownCurrency(s.to_string())),
+                _ => match custom_currencies()
+                    .read()
+                    .expect("poisoned currency registry")
+                    .get(s)
+                {
+                    Some(c) => Ok(Currency::Custom(c)),
+                    _ => Err(SqlxLedgerError::UnknownCurrency(s.to_string())),
+                },
             },
         }
     }
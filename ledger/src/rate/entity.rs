@@ -0,0 +1,53 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use std::collections::HashMap;
+
+use crate::primitives::*;
+
+/// Units of `quote` one unit of `base` was worth from `as_of` on.
+#[derive(Debug, Clone)]
+pub struct ExchangeRate {
+    pub base: Currency,
+    pub quote: Currency,
+    pub rate: Decimal,
+    pub as_of: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The latest rate of every currency pair at a point in time.
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeRates {
+    pub(super) rates: HashMap<(Currency, Currency), Decimal>,
+}
+
+impl ExchangeRates {
+    /// Rate converting `from` into `to`, inverting the reverse pair if only that one is set.
+    pub fn rate(&self, from: Currency, to: Currency) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::ONE);
+        }
+        self.rates.get(&(from, to)).copied().or_else(|| {
+            self.rates
+                .get(&(to, from))
+                .and_then(|rate| Decimal::ONE.checked_div(*rate))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverts_reverse_pair() {
+        let btc: Currency = "BTC".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let mut rates = ExchangeRates::default();
+        rates.rates.insert((btc, usd), Decimal::from(20_000));
+        assert_eq!(rates.rate(btc, usd), Some(Decimal::from(20_000)));
+        assert_eq!(rates.rate(usd, btc), Some(Decimal::new(5, 5)));
+        assert_eq!(rates.rate(usd, usd), Some(Decimal::ONE));
+        assert_eq!(rates.rate(usd, "EUR".parse().unwrap()), None);
+    }
+}
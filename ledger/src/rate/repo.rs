@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use sqlx::{Pool, Postgres};
+use tracing::instrument;
+
+use super::entity::*;
+use crate::{error::*, primitives::*};
+
+#[derive(Debug, Clone)]
+pub struct Rates {
+    pool: Pool<Postgres>,
+    dry_run: bool,
+}
+
+impl Rates {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+        }
+    }
+
+    /// Records that one `base` is worth `rate` units of `quote` from `as_of` on.
+    #[instrument(name = "sqlx_ledger.rates.set", skip(self))]
+    pub async fn set(
+        &self,
+        base: Currency,
+        quote: Currency,
+        rate: Decimal,
+        as_of: DateTime<Utc>,
+    ) -> Result<(), SqlxLedgerError> {
+        if base == quote || rate <= Decimal::ZERO {
+            return Err(SqlxLedgerError::InvalidExchangeRate(base, quote, rate));
+        }
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_exchange_rates (base, quote, rate, as_of) VALUES ($1, $2, $3, $4)
+               ON CONFLICT (base, quote, as_of) DO UPDATE SET rate = EXCLUDED.rate"#,
+            base.code(),
+            quote.code(),
+            rate,
+            as_of
+        )
+        .execute(&mut tx)
+        .await?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// The rate of the pair in effect at `at`.
+    #[instrument(name = "sqlx_ledger.rates.find", skip(self))]
+    pub async fn find(
+        &self,
+        base: Currency,
+        quote: Currency,
+        at: DateTime<Utc>,
+    ) -> Result<Option<ExchangeRate>, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT rate, as_of, created_at FROM sqlx_ledger_exchange_rates
+               WHERE base = $1 AND quote = $2 AND as_of <= $3
+               ORDER BY as_of DESC LIMIT 1"#,
+            base.code(),
+            quote.code(),
+            at
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(record.map(|r| ExchangeRate {
+            base,
+            quote,
+            rate: r.rate,
+            as_of: r.as_of,
+            created_at: r.created_at,
+        }))
+    }
+
+    pub(crate) async fn find_all_at(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<ExchangeRates, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT DISTINCT ON (base, quote) base, quote, rate FROM sqlx_ledger_exchange_rates
+               WHERE as_of <= $1 ORDER BY base, quote, as_of DESC"#,
+            at
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut rates = ExchangeRates::default();
+        for r in records {
+            rates
+                .rates
+                .insert((r.base.parse()?, r.quote.parse()?), r.rate);
+        }
+        Ok(rates)
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::primitives::*;
+
+/// Settled debit and credit totals of every account of a journal with entries
+/// effective on or before `as_of`, ordered by account code and currency.
+#[derive(Debug, Clone)]
+pub struct TrialBalance {
+    pub journal_id: JournalId,
+    pub as_of: NaiveDate,
+    pub lines: Vec<TrialBalanceLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrialBalanceLine {
+    pub account_id: AccountId,
+    pub account_code: String,
+    pub account_name: String,
+    pub normal_balance_type: DebitOrCredit,
+    pub currency: Currency,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+impl TrialBalanceLine {
+    /// Balance in the account's normal direction.
+    pub fn net(&self) -> Decimal {
+        if self.normal_balance_type == DebitOrCredit::Credit {
+            self.credit - self.debit
+        } else {
+            self.debit - self.credit
+        }
+    }
+}
+
+impl TrialBalance {
+    /// Total debits and credits of the lines in `currency`.
+    pub fn totals(&self, currency: Currency) -> (Decimal, Decimal) {
+        self.lines
+            .iter()
+            .filter(|line| line.currency == currency)
+            .fold((Decimal::ZERO, Decimal::ZERO), |(dr, cr), line| {
+                (dr + line.debit, cr + line.credit)
+            })
+    }
+
+    /// Whether debits equal credits in every currency.
+    pub fn is_balanced(&self) -> bool {
+        self.lines.iter().all(|line| {
+            let (dr, cr) = self.totals(line.currency);
+            dr == cr
+        })
+    }
+}
@@ -0,0 +1,63 @@
+use chrono::NaiveDate;
+use sqlx::{Pool, Postgres};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::entity::*;
+use crate::{error::*, primitives::*};
+
+#[derive(Debug, Clone)]
+pub struct Reports {
+    pool: Pool<Postgres>,
+}
+
+impl Reports {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        Self { pool: pool.clone() }
+    }
+
+    /// Debits and credits of the settled entries effective up to `as_of`, per account and currency.
+    /// Entries in detached partitions are left out.
+    #[instrument(name = "sqlx_ledger.reports.trial_balance", skip(self))]
+    pub async fn trial_balance(
+        &self,
+        journal_id: JournalId,
+        as_of: NaiveDate,
+    ) -> Result<TrialBalance, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT e.account_id, a.code, a.name, a.normal_balance_type as "normal_balance_type: DebitOrCredit", e.currency,
+                 COALESCE(SUM(e.units) FILTER (WHERE e.direction = 'debit'), 0) as "debit!",
+                 COALESCE(SUM(e.units) FILTER (WHERE e.direction = 'credit'), 0) as "credit!"
+               FROM sqlx_ledger_entries e
+               JOIN sqlx_ledger_transactions t ON t.id = e.transaction_id AND t.version = 1
+               JOIN LATERAL (
+                 SELECT code, name, normal_balance_type FROM sqlx_ledger_accounts
+                 WHERE id = e.account_id ORDER BY version DESC LIMIT 1
+               ) a ON TRUE
+               WHERE e.journal_id = $1 AND e.layer = 'settled' AND t.effective <= $2
+               GROUP BY e.account_id, a.code, a.name, a.normal_balance_type, e.currency
+               ORDER BY a.code, e.currency"#,
+            Uuid::from(journal_id),
+            as_of
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut lines = Vec::with_capacity(records.len());
+        for r in records {
+            lines.push(TrialBalanceLine {
+                account_id: AccountId::from(r.account_id),
+                account_code: r.code,
+                account_name: r.name,
+                normal_balance_type: r.normal_balance_type,
+                currency: r.currency.parse()?,
+                debit: r.debit,
+                credit: r.credit,
+            });
+        }
+        Ok(TrialBalance {
+            journal_id,
+            as_of,
+            lines,
+        })
+    }
+}
@@ -1,21 +1,181 @@
-use crate::primitives::*;
-use chrono::NaiveDate;
+use crate::{
+    balance::BalanceConstraint,
+    entry::{Entry, ImportedEntry},
+    primitives::*,
+};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
 use derive_builder::Builder;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 
-#[derive(Builder)]
+pub struct Transaction {
+    pub id: TransactionId,
+    pub version: u32,
+    pub journal_id: JournalId,
+    pub tx_template_id: TxTemplateId,
+    /// Version of the template the transaction was posted with.
+    pub tx_template_version: u32,
+    pub effective: NaiveDate,
+    pub correlation_id: CorrelationId,
+    pub external_id: String,
+    pub description: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub actor_id: Option<String>,
+    /// The transaction this one reverses.
+    pub voids: Option<TransactionId>,
+    /// The reversal posted when this transaction was voided.
+    pub voided_by: Option<TransactionId>,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where a transaction stands with respect to voids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    /// Not voided and not a reversal.
+    Posted,
+    /// Reversed by a void.
+    Voided,
+    /// The reversal posted by a void.
+    Reversal,
+}
+
+impl TransactionStatus {
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Posted => "posted",
+            TransactionStatus::Voided => "voided",
+            TransactionStatus::Reversal => "reversal",
+        }
+    }
+}
+
+impl Transaction {
+    /// A voided reversal counts as voided.
+    pub fn status(&self) -> TransactionStatus {
+        if self.voided_by.is_some() {
+            TransactionStatus::Voided
+        } else if self.voids.is_some() {
+            TransactionStatus::Reversal
+        } else {
+            TransactionStatus::Posted
+        }
+    }
+
+    /// Hex encoded SHA-256 over the immutable content of the transaction and its `entries`.
+    /// Entries are sorted and decimals normalized, so the digest depends only on what was
+    /// posted and not on how it was loaded. Metadata and void links can change after posting
+    /// and are left out, so every version of a transaction has the same digest.
+    pub fn content_hash(&self, entries: &[Entry]) -> String {
+        let mut lines: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                let conversion = entry.conversion.map(|conversion| {
+                    vec![
+                        conversion.from_currency.code().to_string(),
+                        conversion.from_units.normalize().to_string(),
+                        conversion.rate.normalize().to_string(),
+                    ]
+                });
+                Value::from(vec![
+                    Value::from(entry.id.to_string()),
+                    Value::from(entry.account_id.to_string()),
+                    Value::from(entry.entry_type.clone()),
+                    Value::from(format!("{:?}", entry.layer)),
+                    Value::from(format!("{:?}", entry.direction)),
+                    Value::from(entry.units.normalize().to_string()),
+                    Value::from(entry.currency.code().to_string()),
+                    Value::from(entry.sequence.to_string()),
+                    Value::from(entry.description.clone().unwrap_or_default()),
+                    Value::from(conversion),
+                ])
+            })
+            .collect();
+        lines.sort_by_key(|line| line.to_string());
+        let canonical = Value::from(vec![
+            Value::from(self.id.to_string()),
+            Value::from(self.journal_id.to_string()),
+            Value::from(self.tx_template_id.to_string()),
+            Value::from(self.effective.to_string()),
+            Value::from(self.correlation_id.to_string()),
+            Value::from(self.external_id.clone()),
+            Value::from(self.description.clone()),
+            Value::from(self.created_at.to_rfc3339_opts(SecondsFormat::Micros, true)),
+            Value::from(lines),
+        ]);
+        format!("{:x}", Sha256::digest(canonical.to_string()))
+    }
+}
+
+/// Criteria for `Transactions::list`. Unset fields don't filter.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    pub journal_id: Option<JournalId>,
+    /// Transactions with at least one entry on the account.
+    pub account_id: Option<AccountId>,
+    pub tx_template_code: Option<String>,
+    /// Inclusive lower bound on `created_at`.
+    pub created_from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on `created_at`.
+    pub created_until: Option<DateTime<Utc>>,
+    pub external_id_prefix: Option<String>,
+    /// Transactions whose metadata contains this value (JSONB `@>`).
+    pub metadata: Option<Value>,
+    /// Transactions in any of these statuses.
+    pub statuses: Option<Vec<TransactionStatus>>,
+}
+
+/// A historical transaction for `SqlxLedger::import`, written as is without evaluating a template.
+#[derive(Builder, Debug, Clone)]
+pub struct ImportedTransaction {
+    #[builder(setter(into))]
+    pub(crate) id: TransactionId,
+    #[builder(setter(into))]
+    pub(crate) journal_id: JournalId,
+    #[builder(setter(into))]
+    pub(crate) tx_template_id: TxTemplateId,
+    pub(crate) effective: NaiveDate,
+    #[builder(setter(strip_option, into), default)]
+    pub(crate) external_id: Option<String>,
+    #[builder(setter(strip_option, into), default)]
+    pub(crate) description: Option<String>,
+    #[builder(setter(strip_option), default)]
+    pub(crate) metadata: Option<Value>,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) entries: Vec<ImportedEntry>,
+}
+
+impl ImportedTransaction {
+    pub fn builder() -> ImportedTransactionBuilder {
+        let mut builder = ImportedTransactionBuilder::default();
+        builder.id(TransactionId::new());
+        builder
+    }
+}
+
+#[derive(Builder, Clone)]
 pub(crate) struct NewTransaction {
     #[builder(setter(into))]
-    pub(super) journal_id: JournalId,
-    pub(super) tx_template_id: TxTemplateId,
-    pub(super) effective: NaiveDate,
+    pub(crate) journal_id: JournalId,
+    pub(crate) tx_template_id: TxTemplateId,
+    pub(super) tx_template_version: u32,
+    pub(crate) effective: NaiveDate,
     #[builder(setter(strip_option), default)]
-    pub(super) correlation_id: Option<CorrelationId>,
+    pub(crate) correlation_id: Option<CorrelationId>,
     #[builder(setter(strip_option), default)]
-    pub(super) external_id: Option<String>,
+    pub(crate) external_id: Option<String>,
     #[builder(setter(strip_option), default)]
     pub(super) description: Option<String>,
     #[builder(setter(strip_option), default)]
     pub(super) metadata: Option<serde_json::Value>,
+    #[builder(setter(strip_option), default)]
+    pub(super) actor_id: Option<String>,
+    #[builder(setter(strip_option), default)]
+    pub(super) voids: Option<TransactionId>,
+    pub(super) created_at: DateTime<Utc>,
+    /// Checked once the transaction is applied. Not persisted.
+    #[builder(default)]
+    pub(crate) balance_constraints: Vec<BalanceConstraint>,
 }
 
 impl NewTransaction {
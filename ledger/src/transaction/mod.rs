@@ -2,4 +2,8 @@ mod entity;
 mod repo;
 
 pub(crate) use entity::*;
+pub use entity::{
+    ImportedTransaction, ImportedTransactionBuilder, Transaction, TransactionFilter,
+    TransactionStatus,
+};
 pub use repo::*;
@@ -1,50 +1,441 @@
-use sqlx::{Pool, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Postgres, QueryBuilder, Transaction as DbTransaction};
+use tracing::instrument;
 use uuid::Uuid;
 
+use std::collections::HashMap;
+
 use super::entity::*;
-use crate::{error::*, primitives::*};
+use crate::{
+    copy::CopyRows, error::*, journal::ensure_journals_exist, pagination::*, primitives::*,
+};
+
+// Postgres accepts at most 65535 bind parameters per statement
+const TRANSACTIONS_PER_STATEMENT: usize = 4096;
+
+pub(crate) const TRANSACTIONS_EXTERNAL_ID_CONSTRAINT: &str =
+    "sqlx_ledger_transactions_external_id_version_key";
 
 #[derive(Debug, Clone)]
 pub struct Transactions {
-    _pool: Pool<Postgres>,
+    pool: Pool<Postgres>,
+    dry_run: bool,
 }
 
 impl Transactions {
     pub fn new(pool: &Pool<Postgres>) -> Self {
         Self {
-            _pool: pool.clone(),
+            pool: pool.clone(),
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
         }
     }
 
     pub(crate) async fn create_in_tx(
         &self,
-        tx: &mut Transaction<'_, Postgres>,
+        tx: &mut DbTransaction<'_, Postgres>,
         NewTransaction {
             journal_id,
             tx_template_id,
+            tx_template_version,
             effective,
             correlation_id,
             external_id,
             description,
             metadata,
+            actor_id,
+            voids,
+            created_at,
+            ..
         }: NewTransaction,
     ) -> Result<(JournalId, TransactionId), SqlxLedgerError> {
         let id = Uuid::new_v4();
         let record = sqlx::query!(
-            r#"INSERT INTO sqlx_ledger_transactions (id, version, journal_id, tx_template_id, effective, correlation_id, external_id, description, metadata)
-            VALUES ($1, 1, (SELECT id FROM sqlx_ledger_journals WHERE id = $2 LIMIT 1), (SELECT id FROM sqlx_ledger_tx_templates WHERE id = $3 LIMIT 1), $4, $5, $6, $7, $8)
+            r#"INSERT INTO sqlx_ledger_transactions (id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, modified_at, created_at)
+            VALUES ($1, 1, (SELECT id FROM sqlx_ledger_journals WHERE id = $2 LIMIT 1), (SELECT id FROM sqlx_ledger_tx_templates WHERE id = $3 LIMIT 1), $4, $5, $6, $7, $8, $9, $10, $11, $12, $12)
             RETURNING id, version, created_at"#,
             id,
             Uuid::from(journal_id),
             Uuid::from(tx_template_id),
+            tx_template_version as i32,
             effective,
             correlation_id.map(Uuid::from).unwrap_or(id),
             external_id.unwrap_or_else(|| id.to_string()),
             description,
-            metadata
+            metadata,
+            actor_id,
+            voids.map(Uuid::from),
+            created_at
         )
         .fetch_one(&mut *tx)
-        .await?;
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(err) if is_missing_journal(&*err) => {
+                SqlxLedgerError::JournalNotFound(journal_id)
+            }
+            e => SqlxLedgerError::from(e),
+        })?;
         Ok((journal_id, TransactionId::from(record.id)))
     }
+
+    /// Inserts several transactions with as few statements as possible.
+    /// Ids are returned in the order the transactions were given.
+    pub(crate) async fn create_all_in_tx(
+        &self,
+        tx: &mut DbTransaction<'_, Postgres>,
+        mut new_transactions: Vec<NewTransaction>,
+    ) -> Result<Vec<TransactionId>, SqlxLedgerError> {
+        let journal_ids: Vec<_> = new_transactions.iter().map(|t| t.journal_id).collect();
+        let mut ids = Vec::new();
+        while !new_transactions.is_empty() {
+            let rest =
+                new_transactions.split_off(new_transactions.len().min(TRANSACTIONS_PER_STATEMENT));
+            let chunk = std::mem::replace(&mut new_transactions, rest);
+            let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+                r#"INSERT INTO sqlx_ledger_transactions (id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, modified_at, created_at)"#,
+            );
+            query_builder.push_values(chunk, |mut builder, new_tx| {
+                let id = Uuid::new_v4();
+                ids.push(TransactionId::from(id));
+                builder.push_bind(id);
+                builder.push_bind(1);
+                builder
+                    .push("(SELECT id FROM sqlx_ledger_journals WHERE id = ")
+                    .push_bind_unseparated(Uuid::from(new_tx.journal_id))
+                    .push_unseparated(" LIMIT 1)");
+                builder.push_bind(Uuid::from(new_tx.tx_template_id));
+                builder.push_bind(new_tx.tx_template_version as i32);
+                builder.push_bind(new_tx.effective);
+                builder.push_bind(new_tx.correlation_id.map(Uuid::from).unwrap_or(id));
+                builder.push_bind(new_tx.external_id.unwrap_or_else(|| id.to_string()));
+                builder.push_bind(new_tx.description);
+                builder.push_bind(new_tx.metadata);
+                builder.push_bind(new_tx.actor_id);
+                builder.push_bind(new_tx.voids.map(Uuid::from));
+                builder.push_bind(new_tx.created_at);
+                builder.push_bind(new_tx.created_at);
+            });
+            if let Err(e) = query_builder.build().execute(&mut *tx).await {
+                return Err(match e {
+                    sqlx::Error::Database(err) if is_missing_journal(&*err) => {
+                        // `tx` is aborted, the journals are looked up outside of it
+                        match ensure_journals_exist(&self.pool, journal_ids).await {
+                            Ok(()) => SqlxLedgerError::from(sqlx::Error::Database(err)),
+                            Err(e) => e,
+                        }
+                    }
+                    e => SqlxLedgerError::from(e),
+                });
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Writes historical transactions with `COPY`. Their entries are written separately.
+    pub(crate) async fn copy_in_tx(
+        &self,
+        tx: &mut DbTransaction<'_, Postgres>,
+        transactions: &[ImportedTransaction],
+    ) -> Result<(), SqlxLedgerError> {
+        let mut rows = CopyRows::default();
+        for t in transactions {
+            rows.push(t.id)
+                .push(t.journal_id)
+                .push(t.tx_template_id)
+                .push(t.id)
+                .push(t.effective)
+                .push(t.external_id.clone().unwrap_or_else(|| t.id.to_string()))
+                .push_opt(t.description.as_ref())
+                .push_opt(t.metadata.as_ref())
+                .push(t.created_at.to_rfc3339())
+                .push(t.created_at.to_rfc3339());
+            rows.end_row();
+        }
+        let mut copy = tx
+            .copy_in_raw(
+                r#"COPY sqlx_ledger_transactions (id, journal_id, tx_template_id, correlation_id, effective, external_id, description, metadata, modified_at, created_at) FROM STDIN"#,
+            )
+            .await?;
+        copy.send(rows.into_bytes()).await?;
+        copy.finish().await?;
+        Ok(())
+    }
+
+    /// Transactions already posted by a template under any of the given external ids.
+    pub(crate) async fn find_ids_by_external_ids(
+        &self,
+        tx: &mut DbTransaction<'_, Postgres>,
+        external_ids: Vec<String>,
+    ) -> Result<HashMap<(TxTemplateId, String), TransactionId>, SqlxLedgerError> {
+        if external_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let records = sqlx::query!(
+            r#"SELECT DISTINCT id, tx_template_id, external_id FROM sqlx_ledger_transactions WHERE external_id = ANY($1)"#,
+            &external_ids[..]
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|r| {
+                (
+                    (TxTemplateId::from(r.tx_template_id), r.external_id),
+                    TransactionId::from(r.id),
+                )
+            })
+            .collect())
+    }
+
+    #[instrument(name = "sqlx_ledger.transactions.find_by_id", skip(self))]
+    pub async fn find_by_id(&self, id: TransactionId) -> Result<Transaction, SqlxLedgerError> {
+        let row = sqlx::query!(
+            r#"SELECT id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, voided_by, modified_at, created_at
+            FROM sqlx_ledger_transactions WHERE id = $1 ORDER BY version DESC LIMIT 1"#,
+            Uuid::from(id)
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(SqlxLedgerError::TransactionNotFound(id))?;
+        Ok(Transaction {
+            id: TransactionId::from(row.id),
+            version: row.version as u32,
+            journal_id: JournalId::from(row.journal_id),
+            tx_template_id: TxTemplateId::from(row.tx_template_id),
+            tx_template_version: row.tx_template_version as u32,
+            effective: row.effective,
+            correlation_id: CorrelationId::from(row.correlation_id),
+            external_id: row.external_id,
+            description: row.description,
+            metadata: row.metadata,
+            actor_id: row.actor_id,
+            voids: row.voids.map(TransactionId::from),
+            voided_by: row.voided_by.map(TransactionId::from),
+            modified_at: row.modified_at,
+            created_at: row.created_at,
+        })
+    }
+
+    /// Replaces the metadata on a new version of the transaction. Its entries are left untouched.
+    #[instrument(name = "sqlx_ledger.transactions.update_metadata", skip(self))]
+    pub async fn update_metadata<T: Serialize + std::fmt::Debug>(
+        &self,
+        id: TransactionId,
+        metadata: T,
+    ) -> Result<(), SqlxLedgerError> {
+        let metadata_json = serde_json::to_value(metadata)?;
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_transactions
+              (id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, voided_by, created_at)
+            SELECT id, version + 1, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, $2, actor_id, voids, voided_by, created_at
+            FROM sqlx_ledger_transactions WHERE id = $1 ORDER BY version DESC LIMIT 1"#,
+            Uuid::from(id),
+            metadata_json
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| match SqlxLedgerError::from(e) {
+            // A concurrent update already took the next version
+            SqlxLedgerError::DuplicateKey(_) => SqlxLedgerError::OptimisticLockingError,
+            e => e,
+        })?;
+        if result.rows_affected() == 0 {
+            return Err(SqlxLedgerError::TransactionNotFound(id));
+        }
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// Records the reversal on a new version of the voided transaction.
+    pub(crate) async fn mark_voided_in_tx(
+        &self,
+        tx: &mut DbTransaction<'_, Postgres>,
+        id: TransactionId,
+        voided_by: TransactionId,
+        modified_at: DateTime<Utc>,
+    ) -> Result<(), SqlxLedgerError> {
+        let result = sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_transactions
+              (id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, voided_by, modified_at, created_at)
+            SELECT id, version + 1, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, $2, $3, created_at
+            FROM sqlx_ledger_transactions WHERE id = $1 AND voided_by IS NULL ORDER BY version DESC LIMIT 1"#,
+            Uuid::from(id),
+            Uuid::from(voided_by),
+            modified_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match SqlxLedgerError::from(e) {
+            // A concurrent void already took the next version
+            SqlxLedgerError::DuplicateKey(_) => SqlxLedgerError::TransactionAlreadyVoided(id),
+            e => e,
+        })?;
+        if result.rows_affected() == 0 {
+            return Err(SqlxLedgerError::TransactionAlreadyVoided(id));
+        }
+        Ok(())
+    }
+
+    /// Latest versions of the transactions matching `filter`, ordered by id.
+    #[instrument(name = "sqlx_ledger.transactions.list", skip(self))]
+    pub async fn list(
+        &self,
+        filter: TransactionFilter,
+        args: PaginatedQueryArgs<TransactionId>,
+    ) -> Result<PaginatedQueryRet<Transaction, TransactionId>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT * FROM (
+                 SELECT DISTINCT ON (id) id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, voided_by, modified_at, created_at
+                 FROM sqlx_ledger_transactions WHERE ($1::uuid IS NULL OR id > $1) ORDER BY id, version DESC
+               ) t
+               WHERE ($2::uuid IS NULL OR t.journal_id = $2)
+                 AND ($3::uuid IS NULL OR EXISTS (
+                   SELECT 1 FROM sqlx_ledger_entries e WHERE e.transaction_id = t.id AND e.account_id = $3))
+                 AND ($4::varchar IS NULL OR t.tx_template_id IN (
+                   SELECT id FROM sqlx_ledger_tx_templates WHERE code = $4))
+                 AND ($5::timestamptz IS NULL OR t.created_at >= $5)
+                 AND ($6::timestamptz IS NULL OR t.created_at < $6)
+                 AND ($7::varchar IS NULL OR starts_with(t.external_id, $7))
+                 AND ($8::jsonb IS NULL OR t.metadata @> $8)
+                 AND ($9::text[] IS NULL OR (CASE
+                   WHEN t.voided_by IS NOT NULL THEN 'voided'
+                   WHEN t.voids IS NOT NULL THEN 'reversal'
+                   ELSE 'posted' END) = ANY($9))
+               ORDER BY t.id
+               LIMIT $10"#,
+            args.after.map(Uuid::from) as Option<Uuid>,
+            filter.journal_id.map(Uuid::from) as Option<Uuid>,
+            filter.account_id.map(Uuid::from) as Option<Uuid>,
+            filter.tx_template_code,
+            filter.created_from,
+            filter.created_until,
+            filter.external_id_prefix,
+            filter.metadata,
+            filter.statuses.map(|statuses| statuses
+                .iter()
+                .map(|status| status.as_str().to_string())
+                .collect::<Vec<_>>()) as Option<Vec<String>>,
+            (args.first + 1) as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let has_next_page = records.len() > args.first;
+        let entities: Vec<_> = records
+            .into_iter()
+            .take(args.first)
+            .map(|row| Transaction {
+                id: TransactionId::from(row.id),
+                version: row.version as u32,
+                journal_id: JournalId::from(row.journal_id),
+                tx_template_id: TxTemplateId::from(row.tx_template_id),
+                tx_template_version: row.tx_template_version as u32,
+                effective: row.effective,
+                correlation_id: CorrelationId::from(row.correlation_id),
+                external_id: row.external_id,
+                description: row.description,
+                metadata: row.metadata,
+                actor_id: row.actor_id,
+                voids: row.voids.map(TransactionId::from),
+                voided_by: row.voided_by.map(TransactionId::from),
+                modified_at: row.modified_at,
+                created_at: row.created_at,
+            })
+            .collect();
+        let end_cursor = entities.last().map(|transaction| transaction.id);
+        Ok(PaginatedQueryRet {
+            entities,
+            has_next_page,
+            end_cursor,
+        })
+    }
+
+    /// Latest versions of the transactions sharing `correlation_id`, in the order they were created.
+    #[instrument(name = "sqlx_ledger.transactions.list_by_correlation_id", skip(self))]
+    pub async fn list_by_correlation_id(
+        &self,
+        correlation_id: CorrelationId,
+    ) -> Result<Vec<Transaction>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT * FROM (
+                 SELECT DISTINCT ON (id) id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, voided_by, modified_at, created_at
+                 FROM sqlx_ledger_transactions WHERE correlation_id = $1 ORDER BY id, version DESC
+               ) t ORDER BY created_at, id"#,
+            Uuid::from(correlation_id)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|row| Transaction {
+                id: TransactionId::from(row.id),
+                version: row.version as u32,
+                journal_id: JournalId::from(row.journal_id),
+                tx_template_id: TxTemplateId::from(row.tx_template_id),
+                tx_template_version: row.tx_template_version as u32,
+                effective: row.effective,
+                correlation_id: CorrelationId::from(row.correlation_id),
+                external_id: row.external_id,
+                description: row.description,
+                metadata: row.metadata,
+                actor_id: row.actor_id,
+                voids: row.voids.map(TransactionId::from),
+                voided_by: row.voided_by.map(TransactionId::from),
+                modified_at: row.modified_at,
+                created_at: row.created_at,
+            })
+            .collect())
+    }
+
+    #[instrument(name = "sqlx_ledger.transactions.list_by_actor_id", skip(self))]
+    pub async fn list_by_actor_id(
+        &self,
+        actor_id: &str,
+    ) -> Result<Vec<Transaction>, SqlxLedgerError> {
+        let records = sqlx::query!(
+            r#"SELECT * FROM (
+                 SELECT DISTINCT ON (id) id, version, journal_id, tx_template_id, tx_template_version, effective, correlation_id, external_id, description, metadata, actor_id, voids, voided_by, modified_at, created_at
+                 FROM sqlx_ledger_transactions WHERE actor_id = $1 ORDER BY id, version DESC
+               ) t ORDER BY created_at"#,
+            actor_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records
+            .into_iter()
+            .map(|row| Transaction {
+                id: TransactionId::from(row.id),
+                version: row.version as u32,
+                journal_id: JournalId::from(row.journal_id),
+                tx_template_id: TxTemplateId::from(row.tx_template_id),
+                tx_template_version: row.tx_template_version as u32,
+                effective: row.effective,
+                correlation_id: CorrelationId::from(row.correlation_id),
+                external_id: row.external_id,
+                description: row.description,
+                metadata: row.metadata,
+                actor_id: row.actor_id,
+                voids: row.voids.map(TransactionId::from),
+                voided_by: row.voided_by.map(TransactionId::from),
+                modified_at: row.modified_at,
+                created_at: row.created_at,
+            })
+            .collect())
+    }
+}
+
+// The journal subselect comes back empty for an unknown journal
+fn is_missing_journal(err: &dyn sqlx::error::DatabaseError) -> bool {
+    err.code().as_deref() == Some("23502") && err.message().contains("\"journal_id\"")
 }
@@ -1,16 +1,17 @@
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use uuid::Uuid;
 
 use std::collections::HashMap;
 
-use crate::{entry::*, error::*, primitives::*, transaction::NewTransaction};
+use crate::{entry::*, error::*, primitives::*, rate::ExchangeRates, transaction::NewTransaction};
 use cel_interpreter::{CelContext, CelExpression};
+use tracing::instrument;
 
 use super::{param_definition::ParamDefinition, tx_params::TxParams};
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub(crate) struct TxInputCel {
     effective: CelExpression,
     journal_id: CelExpression,
@@ -20,7 +21,7 @@ pub(crate) struct TxInputCel {
     metadata: Option<CelExpression>,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub(crate) struct EntryCel {
     entry_type: CelExpression,
     account_id: CelExpression,
@@ -29,30 +30,60 @@ pub(crate) struct EntryCel {
     units: CelExpression,
     currency: CelExpression,
     description: Option<CelExpression>,
+    group: Option<String>,
+    name: Option<String>,
+    condition: Option<CelExpression>,
+    convert_from: Option<CelExpression>,
 }
 
+#[derive(Debug)]
 pub(crate) struct TxTemplateCore {
     pub(super) id: TxTemplateId,
-    pub(super) _code: String,
+    pub(super) code: String,
+    pub(super) version: u32,
     pub(super) params: Option<Vec<ParamDefinition>>,
     pub(super) tx_input: TxInputCel,
     pub(super) entries: Vec<EntryCel>,
 }
 
 impl TxTemplateCore {
+    /// Whether posting needs exchange rates.
+    pub(crate) fn converts(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.convert_from.is_some())
+    }
+
+    #[instrument(
+        name = "sqlx_ledger.tx_template.prep_tx",
+        skip_all,
+        fields(tx_template_code = %self.code, tx_template_version = self.version)
+    )]
     pub(crate) fn prep_tx(
-        mut self,
-        params: TxParams,
+        &self,
+        mut params: TxParams,
+        now: DateTime<Utc>,
+        rates: &ExchangeRates,
     ) -> Result<(NewTransaction, Vec<NewEntry>), SqlxLedgerError> {
         let mut tx_builder = NewTransaction::builder();
         tx_builder.tx_template_id(self.id);
+        tx_builder.tx_template_version(self.version);
+        tx_builder.created_at(now);
+        if let Some(actor_id) = params.actor_id.take() {
+            tx_builder.actor_id(actor_id);
+        }
+        tx_builder.balance_constraints(std::mem::take(&mut params.balance_constraints));
+        let effective_override = params.effective.take();
 
-        let ctx = params.to_context(self.params.take())?;
+        let ctx = params.into_context_at(self.params.as_deref(), now)?;
 
         let journal_id: Uuid = self.tx_input.journal_id.try_evaluate(&ctx)?;
         tx_builder.journal_id(journal_id);
 
-        let effective: NaiveDate = self.tx_input.effective.try_evaluate(&ctx)?;
+        let effective: NaiveDate = match effective_override {
+            Some(effective) => effective,
+            None => self.tx_input.effective.try_evaluate(&ctx)?,
+        };
         tx_builder.effective(effective);
 
         if let Some(correlation_id) = self.tx_input.correlation_id.as_ref() {
@@ -76,15 +107,26 @@ impl TxTemplateCore {
         }
 
         let tx = tx_builder.build().expect("tx_build should succeed");
-        let entries = self.prep_entries(ctx)?;
+        let entries = self.prep_entries(ctx, rates)?;
 
         Ok((tx, entries))
     }
 
-    fn prep_entries(mut self, ctx: CelContext) -> Result<Vec<NewEntry>, SqlxLedgerError> {
+    fn prep_entries(
+        &self,
+        ctx: CelContext,
+        rates: &ExchangeRates,
+    ) -> Result<Vec<NewEntry>, SqlxLedgerError> {
         let mut new_entries = Vec::new();
         let mut totals = HashMap::new();
-        for entry in self.entries.drain(..) {
+        let mut group_totals = HashMap::new();
+        for entry in self.entries.iter() {
+            if let Some(condition) = entry.condition.as_ref() {
+                let emit: bool = condition.try_evaluate(&ctx)?;
+                if !emit {
+                    continue;
+                }
+            }
             let mut builder = NewEntry::builder();
             let account_id: Uuid = entry.account_id.try_evaluate(&ctx)?;
             builder.account_id(account_id.into());
@@ -95,8 +137,21 @@ impl TxTemplateCore {
             let layer: Layer = entry.layer.try_evaluate(&ctx)?;
             builder.layer(layer);
 
-            let units: Decimal = entry.units.try_evaluate(&ctx)?;
+            let mut units: Decimal = entry.units.try_evaluate(&ctx)?;
             let currency: Currency = entry.currency.try_evaluate(&ctx)?;
+            if let Some(convert_from) = entry.convert_from.as_ref() {
+                let from_currency: Currency = convert_from.try_evaluate(&ctx)?;
+                let rate = rates.rate(from_currency, currency).ok_or(
+                    SqlxLedgerError::ExchangeRateNotFound(from_currency, currency),
+                )?;
+                builder.conversion(Conversion {
+                    from_currency,
+                    from_units: units,
+                    rate,
+                });
+                units = (units * rate).round_dp(currency.exponent());
+            }
+            currency.check_units(units)?;
             let direction: DebitOrCredit = entry.direction.try_evaluate(&ctx)?;
 
             let total = totals.entry(currency).or_insert(Decimal::ZERO);
@@ -104,6 +159,15 @@ impl TxTemplateCore {
                 DebitOrCredit::Debit => *total -= units,
                 DebitOrCredit::Credit => *total += units,
             };
+            if let Some(group) = entry.group.clone() {
+                let total = group_totals
+                    .entry((group, currency))
+                    .or_insert(Decimal::ZERO);
+                match direction {
+                    DebitOrCredit::Debit => *total -= units,
+                    DebitOrCredit::Credit => *total += units,
+                };
+            }
             builder.units(units);
             builder.currency(currency);
             builder.direction(direction);
@@ -113,8 +177,15 @@ impl TxTemplateCore {
                 builder.description(description);
             }
 
+            if let Some(name) = entry.name.as_ref() {
+                builder.name(name.clone());
+            }
+
             new_entries.push(builder.build().expect("Couldn't build entry"));
         }
+        if new_entries.is_empty() {
+            return Err(SqlxLedgerError::NoEntriesEmitted(self.code.clone()));
+        }
 
         for (k, v) in totals {
             if v != Decimal::ZERO {
@@ -122,6 +193,12 @@ impl TxTemplateCore {
             }
         }
 
+        for ((group, currency), v) in group_totals {
+            if v != Decimal::ZERO {
+                return Err(SqlxLedgerError::UnbalancedEntryGroup(group, currency, v));
+            }
+        }
+
         Ok(new_entries)
     }
 }
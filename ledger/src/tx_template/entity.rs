@@ -1,11 +1,42 @@
+use chrono::{DateTime, Utc};
 use derive_builder::Builder;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use cel_interpreter::CelExpression;
+use cel_interpreter::{CelContext, CelError, CelExpression, CelMap};
 
 use super::param_definition::*;
+use crate::primitives::*;
+
+/// One version of a tx template. Versions are never modified once created.
+#[derive(Debug, Clone)]
+pub struct TxTemplate {
+    pub id: TxTemplateId,
+    pub code: String,
+    pub version: u32,
+    pub description: Option<String>,
+    pub params: Option<Vec<ParamDefinition>>,
+    pub tx_input: TxInput,
+    pub entries: Vec<EntryInput>,
+    pub metadata: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A problem found while type checking the expression of one template field.
+#[derive(Debug)]
+pub struct TxTemplateTypeError {
+    /// Path of the field, e.g. `entries[1].units`.
+    pub field: String,
+    pub error: CelError,
+}
+
+impl std::fmt::Display for TxTemplateTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.error)
+    }
+}
 
 #[derive(Builder)]
+#[builder(build_fn(validate = "Self::validate"))]
 pub struct NewTxTemplate {
     #[builder(setter(into))]
     pub(super) code: String,
@@ -23,6 +54,83 @@ impl NewTxTemplate {
     pub fn builder() -> NewTxTemplateBuilder {
         NewTxTemplateBuilder::default()
     }
+
+    /// Checks every expression against the declared param types without evaluating it.
+    pub(super) fn type_check(&self) -> Result<(), Vec<TxTemplateTypeError>> {
+        let mut ctx = CelContext::new();
+        if let Some(params) = self.params.as_ref() {
+            let mut samples = CelMap::new();
+            for param in params {
+                samples.insert(param.name.as_str(), param.r#type.sample_value());
+            }
+            ctx.add_variable("params", samples);
+        }
+
+        let mut fields = vec![
+            (
+                "tx_input.effective".to_string(),
+                Some(&self.tx_input.effective),
+            ),
+            (
+                "tx_input.journal_id".to_string(),
+                Some(&self.tx_input.journal_id),
+            ),
+            (
+                "tx_input.correlation_id".to_string(),
+                self.tx_input.correlation_id.as_ref(),
+            ),
+            (
+                "tx_input.external_id".to_string(),
+                self.tx_input.external_id.as_ref(),
+            ),
+            (
+                "tx_input.description".to_string(),
+                self.tx_input.description.as_ref(),
+            ),
+            (
+                "tx_input.metadata".to_string(),
+                self.tx_input.metadata.as_ref(),
+            ),
+        ];
+        for (i, entry) in self.entries.iter().enumerate() {
+            fields.extend([
+                (format!("entries[{i}].entry_type"), Some(&entry.entry_type)),
+                (format!("entries[{i}].account_id"), Some(&entry.account_id)),
+                (format!("entries[{i}].layer"), Some(&entry.layer)),
+                (format!("entries[{i}].direction"), Some(&entry.direction)),
+                (format!("entries[{i}].units"), Some(&entry.units)),
+                (format!("entries[{i}].currency"), Some(&entry.currency)),
+                (
+                    format!("entries[{i}].description"),
+                    entry.description.as_ref(),
+                ),
+                (format!("entries[{i}].condition"), entry.condition.as_ref()),
+                (
+                    format!("entries[{i}].convert_from"),
+                    entry.convert_from.as_ref(),
+                ),
+            ]);
+        }
+
+        let mut errors = Vec::new();
+        for (field, source) in fields {
+            let Some(source) = source else { continue };
+            let res = CelExpression::try_from(source.as_str())
+                .map_err(|e| vec![e])
+                .and_then(|expr| expr.type_check(&ctx));
+            if let Err(errs) = res {
+                errors.extend(errs.into_iter().map(|error| TxTemplateTypeError {
+                    field: field.clone(),
+                    error,
+                }));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 impl NewTxTemplateBuilder {
@@ -33,9 +141,24 @@ impl NewTxTemplateBuilder {
         self.metadata = Some(Some(serde_json::to_value(metadata)?));
         Ok(self)
     }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut names = std::collections::HashSet::new();
+        for name in self
+            .entries
+            .iter()
+            .flatten()
+            .filter_map(|entry| entry.name.as_ref())
+        {
+            if !names.insert(name) {
+                return Err(format!("Duplicate entry name '{name}'"));
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Clone, Serialize, Builder)]
+#[derive(Debug, Clone, Serialize, Deserialize, Builder)]
 #[builder(build_fn(validate = "Self::validate"))]
 pub struct TxInput {
     #[builder(setter(into))]
@@ -77,7 +200,7 @@ impl TxInputBuilder {
     }
 }
 
-#[derive(Clone, Serialize, Builder)]
+#[derive(Debug, Clone, Serialize, Deserialize, Builder)]
 #[builder(build_fn(validate = "Self::validate"))]
 pub struct EntryInput {
     #[builder(setter(into))]
@@ -92,8 +215,19 @@ pub struct EntryInput {
     units: String,
     #[builder(setter(into))]
     currency: String,
-    #[builder(setter(strip_option), default)]
+    #[builder(setter(strip_option, into), default)]
     description: Option<String>,
+    #[builder(setter(strip_option, into), default)]
+    group: Option<String>,
+    #[builder(setter(strip_option, into), default)]
+    name: Option<String>,
+    /// Boolean expression; the entry is only emitted when it evaluates to `true`.
+    #[builder(setter(strip_option, into), default)]
+    condition: Option<String>,
+    /// Currency expression; `units` is then an amount in this currency, converted into
+    /// `currency` at the latest exchange rate when posting.
+    #[builder(setter(strip_option, into), default)]
+    convert_from: Option<String>,
 }
 
 impl EntryInput {
@@ -133,7 +267,9 @@ impl EntryInputBuilder {
                 .as_ref()
                 .expect("Mandatory field 'currency' not set"),
         )?;
-        validate_optional_expression(&self.description)
+        validate_optional_expression(&self.description)?;
+        validate_optional_expression(&self.condition)?;
+        validate_optional_expression(&self.convert_from)
     }
 }
 
@@ -185,4 +321,30 @@ mod tests {
         let new_account = NewTxTemplate::builder().build();
         assert!(new_account.is_err());
     }
+
+    #[test]
+    fn fails_on_duplicate_entry_names() {
+        let entry = EntryInput::builder()
+            .entry_type("'TEST_DR'")
+            .account_id("param.recipient")
+            .layer("'Settled'")
+            .direction("'Settled'")
+            .units("1290")
+            .currency("'BTC'")
+            .name("principal")
+            .build()
+            .unwrap();
+        let new_template = NewTxTemplate::builder()
+            .code("CODE")
+            .tx_input(
+                TxInput::builder()
+                    .effective("date('2022-11-01')")
+                    .journal_id("'00000000-0000-0000-0000-000000000000'")
+                    .build()
+                    .unwrap(),
+            )
+            .entries(vec![entry.clone(), entry])
+            .build();
+        assert!(new_template.is_err());
+    }
 }
@@ -4,6 +4,7 @@ mod param_definition;
 mod repo;
 mod tx_params;
 
+pub(crate) use self::core::TxTemplateCore;
 pub use entity::*;
 pub use param_definition::*;
 pub use repo::*;
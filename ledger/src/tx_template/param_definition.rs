@@ -2,6 +2,8 @@ use cel_interpreter::{CelContext, CelExpression, CelType, CelValue};
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
+use std::sync::OnceLock;
+
 #[derive(Clone, Debug, Deserialize, Serialize, Builder)]
 #[builder(build_fn(validate = "Self::validate"))]
 pub struct ParamDefinition {
@@ -12,6 +14,9 @@ pub struct ParamDefinition {
     pub(super) default: Option<String>,
     #[builder(setter(strip_option, into), default)]
     pub(super) description: Option<String>,
+    #[serde(skip)]
+    #[builder(setter(skip), default)]
+    compiled_default: OnceLock<Option<CelExpression>>,
 }
 
 impl ParamDefinition {
@@ -19,10 +24,15 @@ impl ParamDefinition {
         ParamDefinitionBuilder::default()
     }
 
-    pub fn default_expr(&self) -> Option<CelExpression> {
-        self.default
+    /// The parsed default expression, parsed on first use only.
+    pub fn default_expr(&self) -> Option<&CelExpression> {
+        self.compiled_default
+            .get_or_init(|| {
+                self.default
+                    .as_ref()
+                    .map(|v| v.parse().expect("Couldn't create default_expr"))
+            })
             .as_ref()
-            .map(|v| v.parse().expect("Couldn't create default_expr"))
     }
 }
 
@@ -59,16 +69,36 @@ pub enum ParamDataType {
     JSON,
 }
 
+impl ParamDataType {
+    /// A value of this type, standing in for the actual param when type checking expressions.
+    /// `JSON` params can take any shape so they are represented by `null`.
+    pub(super) fn sample_value(&self) -> CelValue {
+        match self {
+            ParamDataType::STRING => CelValue::from(""),
+            ParamDataType::INTEGER => CelValue::Int(1),
+            ParamDataType::DECIMAL => CelValue::Double(rust_decimal::Decimal::ONE),
+            ParamDataType::BOOLEAN => CelValue::Bool(true),
+            ParamDataType::UUID => CelValue::Uuid(uuid::Uuid::nil()),
+            ParamDataType::DATE => CelValue::Date(chrono::NaiveDate::MIN),
+            ParamDataType::TIMESTAMP => {
+                CelValue::Timestamp(chrono::DateTime::<chrono::Utc>::MIN_UTC)
+            }
+            ParamDataType::JSON => CelValue::Null,
+        }
+    }
+}
+
 impl TryFrom<&CelValue> for ParamDataType {
     type Error = String;
 
     fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
         use cel_interpreter::CelType::*;
         match CelType::from(value) {
-            Int => Ok(ParamDataType::INTEGER),
+            Int | BigInt => Ok(ParamDataType::INTEGER),
             String => Ok(ParamDataType::STRING),
-            Map => Ok(ParamDataType::JSON),
+            Map | List => Ok(ParamDataType::JSON),
             Date => Ok(ParamDataType::DATE),
+            Timestamp => Ok(ParamDataType::TIMESTAMP),
             Uuid => Ok(ParamDataType::UUID),
             Double => Ok(ParamDataType::DECIMAL),
             _ => Err(format!("Unsupported type: {:?}", value)),
@@ -1,31 +1,114 @@
-use sqlx::{Pool, Postgres};
+use sqlx::{PgConnection, Pool, Postgres, Transaction};
 use tracing::instrument;
 
-use super::{core::*, entity::*};
-use crate::{error::*, primitives::*};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::{core::*, entity::*, tx_params::TxParams};
+use crate::{
+    clock::*,
+    entry::EvaluatedEntry,
+    error::*,
+    primitives::*,
+    rate::{ExchangeRates, Rates},
+};
+
+/// Outcome of evaluating a tx template against one set of sample params.
+#[derive(Debug)]
+pub enum FixtureResult {
+    Passed(TxTemplateOutput),
+    Failed(SqlxLedgerError),
+}
+
+/// Entries produced by a template, in declaration order and keyed by their declared names.
+#[derive(Debug)]
+pub struct TxTemplateOutput {
+    pub entries: Vec<EvaluatedEntry>,
+    pub named_entries: HashMap<String, EvaluatedEntry>,
+}
+
+impl From<Vec<EvaluatedEntry>> for TxTemplateOutput {
+    fn from(entries: Vec<EvaluatedEntry>) -> Self {
+        let named_entries = entries
+            .iter()
+            .filter_map(|entry| entry.name.clone().map(|name| (name, entry.clone())))
+            .collect();
+        Self {
+            entries,
+            named_entries,
+        }
+    }
+}
+
+// Parsed templates keyed by code and version
+type TemplateCache = HashMap<(String, u32), Arc<TxTemplateCore>>;
 
 #[derive(Debug, Clone)]
 pub struct TxTemplates {
     pool: Pool<Postgres>,
+    dry_run: bool,
+    clock: Arc<dyn Clock>,
+    // Template versions are immutable once created so parsed versions can be kept for the
+    // lifetime of the process.
+    cache: Arc<RwLock<TemplateCache>>,
 }
 
 impl TxTemplates {
     pub fn new(pool: &Pool<Postgres>) -> Self {
-        Self { pool: pool.clone() }
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+            clock: Arc::new(SystemClock),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+            clock: Arc::clone(&self.clock),
+            cache: Arc::clone(&self.cache),
+        }
+    }
+
+    pub(crate) fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
     }
 
-    #[instrument(name = "sqlx_ledger.tx_templates.create", skip_all)]
     pub async fn create(
         &self,
-        NewTxTemplate {
+        new_tx_template: NewTxTemplate,
+    ) -> Result<TxTemplateId, SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        let res = self.create_in_tx(&mut tx, new_tx_template).await?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(res)
+    }
+
+    #[instrument(name = "sqlx_ledger.tx_templates.create", skip_all)]
+    pub async fn create_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        new_tx_template: NewTxTemplate,
+    ) -> Result<TxTemplateId, SqlxLedgerError> {
+        new_tx_template
+            .type_check()
+            .map_err(SqlxLedgerError::TxTemplateTypeErrors)?;
+        let NewTxTemplate {
             code,
             description,
             params,
             tx_input,
             entries,
             metadata,
-        }: NewTxTemplate,
-    ) -> Result<TxTemplateId, SqlxLedgerError> {
+        } = new_tx_template;
         let params_json = serde_json::to_value(&params)?;
         let tx_input_json = serde_json::to_value(&tx_input)?;
         let entries_json = serde_json::to_value(&entries)?;
@@ -40,17 +123,160 @@ impl TxTemplates {
             entries_json,
             metadata
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
         Ok(TxTemplateId::from(record.id))
     }
 
-    pub(crate) async fn find_core(&self, code: &str) -> Result<TxTemplateCore, SqlxLedgerError> {
+    /// Creates the next version of the template with the same code, returning its number.
+    /// Transactions posted from then on use the new version; earlier versions stay untouched.
+    #[instrument(name = "sqlx_ledger.tx_templates.update", skip_all)]
+    pub async fn update(&self, new_tx_template: NewTxTemplate) -> Result<u32, SqlxLedgerError> {
+        new_tx_template
+            .type_check()
+            .map_err(SqlxLedgerError::TxTemplateTypeErrors)?;
+        let NewTxTemplate {
+            code,
+            description,
+            params,
+            tx_input,
+            entries,
+            metadata,
+        } = new_tx_template;
+        let params_json = serde_json::to_value(&params)?;
+        let tx_input_json = serde_json::to_value(&tx_input)?;
+        let entries_json = serde_json::to_value(&entries)?;
+        let mut tx = self.pool.begin().await?;
+        let record = sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_tx_templates (id, code, version, description, params, tx_input, entries, metadata, created_at)
+            SELECT id, code, version + 1, $2, $3, $4, $5, $6, created_at
+            FROM sqlx_ledger_tx_templates WHERE code = $1 ORDER BY version DESC LIMIT 1
+            RETURNING version"#,
+            code,
+            description,
+            params_json,
+            tx_input_json,
+            entries_json,
+            metadata
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(|e| match SqlxLedgerError::from(e) {
+            // A concurrent update already took the next version
+            SqlxLedgerError::DuplicateKey(_) => SqlxLedgerError::OptimisticLockingError,
+            e => e,
+        })?
+        .ok_or_else(|| SqlxLedgerError::TxTemplateNotFound(code))?;
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(record.version as u32)
+    }
+
+    #[instrument(name = "sqlx_ledger.tx_templates.find_version", skip(self))]
+    pub async fn find_version(
+        &self,
+        code: &str,
+        version: u32,
+    ) -> Result<TxTemplate, SqlxLedgerError> {
         let record = sqlx::query!(
-            r#"SELECT id, code, params, tx_input, entries FROM sqlx_ledger_tx_templates WHERE code = $1 LIMIT 1"#,
+            r#"SELECT id, code, version, description, params, tx_input, entries, metadata, created_at
+            FROM sqlx_ledger_tx_templates WHERE code = $1 AND version = $2"#,
+            code,
+            version as i32
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| SqlxLedgerError::TxTemplateVersionNotFound(code.to_string(), version))?;
+        Ok(TxTemplate {
+            id: TxTemplateId::from(record.id),
+            code: record.code,
+            version: record.version as u32,
+            description: record.description,
+            params: match record.params {
+                Some(serde_json::Value::Null) | None => None,
+                Some(params) => Some(serde_json::from_value(params)?),
+            },
+            tx_input: serde_json::from_value(record.tx_input)?,
+            entries: serde_json::from_value(record.entries)?,
+            metadata: record.metadata,
+            created_at: record.created_at,
+        })
+    }
+
+    /// Evaluates the template against each fixture without posting anything,
+    /// checking that the params are accepted and the resulting entries balance.
+    #[instrument(
+        name = "sqlx_ledger.tx_templates.verify_fixtures",
+        skip(self, fixtures)
+    )]
+    pub async fn verify_fixtures(
+        &self,
+        code: &str,
+        fixtures: Vec<TxParams>,
+    ) -> Result<Vec<FixtureResult>, SqlxLedgerError> {
+        let core = self.find_core(code).await?;
+        let now = self.clock.now();
+        let rates = if core.converts() {
+            Rates::new(&self.pool).find_all_at(now).await?
+        } else {
+            ExchangeRates::default()
+        };
+        Ok(fixtures
+            .into_iter()
+            .map(|params| match core.prep_tx(params, now, &rates) {
+                Ok((_, entries)) => FixtureResult::Passed(TxTemplateOutput::from(
+                    entries
+                        .into_iter()
+                        .map(EvaluatedEntry::from)
+                        .collect::<Vec<_>>(),
+                )),
+                Err(e) => FixtureResult::Failed(e),
+            })
+            .collect())
+    }
+
+    pub(crate) async fn find_core(
+        &self,
+        code: &str,
+    ) -> Result<Arc<TxTemplateCore>, SqlxLedgerError> {
+        let mut conn = self.pool.acquire().await?;
+        self.find_core_with(&mut conn, code).await
+    }
+
+    /// Reads the template as seen from within `tx`.
+    pub(crate) async fn find_core_in_tx(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        code: &str,
+    ) -> Result<Arc<TxTemplateCore>, SqlxLedgerError> {
+        self.find_core_with(tx, code).await
+    }
+
+    async fn find_core_with(
+        &self,
+        conn: &mut PgConnection,
+        code: &str,
+    ) -> Result<Arc<TxTemplateCore>, SqlxLedgerError> {
+        let version = sqlx::query!(
+            r#"SELECT version FROM sqlx_ledger_tx_templates WHERE code = $1 ORDER BY version DESC LIMIT 1"#,
             code
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *conn)
+        .await?
+        .version;
+        let key = (code.to_string(), version as u32);
+        if let Some(core) = self.cache.read().expect("poisoned lock").get(&key) {
+            return Ok(Arc::clone(core));
+        }
+        let record = sqlx::query!(
+            r#"SELECT id, code, version, params, tx_input, entries FROM sqlx_ledger_tx_templates WHERE code = $1 AND version = $2"#,
+            code,
+            version
+        )
+        .fetch_one(&mut *conn)
         .await?;
         let params = match record.params {
             Some(serde_json::Value::Null) => None,
@@ -58,12 +284,21 @@ impl TxTemplates {
             None => None,
         };
         let tx_input = serde_json::from_value(record.tx_input)?;
-        Ok(TxTemplateCore {
+        let core = Arc::new(TxTemplateCore {
             id: TxTemplateId::from(record.id),
-            _code: record.code,
+            code: record.code,
+            version: record.version as u32,
             params,
             entries: serde_json::from_value(record.entries)?,
             tx_input,
-        })
+        });
+        // Templates created in dry-run mode are rolled back and must not be cached
+        if !self.dry_run {
+            self.cache
+                .write()
+                .expect("poisoned lock")
+                .insert(key, Arc::clone(&core));
+        }
+        Ok(core)
     }
 }
@@ -1,18 +1,26 @@
 use cel_interpreter::{CelContext, CelMap, CelValue};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 use super::param_definition::{ParamDataType, ParamDefinition};
-use crate::error::SqlxLedgerError;
+use crate::{balance::BalanceConstraint, clock::*, error::SqlxLedgerError, primitives::*};
 
 #[derive(Debug)]
 pub struct TxParams {
     values: HashMap<String, CelValue>,
+    pub(super) actor_id: Option<String>,
+    pub(super) balance_constraints: Vec<BalanceConstraint>,
+    pub(super) effective: Option<NaiveDate>,
 }
 
 impl TxParams {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            actor_id: None,
+            balance_constraints: Vec::new(),
+            effective: None,
         }
     }
 
@@ -20,25 +28,63 @@ impl TxParams {
         self.values.insert(k.into(), v.into());
     }
 
+    /// Records who initiated the transaction. Not visible to template expressions.
+    pub fn set_actor_id(&mut self, actor_id: impl Into<String>) {
+        self.actor_id = Some(actor_id.into());
+    }
+
+    /// Posts the transaction with this effective date instead of the one the template evaluates,
+    /// e.g. to backdate a correction.
+    pub fn set_effective(&mut self, effective: NaiveDate) {
+        self.effective = Some(effective);
+    }
+
+    /// Fails the posting with `SqlxLedgerError::InsufficientBalance` unless the `layer` balance
+    /// of the account in `currency` is at least `min` once the transaction has been applied.
+    /// Checked while the balance is locked, so concurrent postings can't invalidate it.
+    pub fn require_min_balance(
+        &mut self,
+        account_id: AccountId,
+        currency: Currency,
+        layer: Layer,
+        min: Decimal,
+    ) {
+        self.balance_constraints.push(BalanceConstraint {
+            account_id,
+            currency,
+            layer,
+            min,
+        });
+    }
+
     pub fn to_context(
-        mut self,
+        self,
         defs: Option<Vec<ParamDefinition>>,
+    ) -> Result<CelContext, SqlxLedgerError> {
+        self.into_context_at(defs.as_deref(), SystemClock.now())
+    }
+
+    pub(crate) fn into_context_at(
+        mut self,
+        defs: Option<&[ParamDefinition]>,
+        now: DateTime<Utc>,
     ) -> Result<CelContext, SqlxLedgerError> {
         let mut ctx = CelContext::new();
+        ctx.set_now(now);
         if let Some(defs) = defs {
             let mut cel_map = CelMap::new();
             for d in defs {
                 if let Some(v) = self.values.remove(&d.name) {
                     match ParamDataType::try_from(&v) {
                         Ok(t) if t == d.r#type => {
-                            cel_map.insert(d.name, v);
+                            cel_map.insert(d.name.as_str(), v);
                             continue;
                         }
-                        _ => return Err(SqlxLedgerError::TxParamTypeMismatch(d.r#type)),
+                        _ => return Err(SqlxLedgerError::TxParamTypeMismatch(d.r#type.clone())),
                     }
                 }
                 if let Some(expr) = d.default_expr() {
-                    cel_map.insert(d.name, expr.evaluate(&ctx)?);
+                    cel_map.insert(d.name.as_str(), expr.evaluate(&ctx)?);
                 }
             }
             ctx.add_variable("params", cel_map);
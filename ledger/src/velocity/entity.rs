@@ -0,0 +1,168 @@
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc};
+use derive_builder::Builder;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::*;
+
+/// Caps the units an account may move in one direction over a time window.
+pub struct VelocityLimit {
+    pub id: VelocityLimitId,
+    pub name: String,
+    pub description: Option<String>,
+    /// Unset applies the limit to every currency, each tracked separately.
+    pub currency: Option<Currency>,
+    pub layer: Layer,
+    pub direction: DebitOrCredit,
+    pub window: VelocityWindow,
+    pub limit: Decimal,
+    /// CEL expression over `entry` deciding whether an entry counts towards the limit.
+    pub condition: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocityWindow {
+    /// The given number of seconds leading up to the posting.
+    Rolling { seconds: i64 },
+    /// Since the start of the current UTC calendar period.
+    Calendar(CalendarPeriod),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalendarPeriod {
+    Day,
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+// Longest rolling window accepted, about a century
+pub(super) const MAX_ROLLING_WINDOW_SECONDS: i64 = 100 * 366 * 24 * 60 * 60;
+
+impl VelocityWindow {
+    pub fn rolling(duration: Duration) -> Self {
+        VelocityWindow::Rolling {
+            seconds: duration.num_seconds(),
+        }
+    }
+
+    /// Earliest instant whose usage counts towards the limit at `now`.
+    pub fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let day = now.date_naive();
+        let day = match self {
+            VelocityWindow::Rolling { seconds } => {
+                let window = Duration::seconds((*seconds).clamp(0, MAX_ROLLING_WINDOW_SECONDS));
+                return now
+                    .checked_sub_signed(window)
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            }
+            VelocityWindow::Calendar(CalendarPeriod::Day) => day,
+            VelocityWindow::Calendar(CalendarPeriod::Week) => {
+                day - Duration::days(day.weekday().num_days_from_monday() as i64)
+            }
+            VelocityWindow::Calendar(CalendarPeriod::Month) => {
+                day.with_day(1).expect("first of month")
+            }
+        };
+        Utc.from_utc_datetime(&day.and_time(NaiveTime::MIN))
+    }
+}
+
+#[derive(Builder, Debug)]
+pub struct NewVelocityLimit {
+    #[builder(setter(into))]
+    pub id: VelocityLimitId,
+    #[builder(setter(into))]
+    pub(super) name: String,
+    #[builder(setter(strip_option, into), default)]
+    pub(super) description: Option<String>,
+    #[builder(setter(strip_option), default)]
+    pub(super) currency: Option<Currency>,
+    #[builder(default = "Layer::Settled")]
+    pub(super) layer: Layer,
+    pub(super) direction: DebitOrCredit,
+    pub(super) window: VelocityWindow,
+    pub(super) limit: Decimal,
+    #[builder(setter(strip_option, into), default)]
+    pub(super) condition: Option<String>,
+}
+
+impl NewVelocityLimit {
+    pub fn builder() -> NewVelocityLimitBuilder {
+        let mut builder = NewVelocityLimitBuilder::default();
+        builder.id(VelocityLimitId::new());
+        builder
+    }
+}
+
+/// Details of the limit that rejected a posting.
+#[derive(Debug, Clone)]
+pub struct VelocityLimitExceeded {
+    pub velocity_limit_id: VelocityLimitId,
+    pub account_id: AccountId,
+    pub currency: Currency,
+    pub limit: Decimal,
+    /// Usage within the window had the posting gone through.
+    pub attempted: Decimal,
+    pub window_start: DateTime<Utc>,
+}
+
+impl std::fmt::Display for VelocityLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "account {} would reach {} {} since {} on limit {} of {}",
+            self.account_id,
+            self.attempted,
+            self.currency,
+            self.window_start,
+            self.velocity_limit_id,
+            self.limit
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_start() {
+        // A Wednesday
+        let now = Utc.with_ymd_and_hms(2023, 3, 15, 13, 30, 0).unwrap();
+        assert_eq!(
+            VelocityWindow::rolling(Duration::hours(1)).start(now),
+            Utc.with_ymd_and_hms(2023, 3, 15, 12, 30, 0).unwrap()
+        );
+        assert_eq!(
+            VelocityWindow::Calendar(CalendarPeriod::Day).start(now),
+            Utc.with_ymd_and_hms(2023, 3, 15, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            VelocityWindow::Calendar(CalendarPeriod::Week).start(now),
+            Utc.with_ymd_and_hms(2023, 3, 13, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            VelocityWindow::Calendar(CalendarPeriod::Month).start(now),
+            Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn rolling_window_is_bounded() {
+        let now = Utc.with_ymd_and_hms(2023, 3, 15, 13, 30, 0).unwrap();
+        assert_eq!(
+            VelocityWindow::Rolling { seconds: i64::MAX }.start(now),
+            now - Duration::seconds(MAX_ROLLING_WINDOW_SECONDS)
+        );
+    }
+
+    #[test]
+    fn fails_when_mandatory_fields_are_missing() {
+        let new_limit = NewVelocityLimit::builder().name("name").build();
+        assert!(new_limit.is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use cel_interpreter::{CelContext, CelExpression, CelMap};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use sqlx::{Pool, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::entity::*;
+use crate::{entry::StagedEntry, error::*, primitives::*};
+
+/// Namespace (first key) of the advisory locks serializing usage of a velocity limit.
+/// The second key is `hashtext('<velocity_limit_id>:<account_id>:<currency>')`.
+pub const VELOCITY_ADVISORY_LOCK_NAMESPACE: i32 = 0x5351_4c56;
+
+#[derive(Debug, Clone)]
+pub struct VelocityLimits {
+    pool: Pool<Postgres>,
+    dry_run: bool,
+}
+
+struct Control {
+    limit: VelocityLimit,
+    condition: Option<CelExpression>,
+    account_ids: Vec<AccountId>,
+    // An attached template covers every account it posts to
+    tx_template_ids: Vec<TxTemplateId>,
+    // An attached set covers its members, nested ones included, within its journal
+    set_accounts: Vec<(JournalId, AccountId)>,
+}
+
+impl VelocityLimits {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        Self {
+            pool: pool.clone(),
+            dry_run: false,
+        }
+    }
+
+    pub(crate) fn dry_run(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            dry_run: true,
+        }
+    }
+
+    #[instrument(name = "sqlx_ledger.velocity_limits.create", skip(self))]
+    pub async fn create(
+        &self,
+        new_limit: NewVelocityLimit,
+    ) -> Result<VelocityLimitId, SqlxLedgerError> {
+        let NewVelocityLimit {
+            id,
+            name,
+            description,
+            currency,
+            layer,
+            direction,
+            window,
+            limit,
+            condition,
+        } = new_limit;
+        if let Some(condition) = condition.as_ref() {
+            condition.parse::<CelExpression>()?;
+        }
+        if let VelocityWindow::Rolling { seconds } = window {
+            if !(1..=MAX_ROLLING_WINDOW_SECONDS).contains(&seconds) {
+                return Err(SqlxLedgerError::InvalidVelocityWindow(seconds));
+            }
+        }
+        let window_json = serde_json::to_value(window)?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_velocity_limits (id, name, description, currency, layer, direction, time_window, limit_units, condition)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            Uuid::from(id),
+            name,
+            description,
+            currency.map(|c| c.code()),
+            layer as Layer,
+            direction as DebitOrCredit,
+            window_json,
+            limit,
+            condition
+        )
+        .execute(&mut tx)
+        .await?;
+        self.finish(tx).await?;
+        Ok(id)
+    }
+
+    #[instrument(name = "sqlx_ledger.velocity_limits.find", skip(self))]
+    pub async fn find(&self, id: VelocityLimitId) -> Result<VelocityLimit, SqlxLedgerError> {
+        let record = sqlx::query!(
+            r#"SELECT id, name, description, currency, layer as "layer: Layer", direction as "direction: DebitOrCredit",
+                 time_window, limit_units, condition, created_at
+               FROM sqlx_ledger_velocity_limits WHERE id = $1"#,
+            Uuid::from(id)
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(VelocityLimit {
+            id: VelocityLimitId::from(record.id),
+            name: record.name,
+            description: record.description,
+            currency: record.currency.map(|c| c.parse()).transpose()?,
+            layer: record.layer,
+            direction: record.direction,
+            window: serde_json::from_value(record.time_window)?,
+            limit: record.limit_units,
+            condition: record.condition,
+            created_at: record.created_at,
+        })
+    }
+
+    /// Enforces the limit on every entry posted to the account.
+    #[instrument(name = "sqlx_ledger.velocity_limits.attach_to_account", skip(self))]
+    pub async fn attach_to_account(
+        &self,
+        id: VelocityLimitId,
+        account_id: AccountId,
+    ) -> Result<(), SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_velocity_controls (velocity_limit_id, account_id) VALUES ($1, $2)"#,
+            Uuid::from(id),
+            Uuid::from(account_id)
+        )
+        .execute(&mut tx)
+        .await?;
+        self.finish(tx).await
+    }
+
+    /// Enforces the limit, per account, on the entries of transactions posted with the template.
+    #[instrument(name = "sqlx_ledger.velocity_limits.attach_to_tx_template", skip(self))]
+    pub async fn attach_to_tx_template(
+        &self,
+        id: VelocityLimitId,
+        tx_template_id: TxTemplateId,
+    ) -> Result<(), SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_velocity_controls (velocity_limit_id, tx_template_id) VALUES ($1, $2)"#,
+            Uuid::from(id),
+            Uuid::from(tx_template_id)
+        )
+        .execute(&mut tx)
+        .await?;
+        self.finish(tx).await
+    }
+
+    /// Enforces the limit, per account, on the entries posted to the members of the set
+    /// (nested sets included) in the set's journal.
+    #[instrument(name = "sqlx_ledger.velocity_limits.attach_to_account_set", skip(self))]
+    pub async fn attach_to_account_set(
+        &self,
+        id: VelocityLimitId,
+        account_set_id: AccountSetId,
+    ) -> Result<(), SqlxLedgerError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO sqlx_ledger_velocity_controls (velocity_limit_id, account_set_id) VALUES ($1, $2)"#,
+            Uuid::from(id),
+            Uuid::from(account_set_id)
+        )
+        .execute(&mut tx)
+        .await?;
+        self.finish(tx).await
+    }
+
+    /// Records the usage of the limits covering `entries`, failing if any of them is exceeded.
+    /// Usage is serialized per limit, account and currency so concurrent postings can't overshoot.
+    pub(crate) async fn enforce_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        tx_template_ids: &HashMap<TransactionId, TxTemplateId>,
+        entries: &[StagedEntry],
+        now: DateTime<Utc>,
+    ) -> Result<(), SqlxLedgerError> {
+        let controls = self.controls_in_tx(tx, tx_template_ids, entries).await?;
+        if controls.is_empty() {
+            return Ok(());
+        }
+        let mut usage: BTreeMap<_, BTreeMap<TransactionId, Decimal>> = BTreeMap::new();
+        for entry in entries {
+            let tx_template_id = tx_template_ids[&entry.transaction_id];
+            for control in controls.values() {
+                if !control.covers(entry, tx_template_id)? {
+                    continue;
+                }
+                *usage
+                    .entry((control.limit.id, entry.account_id, entry.currency.code()))
+                    .or_default()
+                    .entry(entry.transaction_id)
+                    .or_insert(Decimal::ZERO) += entry.units;
+            }
+        }
+
+        for ((limit_id, account_id, currency), transactions) in usage {
+            let limit = &controls[&limit_id].limit;
+            let window_start = limit.window.start(now);
+            sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+                .bind(VELOCITY_ADVISORY_LOCK_NAMESPACE)
+                .bind(format!("{limit_id}:{account_id}:{currency}"))
+                .execute(&mut *tx)
+                .await?;
+            let used = sqlx::query!(
+                r#"SELECT COALESCE(SUM(units), 0) as "used!" FROM sqlx_ledger_velocity_usage
+                   WHERE velocity_limit_id = $1 AND account_id = $2 AND currency = $3 AND created_at >= $4"#,
+                Uuid::from(limit_id),
+                Uuid::from(account_id),
+                currency,
+                window_start
+            )
+            .fetch_one(&mut *tx)
+            .await?
+            .used;
+            let attempted = used + transactions.values().sum::<Decimal>();
+            if attempted > limit.limit {
+                return Err(SqlxLedgerError::VelocityLimitExceeded(
+                    VelocityLimitExceeded {
+                        velocity_limit_id: limit_id,
+                        account_id,
+                        currency: currency.parse()?,
+                        limit: limit.limit,
+                        attempted,
+                        window_start,
+                    },
+                ));
+            }
+            for (transaction_id, units) in transactions {
+                sqlx::query!(
+                    r#"INSERT INTO sqlx_ledger_velocity_usage (velocity_limit_id, account_id, currency, transaction_id, units, created_at)
+                       VALUES ($1, $2, $3, $4, $5, $6)"#,
+                    Uuid::from(limit_id),
+                    Uuid::from(account_id),
+                    currency,
+                    Uuid::from(transaction_id),
+                    units,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the usage recorded for a transaction so it no longer counts towards any limit.
+    pub(crate) async fn release_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        transaction_id: TransactionId,
+    ) -> Result<(), SqlxLedgerError> {
+        sqlx::query!(
+            r#"DELETE FROM sqlx_ledger_velocity_usage WHERE transaction_id = $1"#,
+            Uuid::from(transaction_id)
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    async fn controls_in_tx<'a>(
+        &self,
+        tx: &mut Transaction<'a, Postgres>,
+        tx_template_ids: &HashMap<TransactionId, TxTemplateId>,
+        entries: &[StagedEntry],
+    ) -> Result<HashMap<VelocityLimitId, Control>, SqlxLedgerError> {
+        let account_ids: Vec<Uuid> = entries.iter().map(|e| Uuid::from(e.account_id)).collect();
+        let tx_template_ids: Vec<Uuid> =
+            tx_template_ids.values().map(|&id| Uuid::from(id)).collect();
+        let records = sqlx::query!(
+            r#"WITH RECURSIVE ancestors AS (
+                 SELECT account_set_id, member_account_id AS account_id FROM sqlx_ledger_account_set_members
+                 WHERE member_account_id = ANY($1)
+                 UNION
+                 SELECT m.account_set_id, a.account_id FROM sqlx_ledger_account_set_members m
+                 JOIN ancestors a ON m.member_account_set_id = a.account_set_id
+               )
+               SELECT l.id, l.name, l.description, l.currency, l.layer as "layer: Layer", l.direction as "direction: DebitOrCredit",
+                 l.time_window, l.limit_units, l.condition, l.created_at, c.account_id, c.tx_template_id,
+                 a.account_id as "set_account_id?", s.journal_id as "set_journal_id?"
+               FROM sqlx_ledger_velocity_controls c JOIN sqlx_ledger_velocity_limits l ON l.id = c.velocity_limit_id
+               LEFT JOIN ancestors a ON a.account_set_id = c.account_set_id
+               LEFT JOIN sqlx_ledger_account_sets s ON s.id = c.account_set_id
+               WHERE c.account_id = ANY($1) OR c.tx_template_id = ANY($2) OR a.account_id IS NOT NULL"#,
+            &account_ids[..],
+            &tx_template_ids[..]
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut controls: HashMap<VelocityLimitId, Control> = HashMap::new();
+        for record in records {
+            let id = VelocityLimitId::from(record.id);
+            let control = match controls.get_mut(&id) {
+                Some(control) => control,
+                None => {
+                    let condition = record
+                        .condition
+                        .as_deref()
+                        .map(|c| c.parse::<CelExpression>())
+                        .transpose()?;
+                    let limit = VelocityLimit {
+                        id,
+                        name: record.name,
+                        description: record.description,
+                        currency: record.currency.map(|c| c.parse()).transpose()?,
+                        layer: record.layer,
+                        direction: record.direction,
+                        window: serde_json::from_value(record.time_window)?,
+                        limit: record.limit_units,
+                        condition: record.condition,
+                        created_at: record.created_at,
+                    };
+                    controls.entry(id).or_insert(Control {
+                        limit,
+                        condition,
+                        account_ids: Vec::new(),
+                        tx_template_ids: Vec::new(),
+                        set_accounts: Vec::new(),
+                    })
+                }
+            };
+            if let Some(account_id) = record.account_id {
+                control.account_ids.push(account_id.into());
+            }
+            if let Some(tx_template_id) = record.tx_template_id {
+                control.tx_template_ids.push(tx_template_id.into());
+            }
+            if let (Some(journal_id), Some(account_id)) =
+                (record.set_journal_id, record.set_account_id)
+            {
+                control
+                    .set_accounts
+                    .push((journal_id.into(), account_id.into()));
+            }
+        }
+        Ok(controls)
+    }
+
+    async fn finish(&self, tx: Transaction<'_, Postgres>) -> Result<(), SqlxLedgerError> {
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Control {
+    fn covers(
+        &self,
+        entry: &StagedEntry,
+        tx_template_id: TxTemplateId,
+    ) -> Result<bool, SqlxLedgerError> {
+        let limit = &self.limit;
+        if limit.layer != entry.layer
+            || limit.direction != entry.direction
+            || limit.currency.is_some_and(|c| c != entry.currency)
+            || !(self.account_ids.contains(&entry.account_id)
+                || self.tx_template_ids.contains(&tx_template_id)
+                || self
+                    .set_accounts
+                    .contains(&(entry.journal_id, entry.account_id)))
+        {
+            return Ok(false);
+        }
+        let condition = match self.condition.as_ref() {
+            Some(condition) => condition,
+            None => return Ok(true),
+        };
+        let mut entry_map = CelMap::new();
+        entry_map.insert("account_id", entry.account_id);
+        entry_map.insert("entry_type", entry.entry_type.as_str());
+        entry_map.insert("layer", entry.layer);
+        entry_map.insert("direction", entry.direction);
+        entry_map.insert("units", entry.units);
+        entry_map.insert("currency", entry.currency.code());
+        let mut ctx = CelContext::new();
+        ctx.add_variable("entry", entry_map);
+        Ok(condition.try_evaluate(&ctx)?)
+    }
+}
@@ -1,7 +1,8 @@
 mod helpers;
 
 use rand::distributions::{Alphanumeric, DistString};
-use sqlx_ledger::{account::NewAccount, *};
+use rust_decimal::Decimal;
+use sqlx_ledger::{account::*, journal::*, *};
 
 #[tokio::test]
 async fn test_account() -> anyhow::Result<()> {
@@ -24,3 +25,192 @@ async fn test_account() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn opening_balances_net_to_zero() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let ledger = SqlxLedger::new(&pool);
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Equity Account {code}"))
+        .code(code)
+        .build()
+        .unwrap();
+    let equity_account_id = ledger.accounts().create(new_account).await.unwrap();
+    let ledger = ledger.with_opening_balance_equity_account(equity_account_id);
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let mut account_ids = vec![equity_account_id];
+    for (units, normal_balance_type) in [(100, DebitOrCredit::Credit), (50, DebitOrCredit::Debit)] {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .normal_balance_type(normal_balance_type)
+            .opening_balance(OpeningBalance {
+                journal_id,
+                units: Decimal::from(units),
+                currency: btc,
+            })
+            .build()
+            .unwrap();
+        let account_id = ledger.create_account(new_account).await.unwrap();
+        let balance = ledger
+            .balances()
+            .find(journal_id, account_id, btc)
+            .await?
+            .unwrap();
+        assert_eq!(balance.settled(), Decimal::from(units));
+        account_ids.push(account_id);
+    }
+
+    let mut net = Decimal::ZERO;
+    for account_id in account_ids {
+        let balance = ledger
+            .balances()
+            .find(journal_id, account_id, btc)
+            .await?
+            .unwrap();
+        net += balance.details.settled_dr_balance - balance.details.settled_cr_balance;
+    }
+    assert_eq!(net, Decimal::ZERO);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn opening_balance_requires_equity_account() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let new_account = || {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .opening_balance(OpeningBalance {
+                journal_id: JournalId::new(),
+                units: Decimal::ONE,
+                currency: "BTC".parse().unwrap(),
+            })
+            .build()
+            .unwrap()
+    };
+    let result = SqlxLedger::new(&pool).create_account(new_account()).await;
+    assert!(matches!(
+        result,
+        Err(SqlxLedgerError::OpeningBalanceEquityAccountNotConfigured)
+    ));
+
+    // Even with an equity account configured, `Accounts` can't post the opening balance
+    let ledger = SqlxLedger::new(&pool).with_opening_balance_equity_account(AccountId::new());
+    let result = ledger.accounts().create(new_account()).await;
+    assert!(matches!(
+        result,
+        Err(SqlxLedgerError::OpeningBalanceRequiresLedger)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dry_run_opening_balance_leaves_no_template() -> anyhow::Result<()> {
+    // A database of its own, so that no other test has installed the template yet
+    let database = helpers::TestDatabase::create().await?;
+    let pool = sqlx::PgPool::connect_with(database.connect_options()).await?;
+    sqlx::migrate!("../migrations").run(&pool).await?;
+
+    let ledger = SqlxLedger::new(&pool);
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await?;
+    let new_account = NewAccount::builder()
+        .name("Test Equity Account")
+        .code("EQUITY")
+        .build()
+        .unwrap();
+    let equity_account_id = ledger.accounts().create(new_account).await?;
+    let ledger = ledger.with_opening_balance_equity_account(equity_account_id);
+
+    let new_account = || {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .opening_balance(OpeningBalance {
+                journal_id,
+                units: Decimal::ONE,
+                currency: "BTC".parse().unwrap(),
+            })
+            .build()
+            .unwrap()
+    };
+    ledger.dry_run().create_account(new_account()).await?;
+    let templates: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlx_ledger_tx_templates")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(templates, 0);
+
+    // Nothing of the dry run is cached either
+    let account_id = ledger.create_account(new_account()).await?;
+    let balance = ledger
+        .balances()
+        .find(journal_id, account_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::ONE);
+    pool.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn list_accounts() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let mut created = Vec::new();
+    for _ in 0..3 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        created.push(ledger.accounts().create(new_account).await?);
+    }
+    created.sort();
+    ledger
+        .accounts()
+        .update(created[0], Some("updated".to_string()), None::<()>)
+        .await?;
+
+    let mut listed = Vec::new();
+    let mut after = None;
+    loop {
+        let page = ledger
+            .accounts()
+            .list::<serde_json::Value>(PaginatedQueryArgs { first: 500, after })
+            .await?;
+        assert!(page.entities.len() <= 500);
+        if let Some(account) = page.entities.iter().find(|a| a.id == created[0]) {
+            assert_eq!(account.version, 2);
+            assert_eq!(account.description.as_deref(), Some("updated"));
+        }
+        listed.extend(page.entities.into_iter().map(|a| a.id));
+        if !page.has_next_page {
+            break;
+        }
+        after = page.end_cursor;
+    }
+    let listed_created: Vec<_> = listed.iter().filter(|id| created.contains(id)).collect();
+    assert_eq!(listed_created, created.iter().collect::<Vec<_>>());
+    let mut sorted = listed.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted, listed);
+    Ok(())
+}
@@ -0,0 +1,171 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{account::*, account_set::*, journal::*, tx_template::*, *};
+
+#[tokio::test]
+async fn account_set_balances_roll_up() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let mut account_ids = Vec::new();
+    for _ in 0..3 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await.unwrap());
+    }
+    let (treasury, alice, bob) = (account_ids[0], account_ids[1], account_ids[2]);
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let params = vec![
+        ParamDefinition::builder()
+            .name("sender")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("recipient")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("journal_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("units")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+    ];
+    let entries = helpers::transfer_entries("params.units", "'BTC'");
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await.unwrap();
+    let transfer = |sender: AccountId, recipient: AccountId, units: u32| {
+        let mut params = TxParams::new();
+        params.insert("journal_id", journal_id);
+        params.insert("sender", sender);
+        params.insert("recipient", recipient);
+        params.insert("units", Decimal::from(units));
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+
+    // Balances posted before an account joins a set are rolled up when it is added
+    transfer(treasury, alice, 100).await?;
+
+    let sets = ledger.account_sets();
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let customers = sets
+        .create(
+            NewAccountSet::builder()
+                .journal_id(journal_id)
+                .name(name)
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let liabilities = sets
+        .create(
+            NewAccountSet::builder()
+                .journal_id(journal_id)
+                .name(name)
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    sets.add_member(customers, AccountSetMember::Account(alice))
+        .await?;
+    sets.add_member(liabilities, AccountSetMember::AccountSet(customers))
+        .await?;
+    // Reachable through `customers` already, so it isn't counted twice
+    sets.add_member(liabilities, AccountSetMember::Account(alice))
+        .await?;
+    sets.add_member(customers, AccountSetMember::Account(bob))
+        .await?;
+
+    transfer(treasury, bob, 30).await?;
+    transfer(alice, bob, 10).await?;
+
+    let btc = "BTC".parse()?;
+    let balance = sets.balance(customers, btc).await?.unwrap();
+    assert_eq!(balance.settled(), Decimal::from(130));
+    assert_eq!(balance.settled_dr_balance, Decimal::from(10));
+    let balance = sets.balance(liabilities, btc).await?.unwrap();
+    assert_eq!(balance.settled(), Decimal::from(130));
+    assert!(sets.balance(customers, "USD".parse()?).await?.is_none());
+
+    assert_eq!(
+        sets.list_members(customers).await?,
+        vec![
+            AccountSetMember::Account(alice),
+            AccountSetMember::Account(bob)
+        ]
+    );
+    assert!(matches!(
+        sets.add_member(customers, AccountSetMember::AccountSet(liabilities))
+            .await,
+        Err(SqlxLedgerError::AccountSetCycle(_, _))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn add_member_uses_ledger_clock() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let now = "2023-01-15T12:00:00Z".parse::<chrono::DateTime<chrono::Utc>>()?;
+    let ledger = SqlxLedger::new(&pool).with_clock(FixedClock(now));
+
+    let journal = helpers::TestJournal::create(&ledger).await?;
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+    ledger
+        .post_transaction(&tx_code, Some(journal.params()))
+        .await?;
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let set_id = ledger
+        .account_sets()
+        .create(
+            NewAccountSet::builder()
+                .journal_id(journal.journal_id)
+                .name(name)
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    ledger
+        .account_sets()
+        .add_member(
+            set_id,
+            AccountSetMember::Account(journal.recipient_account_id),
+        )
+        .await?;
+    let balance = ledger
+        .account_sets()
+        .balance(set_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.modified_at, now);
+    Ok(())
+}
@@ -0,0 +1,236 @@
+mod helpers;
+
+use chrono::TimeZone;
+use rust_decimal::Decimal;
+use sqlx_ledger::{tx_template::*, *};
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn balance_delta_between_versions() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let mut first_balance = None;
+    for amount in [100, 20, 3] {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::from(amount));
+        ledger
+            .post_transaction(&tx_code, Some(params))
+            .await
+            .unwrap();
+        if first_balance.is_none() {
+            first_balance = Some(
+                ledger
+                    .balances()
+                    .find_at_version(journal_id, recipient_account_id, btc, 1)
+                    .await?
+                    .settled(),
+            );
+        }
+    }
+
+    let balance = ledger
+        .balances()
+        .find_at_version(journal_id, recipient_account_id, btc, 1)
+        .await?;
+    assert_eq!(balance.settled(), Decimal::from(100));
+    assert_eq!(first_balance, Some(balance.settled()));
+    let delta = ledger
+        .balances()
+        .delta(journal_id, recipient_account_id, btc, 1, 3)
+        .await?;
+    assert_eq!(delta.settled(), Decimal::from(23));
+    let delta = ledger
+        .balances()
+        .delta(journal_id, recipient_account_id, btc, 0, 3)
+        .await?;
+    assert_eq!(delta.settled(), Decimal::from(123));
+    let delta = ledger
+        .balances()
+        .delta(journal_id, sender_account_id, btc, 2, 3)
+        .await?;
+    assert_eq!(delta.settled_dr, Decimal::from(3));
+    assert!(matches!(
+        ledger
+            .balances()
+            .delta(journal_id, recipient_account_id, btc, 1, 4)
+            .await,
+        Err(SqlxLedgerError::BalanceVersionNotFound(4))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn balance_at_point_in_time() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        recipient_account_id,
+        ..
+    } = journal;
+
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let start = chrono::Utc.with_ymd_and_hms(2023, 1, 31, 12, 0, 0).unwrap();
+    for (day, amount) in [(0, 100), (1, 20), (2, 3)] {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::from(amount));
+        ledger
+            .clone()
+            .with_clock(FixedClock(start + chrono::Duration::days(day)))
+            .post_transaction(&tx_code, Some(params))
+            .await?;
+    }
+
+    let balances = ledger.balances();
+    assert!(balances
+        .find_at(
+            journal_id,
+            recipient_account_id,
+            btc,
+            start - chrono::Duration::seconds(1)
+        )
+        .await?
+        .is_none());
+    let balance = balances
+        .find_at(journal_id, recipient_account_id, btc, start)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(100));
+    let month_end = chrono::Utc.with_ymd_and_hms(2023, 2, 1, 0, 0, 0).unwrap();
+    let balance = balances
+        .find_at(journal_id, recipient_account_id, btc, month_end)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(100));
+    assert_eq!(balance.details.version, 1);
+    let balance = balances
+        .find_at(journal_id, recipient_account_id, btc, chrono::Utc::now())
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(123));
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_with_min_balance() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let transfer = |sender: AccountId, recipient: AccountId, amount: u32, min: Option<u32>| {
+        let mut params = TxParams::new();
+        params.insert("journal_id", journal_id);
+        params.insert("sender", sender);
+        params.insert("recipient", recipient);
+        params.insert("amount", Decimal::from(amount));
+        if let Some(min) = min {
+            params.require_min_balance(sender, btc, Layer::Settled, Decimal::from(min));
+        }
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    // Fund the sender, which is credit normal
+    transfer(recipient_account_id, sender_account_id, 100, None).await?;
+
+    transfer(sender_account_id, recipient_account_id, 60, Some(0)).await?;
+    match transfer(sender_account_id, recipient_account_id, 50, Some(0)).await {
+        Err(SqlxLedgerError::InsufficientBalance(account_id, currency, layer, balance)) => {
+            assert_eq!(account_id, sender_account_id);
+            assert_eq!(currency, btc);
+            assert_eq!(layer, Layer::Settled);
+            assert_eq!(balance, Decimal::from(-10));
+        }
+        res => panic!("expected InsufficientBalance, got {:?}", res.map(|_| ())),
+    }
+    let balance = ledger
+        .balances()
+        .find(journal_id, sender_account_id, btc)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(40));
+    assert_eq!(balance.details.version, 2);
+    transfer(sender_account_id, recipient_account_id, 40, Some(0)).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn balance_by_effective_date() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let today = chrono::Utc::now().date_naive();
+    let yesterday = today.pred_opt().unwrap();
+    let mut ids = Vec::new();
+    for (amount, effective) in [(100, None), (20, Some(yesterday))] {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::from(amount));
+        if let Some(effective) = effective {
+            params.set_effective(effective);
+        }
+        ids.push(ledger.post_transaction(&tx_code, Some(params)).await?);
+    }
+    assert_eq!(
+        ledger.transactions().find_by_id(ids[1]).await?.effective,
+        yesterday
+    );
+
+    let balances = ledger.balances();
+    let balance = balances
+        .find_effective_at(journal_id, recipient_account_id, btc, yesterday)
+        .await?;
+    assert_eq!(balance.settled(), Decimal::from(20));
+    let balance = balances
+        .find_effective_at(journal_id, sender_account_id, btc, today)
+        .await?;
+    assert_eq!(balance.settled_dr, Decimal::from(120));
+    assert_eq!(balance.settled(), Decimal::from(-120));
+    let balance = balances
+        .find_effective_at(
+            journal_id,
+            recipient_account_id,
+            btc,
+            yesterday.pred_opt().unwrap(),
+        )
+        .await?;
+    assert_eq!(balance.settled(), Decimal::ZERO);
+    Ok(())
+}
@@ -0,0 +1,116 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{account::*, journal::*, tx_template::*, *};
+
+#[tokio::test]
+async fn post_in_custom_currency() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let mut account_ids = Vec::new();
+    for _ in 0..2 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await.unwrap());
+    }
+    let (sender, recipient) = (account_ids[0], account_ids[1]);
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let params = vec![
+        ParamDefinition::builder()
+            .name("sender")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("recipient")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("journal_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("units")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("currency")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+    ];
+    let entries = helpers::transfer_entries("params.units", "params.currency");
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let code = format!(
+        "PTS{}",
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 8)
+    );
+    let currencies = ledger.currencies();
+    let points = currencies.register(&code, 0).await?;
+    assert_eq!(points.code(), code);
+    assert_eq!(points.exponent(), 0);
+    assert_eq!(code.parse::<Currency>()?, points);
+    // Registering again with the same exponent is idempotent
+    currencies.register(&code, 0).await?;
+    assert!(matches!(
+        currencies.register(&code, 2).await,
+        Err(SqlxLedgerError::CurrencyAlreadyRegistered(_))
+    ));
+    assert!(matches!(
+        currencies.register("USD", 2).await,
+        Err(SqlxLedgerError::CurrencyAlreadyRegistered(_))
+    ));
+    assert!(currencies.load().await?.contains(&points));
+
+    let transfer = |units: Decimal| {
+        let mut params = TxParams::new();
+        params.insert("journal_id", journal_id);
+        params.insert("sender", sender);
+        params.insert("recipient", recipient);
+        params.insert("units", units);
+        params.insert("currency", code.as_str());
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    transfer(Decimal::from(10)).await?;
+    // Trailing zeros don't count towards the precision
+    transfer(Decimal::new(50, 1)).await?;
+    assert!(matches!(
+        transfer(Decimal::new(15, 1)).await,
+        Err(SqlxLedgerError::InvalidUnitsPrecision(currency, _)) if currency == points
+    ));
+
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient, points)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(15));
+    Ok(())
+}
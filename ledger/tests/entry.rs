@@ -0,0 +1,133 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx_ledger::{tx_template::*, *};
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn entry_descriptions_on_statement() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let params = helpers::transfer_params();
+    let entries = vec![
+        EntryInput::builder()
+            .entry_type("'TEST_DR'")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("1000")
+            .currency("'BTC'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'TEST_PRINCIPAL_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("900")
+            .currency("'BTC'")
+            .description("'principal'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'TEST_INTEREST_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("100")
+            .currency("'BTC'")
+            .description("'interest'")
+            .build()
+            .unwrap(),
+    ];
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .description("'repayment'")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let params = journal.params();
+    ledger
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+
+    let statement = ledger
+        .entries()
+        .list_by_account_id(journal_id, recipient_account_id)
+        .await?;
+    assert_eq!(statement.len(), 2);
+    assert_eq!(statement[0].entry_type, "TEST_PRINCIPAL_CR");
+    assert_eq!(statement[0].description.as_deref(), Some("principal"));
+    assert_eq!(statement[1].entry_type, "TEST_INTEREST_CR");
+    assert_eq!(statement[1].description.as_deref(), Some("interest"));
+
+    let statement = ledger
+        .entries()
+        .list_by_account_id(journal_id, sender_account_id)
+        .await?;
+    assert_eq!(statement.len(), 1);
+    assert_eq!(statement[0].description, None);
+
+    let params = journal.params();
+    ledger.post_transaction(&tx_code, Some(params)).await?;
+    let btc = "BTC".parse::<Currency>()?;
+    let mut running = Vec::new();
+    let mut after = None;
+    loop {
+        let page = ledger
+            .entries()
+            .list_for_account(
+                journal_id,
+                recipient_account_id,
+                btc,
+                PaginatedQueryArgs { first: 3, after },
+            )
+            .await?;
+        running.extend(
+            page.entities
+                .iter()
+                .map(|e| (e.entry.entry_type.clone(), e.balance.settled())),
+        );
+        if !page.has_next_page {
+            break;
+        }
+        after = page.end_cursor;
+    }
+    let running: Vec<_> = running
+        .iter()
+        .map(|(entry_type, balance)| (entry_type.as_str(), balance.to_string()))
+        .collect();
+    assert_eq!(
+        running,
+        vec![
+            ("TEST_PRINCIPAL_CR", "900".to_string()),
+            ("TEST_INTEREST_CR", "1000".to_string()),
+            ("TEST_PRINCIPAL_CR", "1900".to_string()),
+            ("TEST_INTEREST_CR", "2000".to_string()),
+        ]
+    );
+    Ok(())
+}
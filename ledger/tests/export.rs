@@ -0,0 +1,126 @@
+mod helpers;
+
+use chrono::{Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx_ledger::{account::*, journal::*, tx_template::*, *};
+
+struct Setup {
+    ledger: SqlxLedger,
+    journal_id: JournalId,
+}
+
+async fn setup() -> anyhow::Result<Setup> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let mut account_ids = Vec::new();
+    for _ in 0..2 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await.unwrap());
+    }
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id(format!("uuid('{journal_id}')"))
+                .description("'Transfer, with a comma'")
+                .build()
+                .unwrap(),
+        )
+        .entries(vec![
+            EntryInput::builder()
+                .entry_type("'TEST_DR'")
+                .account_id(format!("uuid('{}')", account_ids[0]))
+                .layer("SETTLED")
+                .direction("DEBIT")
+                .units("decimal('1.5')")
+                .currency("'BTC'")
+                .build()
+                .unwrap(),
+            EntryInput::builder()
+                .entry_type("'TEST_CR'")
+                .account_id(format!("uuid('{}')", account_ids[1]))
+                .layer("SETTLED")
+                .direction("CREDIT")
+                .units("decimal('1.5')")
+                .currency("'BTC'")
+                .build()
+                .unwrap(),
+        ])
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await?;
+    for _ in 0..2 {
+        ledger.post_transaction(&tx_code, None::<TxParams>).await?;
+    }
+    Ok(Setup { ledger, journal_id })
+}
+
+#[tokio::test]
+async fn export_csv() -> anyhow::Result<()> {
+    let Setup { ledger, journal_id } = setup().await?;
+    let from = Utc::now() - Duration::hours(1);
+    let until = Utc::now() + Duration::hours(1);
+    let export = ledger.export();
+
+    let mut transactions = Vec::new();
+    export
+        .transactions_csv(journal_id, from, until, &mut transactions)
+        .await?;
+    let transactions = String::from_utf8(transactions)?;
+    let lines: Vec<_> = transactions.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("transaction_id,journal_id,tx_template_id"));
+    assert!(lines[1].contains("\"Transfer, with a comma\""));
+
+    let mut entries = Vec::new();
+    export
+        .entries_csv(journal_id, from, until, &mut entries)
+        .await?;
+    let entries = String::from_utf8(entries)?;
+    assert_eq!(entries.lines().count(), 5);
+    assert!(entries.contains(",SETTLED,DEBIT,1.5,BTC,"));
+
+    let mut balances = Vec::new();
+    export
+        .balances_csv(journal_id, from, until, &mut balances)
+        .await?;
+    assert_eq!(String::from_utf8(balances)?.lines().count(), 5);
+
+    let mut empty = Vec::new();
+    export
+        .entries_csv(journal_id, until, until + Duration::hours(1), &mut empty)
+        .await?;
+    assert_eq!(String::from_utf8(empty)?.lines().count(), 1);
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+#[tokio::test]
+async fn export_parquet() -> anyhow::Result<()> {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let Setup { ledger, journal_id } = setup().await?;
+    let from = Utc::now() - Duration::hours(1);
+    let until = Utc::now() + Duration::hours(1);
+    let path = std::env::temp_dir().join(format!("{journal_id}.parquet"));
+    ledger
+        .export()
+        .entries_parquet(journal_id, from, until, std::fs::File::create(&path)?)
+        .await?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path)?)?;
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 4);
+    assert!(reader.schema().field_with_name("units").is_ok());
+    std::fs::remove_file(path)?;
+    Ok(())
+}
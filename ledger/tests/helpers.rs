@@ -1,6 +1,204 @@
+#![allow(dead_code)]
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx::postgres::PgConnectOptions;
+use sqlx_ledger::{account::NewAccount, journal::NewJournal, tx_template::*, *};
+
+pub fn database_url() -> String {
+    std::env::var("DATABASE_URL").unwrap_or_else(|_| {
+        let pg_host = std::env::var("PG_HOST").unwrap_or("localhost".to_string());
+        format!("postgres://user:password@{pg_host}:5432/pg")
+    })
+}
+
 pub async fn init_pool() -> anyhow::Result<sqlx::PgPool> {
-    let pg_host = std::env::var("PG_HOST").unwrap_or("localhost".to_string());
-    let pg_con = format!("postgres://user:password@{pg_host}:5432/pg");
-    let pool = sqlx::PgPool::connect(&pg_con).await?;
+    let pool = sqlx::PgPool::connect(&database_url()).await?;
     Ok(pool)
 }
+
+/// A database of its own on the `database_url` server, dropped together with the guard.
+pub struct TestDatabase {
+    name: String,
+    server: PgConnectOptions,
+}
+
+impl TestDatabase {
+    pub async fn create() -> anyhow::Result<Self> {
+        let server = database_url().parse::<PgConnectOptions>()?;
+        let name = format!(
+            "ledger_{}",
+            Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 16)
+                .to_lowercase()
+        );
+        let pool = sqlx::PgPool::connect_with(server.clone()).await?;
+        sqlx::query(&format!("CREATE DATABASE {name}"))
+            .execute(&pool)
+            .await?;
+        pool.close().await;
+        Ok(Self { name, server })
+    }
+
+    pub fn connect_options(&self) -> PgConnectOptions {
+        self.server.clone().database(&self.name)
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        // Runs on a runtime of its own as the test's runtime may be shutting down
+        let server = self.server.clone();
+        let drop_database = format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.name);
+        let _ = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(async move {
+                    let pool = sqlx::PgPool::connect_with(server).await?;
+                    sqlx::query(&drop_database).execute(&pool).await?;
+                    pool.close().await;
+                    Ok::<_, anyhow::Error>(())
+                })
+        })
+        .join();
+    }
+}
+
+/// A fresh journal with a sender and a recipient account.
+pub struct TestJournal {
+    pub journal_id: JournalId,
+    pub sender_account_id: AccountId,
+    pub recipient_account_id: AccountId,
+}
+
+impl TestJournal {
+    pub async fn create(ledger: &SqlxLedger) -> anyhow::Result<Self> {
+        let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_journal = NewJournal::builder().name(name).build().unwrap();
+        let journal_id = ledger.journals().create(new_journal).await?;
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Sender Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        let sender_account_id = ledger.accounts().create(new_account).await?;
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Recipient Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        let recipient_account_id = ledger.accounts().create(new_account).await?;
+        Ok(Self {
+            journal_id,
+            sender_account_id,
+            recipient_account_id,
+        })
+    }
+
+    /// Values for the `transfer_params`.
+    pub fn params(&self) -> TxParams {
+        let mut params = TxParams::new();
+        params.insert("journal_id", self.journal_id);
+        params.insert("sender", self.sender_account_id);
+        params.insert("recipient", self.recipient_account_id);
+        params
+    }
+}
+
+/// The `recipient`, `sender` and `journal_id` params of a transfer.
+pub fn transfer_params() -> Vec<ParamDefinition> {
+    ["recipient", "sender", "journal_id"]
+        .into_iter()
+        .map(|name| {
+            ParamDefinition::builder()
+                .name(name)
+                .r#type(ParamDataType::UUID)
+                .build()
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Debits the sender and credits the recipient with `units` of `currency`, both CEL expressions.
+pub fn transfer_entries(units: &str, currency: &str) -> Vec<EntryInput> {
+    vec![
+        EntryInput::builder()
+            .entry_type("'TEST_DR'")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units(units)
+            .currency(currency)
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'TEST_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units(units)
+            .currency(currency)
+            .build()
+            .unwrap(),
+    ]
+}
+
+/// Posts `entries` to `params.journal_id`, effective on the day of posting.
+pub fn transfer_template(
+    code: &str,
+    params: Vec<ParamDefinition>,
+    entries: Vec<EntryInput>,
+) -> NewTxTemplate {
+    NewTxTemplate::builder()
+        .code(code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap()
+}
+
+/// Creates a template transferring 1290 BTC and returns its code.
+pub async fn create_transfer_template(ledger: &SqlxLedger) -> anyhow::Result<String> {
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_template = transfer_template(
+        &tx_code,
+        transfer_params(),
+        transfer_entries("1290", "'BTC'"),
+    );
+    ledger.tx_templates().create(new_template).await?;
+    Ok(tx_code)
+}
+
+/// The `transfer_params` and a DECIMAL `amount`.
+pub fn amount_params() -> Vec<ParamDefinition> {
+    let mut params = transfer_params();
+    params.push(
+        ParamDefinition::builder()
+            .name("amount")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+    );
+    params
+}
+
+/// Creates a template transferring `params.amount` BTC and returns its code.
+pub async fn create_amount_template(ledger: &SqlxLedger) -> anyhow::Result<String> {
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_template = transfer_template(
+        &tx_code,
+        amount_params(),
+        transfer_entries("params.amount", "'BTC'"),
+    );
+    ledger.tx_templates().create(new_template).await?;
+    Ok(tx_code)
+}
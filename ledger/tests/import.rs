@@ -0,0 +1,189 @@
+mod helpers;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{
+    account::*, account_set::*, entry::*, journal::*, transaction::*, tx_template::*, *,
+};
+
+#[tokio::test]
+async fn import_historical_transactions() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let mut account_ids = Vec::new();
+    for _ in 0..2 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await.unwrap());
+    }
+    let (sender, recipient) = (account_ids[0], account_ids[1]);
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let set_id = ledger
+        .account_sets()
+        .create(
+            NewAccountSet::builder()
+                .journal_id(journal_id)
+                .name(name)
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    ledger
+        .account_sets()
+        .add_member(set_id, AccountSetMember::Account(recipient))
+        .await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id(format!("uuid('{journal_id}')"))
+                .build()
+                .unwrap(),
+        )
+        .entries(vec![
+            EntryInput::builder()
+                .entry_type("'IMPORT_DR'")
+                .account_id(format!("uuid('{sender}')"))
+                .layer("SETTLED")
+                .direction("DEBIT")
+                .units("decimal('1')")
+                .currency("'BTC'")
+                .build()
+                .unwrap(),
+            EntryInput::builder()
+                .entry_type("'IMPORT_CR'")
+                .account_id(format!("uuid('{recipient}')"))
+                .layer("SETTLED")
+                .direction("CREDIT")
+                .units("decimal('1')")
+                .currency("'BTC'")
+                .build()
+                .unwrap(),
+        ])
+        .build()
+        .unwrap();
+    let tx_template_id = ledger.tx_templates().create(new_template).await?;
+    let btc: Currency = "BTC".parse()?;
+
+    let transfer = |day: u32, units: u32, layer: Layer| {
+        let entry = |account_id: AccountId, direction: DebitOrCredit| {
+            ImportedEntry::builder()
+                .entry_type("IMPORTED")
+                .account_id(account_id)
+                .layer(layer)
+                .direction(direction)
+                .units(Decimal::from(units))
+                .currency(btc)
+                .build()
+                .unwrap()
+        };
+        ImportedTransaction::builder()
+            .journal_id(journal_id)
+            .tx_template_id(tx_template_id)
+            .effective(NaiveDate::from_ymd_opt(2020, 1, day).unwrap())
+            .created_at(Utc.with_ymd_and_hms(2020, 1, day, 12, 0, 0).unwrap())
+            .entries(vec![
+                entry(sender, DebitOrCredit::Debit),
+                entry(recipient, DebitOrCredit::Credit),
+            ])
+            .build()
+            .unwrap()
+    };
+    // Balances posted before the import are rebuilt together with the imported history
+    ledger.post_transaction(&tx_code, None::<TxParams>).await?;
+    let summary = ledger
+        .import(vec![
+            transfer(1, 10, Layer::Settled),
+            transfer(2, 5, Layer::Pending),
+            transfer(3, 20, Layer::Settled),
+        ])
+        .await?;
+    assert_eq!(
+        summary,
+        ImportSummary {
+            transactions: 3,
+            entries: 6,
+            balances: 2,
+        }
+    );
+
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient, btc)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(31));
+    assert_eq!(balance.pending(), Decimal::from(5));
+    assert_eq!(balance.details.version, 4);
+    let at = Utc.with_ymd_and_hms(2020, 1, 2, 18, 0, 0).unwrap();
+    let balance = ledger
+        .balances()
+        .find_at(journal_id, recipient, btc, at)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(10));
+    assert_eq!(balance.pending(), Decimal::from(5));
+    let balance = ledger.account_sets().balance(set_id, btc).await?.unwrap();
+    assert_eq!(balance.settled(), Decimal::from(31));
+
+    // Posting continues from the rebuilt balances
+    ledger.post_transaction(&tx_code, None::<TxParams>).await?;
+    let balance = ledger
+        .balances()
+        .find(journal_id, sender, btc)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(-32));
+    assert_eq!(balance.details.version, 5);
+
+    let transaction_id = TransactionId::new();
+    let csv = format!(
+        "transaction_id,journal_id,tx_template_id,effective,created_at,external_id,entry_type,account_id,direction,units,currency\n\
+         {transaction_id},{journal_id},{tx_template_id},2020-01-04,2020-01-04T12:00:00Z,legacy-{transaction_id},IMPORTED,{sender},DEBIT,3,BTC\n\
+         {transaction_id},{journal_id},{tx_template_id},2020-01-04,2020-01-04T12:00:00Z,,IMPORTED,{recipient},CREDIT,3,BTC\n"
+    );
+    let summary = ledger.import_csv(csv.as_bytes()).await?;
+    assert_eq!(summary.transactions, 1);
+    assert_eq!(summary.entries, 2);
+    let transaction = ledger.transactions().find_by_id(transaction_id).await?;
+    assert_eq!(transaction.external_id, format!("legacy-{transaction_id}"));
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient, btc)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(35));
+
+    let unbalanced = ImportedTransaction::builder()
+        .journal_id(journal_id)
+        .tx_template_id(tx_template_id)
+        .effective(NaiveDate::from_ymd_opt(2020, 1, 5).unwrap())
+        .created_at(Utc.with_ymd_and_hms(2020, 1, 5, 12, 0, 0).unwrap())
+        .entries(vec![ImportedEntry::builder()
+            .entry_type("IMPORTED")
+            .account_id(sender)
+            .direction(DebitOrCredit::Debit)
+            .units(Decimal::ONE)
+            .currency(btc)
+            .build()
+            .unwrap()])
+        .build()
+        .unwrap();
+    assert!(matches!(
+        ledger.import(vec![unbalanced]).await,
+        Err(SqlxLedgerError::UnbalancedTransaction(_, _))
+    ));
+    Ok(())
+}
@@ -0,0 +1,121 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx_ledger::{journal::*, *};
+
+use std::time::{Duration, Instant};
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn journal_lock_serializes_critical_sections() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let ledger = SqlxLedger::new(&pool);
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+
+    let mut handles = Vec::new();
+    for _ in 0..2 {
+        let pool = pool.clone();
+        let journals = ledger.journals().clone();
+        handles.push(tokio::spawn(async move {
+            let mut tx = pool.begin().await.unwrap();
+            journals.lock_in_tx(&mut tx, journal_id).await.unwrap();
+            let start = Instant::now();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let end = Instant::now();
+            tx.commit().await.unwrap();
+            (start, end)
+        }));
+    }
+    let mut sections = Vec::new();
+    for handle in handles {
+        sections.push(handle.await?);
+    }
+    sections.sort();
+    assert!(sections[0].1 <= sections[1].0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn journal_lock_holds_off_postings() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+    let journal = TestJournal::create(&ledger).await?;
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let mut tx = pool.begin().await?;
+    ledger
+        .journals()
+        .lock_in_tx(&mut tx, journal.journal_id)
+        .await?;
+    let posting = {
+        let ledger = ledger.clone();
+        let params = journal.params();
+        tokio::spawn(async move {
+            ledger.post_transaction(&tx_code, Some(params)).await?;
+            Ok::<_, SqlxLedgerError>(Instant::now())
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!posting.is_finished());
+    let released = Instant::now();
+    tx.commit().await?;
+    assert!(posting.await?? >= released);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn list_journals() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let tenant = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let mut created = Vec::new();
+    for _ in 0..3 {
+        let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_journal = NewJournal::builder()
+            .name(name)
+            .metadata(serde_json::json!({ "tenant": tenant }))
+            .unwrap()
+            .build()
+            .unwrap();
+        created.push(ledger.journals().create(new_journal).await?);
+    }
+    created.sort();
+
+    let found = ledger
+        .journals()
+        .find_by_metadata::<serde_json::Value>(serde_json::json!({ "tenant": tenant }))
+        .await?;
+    assert_eq!(found.iter().map(|j| j.id).collect::<Vec<_>>(), created);
+    assert_eq!(
+        found[0].metadata,
+        Some(serde_json::json!({ "tenant": tenant }))
+    );
+
+    let mut listed = Vec::new();
+    let mut after = None;
+    loop {
+        let page = ledger
+            .journals()
+            .list::<serde_json::Value>(PaginatedQueryArgs { first: 2, after })
+            .await?;
+        assert!(page.entities.len() <= 2);
+        listed.extend(page.entities.into_iter().map(|j| j.id));
+        if !page.has_next_page {
+            break;
+        }
+        after = page.end_cursor;
+    }
+    let listed_created: Vec<_> = listed.iter().filter(|id| created.contains(id)).collect();
+    assert_eq!(listed_created, created.iter().collect::<Vec<_>>());
+    let mut deduped = listed.clone();
+    deduped.dedup();
+    assert_eq!(deduped.len(), listed.len());
+    Ok(())
+}
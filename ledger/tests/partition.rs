@@ -0,0 +1,154 @@
+mod helpers;
+
+use chrono::{Datelike, Months, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{account::*, journal::*, period::*, tx_template::*, *};
+
+#[tokio::test]
+async fn entries_partitioned_by_month() -> anyhow::Result<()> {
+    // Partitioning changes the shared entries table, so this runs in a database of its own.
+    let database = helpers::TestDatabase::create().await?;
+    let ledger_pool = sqlx::PgPool::connect_with(database.connect_options()).await?;
+    sqlx::migrate!("../migrations").run(&ledger_pool).await?;
+    let ledger = SqlxLedger::new(&ledger_pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let journal_id = ledger
+        .journals()
+        .create(NewJournal::builder().name(name).build().unwrap())
+        .await?;
+    let mut account_ids = Vec::new();
+    for _ in 0..2 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await?);
+    }
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let params = vec![
+        ParamDefinition::builder()
+            .name("sender")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("recipient")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+    ];
+    let entries = helpers::transfer_entries("decimal('1')", "'BTC'");
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id(format!("uuid('{journal_id}')"))
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await?;
+    let transfer = || {
+        let mut params = TxParams::new();
+        params.insert("sender", account_ids[0]);
+        params.insert("recipient", account_ids[1]);
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    transfer().await?;
+
+    let partitions = ledger.partitions();
+    assert!(partitions.partition_entries_by_month().await?);
+    assert!(!partitions.partition_entries_by_month().await?);
+
+    let today = Utc::now().date_naive();
+    let next_month = today.with_day(1).unwrap() + Months::new(1);
+    let created = partitions
+        .create_entries_partitions(next_month + Months::new(2))
+        .await?;
+    let name = |month: chrono::NaiveDate| {
+        format!(
+            "sqlx_ledger_entries_{:04}_{:02}",
+            month.year(),
+            month.month()
+        )
+    };
+    assert_eq!(
+        created,
+        vec![
+            name(next_month + Months::new(1)),
+            name(next_month + Months::new(2))
+        ]
+    );
+    assert!(partitions
+        .create_entries_partitions(next_month)
+        .await?
+        .is_empty());
+
+    // Entries posted before and after partitioning are both visible
+    transfer().await?;
+    let balance = ledger
+        .balances()
+        .find(journal_id, account_ids[1], "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(2));
+
+    let last_month = AccountingPeriod::month_of(next_month - Months::new(2));
+    let mut params = TxParams::new();
+    params.insert("sender", account_ids[0]);
+    params.insert("recipient", account_ids[1]);
+    params.set_effective(last_month.start);
+    ledger.post_transaction(&tx_code, Some(params)).await?;
+    ledger.periods().close(journal_id, last_month).await?;
+
+    assert!(partitions
+        .detach_entries_partitions(next_month - Months::new(1))
+        .await?
+        .is_empty());
+    let detached = partitions
+        .detach_entries_partitions(next_month + Months::new(1))
+        .await?;
+    assert_eq!(
+        detached,
+        vec!["sqlx_ledger_entries_legacy".to_string(), name(next_month)]
+    );
+    let attached: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlx_ledger_entries")
+        .fetch_one(&ledger_pool)
+        .await?;
+    assert_eq!(attached, 0);
+    let archived: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlx_ledger_entries_legacy")
+        .fetch_one(&ledger_pool)
+        .await?;
+    assert!(archived >= 3);
+
+    // Detached entries drop out of the trial balance, while balances and
+    // the closing balances recorded before detaching stay as they were
+    let trial_balance = ledger.reports().trial_balance(journal_id, today).await?;
+    assert!(trial_balance.lines.is_empty());
+    let balance = ledger
+        .balances()
+        .find(journal_id, account_ids[1], "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(3));
+    let closing = ledger
+        .periods()
+        .closing_balances(journal_id, last_month.start)
+        .await?;
+    let recipient = closing
+        .iter()
+        .find(|b| b.account_id == account_ids[1])
+        .unwrap();
+    assert_eq!(recipient.settled(), Decimal::from(1));
+
+    ledger_pool.close().await;
+    Ok(())
+}
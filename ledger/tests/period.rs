@@ -0,0 +1,71 @@
+mod helpers;
+
+use chrono::Months;
+use rust_decimal::Decimal;
+use sqlx_ledger::{period::*, *};
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn close_period() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let today = chrono::Utc::now().date_naive();
+    let last_month = AccountingPeriod::month_of(today.checked_sub_months(Months::new(1)).unwrap());
+    let post = |amount: u32, effective: chrono::NaiveDate| {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::from(amount));
+        params.set_effective(effective);
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    post(30, last_month.start).await?;
+    post(12, last_month.end.pred_opt().unwrap()).await?;
+    post(100, today).await?;
+
+    let periods = ledger.periods();
+    periods.close(journal_id, last_month).await?;
+    assert!(matches!(
+        periods.close(journal_id, last_month).await,
+        Err(SqlxLedgerError::PeriodAlreadyClosed(_, start)) if start == last_month.start
+    ));
+    let closed = periods.list_closed(journal_id).await?;
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].period, last_month);
+
+    assert!(matches!(
+        post(1, last_month.start).await,
+        Err(SqlxLedgerError::PeriodClosed(id, effective)) if id == journal_id && effective == last_month.start
+    ));
+    // Postings after the period are still allowed
+    post(5, today).await?;
+
+    let balances = periods
+        .closing_balances(journal_id, last_month.start)
+        .await?;
+    assert_eq!(balances.len(), 2);
+    let recipient = balances
+        .iter()
+        .find(|b| b.account_id == recipient_account_id)
+        .unwrap();
+    assert_eq!(recipient.currency, btc);
+    assert_eq!(recipient.effective, last_month.end.pred_opt().unwrap());
+    assert_eq!(recipient.settled(), Decimal::from(42));
+    let sender = balances
+        .iter()
+        .find(|b| b.account_id == sender_account_id)
+        .unwrap();
+    assert_eq!(sender.settled_dr, Decimal::from(42));
+    Ok(())
+}
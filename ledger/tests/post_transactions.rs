@@ -1,98 +1,590 @@
 mod helpers;
 
+use chrono::TimeZone;
 use rand::distributions::{Alphanumeric, DistString};
 use sqlx_ledger::{account::*, journal::*, tx_template::*, *};
 
+use helpers::TestJournal;
+
 #[tokio::test]
 async fn post_transaction() -> anyhow::Result<()> {
     let pool = helpers::init_pool().await?;
 
     let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
 
-    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
-    let new_journal = NewJournal::builder().name(name).build().unwrap();
     let ledger = SqlxLedger::new(&pool);
 
-    let journal_id = ledger.journals().create(new_journal).await.unwrap();
-    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
-    let new_account = NewAccount::builder()
-        .name(format!("Test Sender Account {code}"))
-        .code(code)
-        .build()
-        .unwrap();
-    let sender_account_id = ledger.accounts().create(new_account).await.unwrap();
-    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
-    let new_account = NewAccount::builder()
-        .name(format!("Test Recipient Account {code}"))
-        .code(code)
-        .build()
-        .unwrap();
-    let recipient_account_id = ledger.accounts().create(new_account).await.unwrap();
+    let journal = TestJournal::create(&ledger).await?;
 
-    let params = vec![
-        ParamDefinition::builder()
-            .name("recipient")
-            .r#type(ParamDataType::UUID)
-            .build()
-            .unwrap(),
-        ParamDefinition::builder()
-            .name("sender")
-            .r#type(ParamDataType::UUID)
-            .build()
-            .unwrap(),
-        ParamDefinition::builder()
-            .name("journal_id")
-            .r#type(ParamDataType::UUID)
-            .build()
-            .unwrap(),
+    let mut params = helpers::transfer_params();
+    params.push(
         ParamDefinition::builder()
             .name("effective")
             .r#type(ParamDataType::DATE)
             .default_expr("date()")
             .build()
             .unwrap(),
-    ];
+    );
+    let entries = helpers::transfer_entries("1290", "'BTC'");
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("params.effective")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await.unwrap();
+    let params = journal.params();
+    ledger
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_with_unbalanced_entry_group() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+
+    let params = helpers::transfer_params();
     let entries = vec![
         EntryInput::builder()
-            .entry_type("'TEST_DR'")
+            .entry_type("'PRINCIPAL_DR'")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("1000")
+            .currency("'BTC'")
+            .group("principal")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'PRINCIPAL_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("1000")
+            .currency("'BTC'")
+            .group("principal")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'FEE_DR'")
             .account_id("params.sender")
             .layer("SETTLED")
             .direction("DEBIT")
-            .units("1290")
+            .units("10")
             .currency("'BTC'")
+            .group("fee")
             .build()
             .unwrap(),
         EntryInput::builder()
-            .entry_type("'TEST_CR'")
+            .entry_type("'FEE_CR'")
             .account_id("params.recipient")
             .layer("SETTLED")
             .direction("CREDIT")
-            .units("1290")
+            .units("10")
             .currency("'BTC'")
             .build()
             .unwrap(),
     ];
-    let new_template = NewTxTemplate::builder()
-        .code(&tx_code)
-        .params(params)
-        .tx_input(
-            TxInput::builder()
-                .effective("params.effective")
-                .journal_id("params.journal_id")
-                .build()
-                .unwrap(),
-        )
-        .entries(entries)
+    let new_template = helpers::transfer_template(&tx_code, params, entries);
+    ledger.tx_templates().create(new_template).await.unwrap();
+    let params = journal.params();
+    let result = ledger.post_transaction(&tx_code, Some(params)).await;
+    assert!(matches!(
+        result,
+        Err(SqlxLedgerError::UnbalancedEntryGroup(group, _, _)) if group == "fee"
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_times_out_on_exhausted_pool() -> anyhow::Result<()> {
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&helpers::database_url())
+        .await?;
+    let _conn = pool.acquire().await?;
+
+    let ledger = SqlxLedger::new(&pool).with_acquire_timeout(std::time::Duration::from_millis(100));
+    let result = ledger.post_transaction("UNKNOWN", None::<TxParams>).await;
+    assert!(matches!(result, Err(SqlxLedgerError::PoolTimeout)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_with_actor_id() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let actor_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let mut params = journal.params();
+    params.set_actor_id(&actor_id);
+    ledger
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+
+    let transactions = ledger.transactions().list_by_actor_id(&actor_id).await?;
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].actor_id.as_deref(), Some(actor_id.as_str()));
+    assert_eq!(transactions[0].journal_id, journal.journal_id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_with_fixed_clock() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let now = chrono::Utc
+        .with_ymd_and_hms(2022, 11, 1, 12, 30, 0)
+        .unwrap();
+    let ledger = SqlxLedger::new(&pool).with_clock(FixedClock(now));
+
+    let journal = TestJournal::create(&ledger).await?;
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let actor_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let mut params = journal.params();
+    params.set_actor_id(&actor_id);
+    ledger
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+
+    let transactions = ledger.transactions().list_by_actor_id(&actor_id).await?;
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].created_at, now);
+    assert_eq!(transactions[0].effective, now.date_naive());
+    Ok(())
+}
+
+#[tokio::test]
+async fn dry_run_does_not_persist() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Sender Account {code}"))
+        .code(code)
+        .build()
+        .unwrap();
+    let sender_account_id = ledger.accounts().create(new_account).await.unwrap();
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Recipient Account {code}"))
+        .code(code.clone())
         .build()
         .unwrap();
+    let recipient_account_id = ledger
+        .dry_run()
+        .accounts()
+        .create(new_account)
+        .await
+        .unwrap();
+    assert!(ledger.accounts().find_by_code(&code).await?.is_none());
+    let new_account = NewAccount::builder()
+        .id(recipient_account_id)
+        .name(format!("Test Recipient Account {code}"))
+        .code(code)
+        .build()
+        .unwrap();
+    ledger.accounts().create(new_account).await.unwrap();
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let mut params = TxParams::new();
+    params.insert("journal_id", journal_id);
+    params.insert("sender", sender_account_id);
+    params.insert("recipient", recipient_account_id);
+    ledger
+        .dry_run()
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient_account_id, "BTC".parse().unwrap())
+        .await?;
+    assert!(balance.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_with_large_amount() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        recipient_account_id,
+        ..
+    } = journal;
+
+    let params = helpers::amount_params();
+    let entries = helpers::transfer_entries("params.amount", "'ETH'");
+    let new_template = helpers::transfer_template(&tx_code, params, entries);
     ledger.tx_templates().create(new_template).await.unwrap();
+
+    let amount = rust_decimal::Decimal::MAX;
+    let mut params = journal.params();
+    params.insert("amount", amount);
+    ledger
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient_account_id, "ETH".parse().unwrap())
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), amount);
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_respects_allowed_direction() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Sender Account {code}"))
+        .code(code)
+        .build()
+        .unwrap();
+    let sender_account_id = ledger.accounts().create(new_account).await.unwrap();
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Debit Only Account {code}"))
+        .code(code)
+        .allowed_direction(DebitOrCredit::Debit)
+        .build()
+        .unwrap();
+    let recipient_account_id = ledger.accounts().create(new_account).await.unwrap();
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
     let mut params = TxParams::new();
     params.insert("journal_id", journal_id);
     params.insert("sender", sender_account_id);
     params.insert("recipient", recipient_account_id);
+    let result = ledger.post_transaction(&tx_code, Some(params)).await;
+    assert!(matches!(
+        result,
+        Err(SqlxLedgerError::DirectionNotAllowed(id, DebitOrCredit::Credit)) if id == recipient_account_id
+    ));
+    let balance = ledger
+        .balances()
+        .find(journal_id, sender_account_id, "BTC".parse().unwrap())
+        .await?;
+    assert!(balance.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_to_unknown_journal() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Sender Account {code}"))
+        .code(code)
+        .build()
+        .unwrap();
+    let sender_account_id = ledger.accounts().create(new_account).await.unwrap();
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Recipient Account {code}"))
+        .code(code)
+        .build()
+        .unwrap();
+    let recipient_account_id = ledger.accounts().create(new_account).await.unwrap();
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let unknown_journal_id = JournalId::new();
+    let params = || {
+        let mut params = TxParams::new();
+        params.insert("journal_id", unknown_journal_id);
+        params.insert("sender", sender_account_id);
+        params.insert("recipient", recipient_account_id);
+        params
+    };
+    let result = ledger.post_transaction(&tx_code, Some(params())).await;
+    assert!(matches!(
+        result,
+        Err(SqlxLedgerError::JournalNotFound(id)) if id == unknown_journal_id
+    ));
+
+    let journal = TestJournal::create(&ledger).await?;
+    let result = ledger
+        .post_transactions(vec![(&tx_code, journal.params()), (&tx_code, params())])
+        .await;
+    assert!(matches!(
+        result,
+        Err(SqlxLedgerError::JournalNotFound(id)) if id == unknown_journal_id
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_content_hash() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let actor_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let mut params = journal.params();
+    params.set_actor_id(&actor_id);
     ledger
         .post_transaction(&tx_code, Some(params))
         .await
         .unwrap();
+
+    let transactions = ledger.transactions().list_by_actor_id(&actor_id).await?;
+    let mut entries = ledger
+        .entries()
+        .list_by_transaction_id(transactions[0].id)
+        .await?;
+    assert_eq!(entries.len(), 2);
+    let hash = transactions[0].content_hash(&entries);
+    assert_eq!(hash.len(), 64);
+
+    entries.reverse();
+    assert_eq!(transactions[0].content_hash(&entries), hash);
+
+    // Metadata is not part of the digest
+    ledger
+        .transactions()
+        .update_metadata(transactions[0].id, serde_json::json!({ "note": "updated" }))
+        .await?;
+    let updated = ledger.transactions().find_by_id(transactions[0].id).await?;
+    assert_eq!(updated.version, 2);
+    assert_eq!(updated.content_hash(&entries), hash);
+
+    entries[0].units += rust_decimal::Decimal::ONE;
+    assert_ne!(transactions[0].content_hash(&entries), hash);
+    entries[0].units -= rust_decimal::Decimal::ONE;
+    entries[0].conversion = Some(entry::Conversion {
+        from_currency: "USD".parse()?,
+        from_units: rust_decimal::Decimal::ONE,
+        rate: rust_decimal::Decimal::ONE,
+    });
+    assert_ne!(transactions[0].content_hash(&entries), hash);
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_is_idempotent_on_external_id() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        recipient_account_id,
+        ..
+    } = journal;
+
+    let mut params = helpers::transfer_params();
+    params.push(
+        ParamDefinition::builder()
+            .name("external_id")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+    );
+    let entries = helpers::transfer_entries("1290", "'BTC'");
+    let new_template = |code: &str| {
+        NewTxTemplate::builder()
+            .code(code)
+            .params(params.clone())
+            .tx_input(
+                TxInput::builder()
+                    .effective("date()")
+                    .journal_id("params.journal_id")
+                    .external_id("params.external_id")
+                    .build()
+                    .unwrap(),
+            )
+            .entries(entries.clone())
+            .build()
+            .unwrap()
+    };
+    ledger
+        .tx_templates()
+        .create(new_template(&tx_code))
+        .await
+        .unwrap();
+
+    let external_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let post = |tx_code: String| {
+        let mut params = journal.params();
+        params.insert("external_id", external_id.clone());
+        let ledger = ledger.clone();
+        async move { ledger.post_transaction(&tx_code, Some(params)).await }
+    };
+    let (first, second) = tokio::join!(post(tx_code.clone()), post(tx_code.clone()));
+    let first = first?;
+    assert_eq!(first, second?);
+    assert_eq!(post(tx_code.clone()).await?, first);
+
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient_account_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.details.version, 1);
+
+    // Repeating an external_id within a batch posts it once
+    let external_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let params = || {
+        let mut params = journal.params();
+        params.insert("external_id", external_id.clone());
+        (tx_code.as_str(), params)
+    };
+    let ids = ledger.post_transactions(vec![params(), params()]).await?;
+    assert_eq!(ids[0], ids[1]);
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient_account_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.details.version, 2);
+
+    // The external_id is only reused within the template that posted it
+    let other_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    ledger
+        .tx_templates()
+        .create(new_template(&other_code))
+        .await?;
+    assert!(matches!(
+        post(other_code).await,
+        Err(SqlxLedgerError::DuplicateKey(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transactions_in_batch() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let mut params = helpers::transfer_params();
+    params.extend([
+        ParamDefinition::builder()
+            .name("units")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("currency")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+    ]);
+    let entries = helpers::transfer_entries("params.units", "params.currency");
+    let new_template = helpers::transfer_template(&tx_code, params, entries);
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let transfer = |units: u32, currency: &str| {
+        let mut params = journal.params();
+        params.insert("units", rust_decimal::Decimal::from(units));
+        params.insert("currency", currency);
+        (tx_code.as_str(), params)
+    };
+    let ids = ledger
+        .post_transactions(vec![
+            transfer(10, "BTC"),
+            transfer(20, "USD"),
+            transfer(5, "BTC"),
+        ])
+        .await?;
+    assert_eq!(ids.len(), 3);
+    for id in ids {
+        assert_eq!(
+            ledger.transactions().find_by_id(id).await?.journal_id,
+            journal_id
+        );
+    }
+
+    let balances = ledger.balances();
+    let btc = balances
+        .find(journal_id, recipient_account_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(btc.settled(), rust_decimal::Decimal::from(15));
+    assert_eq!(btc.details.version, 2);
+    let usd = balances
+        .find(journal_id, sender_account_id, "USD".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(usd.settled(), rust_decimal::Decimal::from(-20));
+    assert_eq!(usd.details.version, 1);
+
+    // An invalid transaction rolls back the whole batch
+    let mut bad = transfer(1, "BTC");
+    bad.1.insert("journal_id", JournalId::new());
+    assert!(ledger
+        .post_transactions(vec![transfer(1, "BTC"), bad])
+        .await
+        .is_err());
+    let btc = balances
+        .find(journal_id, recipient_account_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(btc.details.version, 2);
     Ok(())
 }
@@ -0,0 +1,157 @@
+mod helpers;
+
+use chrono::{TimeZone, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{account::*, journal::*, tx_template::*, *};
+
+#[tokio::test]
+async fn post_converted_entries() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let mut account_ids = Vec::new();
+    for _ in 0..2 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await.unwrap());
+    }
+    let (customer, trading) = (account_ids[0], account_ids[1]);
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let params = vec![
+        ParamDefinition::builder()
+            .name("customer")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("trading")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("journal_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("btc")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("quote")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+    ];
+    // The customer sells BTC for fiat, the trading account takes the other side of both legs
+    let entries = vec![
+        EntryInput::builder()
+            .entry_type("'SELL_BTC_DR'")
+            .account_id("params.customer")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("params.btc")
+            .currency("'BTC'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'SELL_BTC_CR'")
+            .account_id("params.trading")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("params.btc")
+            .currency("'BTC'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'BUY_FIAT_DR'")
+            .account_id("params.trading")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("params.btc")
+            .currency("params.quote")
+            .convert_from("'BTC'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'BUY_FIAT_CR'")
+            .account_id("params.customer")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("params.btc")
+            .currency("params.quote")
+            .convert_from("'BTC'")
+            .build()
+            .unwrap(),
+    ];
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let btc = "BTC".parse::<Currency>()?;
+    let usd = "USD".parse::<Currency>()?;
+    let rates = ledger.rates();
+    let day = |d| Utc.with_ymd_and_hms(2020, 1, d, 0, 0, 0).unwrap();
+    rates.set(btc, usd, Decimal::from(19_000), day(1)).await?;
+    rates.set(btc, usd, Decimal::from(20_000), day(2)).await?;
+    assert!(matches!(
+        rates.set(btc, usd, Decimal::ZERO, day(3)).await,
+        Err(SqlxLedgerError::InvalidExchangeRate(_, _, _))
+    ));
+    let rate = rates.find(btc, usd, day(1)).await?.unwrap();
+    assert_eq!(rate.rate, Decimal::from(19_000));
+
+    let sell = |quote: &str| {
+        let mut params = TxParams::new();
+        params.insert("journal_id", journal_id);
+        params.insert("customer", customer);
+        params.insert("trading", trading);
+        params.insert("btc", Decimal::new(12345, 6));
+        params.insert("quote", quote);
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    let tx_id = sell("USD").await?;
+
+    let entries = ledger.entries().list_by_transaction_id(tx_id).await?;
+    assert_eq!(entries[0].conversion, None);
+    let conversion = entries[3].conversion.unwrap();
+    assert_eq!(conversion.from_currency, btc);
+    assert_eq!(conversion.from_units, Decimal::new(12345, 6));
+    assert_eq!(conversion.rate, Decimal::from(20_000));
+    assert_eq!(entries[3].currency, usd);
+    assert_eq!(entries[3].units, Decimal::new(24690, 2));
+
+    let balance = ledger
+        .balances()
+        .find(journal_id, customer, usd)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::new(24690, 2));
+
+    assert!(matches!(
+        sell("JPY").await,
+        Err(SqlxLedgerError::ExchangeRateNotFound(from, to)) if from == btc && to.code() == "JPY"
+    ));
+    Ok(())
+}
@@ -0,0 +1,63 @@
+mod helpers;
+
+use rust_decimal::Decimal;
+use sqlx_ledger::*;
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn trial_balance() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let btc = "BTC".parse::<Currency>().unwrap();
+    let today = chrono::Utc::now().date_naive();
+    let yesterday = today.pred_opt().unwrap();
+    for (amount, effective) in [(100, yesterday), (20, today)] {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::from(amount));
+        params.set_effective(effective);
+        ledger.post_transaction(&tx_code, Some(params)).await?;
+    }
+
+    let reports = ledger.reports();
+    let trial_balance = reports.trial_balance(journal_id, today).await?;
+    assert_eq!(trial_balance.lines.len(), 2);
+    assert!(trial_balance.is_balanced());
+    assert_eq!(
+        trial_balance.totals(btc),
+        (Decimal::from(120), Decimal::from(120))
+    );
+    let sender = trial_balance
+        .lines
+        .iter()
+        .find(|line| line.account_id == sender_account_id)
+        .unwrap();
+    assert_eq!(sender.debit, Decimal::from(120));
+    assert_eq!(sender.credit, Decimal::ZERO);
+    assert_eq!(sender.net(), Decimal::from(-120));
+
+    let trial_balance = reports.trial_balance(journal_id, yesterday).await?;
+    let recipient = trial_balance
+        .lines
+        .iter()
+        .find(|line| line.account_id == recipient_account_id)
+        .unwrap();
+    assert_eq!(recipient.net(), Decimal::from(100));
+    assert!(reports
+        .trial_balance(journal_id, yesterday.pred_opt().unwrap())
+        .await?
+        .lines
+        .is_empty());
+    Ok(())
+}
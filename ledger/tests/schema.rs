@@ -0,0 +1,42 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx_ledger::{journal::*, *};
+
+#[tokio::test]
+async fn ledger_in_schema() -> anyhow::Result<()> {
+    // sqlx resolves the ledger's enum types by name across schemas,
+    // so this runs in a database of its own.
+    let database = helpers::TestDatabase::create().await?;
+    let app_pool = sqlx::PgPool::connect_with(database.connect_options()).await?;
+    sqlx::query("CREATE SCHEMA ledger")
+        .execute(&app_pool)
+        .await?;
+
+    let ledger_pool = SqlxLedger::pool_options_in_schema("ledger")
+        .connect_with(database.connect_options())
+        .await?;
+    sqlx::migrate!("../migrations").run(&ledger_pool).await?;
+    let ledger = SqlxLedger::new(&ledger_pool);
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let journal_id = ledger
+        .journals()
+        .create(NewJournal::builder().name(name).build().unwrap())
+        .await?;
+
+    let journals: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM ledger.sqlx_ledger_journals WHERE id = $1")
+            .bind(uuid::Uuid::from(journal_id))
+            .fetch_one(&app_pool)
+            .await?;
+    assert_eq!(journals, 1);
+    let in_public: bool =
+        sqlx::query_scalar("SELECT to_regclass('public.sqlx_ledger_journals') IS NOT NULL")
+            .fetch_one(&app_pool)
+            .await?;
+    assert!(!in_public);
+
+    ledger_pool.close().await;
+    app_pool.close().await;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+mod helpers;
+
+use rust_decimal::Decimal;
+use sqlx_ledger::*;
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn simulate_transaction() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let params = |journal_id: JournalId| {
+        let mut params = journal.params();
+        params.insert("journal_id", journal_id);
+        params
+    };
+    ledger
+        .post_transaction(&tx_code, Some(params(journal_id)))
+        .await?;
+
+    let simulated = ledger
+        .simulate_transaction(&tx_code, Some(params(journal_id)))
+        .await?;
+    assert_eq!(simulated.journal_id, journal_id);
+    assert_eq!(simulated.entries.len(), 2);
+    assert_eq!(simulated.entries[0].account_id, sender_account_id);
+    assert_eq!(simulated.balances.len(), 2);
+    let recipient = simulated
+        .balances
+        .iter()
+        .find(|b| b.details.account_id == recipient_account_id)
+        .unwrap();
+    assert_eq!(recipient.settled(), Decimal::from(2580));
+    assert_eq!(recipient.details.version, 2);
+
+    // Nothing was persisted
+    let balance = ledger
+        .balances()
+        .find(journal_id, recipient_account_id, "BTC".parse()?)
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(1290));
+    assert_eq!(balance.details.version, 1);
+
+    assert!(matches!(
+        ledger
+            .simulate_transaction(&tx_code, Some(params(JournalId::new())))
+            .await,
+        Err(SqlxLedgerError::JournalNotFound(_))
+    ));
+    Ok(())
+}
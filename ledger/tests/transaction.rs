@@ -0,0 +1,218 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx_ledger::{account::*, transaction::*, tx_template::*, *};
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn list_transactions() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        recipient_account_id,
+        ..
+    } = journal;
+
+    let mut params = helpers::transfer_params();
+    params.extend([
+        ParamDefinition::builder()
+            .name("external_id")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("meta")
+            .r#type(ParamDataType::JSON)
+            .build()
+            .unwrap(),
+    ]);
+    let entries = helpers::transfer_entries("1290", "'BTC'");
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .external_id("params.external_id")
+                .metadata("params.meta")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let prefix = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    let mut posted = Vec::new();
+    for (i, kind) in ["card", "wire", "card"].into_iter().enumerate() {
+        let mut params = journal.params();
+        params.insert("external_id", format!("{prefix}-{i}"));
+        params.insert("meta", serde_json::json!({ "kind": kind }));
+        posted.push(ledger.post_transaction(&tx_code, Some(params)).await?);
+    }
+
+    let list = |filter: TransactionFilter, first: usize| {
+        let ledger = ledger.clone();
+        async move {
+            let mut ids = Vec::new();
+            let mut after = None;
+            loop {
+                let page = ledger
+                    .transactions()
+                    .list(filter.clone(), PaginatedQueryArgs { first, after })
+                    .await?;
+                assert!(page.entities.len() <= first);
+                ids.extend(page.entities.into_iter().map(|t| t.id));
+                if !page.has_next_page {
+                    break;
+                }
+                after = page.end_cursor;
+            }
+            Ok::<_, SqlxLedgerError>(ids)
+        }
+    };
+    let mut all = posted.clone();
+    all.sort();
+    let by_journal = TransactionFilter {
+        journal_id: Some(journal_id),
+        ..Default::default()
+    };
+    assert_eq!(list(by_journal.clone(), 1).await?, all);
+    let by_account = TransactionFilter {
+        account_id: Some(recipient_account_id),
+        tx_template_code: Some(tx_code.clone()),
+        external_id_prefix: Some(prefix.clone()),
+        ..Default::default()
+    };
+    assert_eq!(list(by_account, 2).await?, all);
+    let cards = TransactionFilter {
+        metadata: Some(serde_json::json!({ "kind": "card" })),
+        ..by_journal.clone()
+    };
+    let mut expected = vec![posted[0], posted[2]];
+    expected.sort();
+    assert_eq!(list(cards, 10).await?, expected);
+    let wire = TransactionFilter {
+        external_id_prefix: Some(format!("{prefix}-1")),
+        ..Default::default()
+    };
+    assert_eq!(list(wire, 10).await?, vec![posted[1]]);
+    let future = TransactionFilter {
+        created_from: Some(chrono::Utc::now()),
+        ..by_journal
+    };
+    assert!(list(future, 10).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn update_transaction_metadata() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let params = journal.params();
+    let id = ledger.post_transaction(&tx_code, Some(params)).await?;
+
+    let batch = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    ledger
+        .transactions()
+        .update_metadata(id, serde_json::json!({ "payout_batch": batch }))
+        .await?;
+    ledger
+        .dry_run()
+        .transactions()
+        .update_metadata(id, serde_json::json!({ "payout_batch": "dry" }))
+        .await?;
+    let transaction = ledger.transactions().find_by_id(id).await?;
+    assert_eq!(transaction.version, 2);
+    assert_eq!(
+        transaction.metadata,
+        Some(serde_json::json!({ "payout_batch": batch }))
+    );
+    assert_eq!(ledger.entries().list_by_transaction_id(id).await?.len(), 2);
+    let found = ledger
+        .transactions()
+        .list(
+            TransactionFilter {
+                metadata: Some(serde_json::json!({ "payout_batch": batch })),
+                ..Default::default()
+            },
+            PaginatedQueryArgs {
+                first: 10,
+                after: None,
+            },
+        )
+        .await?;
+    assert_eq!(found.entities.len(), 1);
+    assert_eq!(found.entities[0].id, id);
+
+    let unknown = TransactionId::new();
+    assert!(matches!(
+        ledger
+            .transactions()
+            .update_metadata(unknown, serde_json::json!({}))
+            .await,
+        Err(SqlxLedgerError::TransactionNotFound(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_transaction_in_caller_tx() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        recipient_account_id,
+        ..
+    } = journal;
+
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    // Rolled back together with the other writes of the caller's transaction
+    let mut tx = pool.begin().await?;
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_account = NewAccount::builder()
+        .name(format!("Test Account {code}"))
+        .code(&code)
+        .build()
+        .unwrap();
+    ledger.create_account_in_tx(&mut tx, new_account).await?;
+    let id = ledger
+        .post_transaction_in_tx(&mut tx, &tx_code, Some(journal.params()))
+        .await?;
+    tx.rollback().await?;
+    assert!(ledger.transactions().find_by_id(id).await.is_err());
+    assert!(ledger.accounts().find_by_code(&code).await?.is_none());
+    let btc = "BTC".parse::<Currency>()?;
+    assert!(ledger
+        .balances()
+        .find(journal_id, recipient_account_id, btc)
+        .await?
+        .is_none());
+
+    let mut tx = pool.begin().await?;
+    let id = ledger
+        .post_transaction_in_tx(&mut tx, &tx_code, Some(journal.params()))
+        .await?;
+    tx.commit().await?;
+    assert_eq!(ledger.transactions().find_by_id(id).await?.id, id);
+    Ok(())
+}
@@ -1,20 +1,30 @@
 mod helpers;
 
 use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
 use sqlx_ledger::{tx_template::*, *};
 
+use helpers::TestJournal;
+
 #[tokio::test]
 async fn test_tx_template() -> anyhow::Result<()> {
     let pool = helpers::init_pool().await?;
 
     let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
 
-    let params = vec![ParamDefinition::builder()
-        .name("input1")
-        .r#type(ParamDataType::STRING)
-        .default_expr("'input'")
-        .build()
-        .unwrap()];
+    let params = vec![
+        ParamDefinition::builder()
+            .name("input1")
+            .r#type(ParamDataType::STRING)
+            .default_expr("'input'")
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("recipient")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+    ];
     let tx_input = TxInput::builder()
         .effective("1")
         .journal_id("1")
@@ -22,7 +32,7 @@ async fn test_tx_template() -> anyhow::Result<()> {
         .unwrap();
     let entries = vec![EntryInput::builder()
         .entry_type("'TEST_DR'")
-        .account_id("param.recipient")
+        .account_id("params.recipient")
         .layer("'Settled'")
         .direction("'Settled'")
         .units("1290")
@@ -44,3 +54,299 @@ async fn test_tx_template() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn tx_template_fails_type_check() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let params = vec![
+        ParamDefinition::builder()
+            .name("recipient")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("amount")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+    ];
+    let tx_input = TxInput::builder()
+        .effective("date()")
+        .journal_id("params.journal_id")
+        .build()
+        .unwrap();
+    let entries = vec![EntryInput::builder()
+        .entry_type("'TEST_DR'")
+        .account_id("params.recipient")
+        .layer("SETTLED")
+        .direction("DEBIT")
+        .units("params.amount * 'BTC'")
+        .currency("'BTC'")
+        .build()
+        .unwrap()];
+    let new_template = NewTxTemplate::builder()
+        .code(code)
+        .params(params)
+        .tx_input(tx_input)
+        .entries(entries)
+        .build()
+        .unwrap();
+    let result = SqlxLedger::new(&pool)
+        .tx_templates()
+        .create(new_template)
+        .await;
+    match result {
+        Err(SqlxLedgerError::TxTemplateTypeErrors(errors)) => {
+            let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+            assert_eq!(fields, vec!["tx_input.journal_id", "entries[0].units"]);
+        }
+        res => panic!("expected type errors, got {res:?}"),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn verify_tx_template_fixtures() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let params = helpers::transfer_params();
+    let entries = vec![
+        EntryInput::builder()
+            .entry_type("'TEST_DR'")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("1290")
+            .currency("'BTC'")
+            .name("principal_dr")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'TEST_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("1290")
+            .currency("'BTC'")
+            .name("principal_cr")
+            .build()
+            .unwrap(),
+    ];
+    let new_template = helpers::transfer_template(&code, params, entries);
+    let ledger = SqlxLedger::new(&pool);
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let recipient = AccountId::new();
+    let mut passing = TxParams::new();
+    passing.insert("journal_id", JournalId::new());
+    passing.insert("sender", AccountId::new());
+    passing.insert("recipient", recipient);
+    let mut failing = TxParams::new();
+    failing.insert("journal_id", JournalId::new());
+    failing.insert("sender", AccountId::new());
+    let results = ledger
+        .tx_templates()
+        .verify_fixtures(&code, vec![passing, failing])
+        .await?;
+    assert_eq!(results.len(), 2);
+    match &results[0] {
+        FixtureResult::Passed(output) => {
+            assert_eq!(output.entries.len(), 2);
+            let principal = &output.named_entries["principal_cr"];
+            assert_eq!(principal.account_id, recipient);
+            assert_eq!(principal.units, rust_decimal::Decimal::from(1290));
+        }
+        FixtureResult::Failed(e) => panic!("fixture should pass: {e}"),
+    }
+    assert!(matches!(results[1], FixtureResult::Failed(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn update_tx_template_creates_version() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+
+    let params = helpers::transfer_params();
+    let new_template = |fee: &str| {
+        helpers::transfer_template(
+            &tx_code,
+            params.clone(),
+            helpers::transfer_entries(fee, "'BTC'"),
+        )
+    };
+    let tx_template_id = ledger.tx_templates().create(new_template("100")).await?;
+    let post = || {
+        let params = journal.params();
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    let first = post().await?;
+    assert_eq!(ledger.tx_templates().update(new_template("200")).await?, 2);
+    let second = post().await?;
+
+    let first = ledger.transactions().find_by_id(first).await?;
+    assert_eq!(first.tx_template_id, tx_template_id);
+    assert_eq!(first.tx_template_version, 1);
+    let second = ledger.transactions().find_by_id(second).await?;
+    assert_eq!(second.tx_template_id, tx_template_id);
+    assert_eq!(second.tx_template_version, 2);
+    let entries = ledger.entries().list_by_transaction_id(second.id).await?;
+    assert_eq!(entries[0].units, Decimal::from(200));
+
+    let v1 = ledger.tx_templates().find_version(&tx_code, 1).await?;
+    assert_eq!(v1.id, tx_template_id);
+    assert_eq!(v1.version, 1);
+    assert_eq!(v1.entries.len(), 2);
+    assert!(matches!(
+        ledger.tx_templates().find_version(&tx_code, 3).await,
+        Err(SqlxLedgerError::TxTemplateVersionNotFound(_, 3))
+    ));
+    let unknown = NewTxTemplate::builder()
+        .code(Alphanumeric.sample_string(&mut rand::thread_rng(), 32))
+        .params(params.clone())
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(vec![])
+        .build()
+        .unwrap();
+    assert!(matches!(
+        ledger.tx_templates().update(unknown).await,
+        Err(SqlxLedgerError::TxTemplateNotFound(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn tx_template_with_conditional_entries() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let mut params = helpers::transfer_params();
+    params.push(
+        ParamDefinition::builder()
+            .name("fee")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+    );
+    let mut entries = helpers::transfer_entries("1290", "'BTC'");
+    entries.extend([
+        EntryInput::builder()
+            .entry_type("'TEST_FEE_DR'")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("params.fee")
+            .currency("'BTC'")
+            .condition("params.fee > decimal('0')")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'TEST_FEE_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("params.fee")
+            .currency("'BTC'")
+            .condition("params.fee > decimal('0')")
+            .name("fee")
+            .build()
+            .unwrap(),
+    ]);
+    let new_template = helpers::transfer_template(&code, params, entries);
+    let ledger = SqlxLedger::new(&pool);
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let fixtures = [0, 10]
+        .into_iter()
+        .map(|fee| {
+            let mut params = TxParams::new();
+            params.insert("journal_id", JournalId::new());
+            params.insert("sender", AccountId::new());
+            params.insert("recipient", AccountId::new());
+            params.insert("fee", Decimal::from(fee));
+            params
+        })
+        .collect();
+    let results = ledger
+        .tx_templates()
+        .verify_fixtures(&code, fixtures)
+        .await?;
+    match &results[0] {
+        FixtureResult::Passed(output) => {
+            assert_eq!(output.entries.len(), 2);
+            assert!(!output.named_entries.contains_key("fee"));
+        }
+        FixtureResult::Failed(e) => panic!("fixture should pass: {e}"),
+    }
+    match &results[1] {
+        FixtureResult::Passed(output) => {
+            assert_eq!(output.entries.len(), 4);
+            assert_eq!(output.named_entries["fee"].units, Decimal::from(10));
+        }
+        FixtureResult::Failed(e) => panic!("fixture should pass: {e}"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn tx_template_without_emitted_entries() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let entries = [
+        ("'TEST_DR'", "params.sender", "DEBIT"),
+        ("'TEST_CR'", "params.recipient", "CREDIT"),
+    ]
+    .into_iter()
+    .map(|(entry_type, account_id, direction)| {
+        EntryInput::builder()
+            .entry_type(entry_type)
+            .account_id(account_id)
+            .layer("SETTLED")
+            .direction(direction)
+            .units("params.amount")
+            .currency("'BTC'")
+            .condition("params.amount > decimal('0')")
+            .build()
+            .unwrap()
+    })
+    .collect();
+    let new_template = helpers::transfer_template(&code, helpers::amount_params(), entries);
+    let ledger = SqlxLedger::new(&pool);
+    ledger.tx_templates().create(new_template).await?;
+
+    let journal = TestJournal::create(&ledger).await?;
+    let params = || {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::ZERO);
+        params
+    };
+    assert!(matches!(
+        ledger.simulate_transaction(&code, Some(params())).await,
+        Err(SqlxLedgerError::NoEntriesEmitted(c)) if c == code
+    ));
+    assert!(matches!(
+        ledger.post_transaction(&code, Some(params())).await,
+        Err(SqlxLedgerError::NoEntriesEmitted(c)) if c == code
+    ));
+    Ok(())
+}
@@ -0,0 +1,283 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{account::*, account_set::*, journal::*, tx_template::*, velocity::*, *};
+
+struct Setup {
+    ledger: SqlxLedger,
+    tx_code: String,
+    tx_template_id: TxTemplateId,
+    journal_id: JournalId,
+    sender: AccountId,
+    recipient: AccountId,
+}
+
+async fn setup() -> anyhow::Result<Setup> {
+    let pool = helpers::init_pool().await?;
+    let ledger = SqlxLedger::new(&pool);
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let new_journal = NewJournal::builder().name(name).build().unwrap();
+    let journal_id = ledger.journals().create(new_journal).await.unwrap();
+    let mut account_ids = Vec::new();
+    for _ in 0..2 {
+        let code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let new_account = NewAccount::builder()
+            .name(format!("Test Account {code}"))
+            .code(code)
+            .build()
+            .unwrap();
+        account_ids.push(ledger.accounts().create(new_account).await.unwrap());
+    }
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let params = vec![
+        ParamDefinition::builder()
+            .name("sender")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("recipient")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("journal_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("units")
+            .r#type(ParamDataType::DECIMAL)
+            .build()
+            .unwrap(),
+        ParamDefinition::builder()
+            .name("kind")
+            .r#type(ParamDataType::STRING)
+            .build()
+            .unwrap(),
+    ];
+    let entries = vec![
+        EntryInput::builder()
+            .entry_type("params.kind")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("params.units")
+            .currency("'USD'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("params.kind")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("params.units")
+            .currency("'USD'")
+            .build()
+            .unwrap(),
+    ];
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(entries)
+        .build()
+        .unwrap();
+    let tx_template_id = ledger.tx_templates().create(new_template).await.unwrap();
+    Ok(Setup {
+        ledger,
+        tx_code,
+        tx_template_id,
+        journal_id,
+        sender: account_ids[0],
+        recipient: account_ids[1],
+    })
+}
+
+async fn transfer(setup: &Setup, units: u32, kind: &str) -> Result<TransactionId, SqlxLedgerError> {
+    let mut params = TxParams::new();
+    params.insert("journal_id", setup.journal_id);
+    params.insert("sender", setup.sender);
+    params.insert("recipient", setup.recipient);
+    params.insert("units", Decimal::from(units));
+    params.insert("kind", kind);
+    setup
+        .ledger
+        .post_transaction(&setup.tx_code, Some(params))
+        .await
+}
+
+#[tokio::test]
+async fn account_velocity_limit() -> anyhow::Result<()> {
+    let setup = setup().await?;
+    let limits = setup.ledger.velocity_limits();
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let limit_id = limits
+        .create(
+            NewVelocityLimit::builder()
+                .name(name)
+                .currency("USD".parse()?)
+                .direction(DebitOrCredit::Debit)
+                .window(VelocityWindow::Calendar(CalendarPeriod::Day))
+                .limit(Decimal::from(100))
+                .condition("entry.entry_type == 'CARD'")
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    limits.attach_to_account(limit_id, setup.sender).await?;
+
+    transfer(&setup, 60, "CARD").await?;
+    // Entries the condition doesn't match are not counted
+    transfer(&setup, 500, "TOP_UP").await?;
+    let err = transfer(&setup, 41, "CARD").await.unwrap_err();
+    match err {
+        SqlxLedgerError::VelocityLimitExceeded(exceeded) => {
+            assert_eq!(exceeded.velocity_limit_id, limit_id);
+            assert_eq!(exceeded.account_id, setup.sender);
+            assert_eq!(exceeded.attempted, Decimal::from(101));
+            assert_eq!(exceeded.limit, Decimal::from(100));
+        }
+        e => panic!("unexpected error {e}"),
+    }
+    // The rejected posting left no usage behind
+    let last = transfer(&setup, 40, "CARD").await?;
+    // Voiding a transaction gives its usage back
+    assert!(transfer(&setup, 40, "CARD").await.is_err());
+    setup.ledger.void_transaction(last).await?;
+    transfer(&setup, 40, "CARD").await?;
+    // Credits to the account are not limited
+    let limited = setup.sender;
+    let reverse = Setup {
+        sender: setup.recipient,
+        recipient: limited,
+        ..setup
+    };
+    transfer(&reverse, 1000, "CARD").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tx_template_velocity_limit() -> anyhow::Result<()> {
+    let setup = setup().await?;
+    let limits = setup.ledger.velocity_limits();
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let limit_id = limits
+        .create(
+            NewVelocityLimit::builder()
+                .name(name)
+                .direction(DebitOrCredit::Credit)
+                .window(VelocityWindow::rolling(chrono::Duration::hours(1)))
+                .limit(Decimal::from(10))
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    limits
+        .attach_to_tx_template(limit_id, setup.tx_template_id)
+        .await?;
+    let limit = limits.find(limit_id).await?;
+    assert_eq!(limit.window, VelocityWindow::Rolling { seconds: 3600 });
+    assert_eq!(limit.currency, None);
+
+    transfer(&setup, 10, "WITHDRAWAL").await?;
+    assert!(matches!(
+        transfer(&setup, 1, "WITHDRAWAL").await,
+        Err(SqlxLedgerError::VelocityLimitExceeded(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn account_set_velocity_limit() -> anyhow::Result<()> {
+    let setup = setup().await?;
+    let sets = setup.ledger.account_sets();
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let customers = sets
+        .create(
+            NewAccountSet::builder()
+                .journal_id(setup.journal_id)
+                .name(name)
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let all = sets
+        .create(
+            NewAccountSet::builder()
+                .journal_id(setup.journal_id)
+                .name(name)
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    sets.add_member(customers, AccountSetMember::Account(setup.sender))
+        .await?;
+    sets.add_member(all, AccountSetMember::AccountSet(customers))
+        .await?;
+
+    let limits = setup.ledger.velocity_limits();
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let limit_id = limits
+        .create(
+            NewVelocityLimit::builder()
+                .name(name)
+                .direction(DebitOrCredit::Debit)
+                .window(VelocityWindow::rolling(chrono::Duration::hours(1)))
+                .limit(Decimal::from(10))
+                .build()
+                .unwrap(),
+        )
+        .await?;
+    limits.attach_to_account_set(limit_id, all).await?;
+
+    transfer(&setup, 10, "WITHDRAWAL").await?;
+    assert!(matches!(
+        transfer(&setup, 1, "WITHDRAWAL").await,
+        Err(SqlxLedgerError::VelocityLimitExceeded(_))
+    ));
+    // The recipient isn't a member of the set
+    let reverse = Setup {
+        sender: setup.recipient,
+        recipient: setup.sender,
+        ..setup
+    };
+    transfer(&reverse, 100, "WITHDRAWAL").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn rolling_window_must_be_positive() -> anyhow::Result<()> {
+    let setup = setup().await?;
+    for seconds in [0, -1, i64::MAX] {
+        let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let result = setup
+            .ledger
+            .velocity_limits()
+            .create(
+                NewVelocityLimit::builder()
+                    .name(name)
+                    .direction(DebitOrCredit::Debit)
+                    .window(VelocityWindow::Rolling { seconds })
+                    .limit(Decimal::from(10))
+                    .build()
+                    .unwrap(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(SqlxLedgerError::InvalidVelocityWindow(s)) if s == seconds
+        ));
+    }
+    Ok(())
+}
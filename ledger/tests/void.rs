@@ -0,0 +1,324 @@
+mod helpers;
+
+use rand::distributions::{Alphanumeric, DistString};
+use rust_decimal::Decimal;
+use sqlx_ledger::{period::*, transaction::*, tx_template::*, *};
+
+use helpers::TestJournal;
+
+#[tokio::test]
+async fn void_transaction() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+
+    let journal = TestJournal::create(&ledger).await?;
+    let TestJournal {
+        journal_id,
+        sender_account_id,
+        recipient_account_id,
+    } = journal;
+
+    let params = helpers::transfer_params();
+    let entries = vec![
+        EntryInput::builder()
+            .entry_type("'TEST_DR'")
+            .account_id("params.sender")
+            .layer("SETTLED")
+            .direction("DEBIT")
+            .units("1290")
+            .currency("'BTC'")
+            .build()
+            .unwrap(),
+        EntryInput::builder()
+            .entry_type("'TEST_CR'")
+            .account_id("params.recipient")
+            .layer("SETTLED")
+            .direction("CREDIT")
+            .units("1290")
+            .currency("'BTC'")
+            .description("'payment'")
+            .build()
+            .unwrap(),
+    ];
+    let new_template = helpers::transfer_template(&tx_code, params, entries);
+    ledger.tx_templates().create(new_template).await.unwrap();
+
+    let actor_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let mut params = journal.params();
+    params.set_actor_id(&actor_id);
+    ledger
+        .post_transaction(&tx_code, Some(params))
+        .await
+        .unwrap();
+
+    let original = ledger.transactions().list_by_actor_id(&actor_id).await?[0].id;
+    let void_id = ledger.void_transaction(original).await?;
+
+    let voided = ledger.transactions().find_by_id(original).await?;
+    assert_eq!(voided.voided_by, Some(void_id));
+    assert_eq!(voided.version, 2);
+    let reversal = ledger.transactions().find_by_id(void_id).await?;
+    assert_eq!(reversal.voids, Some(original));
+    assert_eq!(reversal.correlation_id, voided.correlation_id);
+
+    let entries = ledger.entries().list_by_transaction_id(void_id).await?;
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].account_id, sender_account_id);
+    assert_eq!(entries[0].direction, DebitOrCredit::Credit);
+    assert_eq!(entries[1].direction, DebitOrCredit::Debit);
+    assert_eq!(entries[1].description.as_deref(), Some("payment"));
+    let btc = "BTC".parse()?;
+    for account_id in [sender_account_id, recipient_account_id] {
+        let balance = ledger
+            .balances()
+            .find(journal_id, account_id, btc)
+            .await?
+            .unwrap();
+        assert_eq!(balance.settled(), Decimal::ZERO);
+        assert_eq!(balance.details.version, 2);
+    }
+
+    assert!(matches!(
+        ledger.void_transaction(original).await,
+        Err(SqlxLedgerError::TransactionAlreadyVoided(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn void_policy_rejects_closed_and_old_transactions() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool).with_void_policy(VoidPolicy {
+        reject_closed_periods: true,
+        max_age: Some(chrono::Duration::days(40)),
+    });
+    let journal = TestJournal::create(&ledger).await?;
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+    let post = |effective: chrono::NaiveDate| {
+        let mut params = journal.params();
+        params.set_effective(effective);
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+
+    let today = chrono::Utc::now().date_naive();
+    let last_month =
+        AccountingPeriod::month_of(today.checked_sub_months(chrono::Months::new(1)).unwrap());
+    let closed = post(last_month.start).await?;
+    let old = post(today - chrono::Duration::days(41)).await?;
+    let recent = post(today).await?;
+    ledger
+        .periods()
+        .close(journal.journal_id, last_month)
+        .await?;
+
+    assert!(matches!(
+        ledger.void_transaction(closed).await,
+        Err(SqlxLedgerError::TransactionNotVoidable(id, effective))
+            if id == closed && effective == last_month.start
+    ));
+    assert!(matches!(
+        ledger.void_transaction(old).await,
+        Err(SqlxLedgerError::TransactionNotVoidable(id, _)) if id == old
+    ));
+    let void_id = ledger.force_void_transaction(closed).await?;
+    assert_eq!(
+        ledger.transactions().find_by_id(closed).await?.voided_by,
+        Some(void_id)
+    );
+    ledger.void_transaction(recent).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn void_correlation() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let tx_code = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    let ledger = SqlxLedger::new(&pool);
+    let journal = TestJournal::create(&ledger).await?;
+    let mut params = helpers::transfer_params();
+    params.push(
+        ParamDefinition::builder()
+            .name("correlation_id")
+            .r#type(ParamDataType::UUID)
+            .build()
+            .unwrap(),
+    );
+    let new_template = NewTxTemplate::builder()
+        .code(&tx_code)
+        .params(params)
+        .tx_input(
+            TxInput::builder()
+                .effective("date()")
+                .journal_id("params.journal_id")
+                .correlation_id("params.correlation_id")
+                .build()
+                .unwrap(),
+        )
+        .entries(helpers::transfer_entries("1290", "'BTC'"))
+        .build()
+        .unwrap();
+    ledger.tx_templates().create(new_template).await?;
+
+    let correlation_id = CorrelationId::new();
+    let post = |effective: chrono::NaiveDate| {
+        let mut params = journal.params();
+        params.insert("correlation_id", correlation_id);
+        params.set_effective(effective);
+        ledger.post_transaction(&tx_code, Some(params))
+    };
+    let today = chrono::Utc::now().date_naive();
+    let last_month =
+        AccountingPeriod::month_of(today.checked_sub_months(chrono::Months::new(1)).unwrap());
+    let voided = post(today).await?;
+    let current = post(today).await?;
+    let closed = post(last_month.start).await?;
+    ledger.void_transaction(voided).await?;
+    ledger
+        .periods()
+        .close(journal.journal_id, last_month)
+        .await?;
+
+    // Rejecting one of the voids rolls back the others
+    let strict = ledger.clone().with_void_policy(VoidPolicy {
+        reject_closed_periods: true,
+        ..VoidPolicy::default()
+    });
+    assert!(matches!(
+        strict.void_correlation(correlation_id).await,
+        Err(SqlxLedgerError::TransactionNotVoidable(id, _)) if id == closed
+    ));
+    assert_eq!(
+        ledger.transactions().find_by_id(current).await?.voided_by,
+        None
+    );
+
+    let void_ids = ledger.void_correlation(correlation_id).await?;
+    assert_eq!(void_ids.len(), 2);
+    for (original, void_id) in [current, closed].into_iter().zip(void_ids) {
+        let original = ledger.transactions().find_by_id(original).await?;
+        assert_eq!(original.voided_by, Some(void_id));
+        let reversal = ledger.transactions().find_by_id(void_id).await?;
+        assert_eq!(reversal.correlation_id, correlation_id);
+    }
+    let balance = ledger
+        .balances()
+        .find(
+            journal.journal_id,
+            journal.recipient_account_id,
+            "BTC".parse()?,
+        )
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::ZERO);
+    assert!(ledger.void_correlation(correlation_id).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn amend_transaction() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+    let journal = TestJournal::create(&ledger).await?;
+    let tx_code = helpers::create_amount_template(&ledger).await?;
+
+    let post_params = |amount: i32| {
+        let mut params = journal.params();
+        params.insert("amount", Decimal::from(amount));
+        params
+    };
+    let original = ledger
+        .post_transaction(&tx_code, Some(post_params(100)))
+        .await?;
+    let (void_id, correction_id) = ledger
+        .amend_transaction(original, &tx_code, Some(post_params(70)))
+        .await?;
+
+    let original = ledger.transactions().find_by_id(original).await?;
+    assert_eq!(original.voided_by, Some(void_id));
+    for id in [void_id, correction_id] {
+        let tx = ledger.transactions().find_by_id(id).await?;
+        assert_eq!(tx.correlation_id, original.correlation_id);
+    }
+    let balance = ledger
+        .balances()
+        .find(
+            journal.journal_id,
+            journal.recipient_account_id,
+            "BTC".parse()?,
+        )
+        .await?
+        .unwrap();
+    assert_eq!(balance.settled(), Decimal::from(70));
+
+    assert!(matches!(
+        ledger
+            .amend_transaction(original.id, &tx_code, Some(post_params(50)))
+            .await,
+        Err(SqlxLedgerError::TransactionAlreadyVoided(_))
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn filter_transactions_by_status() -> anyhow::Result<()> {
+    let pool = helpers::init_pool().await?;
+
+    let ledger = SqlxLedger::new(&pool);
+    let journal = TestJournal::create(&ledger).await?;
+    let tx_code = helpers::create_transfer_template(&ledger).await?;
+
+    let kept = ledger
+        .post_transaction(&tx_code, Some(journal.params()))
+        .await?;
+    let voided = ledger
+        .post_transaction(&tx_code, Some(journal.params()))
+        .await?;
+    let void_id = ledger.void_transaction(voided).await?;
+
+    for (id, status) in [
+        (kept, TransactionStatus::Posted),
+        (voided, TransactionStatus::Voided),
+        (void_id, TransactionStatus::Reversal),
+    ] {
+        assert_eq!(ledger.transactions().find_by_id(id).await?.status(), status);
+    }
+
+    let list = |statuses: Vec<TransactionStatus>| {
+        let filter = TransactionFilter {
+            journal_id: Some(journal.journal_id),
+            statuses: Some(statuses),
+            ..Default::default()
+        };
+        let transactions = ledger.transactions();
+        async move {
+            let page = transactions
+                .list(
+                    filter,
+                    PaginatedQueryArgs {
+                        first: 10,
+                        after: None,
+                    },
+                )
+                .await?;
+            let mut ids: Vec<_> = page.entities.into_iter().map(|tx| tx.id).collect();
+            ids.sort();
+            Ok::<_, SqlxLedgerError>(ids)
+        }
+    };
+    // The activity view leaves out voided pairs
+    assert_eq!(list(vec![TransactionStatus::Posted]).await?, vec![kept]);
+    let mut pair = vec![voided, void_id];
+    pair.sort();
+    assert_eq!(
+        list(vec![TransactionStatus::Voided, TransactionStatus::Reversal]).await?,
+        pair
+    );
+    Ok(())
+}
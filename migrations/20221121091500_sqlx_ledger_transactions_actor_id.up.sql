@@ -0,0 +1 @@
+ALTER TABLE sqlx_ledger_transactions ADD COLUMN actor_id VARCHAR;
@@ -0,0 +1 @@
+ALTER TABLE sqlx_ledger_accounts DROP COLUMN allowed_direction;
@@ -0,0 +1 @@
+ALTER TABLE sqlx_ledger_accounts ADD COLUMN allowed_direction DebitOrCredit;
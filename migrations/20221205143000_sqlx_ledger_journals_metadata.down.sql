@@ -0,0 +1 @@
+ALTER TABLE sqlx_ledger_journals DROP COLUMN metadata;
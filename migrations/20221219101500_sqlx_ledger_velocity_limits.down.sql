@@ -0,0 +1,3 @@
+DROP TABLE sqlx_ledger_velocity_limits;
+DROP TABLE sqlx_ledger_velocity_controls;
+DROP TABLE sqlx_ledger_velocity_usage;
@@ -0,0 +1,35 @@
+CREATE TABLE sqlx_ledger_velocity_limits (
+  id UUID NOT NULL,
+  name VARCHAR NOT NULL,
+  description VARCHAR,
+  currency VARCHAR,
+  layer Layer NOT NULL,
+  direction DebitOrCredit NOT NULL,
+  time_window JSONB NOT NULL,
+  limit_units NUMERIC NOT NULL,
+  condition VARCHAR,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE(id),
+  UNIQUE(name)
+);
+
+CREATE TABLE sqlx_ledger_velocity_controls (
+  velocity_limit_id UUID NOT NULL,
+  account_id UUID,
+  tx_template_id UUID,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK ((account_id IS NULL) <> (tx_template_id IS NULL)),
+  UNIQUE(velocity_limit_id, account_id),
+  UNIQUE(velocity_limit_id, tx_template_id)
+);
+
+CREATE TABLE sqlx_ledger_velocity_usage (
+  velocity_limit_id UUID NOT NULL,
+  account_id UUID NOT NULL,
+  currency VARCHAR NOT NULL,
+  transaction_id UUID NOT NULL,
+  units NUMERIC NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX sqlx_ledger_velocity_usage_window_idx
+  ON sqlx_ledger_velocity_usage (velocity_limit_id, account_id, currency, created_at);
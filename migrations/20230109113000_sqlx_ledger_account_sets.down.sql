@@ -0,0 +1,3 @@
+DROP TABLE sqlx_ledger_account_sets;
+DROP TABLE sqlx_ledger_account_set_members;
+DROP TABLE sqlx_ledger_account_set_balances;
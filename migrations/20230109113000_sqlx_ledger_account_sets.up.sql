@@ -0,0 +1,38 @@
+CREATE TABLE sqlx_ledger_account_sets (
+  id UUID NOT NULL,
+  journal_id UUID NOT NULL,
+  name VARCHAR NOT NULL,
+  normal_balance_type DebitOrCredit NOT NULL,
+  description VARCHAR,
+  metadata JSONB,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE(id),
+  UNIQUE(journal_id, name)
+);
+
+CREATE TABLE sqlx_ledger_account_set_members (
+  account_set_id UUID NOT NULL,
+  member_account_id UUID,
+  member_account_set_id UUID,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK ((member_account_id IS NULL) <> (member_account_set_id IS NULL)),
+  UNIQUE(account_set_id, member_account_id),
+  UNIQUE(account_set_id, member_account_set_id)
+);
+CREATE INDEX sqlx_ledger_account_set_members_account_idx
+  ON sqlx_ledger_account_set_members (member_account_id);
+CREATE INDEX sqlx_ledger_account_set_members_set_idx
+  ON sqlx_ledger_account_set_members (member_account_set_id);
+
+CREATE TABLE sqlx_ledger_account_set_balances (
+  account_set_id UUID NOT NULL,
+  currency VARCHAR NOT NULL,
+  settled_dr_balance NUMERIC NOT NULL,
+  settled_cr_balance NUMERIC NOT NULL,
+  pending_dr_balance NUMERIC NOT NULL,
+  pending_cr_balance NUMERIC NOT NULL,
+  encumbered_dr_balance NUMERIC NOT NULL,
+  encumbered_cr_balance NUMERIC NOT NULL,
+  modified_at TIMESTAMPTZ NOT NULL,
+  UNIQUE(account_set_id, currency)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE sqlx_ledger_transactions DROP COLUMN voided_by;
+ALTER TABLE sqlx_ledger_transactions DROP COLUMN voids;
@@ -0,0 +1,2 @@
+ALTER TABLE sqlx_ledger_transactions ADD COLUMN voids UUID;
+ALTER TABLE sqlx_ledger_transactions ADD COLUMN voided_by UUID;
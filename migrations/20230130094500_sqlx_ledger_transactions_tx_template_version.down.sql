@@ -0,0 +1 @@
+ALTER TABLE sqlx_ledger_transactions DROP COLUMN tx_template_version;
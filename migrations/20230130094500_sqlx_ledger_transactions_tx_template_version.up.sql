@@ -0,0 +1 @@
+ALTER TABLE sqlx_ledger_transactions ADD COLUMN tx_template_version INT NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+DROP TABLE sqlx_ledger_closed_periods;
+DROP TABLE sqlx_ledger_period_closing_balances;
@@ -0,0 +1,22 @@
+CREATE TABLE sqlx_ledger_closed_periods (
+  journal_id UUID NOT NULL,
+  period_start DATE NOT NULL,
+  period_end DATE NOT NULL,
+  closed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK (period_start < period_end),
+  UNIQUE(journal_id, period_start)
+);
+
+CREATE TABLE sqlx_ledger_period_closing_balances (
+  journal_id UUID NOT NULL,
+  period_start DATE NOT NULL,
+  account_id UUID NOT NULL,
+  currency VARCHAR NOT NULL,
+  settled_dr_balance NUMERIC NOT NULL,
+  settled_cr_balance NUMERIC NOT NULL,
+  pending_dr_balance NUMERIC NOT NULL,
+  pending_cr_balance NUMERIC NOT NULL,
+  encumbered_dr_balance NUMERIC NOT NULL,
+  encumbered_cr_balance NUMERIC NOT NULL,
+  UNIQUE(journal_id, period_start, account_id, currency)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE sqlx_ledger_entries DROP COLUMN conversion_rate;
+ALTER TABLE sqlx_ledger_entries DROP COLUMN converted_from_units;
+ALTER TABLE sqlx_ledger_entries DROP COLUMN converted_from_currency;
+DROP TABLE sqlx_ledger_exchange_rates;
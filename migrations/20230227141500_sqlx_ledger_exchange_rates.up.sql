@@ -0,0 +1,13 @@
+CREATE TABLE sqlx_ledger_exchange_rates (
+  base VARCHAR NOT NULL,
+  quote VARCHAR NOT NULL,
+  rate NUMERIC NOT NULL,
+  as_of TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK (rate > 0),
+  UNIQUE(base, quote, as_of)
+);
+
+ALTER TABLE sqlx_ledger_entries ADD COLUMN converted_from_currency VARCHAR;
+ALTER TABLE sqlx_ledger_entries ADD COLUMN converted_from_units NUMERIC;
+ALTER TABLE sqlx_ledger_entries ADD COLUMN conversion_rate NUMERIC;
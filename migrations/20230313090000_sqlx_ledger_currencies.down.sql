@@ -0,0 +1 @@
+DROP TABLE sqlx_ledger_currencies;
@@ -0,0 +1,6 @@
+CREATE TABLE sqlx_ledger_currencies (
+  code VARCHAR PRIMARY KEY,
+  exponent INT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK (exponent >= 0 AND exponent <= 28)
+);
@@ -0,0 +1 @@
+DROP INDEX sqlx_ledger_velocity_usage_transaction_idx;
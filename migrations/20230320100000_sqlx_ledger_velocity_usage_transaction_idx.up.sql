@@ -0,0 +1,2 @@
+CREATE INDEX sqlx_ledger_velocity_usage_transaction_idx
+  ON sqlx_ledger_velocity_usage (transaction_id);
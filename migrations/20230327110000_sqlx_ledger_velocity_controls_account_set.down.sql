@@ -0,0 +1,5 @@
+DELETE FROM sqlx_ledger_velocity_controls WHERE account_set_id IS NOT NULL;
+ALTER TABLE sqlx_ledger_velocity_controls DROP CONSTRAINT sqlx_ledger_velocity_controls_check;
+ALTER TABLE sqlx_ledger_velocity_controls DROP COLUMN account_set_id;
+ALTER TABLE sqlx_ledger_velocity_controls ADD CONSTRAINT sqlx_ledger_velocity_controls_check
+  CHECK ((account_id IS NULL) <> (tx_template_id IS NULL));
@@ -0,0 +1,6 @@
+ALTER TABLE sqlx_ledger_velocity_controls ADD COLUMN account_set_id UUID;
+ALTER TABLE sqlx_ledger_velocity_controls DROP CONSTRAINT sqlx_ledger_velocity_controls_check;
+ALTER TABLE sqlx_ledger_velocity_controls ADD CONSTRAINT sqlx_ledger_velocity_controls_check
+  CHECK (num_nonnulls(account_id, tx_template_id, account_set_id) = 1);
+ALTER TABLE sqlx_ledger_velocity_controls ADD CONSTRAINT sqlx_ledger_velocity_controls_velocity_limit_id_account_set_key
+  UNIQUE (velocity_limit_id, account_set_id);